@@ -0,0 +1,73 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+fn main() {
+    println!("cargo:rerun-if-changed=importer.c");
+    println!("cargo:rerun-if-changed=fixtures");
+    println!("cargo:rerun-if-changed=../../import/include/treasury_import.h");
+
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+
+    compile(
+        &manifest_dir.join("importer.c"),
+        "c_importer",
+        "C_IMPORTER_PATH",
+    );
+    compile(
+        &manifest_dir.join("fixtures/rev3.c"),
+        "c_importer_rev3",
+        "C_IMPORTER_REV3_PATH",
+    );
+    compile(
+        &manifest_dir.join("fixtures/factory.c"),
+        "c_importer_factory",
+        "C_IMPORTER_FACTORY_PATH",
+    );
+    compile(
+        &manifest_dir.join("fixtures/printing.c"),
+        "c_importer_printing",
+        "C_IMPORTER_PRINTING_PATH",
+    );
+}
+
+/// Compiles `source` into shared library and exposes its path in `env` variable.
+fn compile(source: &Path, name: &str, env: &str) {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    let include = manifest_dir.join("../../import/include");
+
+    let lib_name = match env::var("CARGO_CFG_TARGET_OS").unwrap().as_str() {
+        "windows" => format!("{}.dll", name),
+        "macos" | "ios" => format!("lib{}.dylib", name),
+        _ => format!("lib{}.so", name),
+    };
+    let lib_path = out_dir.join(lib_name);
+
+    let compiler = cc::Build::new().get_compiler();
+    let mut command = compiler.to_command();
+
+    if compiler.is_like_msvc() {
+        command
+            .arg("/LD")
+            .arg(format!("/I{}", include.display()))
+            .arg(format!("/Fo{}\\", out_dir.display()))
+            .arg(source)
+            .arg(format!("/Fe{}", lib_path.display()));
+    } else {
+        command
+            .arg("-shared")
+            .arg("-I")
+            .arg(&include)
+            .arg(source)
+            .arg("-o")
+            .arg(&lib_path);
+    }
+
+    let status = command.status().expect("Failed to run C compiler");
+    assert!(status.success(), "Failed to compile '{}'", source.display());
+
+    println!("cargo:rustc-env={}={}", env, lib_path.display());
+}
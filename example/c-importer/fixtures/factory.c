@@ -0,0 +1,264 @@
+/*
+ * Importers library that creates its importer from configuration.
+ *
+ * Configuration is JSON string that importer appends to the source.
+ * Library logs creation and dropping of importers and each import.
+ * Like libraries defined with `make_treasury_importers_library!`,
+ * it keeps the first logger it is given while it is loaded.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "treasury_import.h"
+
+#ifdef _WIN32
+#include <wchar.h>
+#endif
+
+typedef void (*log_fn)(const void *logger, const uint8_t *record_ptr, uint32_t record_len);
+
+struct treasury_importer {
+    char *suffix;
+    uint32_t suffix_len;
+};
+
+struct treasury_importers {
+    treasury_importer importer;
+};
+
+static const void *LOGGER = NULL;
+static log_fn LOG = NULL;
+
+/* Logs info event. */
+static void log_info(const char *message) {
+    uint8_t record[256];
+    treasury_writer writer = treasury_writer_new(record, sizeof(record));
+
+    if (LOG == NULL) {
+        return;
+    }
+
+    /* Event of info level. */
+    treasury_write_u8(&writer, 0);
+    treasury_write_u8(&writer, 3);
+    treasury_write_str(&writer, "c_factory");
+    treasury_write_str(&writer, message);
+
+    if (writer.len <= writer.cap) {
+        LOG(LOGGER, record, writer.len);
+    }
+}
+
+static FILE *open_path(const treasury_os_char *ptr, uint32_t len, int write) {
+    FILE *file;
+#ifdef _WIN32
+    wchar_t *path = (wchar_t *)malloc((len + 1) * sizeof(wchar_t));
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len * sizeof(wchar_t));
+    path[len] = 0;
+    file = _wfopen(path, write ? L"wb" : L"rb");
+#else
+    char *path = (char *)malloc(len + 1);
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len);
+    path[len] = 0;
+    file = fopen(path, write ? "wb" : "rb");
+#endif
+    free(path);
+    return file;
+}
+
+static int32_t finish(treasury_writer *result, uint32_t *result_len, int32_t code) {
+    if (result->len > *result_len) {
+        *result_len = result->len;
+        return TREASURY_BUFFER_IS_TOO_SMALL;
+    }
+    *result_len = result->len;
+    return code;
+}
+
+static int32_t fail(treasury_writer *result, uint32_t *result_len, const char *reason) {
+    treasury_write_str(result, reason);
+    /* No diagnostics. */
+    treasury_write_u32(result, 0);
+    return finish(result, result_len, TREASURY_OTHER_ERROR);
+}
+
+static int32_t suffix_import(
+    const treasury_importer *importer,
+    const treasury_os_char *source_ptr,
+    uint32_t source_len,
+    const treasury_os_char *output_ptr,
+    uint32_t output_len,
+    const uint8_t *options_ptr,
+    uint32_t options_len,
+    treasury_sources *sources,
+    treasury_sources_get_fn sources_get,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    const treasury_cancellation *cancellation,
+    treasury_cancellation_is_cancelled_fn cancellation_is_cancelled,
+    treasury_progress *progress,
+    treasury_progress_report_fn progress_report,
+    uint8_t *result_ptr,
+    uint32_t *result_len) {
+    treasury_writer result = treasury_writer_new(result_ptr, *result_len);
+    char data[4096];
+    size_t len;
+    FILE *file;
+    int ok;
+
+    (void)options_ptr;
+    (void)options_len;
+    (void)sources;
+    (void)sources_get;
+    (void)dependencies;
+    (void)dependencies_get;
+    (void)cancellation;
+    (void)cancellation_is_cancelled;
+    (void)progress;
+    (void)progress_report;
+
+    log_info("Importing");
+
+    file = open_path(source_ptr, source_len, 0);
+    if (file == NULL) {
+        return fail(&result, result_len, "Failed to open source");
+    }
+    len = fread(data, 1, sizeof(data), file);
+    fclose(file);
+
+    file = open_path(output_ptr, output_len, 1);
+    if (file == NULL) {
+        return fail(&result, result_len, "Failed to open output");
+    }
+    ok = fwrite(data, 1, len, file) == len;
+    ok = ok && fwrite(importer->suffix, 1, importer->suffix_len, file) == importer->suffix_len;
+    if (fclose(file) != 0 || !ok) {
+        return fail(&result, result_len, "Failed to write output");
+    }
+
+    /* Empty payload and no diagnostics. */
+    treasury_write_u32(&result, 0);
+    treasury_write_u32(&result, 0);
+    return finish(&result, result_len, TREASURY_SUCCESS);
+}
+
+static int32_t suffix_probe(const treasury_importer *importer, const uint8_t *head_ptr, uint32_t head_len) {
+    (void)importer;
+    (void)head_ptr;
+    (void)head_len;
+    return TREASURY_NOT_FOUND;
+}
+
+static uint32_t suffix_describe(const treasury_importer *importer, uint8_t *buffer, uint32_t cap) {
+    treasury_writer writer = treasury_writer_new(buffer, cap);
+    (void)importer;
+
+    treasury_write_str(&writer, "C suffix importer");
+
+    /* Formats. */
+    treasury_write_u32(&writer, 1);
+    treasury_write_str(&writer, "suffix");
+
+    /* Extensions. */
+    treasury_write_u32(&writer, 1);
+    treasury_write_str(&writer, "suf");
+
+    treasury_write_str(&writer, "text");
+
+    /* No magic. */
+    treasury_write_u32(&writer, 0);
+
+    treasury_write_str(&writer, "Appends configured suffix to text");
+    treasury_write_optional_str(&writer, NULL);
+    treasury_write_optional_str(&writer, NULL);
+    treasury_write_optional_str(&writer, NULL);
+
+    return writer.len;
+}
+
+static void write_error(const char *message, uint8_t *error_ptr, uint32_t *error_len) {
+    uint32_t len = (uint32_t)strlen(message);
+    if (len > *error_len) {
+        len = *error_len;
+    }
+    memcpy(error_ptr, message, len);
+    *error_len = len;
+}
+
+TREASURY_EXPORT const uint32_t TREASURY_DYLIB_MAGIC = TREASURY_MAGIC;
+
+TREASURY_EXPORT void treasury_importer_ffi_revisions(uint32_t *min, uint32_t *max) {
+    *min = TREASURY_FFI_REVISION;
+    *max = TREASURY_FFI_REVISION;
+}
+
+TREASURY_EXPORT uint64_t treasury_importer_ffi_layout(uint32_t revision) {
+    return revision == TREASURY_FFI_REVISION ? TREASURY_FFI_LAYOUT : 0;
+}
+
+TREASURY_EXPORT void treasury_set_logger(const void *logger, log_fn log, uint32_t max_level) {
+    (void)max_level;
+
+    if (LOG == NULL) {
+        LOGGER = logger;
+        LOG = log;
+    }
+}
+
+TREASURY_EXPORT treasury_importers *treasury_create_importers(
+    const uint8_t *config_ptr,
+    uint32_t config_len,
+    uint8_t *error_ptr,
+    uint32_t *error_len) {
+    treasury_importers *importers;
+
+    if (config_ptr == NULL || config_len < 2 || config_ptr[0] != '"' || config_ptr[config_len - 1] != '"') {
+        write_error("Configuration must be a string", error_ptr, error_len);
+        return NULL;
+    }
+
+    importers = (treasury_importers *)malloc(sizeof(treasury_importers));
+    if (importers == NULL) {
+        write_error("Out of memory", error_ptr, error_len);
+        return NULL;
+    }
+
+    importers->importer.suffix_len = config_len - 2;
+    importers->importer.suffix = (char *)malloc(config_len - 2);
+    if (importers->importer.suffix == NULL && config_len > 2) {
+        free(importers);
+        write_error("Out of memory", error_ptr, error_len);
+        return NULL;
+    }
+    memcpy(importers->importer.suffix, config_ptr + 1, config_len - 2);
+
+    log_info("Created importers");
+    return importers;
+}
+
+TREASURY_EXPORT uint32_t treasury_export_created_importers(
+    const treasury_importers *importers,
+    treasury_importer_ffi *buffer,
+    uint32_t cap) {
+    if (cap > 0) {
+        buffer[0].importer = &importers->importer;
+        buffer[0].import = suffix_import;
+        buffer[0].probe = suffix_probe;
+        buffer[0].describe = suffix_describe;
+        buffer[0].import_stream = NULL;
+    }
+    return 1;
+}
+
+TREASURY_EXPORT void treasury_drop_importers(treasury_importers *importers) {
+    log_info("Dropped importers");
+    free(importers->importer.suffix);
+    free(importers);
+}
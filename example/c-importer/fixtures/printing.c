@@ -0,0 +1,180 @@
+/*
+ * Importers library that prints to stdout.
+ *
+ * Importer copies the source and prints when importers are exported
+ * and on each import, like libraries with leftover debug output do.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "treasury_import.h"
+
+#ifdef _WIN32
+#include <wchar.h>
+#endif
+
+struct treasury_importer {
+    int unused;
+};
+
+static treasury_importer IMPORTER;
+
+static FILE *open_path(const treasury_os_char *ptr, uint32_t len, int write) {
+    FILE *file;
+#ifdef _WIN32
+    wchar_t *path = (wchar_t *)malloc((len + 1) * sizeof(wchar_t));
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len * sizeof(wchar_t));
+    path[len] = 0;
+    file = _wfopen(path, write ? L"wb" : L"rb");
+#else
+    char *path = (char *)malloc(len + 1);
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len);
+    path[len] = 0;
+    file = fopen(path, write ? "wb" : "rb");
+#endif
+    free(path);
+    return file;
+}
+
+static int32_t finish(treasury_writer *result, uint32_t *result_len, int32_t code) {
+    if (result->len > *result_len) {
+        *result_len = result->len;
+        return TREASURY_BUFFER_IS_TOO_SMALL;
+    }
+    *result_len = result->len;
+    return code;
+}
+
+static int32_t fail(treasury_writer *result, uint32_t *result_len, const char *reason) {
+    treasury_write_str(result, reason);
+    /* No diagnostics. */
+    treasury_write_u32(result, 0);
+    return finish(result, result_len, TREASURY_OTHER_ERROR);
+}
+
+static int32_t printing_import(
+    const treasury_importer *importer,
+    const treasury_os_char *source_ptr,
+    uint32_t source_len,
+    const treasury_os_char *output_ptr,
+    uint32_t output_len,
+    const uint8_t *options_ptr,
+    uint32_t options_len,
+    treasury_sources *sources,
+    treasury_sources_get_fn sources_get,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    const treasury_cancellation *cancellation,
+    treasury_cancellation_is_cancelled_fn cancellation_is_cancelled,
+    treasury_progress *progress,
+    treasury_progress_report_fn progress_report,
+    uint8_t *result_ptr,
+    uint32_t *result_len) {
+    treasury_writer result = treasury_writer_new(result_ptr, *result_len);
+    char data[4096];
+    size_t len;
+    FILE *file;
+    int ok;
+
+    (void)importer;
+    (void)options_ptr;
+    (void)options_len;
+    (void)sources;
+    (void)sources_get;
+    (void)dependencies;
+    (void)dependencies_get;
+    (void)cancellation;
+    (void)cancellation_is_cancelled;
+    (void)progress;
+    (void)progress_report;
+
+    printf("Importing\n");
+    fflush(stdout);
+
+    file = open_path(source_ptr, source_len, 0);
+    if (file == NULL) {
+        return fail(&result, result_len, "Failed to open source");
+    }
+    len = fread(data, 1, sizeof(data), file);
+    fclose(file);
+
+    file = open_path(output_ptr, output_len, 1);
+    if (file == NULL) {
+        return fail(&result, result_len, "Failed to open output");
+    }
+    ok = fwrite(data, 1, len, file) == len;
+    if (fclose(file) != 0 || !ok) {
+        return fail(&result, result_len, "Failed to write output");
+    }
+
+    /* Empty payload and no diagnostics. */
+    treasury_write_u32(&result, 0);
+    treasury_write_u32(&result, 0);
+    return finish(&result, result_len, TREASURY_SUCCESS);
+}
+
+static int32_t printing_probe(const treasury_importer *importer, const uint8_t *head_ptr, uint32_t head_len) {
+    (void)importer;
+    (void)head_ptr;
+    (void)head_len;
+    return TREASURY_NOT_FOUND;
+}
+
+static uint32_t printing_describe(const treasury_importer *importer, uint8_t *buffer, uint32_t cap) {
+    treasury_writer writer = treasury_writer_new(buffer, cap);
+    (void)importer;
+
+    treasury_write_str(&writer, "C printing importer");
+
+    /* Formats. */
+    treasury_write_u32(&writer, 1);
+    treasury_write_str(&writer, "print");
+
+    /* Extensions. */
+    treasury_write_u32(&writer, 1);
+    treasury_write_str(&writer, "print");
+
+    treasury_write_str(&writer, "text");
+
+    /* No magic. */
+    treasury_write_u32(&writer, 0);
+
+    treasury_write_str(&writer, "Copies text and prints to stdout");
+    treasury_write_optional_str(&writer, NULL);
+    treasury_write_optional_str(&writer, NULL);
+    treasury_write_optional_str(&writer, NULL);
+
+    return writer.len;
+}
+
+TREASURY_EXPORT const uint32_t TREASURY_DYLIB_MAGIC = TREASURY_MAGIC;
+
+TREASURY_EXPORT void treasury_importer_ffi_revisions(uint32_t *min, uint32_t *max) {
+    *min = TREASURY_FFI_REVISION;
+    *max = TREASURY_FFI_REVISION;
+}
+
+TREASURY_EXPORT uint64_t treasury_importer_ffi_layout(uint32_t revision) {
+    return revision == TREASURY_FFI_REVISION ? TREASURY_FFI_LAYOUT : 0;
+}
+
+TREASURY_EXPORT uint32_t treasury_export_importers_rev5(treasury_importer_ffi *buffer, uint32_t cap) {
+    printf("Exporting importers\n");
+    fflush(stdout);
+
+    if (cap > 0) {
+        buffer[0].importer = &IMPORTER;
+        buffer[0].import = printing_import;
+        buffer[0].probe = printing_probe;
+        buffer[0].describe = printing_describe;
+        buffer[0].import_stream = NULL;
+    }
+    return 1;
+}
@@ -0,0 +1,203 @@
+/*
+ * Importers library of ABI revision 3, as built with `treasury-import` 0.3.
+ * It exports only symbols of that revision, so loader has to use its adapter.
+ *
+ * Importer converts text to lower case.
+ * Source that starts with `+<name>` line is followed by `<name>` source.
+ */
+
+#include <ctype.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "treasury_import.h"
+
+#ifdef _WIN32
+#include <wchar.h>
+#endif
+
+#define MAX_EXTENSION_LEN 16
+#define MAX_EXTENSION_COUNT 16
+#define MAX_FFI_NAME_LEN 64
+#define MAX_FORMATS_COUNT 32
+
+/* Result buffer receives only the payload, without diagnostics. */
+typedef int32_t (*rev3_import_fn)(
+    const treasury_importer *importer,
+    const treasury_os_char *source_ptr,
+    uint32_t source_len,
+    const treasury_os_char *output_ptr,
+    uint32_t output_len,
+    treasury_sources *sources,
+    treasury_sources_get_fn sources_get,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    uint8_t *result_ptr,
+    uint32_t *result_len);
+
+typedef struct rev3_importer_ffi {
+    const treasury_importer *importer;
+    rev3_import_fn import;
+    char name[MAX_FFI_NAME_LEN];
+    char formats[MAX_FORMATS_COUNT][MAX_FFI_NAME_LEN];
+    char target[MAX_FFI_NAME_LEN];
+    char extensions[MAX_EXTENSION_COUNT][MAX_EXTENSION_LEN];
+} rev3_importer_ffi;
+
+static FILE *open_path(const treasury_os_char *ptr, uint32_t len, int write) {
+    FILE *file;
+#ifdef _WIN32
+    wchar_t *path = (wchar_t *)malloc((len + 1) * sizeof(wchar_t));
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len * sizeof(wchar_t));
+    path[len] = 0;
+    file = _wfopen(path, write ? L"wb" : L"rb");
+#else
+    char *path = (char *)malloc(len + 1);
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len);
+    path[len] = 0;
+    file = fopen(path, write ? "wb" : "rb");
+#endif
+    free(path);
+    return file;
+}
+
+/* Source paths longer than this are not supported. */
+#define PATH_BUF_LEN 4096
+
+static void put_u32(uint8_t *ptr, uint32_t value) {
+    ptr[0] = (uint8_t)value;
+    ptr[1] = (uint8_t)(value >> 8);
+    ptr[2] = (uint8_t)(value >> 16);
+    ptr[3] = (uint8_t)(value >> 24);
+}
+
+/* Copies source to output in lower case. Returns zero on failure. */
+static int copy_lower(FILE *source, FILE *output) {
+    int c;
+    while ((c = fgetc(source)) != EOF) {
+        if (fputc(tolower(c), output) == EOF) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+static int32_t fail(uint8_t *result_ptr, uint32_t *result_len, const char *reason) {
+    uint32_t len = (uint32_t)strlen(reason);
+    if (len > *result_len) {
+        *result_len = len;
+        return TREASURY_BUFFER_IS_TOO_SMALL;
+    }
+    memcpy(result_ptr, reason, len);
+    *result_len = len;
+    return TREASURY_OTHER_ERROR;
+}
+
+static int32_t lowercase_import(
+    const treasury_importer *importer,
+    const treasury_os_char *source_ptr,
+    uint32_t source_len,
+    const treasury_os_char *output_ptr,
+    uint32_t output_len,
+    treasury_sources *sources,
+    treasury_sources_get_fn sources_get,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    uint8_t *result_ptr,
+    uint32_t *result_len) {
+    FILE *source;
+    FILE *output;
+    char name[PATH_BUF_LEN];
+    uint32_t name_len = 0;
+    int c;
+    int ok;
+
+    (void)importer;
+    (void)dependencies;
+    (void)dependencies_get;
+
+    source = open_path(source_ptr, source_len, 0);
+    if (source == NULL) {
+        return fail(result_ptr, result_len, "Failed to open source");
+    }
+
+    /* Reads `+<name>` line if present. */
+    c = fgetc(source);
+    if (c == '+') {
+        while ((c = fgetc(source)) != EOF && c != '\n' && name_len < PATH_BUF_LEN) {
+            name[name_len++] = (char)c;
+        }
+    } else if (c != EOF) {
+        ungetc(c, source);
+    }
+
+    output = open_path(output_ptr, output_len, 1);
+    if (output == NULL) {
+        fclose(source);
+        return fail(result_ptr, result_len, "Failed to open output");
+    }
+
+    ok = copy_lower(source, output);
+    fclose(source);
+
+    if (ok && name_len > 0) {
+        treasury_os_char path[PATH_BUF_LEN];
+        uint32_t path_len = PATH_BUF_LEN;
+        int32_t code = sources_get(sources, (const uint8_t *)name, name_len, path, &path_len);
+
+        if (code == TREASURY_NOT_FOUND) {
+            /* Payload is list of one source. */
+            uint32_t len = 4 + 4 + name_len;
+            fclose(output);
+            if (len > *result_len) {
+                *result_len = len;
+                return TREASURY_BUFFER_IS_TOO_SMALL;
+            }
+            put_u32(result_ptr, 1);
+            put_u32(result_ptr + 4, name_len);
+            memcpy(result_ptr + 8, name, name_len);
+            *result_len = len;
+            return TREASURY_REQUIRE_SOURCES;
+        }
+
+        source = code == TREASURY_SUCCESS ? open_path(path, path_len, 0) : NULL;
+        if (source == NULL) {
+            fclose(output);
+            return fail(result_ptr, result_len, "Failed to open included source");
+        }
+        ok = copy_lower(source, output);
+        fclose(source);
+    }
+
+    if (fclose(output) != 0 || !ok) {
+        return fail(result_ptr, result_len, "Failed to write output");
+    }
+
+    *result_len = 0;
+    return TREASURY_SUCCESS;
+}
+
+TREASURY_EXPORT const uint32_t TREASURY_DYLIB_MAGIC = TREASURY_MAGIC;
+
+TREASURY_EXPORT uint32_t treasury_importer_ffi_version_minor(void) {
+    return 3;
+}
+
+TREASURY_EXPORT uint32_t treasury_export_importers(rev3_importer_ffi *buffer, uint32_t cap) {
+    if (cap > 0) {
+        memset(&buffer[0], 0, sizeof(rev3_importer_ffi));
+        buffer[0].importer = NULL;
+        buffer[0].import = lowercase_import;
+        strcpy(buffer[0].name, "C lowercase importer");
+        strcpy(buffer[0].formats[0], "lowercase");
+        strcpy(buffer[0].target, "text");
+        strcpy(buffer[0].extensions[0], "lower");
+    }
+    return 1;
+}
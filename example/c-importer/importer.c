@@ -0,0 +1,384 @@
+/*
+ * Importer written in C that converts text to upper case.
+ *
+ * Source that starts with `+<name>` line is followed by `<name>` source.
+ * Importer supports both paths and streams.
+ */
+
+#include <ctype.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "treasury_import.h"
+
+#ifdef _WIN32
+#include <wchar.h>
+#endif
+
+/* Source paths longer than this are not supported. */
+#define PATH_BUF_LEN 4096
+
+struct treasury_importer {
+    const char *name;
+    const char *target;
+};
+
+static const treasury_importer UPPERCASE = {"C uppercase importer", "text"};
+
+static FILE *open_path(const treasury_os_char *ptr, uint32_t len, int write) {
+    FILE *file;
+#ifdef _WIN32
+    wchar_t *path = (wchar_t *)malloc((len + 1) * sizeof(wchar_t));
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len * sizeof(wchar_t));
+    path[len] = 0;
+    file = _wfopen(path, write ? L"wb" : L"rb");
+#else
+    char *path = (char *)malloc(len + 1);
+    if (path == NULL) {
+        return NULL;
+    }
+    memcpy(path, ptr, len);
+    path[len] = 0;
+    file = fopen(path, write ? "wb" : "rb");
+#endif
+    free(path);
+    return file;
+}
+
+/* Appends whole file to `*data`. Returns zero on failure. */
+static int read_path(const treasury_os_char *ptr, uint32_t len, char **data, size_t *data_len) {
+    char chunk[4096];
+    size_t read;
+    FILE *file = open_path(ptr, len, 0);
+    if (file == NULL) {
+        return 0;
+    }
+
+    while ((read = fread(chunk, 1, sizeof(chunk), file)) > 0) {
+        char *grown = (char *)realloc(*data, *data_len + read);
+        if (grown == NULL) {
+            fclose(file);
+            return 0;
+        }
+        memcpy(grown + *data_len, chunk, read);
+        *data = grown;
+        *data_len += read;
+    }
+
+    fclose(file);
+    return 1;
+}
+
+static int write_path(const treasury_os_char *ptr, uint32_t len, const char *data, size_t data_len) {
+    int ok;
+    FILE *file = open_path(ptr, len, 1);
+    if (file == NULL) {
+        return 0;
+    }
+    ok = fwrite(data, 1, data_len, file) == data_len;
+    return fclose(file) == 0 && ok;
+}
+
+/* Appends whole stream to `*data`. Returns zero on failure. */
+static int read_stream(treasury_stream_reader *reader, treasury_stream_read_fn read_fn, char **data, size_t *data_len) {
+    uint8_t chunk[4096];
+    uint32_t read = sizeof(chunk);
+
+    while (read_fn(reader, chunk, &read) == TREASURY_SUCCESS) {
+        char *grown;
+        if (read == 0) {
+            return 1;
+        }
+        grown = (char *)realloc(*data, *data_len + read);
+        if (grown == NULL) {
+            return 0;
+        }
+        memcpy(grown + *data_len, chunk, read);
+        *data = grown;
+        *data_len += read;
+        read = sizeof(chunk);
+    }
+    return 0;
+}
+
+/*
+ * Finds `+<name>` line at the start of the data.
+ * Returns offset of the text that follows it, zero if there is no such line.
+ */
+static size_t include_line(const char *data, size_t data_len, const char **name, uint32_t *name_len) {
+    *name = data + 1;
+    *name_len = 0;
+
+    if (data_len == 0 || data[0] != '+') {
+        return 0;
+    }
+    while (1 + *name_len < data_len && (*name)[*name_len] != '\n') {
+        *name_len += 1;
+    }
+    return 1 + *name_len < data_len ? 2 + *name_len : data_len;
+}
+
+static void to_upper(char *data, size_t data_len) {
+    size_t i;
+    for (i = 0; i < data_len; ++i) {
+        data[i] = (char)toupper((unsigned char)data[i]);
+    }
+}
+
+/* Writes result with the code and payload into the writer, returns the code to the store. */
+static int32_t finish(treasury_writer *result, uint32_t *result_len, int32_t code) {
+    if (result->len > *result_len) {
+        *result_len = result->len;
+        return TREASURY_BUFFER_IS_TOO_SMALL;
+    }
+    *result_len = result->len;
+    return code;
+}
+
+static int32_t fail(treasury_writer *result, uint32_t *result_len, const char *reason) {
+    treasury_write_str(result, reason);
+    /* No diagnostics. */
+    treasury_write_u32(result, 0);
+    return finish(result, result_len, TREASURY_OTHER_ERROR);
+}
+
+static int32_t require_source(treasury_writer *result, uint32_t *result_len, const char *name, uint32_t name_len) {
+    /* Payload is list of one source. */
+    treasury_write_u32(result, 4 + 4 + name_len);
+    treasury_write_u32(result, 1);
+    treasury_write_bytes(result, name, name_len);
+    treasury_write_u32(result, 0);
+    return finish(result, result_len, TREASURY_REQUIRE_SOURCES);
+}
+
+static int32_t cancelled(treasury_writer *result, uint32_t *result_len) {
+    /* Empty payload and no diagnostics. */
+    treasury_write_u32(result, 0);
+    treasury_write_u32(result, 0);
+    return finish(result, result_len, TREASURY_CANCELLED);
+}
+
+static int32_t converted(treasury_writer *result, uint32_t *result_len, size_t len) {
+    char message[64];
+    snprintf(message, sizeof(message), "Converted %lu bytes", (unsigned long)len);
+
+    /* Empty payload and one diagnostic. */
+    treasury_write_u32(result, 0);
+    treasury_write_u32(result, 1);
+    treasury_write_diagnostic(result, TREASURY_SEVERITY_INFO, NULL, message);
+    return finish(result, result_len, TREASURY_SUCCESS);
+}
+
+static const char STAGE[] = "Converting";
+
+static int32_t uppercase_import(
+    const treasury_importer *importer,
+    const treasury_os_char *source_ptr,
+    uint32_t source_len,
+    const treasury_os_char *output_ptr,
+    uint32_t output_len,
+    const uint8_t *options_ptr,
+    uint32_t options_len,
+    treasury_sources *sources,
+    treasury_sources_get_fn sources_get,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    const treasury_cancellation *cancellation,
+    treasury_cancellation_is_cancelled_fn cancellation_is_cancelled,
+    treasury_progress *progress,
+    treasury_progress_report_fn progress_report,
+    uint8_t *result_ptr,
+    uint32_t *result_len) {
+    treasury_writer result = treasury_writer_new(result_ptr, *result_len);
+    char *data = NULL;
+    size_t data_len = 0;
+    size_t start;
+    const char *name;
+    uint32_t name_len;
+
+    (void)importer;
+    (void)options_ptr;
+    (void)options_len;
+    (void)dependencies;
+    (void)dependencies_get;
+
+    if (!read_path(source_ptr, source_len, &data, &data_len)) {
+        free(data);
+        return fail(&result, result_len, "Failed to read source");
+    }
+
+    start = include_line(data, data_len, &name, &name_len);
+    if (start > 0) {
+        treasury_os_char path[PATH_BUF_LEN];
+        uint32_t path_len = PATH_BUF_LEN;
+        int32_t code = sources_get(sources, (const uint8_t *)name, name_len, path, &path_len);
+        if (code == TREASURY_NOT_FOUND) {
+            code = require_source(&result, result_len, name, name_len);
+            free(data);
+            return code;
+        }
+        if (code != TREASURY_SUCCESS || !read_path(path, path_len, &data, &data_len)) {
+            free(data);
+            return fail(&result, result_len, "Failed to read included source");
+        }
+    }
+
+    if (cancellation_is_cancelled(cancellation) == TREASURY_CANCELLED) {
+        free(data);
+        return cancelled(&result, result_len);
+    }
+
+    progress_report(progress, 0.5f, (const uint8_t *)STAGE, sizeof(STAGE) - 1);
+    to_upper(data + start, data_len - start);
+
+    if (!write_path(output_ptr, output_len, data + start, data_len - start)) {
+        free(data);
+        return fail(&result, result_len, "Failed to write output");
+    }
+
+    free(data);
+    return converted(&result, result_len, data_len - start);
+}
+
+/* Same as `uppercase_import`, but with streams. Result is not kept, so it must fit. */
+static int32_t uppercase_import_stream(
+    const treasury_importer *importer,
+    treasury_stream_reader *source,
+    treasury_stream_read_fn reader_read,
+    treasury_stream_writer *output,
+    treasury_stream_write_fn writer_write,
+    const uint8_t *options_ptr,
+    uint32_t options_len,
+    treasury_source_streams *streams,
+    treasury_source_streams_open_fn streams_open,
+    treasury_source_streams_close_fn streams_close,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    const treasury_cancellation *cancellation,
+    treasury_cancellation_is_cancelled_fn cancellation_is_cancelled,
+    treasury_progress *progress,
+    treasury_progress_report_fn progress_report,
+    uint8_t *result_ptr,
+    uint32_t *result_len) {
+    treasury_writer result = treasury_writer_new(result_ptr, *result_len);
+    char *data = NULL;
+    size_t data_len = 0;
+    size_t start;
+    const char *name;
+    uint32_t name_len;
+    int32_t code;
+
+    (void)importer;
+    (void)options_ptr;
+    (void)options_len;
+    (void)dependencies;
+    (void)dependencies_get;
+
+    if (source == NULL) {
+        return fail(&result, result_len, "Importer kept no result");
+    }
+
+    if (!read_stream(source, reader_read, &data, &data_len)) {
+        free(data);
+        return fail(&result, result_len, "Failed to read source");
+    }
+
+    start = include_line(data, data_len, &name, &name_len);
+    if (start > 0) {
+        treasury_stream_reader *included = NULL;
+        int ok;
+
+        code = streams_open(streams, (const uint8_t *)name, name_len, &included);
+        if (code == TREASURY_NOT_FOUND) {
+            code = require_source(&result, result_len, name, name_len);
+            free(data);
+            return code;
+        }
+        if (code != TREASURY_SUCCESS) {
+            free(data);
+            return fail(&result, result_len, "Failed to open included source");
+        }
+
+        ok = read_stream(included, reader_read, &data, &data_len);
+        streams_close(streams, included);
+        if (!ok) {
+            free(data);
+            return fail(&result, result_len, "Failed to read included source");
+        }
+    }
+
+    if (cancellation_is_cancelled(cancellation) == TREASURY_CANCELLED) {
+        free(data);
+        return cancelled(&result, result_len);
+    }
+
+    progress_report(progress, 0.5f, (const uint8_t *)STAGE, sizeof(STAGE) - 1);
+    to_upper(data + start, data_len - start);
+
+    if (data_len - start > UINT32_MAX ||
+        writer_write(output, (const uint8_t *)data + start, (uint32_t)(data_len - start)) != TREASURY_SUCCESS) {
+        free(data);
+        return fail(&result, result_len, "Failed to write output");
+    }
+
+    free(data);
+    return converted(&result, result_len, data_len - start);
+}
+
+static int32_t uppercase_probe(const treasury_importer *importer, const uint8_t *head_ptr, uint32_t head_len) {
+    (void)importer;
+    (void)head_ptr;
+    (void)head_len;
+    return TREASURY_NOT_FOUND;
+}
+
+static uint32_t uppercase_describe(const treasury_importer *importer, uint8_t *buffer, uint32_t cap) {
+    treasury_writer writer = treasury_writer_new(buffer, cap);
+
+    treasury_write_str(&writer, importer->name);
+
+    /* Formats. */
+    treasury_write_u32(&writer, 1);
+    treasury_write_str(&writer, "uppercase");
+
+    /* Extensions. */
+    treasury_write_u32(&writer, 1);
+    treasury_write_str(&writer, "upper");
+
+    treasury_write_str(&writer, importer->target);
+
+    /* No magic. */
+    treasury_write_u32(&writer, 0);
+
+    treasury_write_str(&writer, "Converts ASCII text to upper case");
+    treasury_write_optional_str(&writer, "0.1.0");
+    treasury_write_optional_str(&writer, NULL);
+    treasury_write_optional_str(&writer, NULL);
+
+    return writer.len;
+}
+
+TREASURY_EXPORT const uint32_t TREASURY_DYLIB_MAGIC = TREASURY_MAGIC;
+
+TREASURY_EXPORT void treasury_importer_ffi_revisions(uint32_t *min, uint32_t *max) {
+    *min = TREASURY_FFI_REVISION;
+    *max = TREASURY_FFI_REVISION;
+}
+
+TREASURY_EXPORT uint64_t treasury_importer_ffi_layout(uint32_t revision) {
+    return revision == TREASURY_FFI_REVISION ? TREASURY_FFI_LAYOUT : 0;
+}
+
+TREASURY_EXPORT uint32_t treasury_export_importers_rev5(treasury_importer_ffi *buffer, uint32_t cap) {
+    if (cap > 0) {
+        buffer[0].importer = &UPPERCASE;
+        buffer[0].import = uppercase_import;
+        buffer[0].probe = uppercase_probe;
+        buffer[0].describe = uppercase_describe;
+        buffer[0].import_stream = uppercase_import_stream;
+    }
+    return 1;
+}
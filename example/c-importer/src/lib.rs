@@ -0,0 +1,18 @@
+//! Importers library written in C.
+//!
+//! Build script compiles `importer.c` into shared library against `treasury_import.h`.
+//! Tests load it like any other importers library.
+//!
+//! Libraries in `fixtures` export older ABI revisions to test loader adapters.
+
+/// Path to the compiled library.
+pub const LIBRARY_PATH: &str = env!("C_IMPORTER_PATH");
+
+/// Path to the library of ABI revision 3 that converts text to lower case.
+pub const REV3_LIBRARY_PATH: &str = env!("C_IMPORTER_REV3_PATH");
+
+/// Path to the library that creates importer appending configured suffix and logs its lifecycle.
+pub const FACTORY_LIBRARY_PATH: &str = env!("C_IMPORTER_FACTORY_PATH");
+
+/// Path to the library that copies text and prints to stdout.
+pub const PRINTING_LIBRARY_PATH: &str = env!("C_IMPORTER_PRINTING_PATH");
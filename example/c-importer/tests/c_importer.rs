@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+use treasury_import::{c_api, loading::DylibImporter, Importer, Severity};
+use treasury_import_testing::{load_importers, Fixture, RunError, UPDATE_SNAPSHOTS_VAR};
+
+fn importer() -> DylibImporter {
+    let mut importers = unsafe { load_importers(Path::new(c_importer::LIBRARY_PATH)).unwrap() };
+    assert_eq!(importers.len(), 1);
+    importers.pop().unwrap()
+}
+
+#[test]
+fn describes_importer() {
+    let importer = importer();
+    assert_eq!(importer.name(), "C uppercase importer");
+    assert_eq!(importer.formats(), ["uppercase"]);
+    assert_eq!(importer.extensions(), ["upper"]);
+    assert_eq!(importer.target(), "text");
+    assert_eq!(importer.description(), "Converts ASCII text to upper case");
+    assert_eq!(importer.version(), Some("0.1.0"));
+    assert_eq!(importer.package(), None);
+    assert!(importer.magic().is_empty());
+    assert!(!importer.probe(b"anything"));
+    assert!(importer.supports_streams());
+}
+
+#[test]
+fn imports_source() {
+    let mut fixture = Fixture::new("hello.upper", "Hello, World!").unwrap();
+    let imported = fixture.run(&importer()).unwrap();
+
+    assert_eq!(imported.output, b"HELLO, WORLD!");
+    assert_eq!(imported.attempts, 1);
+    assert_eq!(imported.progress, [(0.5, "Converting".to_owned())]);
+    assert_eq!(imported.diagnostics.len(), 1);
+    assert_eq!(imported.diagnostics[0].severity, Severity::Info);
+    assert_eq!(imported.diagnostics[0].message, "Converted 13 bytes");
+}
+
+#[test]
+fn requires_sources() {
+    let mut fixture = Fixture::new("main.upper", "+other.upper\nmain, ")
+        .unwrap()
+        .with_source("other.upper", "other");
+    let imported = fixture.run(&importer()).unwrap();
+
+    assert_eq!(imported.output, b"MAIN, OTHER");
+    assert_eq!(imported.attempts, 2);
+    assert_eq!(fixture.sources().requests(), ["other.upper", "other.upper"]);
+}
+
+#[test]
+fn imports_paths_same_as_streams() {
+    let mut fixture = Fixture::new("main.upper", "+other.upper\nmain, ")
+        .unwrap()
+        .with_source("other.upper", "other");
+    let importer = importer();
+
+    let streamed = fixture.run(&importer).unwrap();
+    let imported = fixture.run_paths(&importer).unwrap();
+
+    assert_eq!(imported.output, streamed.output);
+    assert_eq!(imported.attempts, streamed.attempts);
+    assert_eq!(imported.diagnostics, streamed.diagnostics);
+}
+
+#[test]
+fn reports_missing_sources() {
+    let mut fixture = Fixture::new("main.upper", "+missing.upper\nmain").unwrap();
+
+    match fixture.run(&importer()) {
+        Err(RunError::MissingSources(sources)) => assert_eq!(sources, ["missing.upper"]),
+        result => panic!(
+            "Unexpected result {:?}",
+            result.map(|imported| imported.output)
+        ),
+    }
+}
+
+/// Header is generated from the Rust definitions of the ABI.
+/// Set `TREASURY_UPDATE_SNAPSHOTS` to regenerate it.
+#[test]
+fn header_is_up_to_date() {
+    let path =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../import/include/treasury_import.h");
+    let header = c_api::header();
+
+    if std::env::var_os(UPDATE_SNAPSHOTS_VAR).is_some() {
+        std::fs::write(&path, header).unwrap();
+        return;
+    }
+
+    let committed = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        committed == header,
+        "'{}' is outdated, set {} to regenerate it",
+        path.display(),
+        UPDATE_SNAPSHOTS_VAR
+    );
+}
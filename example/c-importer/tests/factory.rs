@@ -0,0 +1,107 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tracing::level_filters::LevelFilter;
+use treasury_import::{
+    loading::{load_importers_with_sink, DylibImporter, LoadingError},
+    logging::{LogRecord, LogSink},
+    Importer,
+};
+use treasury_import_testing::Fixture;
+
+/// Tests share the loaded library and its logger, so they run one at a time.
+static LIBRARY: Mutex<()> = Mutex::new(());
+
+fn lock() -> MutexGuard<'static, ()> {
+    LIBRARY.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Collects messages of forwarded events.
+#[derive(Default)]
+struct Messages(Mutex<Vec<String>>);
+
+impl Messages {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl LogSink for Messages {
+    fn log(&self, record: &[u8]) {
+        if let Some(LogRecord::Event { message, .. }) = LogRecord::decode(record) {
+            self.0.lock().unwrap().push(message);
+        }
+    }
+}
+
+fn load(
+    config: Option<&str>,
+    messages: &Arc<Messages>,
+) -> Result<Vec<DylibImporter>, LoadingError> {
+    let importers = unsafe {
+        load_importers_with_sink(
+            Path::new(c_importer::FACTORY_LIBRARY_PATH),
+            config,
+            messages.clone(),
+            LevelFilter::INFO,
+        )?
+    };
+    Ok(importers.collect())
+}
+
+#[test]
+fn creates_and_drops_importers() {
+    let _lock = lock();
+    let messages = Arc::new(Messages::default());
+
+    let importers = load(Some(r#""!""#), &messages).unwrap();
+    assert_eq!(importers.len(), 1);
+    assert_eq!(importers[0].name(), "C suffix importer");
+    assert_eq!(messages.take(), ["Created importers"]);
+
+    let mut fixture = Fixture::new("hello.suf", "Hello").unwrap();
+    let imported = fixture.run(&importers[0]).unwrap();
+    assert_eq!(imported.output, b"Hello!");
+    assert_eq!(messages.take(), ["Importing"]);
+
+    drop(importers);
+    assert_eq!(messages.take(), ["Dropped importers"]);
+}
+
+#[test]
+fn reports_factory_error() {
+    let _lock = lock();
+    let messages = Arc::new(Messages::default());
+
+    match load(None, &messages) {
+        Err(LoadingError::CreateImporters(error)) => {
+            assert_eq!(error, "Configuration must be a string")
+        }
+        Err(err) => panic!("Unexpected error {}", err),
+        Ok(_) => panic!("Expected factory error"),
+    }
+}
+
+/// Library keeps logger of the first load,
+/// records must reach live loads after the first one is dropped.
+#[test]
+fn logs_after_first_load_is_dropped() {
+    let _lock = lock();
+    let first_messages = Arc::new(Messages::default());
+    let second_messages = Arc::new(Messages::default());
+
+    let first = load(Some(r#""1""#), &first_messages).unwrap();
+    let second = load(Some(r#""2""#), &second_messages).unwrap();
+    drop(first);
+    first_messages.take();
+    second_messages.take();
+
+    let mut fixture = Fixture::new("hello.suf", "Hello").unwrap();
+    let imported = fixture.run(&second[0]).unwrap();
+    assert_eq!(imported.output, b"Hello2");
+
+    assert!(first_messages.take().is_empty());
+    assert_eq!(second_messages.take(), ["Importing"]);
+}
@@ -1,79 +1,89 @@
-use std::{fs::File, path::Path};
-
 use treasury_import::{
-    make_treasury_importers_library, Dependencies, ImportError, Importer, Sources,
+    importer, make_treasury_importers_library, Diagnostic, ImportContext, Location,
 };
 
-struct FooImporter;
+const OPTIONS_SCHEMA: &str = r#"{
+    "type": "object",
+    "properties": {
+        "pretty": { "type": "boolean", "default": true }
+    },
+    "additionalProperties": false
+}"#;
 
-impl Importer for FooImporter {
-    fn name(&self) -> &str {
-        "Foo importer"
-    }
+fn is_json_object(head: &[u8]) -> bool {
+    // Foo sources are JSON objects.
+    head.iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|&b| b == b'{')
+}
 
-    fn formats(&self) -> &[&str] {
-        &["foo"]
-    }
+#[importer(
+    name = "Foo importer",
+    formats = ["foo"],
+    extensions = ["json"],
+    target = "foo",
+    description = "Validates JSON and writes it formatted",
+    version = env!("CARGO_PKG_VERSION"),
+    package = env!("CARGO_PKG_NAME"),
+    options_schema = OPTIONS_SCHEMA,
+    probe = is_json_object
+)]
+fn foo_importer(ctx: &mut ImportContext) -> Result<(), String> {
+    // Options are validated against the schema by the store.
+    let pretty = ctx
+        .options()
+        .and_then(|options| serde_json::from_str::<serde_json::Value>(options).ok())
+        .and_then(|options| options.get("pretty")?.as_bool())
+        .unwrap_or(true);
 
-    fn target(&self) -> &str {
-        "foo"
-    }
+    let src = ctx.open_source().map_err(|err| err.to_string())?;
+    let dst = ctx.create_output().map_err(|err| err.to_string())?;
 
-    fn extensions(&self) -> &[&str] {
-        &["json"]
-    }
+    ctx.progress(0.0, "Reading");
 
-    fn import(
-        &self,
-        source: &Path,
-        output: &Path,
-        _sources: &mut dyn Sources,
-        _dependencies: &mut dyn Dependencies,
-    ) -> Result<(), ImportError> {
-        let mut src = match File::open(source) {
-            Ok(f) => f,
-            Err(err) => {
-                return Err(ImportError::Other {
-                    reason: format!(
-                        "Failed to open source file '{}'. {:#}",
-                        source.display(),
-                        err
-                    ),
-                })
-            }
-        };
-
-        let mut dst = match File::create(output) {
-            Ok(f) => f,
-            Err(err) => {
-                return Err(ImportError::Other {
-                    reason: format!(
-                        "Failed to open output file '{}'. {:#}",
-                        output.display(),
-                        err
-                    ),
-                })
-            }
-        };
+    let value: serde_json::Value = match serde_json::from_reader(src) {
+        Ok(value) => value,
+        Err(err) => {
+            ctx.report(
+                Diagnostic::error(err.to_string())
+                    .with_code("json")
+                    .with_location(Location {
+                        source: None,
+                        line: Some(err.line() as u32),
+                        column: Some(err.column() as u32),
+                    }),
+            );
+            return Err(format!(
+                "Failed to read json from '{}'",
+                ctx.source().display()
+            ));
+        }
+    };
 
-        let value: serde_json::Value = match serde_json::from_reader(&mut src) {
-            Ok(value) => value,
-            Err(err) => {
-                return Err(ImportError::Other {
-                    reason: format!("Failed to read json from '{}'. {:#}", source.display(), err),
-                })
-            }
-        };
+    ctx.progress(0.5, "Writing");
 
-        match serde_json::to_writer_pretty(&mut dst, &value) {
-            Ok(()) => Ok(()),
-            Err(err) => Err(ImportError::Other {
-                reason: format!("Failed to write json to '{}'. {:#}", output.display(), err),
-            }),
-        }
+    if !value.is_object() {
+        ctx.report(
+            Diagnostic::warning("Foo source is expected to be a JSON object")
+                .with_code("not-object"),
+        );
     }
+
+    let result = if pretty {
+        serde_json::to_writer_pretty(dst, &value)
+    } else {
+        serde_json::to_writer(dst, &value)
+    };
+
+    result.map_err(|err| {
+        format!(
+            "Failed to write json to '{}'. {:#}",
+            ctx.output().display(),
+            err
+        )
+    })
 }
 
 make_treasury_importers_library! {
-    &FooImporter;
+    &foo_importer;
 }
@@ -0,0 +1,448 @@
+//! C API of importers libraries.
+//!
+//! [`header`] generates `treasury_import.h` that declares [`ffi`](crate::ffi) types,
+//! return codes and exports of importers library for libraries written in C or C++,
+//! along with helpers to encode importer description and import result.
+//! The header is shipped as `include/treasury_import.h` in this package.
+
+use std::mem::size_of;
+
+use crate::{
+    factory::{CREATE_IMPORTERS_FN_NAME, DROP_IMPORTERS_FN_NAME, EXPORT_CREATED_IMPORTERS_FN_NAME},
+    ffi::{
+        layout_fingerprint, FfiType, ImporterFFI, OsChar, ANY_BUF_LEN_LIMIT, BUFFER_IS_TOO_SMALL,
+        CANCELLED, EXPORT_IMPORTERS_FN_NAME, FFI_REVISION, LAYOUT_FN_NAME, MAGIC_NAME, NOT_FOUND,
+        NOT_UTF8, OTHER_ERROR, PANICKED, REQUIRE_DEPENDENCIES, REQUIRE_SOURCES, REVISIONS_FN_NAME,
+        SUCCESS,
+    },
+    MAGIC,
+};
+
+/// Fingerprint of [`ImporterFFI`] layout on target with given pointer size and [`OsChar`] type `C`.
+const fn layout<C: FfiType>(ptr: u64) -> u64 {
+    let [importer, import, probe, describe, import_stream] = ImporterFFI::signatures::<C>();
+
+    layout_fingerprint(&[
+        FFI_REVISION as u64,
+        5 * ptr,
+        ptr,
+        0,
+        ptr,
+        2 * ptr,
+        3 * ptr,
+        4 * ptr,
+        size_of::<C>() as u64,
+        importer,
+        import,
+        probe,
+        describe,
+        import_stream,
+    ])
+}
+
+// Header computes fingerprints for all targets with the same formula.
+const _: () = assert!(layout::<OsChar>(size_of::<usize>() as u64) == ImporterFFI::FINGERPRINT);
+
+/// Returns C header for importers libraries of [`FFI_REVISION`].
+pub fn header() -> String {
+    let values = [
+        ("REVISION", FFI_REVISION.to_string()),
+        ("MAGIC", format!("0x{:08X}", MAGIC)),
+        ("LAYOUT_64", format!("0x{:016X}", layout::<u8>(8))),
+        ("LAYOUT_64_WIDE", format!("0x{:016X}", layout::<u16>(8))),
+        ("LAYOUT_32", format!("0x{:016X}", layout::<u8>(4))),
+        ("LAYOUT_32_WIDE", format!("0x{:016X}", layout::<u16>(4))),
+        ("BUF_LEN_LIMIT", ANY_BUF_LEN_LIMIT.to_string()),
+        ("SUCCESS", SUCCESS.to_string()),
+        ("REQUIRE_SOURCES", REQUIRE_SOURCES.to_string()),
+        ("REQUIRE_DEPENDENCIES", REQUIRE_DEPENDENCIES.to_string()),
+        ("NOT_FOUND", NOT_FOUND.to_string()),
+        ("NOT_UTF8", NOT_UTF8.to_string()),
+        ("BUFFER_IS_TOO_SMALL", BUFFER_IS_TOO_SMALL.to_string()),
+        ("OTHER_ERROR", OTHER_ERROR.to_string()),
+        ("CANCELLED", CANCELLED.to_string()),
+        ("PANICKED", PANICKED.to_string()),
+        ("MAGIC_NAME", MAGIC_NAME.to_owned()),
+        ("REVISIONS_FN_NAME", REVISIONS_FN_NAME.to_owned()),
+        ("LAYOUT_FN_NAME", LAYOUT_FN_NAME.to_owned()),
+        (
+            "EXPORT_IMPORTERS_FN_NAME",
+            EXPORT_IMPORTERS_FN_NAME.to_owned(),
+        ),
+        (
+            "CREATE_IMPORTERS_FN_NAME",
+            CREATE_IMPORTERS_FN_NAME.to_owned(),
+        ),
+        (
+            "EXPORT_CREATED_IMPORTERS_FN_NAME",
+            EXPORT_CREATED_IMPORTERS_FN_NAME.to_owned(),
+        ),
+        ("DROP_IMPORTERS_FN_NAME", DROP_IMPORTERS_FN_NAME.to_owned()),
+    ];
+
+    let mut header = TEMPLATE.to_owned();
+    for (name, value) in values {
+        header = header.replace(&format!("@{}@", name), &value);
+    }
+    debug_assert!(!header.contains('@'), "Unknown placeholder in C header");
+    header
+}
+
+const TEMPLATE: &str = r#"/*
+ * C API of treasury importers libraries, ABI revision @REVISION@.
+ *
+ * Generated by `treasury_import::c_api::header`, do not edit.
+ *
+ * Importers library is a shared library that defines `@MAGIC_NAME@`,
+ * `@REVISIONS_FN_NAME@` and either `@EXPORT_IMPORTERS_FN_NAME@`
+ * or importers factory functions. `@LAYOUT_FN_NAME@` is optional.
+ *
+ * Integers are little-endian, strings are UTF-8 and not null-terminated.
+ * Encoded strings are prefixed with `uint32_t` length, lists with `uint32_t` count
+ * and optional values with `uint8_t` flag, 0 or 1. `treasury_writer` encodes them.
+ *
+ * Importers must be callable from any thread while the library is loaded.
+ * Requires C99 or C++11.
+ */
+
+#ifndef TREASURY_IMPORT_H
+#define TREASURY_IMPORT_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <string.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TREASURY_FFI_REVISION @REVISION@u
+#define TREASURY_MAGIC @MAGIC@u
+
+/* Fingerprint of `treasury_importer_ffi` layout of `TREASURY_FFI_REVISION`. */
+#if defined(_WIN32) && UINTPTR_MAX > 0xFFFFFFFFu
+#define TREASURY_FFI_LAYOUT @LAYOUT_64_WIDE@ull
+#elif defined(_WIN32)
+#define TREASURY_FFI_LAYOUT @LAYOUT_32_WIDE@ull
+#elif UINTPTR_MAX > 0xFFFFFFFFu
+#define TREASURY_FFI_LAYOUT @LAYOUT_64@ull
+#else
+#define TREASURY_FFI_LAYOUT @LAYOUT_32@ull
+#endif
+
+/* Store does not grow buffers past this length. */
+#define TREASURY_BUF_LEN_LIMIT @BUF_LEN_LIMIT@u
+
+#define TREASURY_SUCCESS (@SUCCESS@)
+/* Import requires sources listed in the result payload. */
+#define TREASURY_REQUIRE_SOURCES (@REQUIRE_SOURCES@)
+/* Import requires dependencies listed in the result payload. */
+#define TREASURY_REQUIRE_DEPENDENCIES (@REQUIRE_DEPENDENCIES@)
+/* Source or dependency is not available yet, or importer does not recognize the source. */
+#define TREASURY_NOT_FOUND (@NOT_FOUND@)
+#define TREASURY_NOT_UTF8 (@NOT_UTF8@)
+/* Output does not fit into the buffer, required length is written instead. */
+#define TREASURY_BUFFER_IS_TOO_SMALL (@BUFFER_IS_TOO_SMALL@)
+/* Import failed with error message in the result payload. */
+#define TREASURY_OTHER_ERROR (@OTHER_ERROR@)
+#define TREASURY_CANCELLED (@CANCELLED@)
+/* Importer crashed, message is in the result payload. */
+#define TREASURY_PANICKED (@PANICKED@)
+
+#define TREASURY_SEVERITY_INFO 0
+#define TREASURY_SEVERITY_WARNING 1
+#define TREASURY_SEVERITY_ERROR 2
+
+#ifdef _WIN32
+#define TREASURY_EXPORT __declspec(dllexport)
+/* Paths are UTF-16 code units on Windows. */
+typedef uint16_t treasury_os_char;
+#else
+#define TREASURY_EXPORT __attribute__((visibility("default")))
+/* Paths are bytes. */
+typedef uint8_t treasury_os_char;
+#endif
+
+/* Opaque to the store, defined by the library. */
+typedef struct treasury_importer treasury_importer;
+typedef struct treasury_importers treasury_importers;
+
+/* Opaque to the library. */
+typedef struct treasury_sources treasury_sources;
+typedef struct treasury_dependencies treasury_dependencies;
+typedef struct treasury_cancellation treasury_cancellation;
+typedef struct treasury_progress treasury_progress;
+typedef struct treasury_stream_reader treasury_stream_reader;
+typedef struct treasury_stream_writer treasury_stream_writer;
+typedef struct treasury_source_streams treasury_source_streams;
+
+/*
+ * Writes path to data of `source` into `path_ptr` and its length into `*path_len`.
+ * Returns `TREASURY_SUCCESS`, `TREASURY_NOT_FOUND` if source is not available yet,
+ * `TREASURY_NOT_UTF8` or `TREASURY_OTHER_ERROR`.
+ * Returns `TREASURY_BUFFER_IS_TOO_SMALL` with required length in `*path_len` if path does not fit.
+ */
+typedef int32_t (*treasury_sources_get_fn)(
+    treasury_sources *sources,
+    const uint8_t *source_ptr,
+    uint32_t source_len,
+    treasury_os_char *path_ptr,
+    uint32_t *path_len);
+
+/*
+ * Writes id of asset imported from `source` to `target` format into `id_ptr`.
+ * Returns `TREASURY_SUCCESS`, `TREASURY_NOT_FOUND` if asset is not stored yet,
+ * `TREASURY_NOT_UTF8` or `TREASURY_OTHER_ERROR`.
+ */
+typedef int32_t (*treasury_dependencies_get_fn)(
+    treasury_dependencies *dependencies,
+    const uint8_t *source_ptr,
+    uint32_t source_len,
+    const uint8_t *target_ptr,
+    uint32_t target_len,
+    uint64_t *id_ptr);
+
+/* Returns `TREASURY_CANCELLED` if import is cancelled and `TREASURY_SUCCESS` otherwise. */
+typedef int32_t (*treasury_cancellation_is_cancelled_fn)(
+    const treasury_cancellation *cancellation);
+
+/* Reports that `fraction` of the import is done. */
+typedef void (*treasury_progress_report_fn)(
+    treasury_progress *progress,
+    float fraction,
+    const uint8_t *stage_ptr,
+    uint32_t stage_len);
+
+/*
+ * Reads up to `*buf_len` bytes into `buf_ptr` and writes number of bytes read into `*buf_len`.
+ * Zero bytes are read only at the end of the stream.
+ * Returns `TREASURY_SUCCESS` or `TREASURY_OTHER_ERROR`.
+ */
+typedef int32_t (*treasury_stream_read_fn)(
+    treasury_stream_reader *reader,
+    uint8_t *buf_ptr,
+    uint32_t *buf_len);
+
+/* Writes all `buf_len` bytes from `buf_ptr`. Returns `TREASURY_SUCCESS` or `TREASURY_OTHER_ERROR`. */
+typedef int32_t (*treasury_stream_write_fn)(
+    treasury_stream_writer *writer,
+    const uint8_t *buf_ptr,
+    uint32_t buf_len);
+
+/*
+ * Opens stream of data from `source` and writes it into `*reader`.
+ * Opened stream is read with `treasury_stream_read_fn` passed to the import
+ * and closed with `treasury_source_streams_close_fn` before the import returns.
+ * At most one source stream is open at a time.
+ * Returns `TREASURY_SUCCESS`, `TREASURY_NOT_FOUND` if source is not available yet,
+ * `TREASURY_NOT_UTF8` or `TREASURY_OTHER_ERROR`.
+ */
+typedef int32_t (*treasury_source_streams_open_fn)(
+    treasury_source_streams *streams,
+    const uint8_t *source_ptr,
+    uint32_t source_len,
+    treasury_stream_reader **reader);
+
+/* Closes stream opened with `treasury_source_streams_open_fn`. */
+typedef void (*treasury_source_streams_close_fn)(
+    treasury_source_streams *streams,
+    treasury_stream_reader *reader);
+
+/*
+ * Imports `source` file into `output` file. `options_ptr` is NULL if no options are set.
+ *
+ * Writes to the result buffer length-prefixed payload specific to the returned code
+ * followed by the list of diagnostics.
+ * Payload is empty for `TREASURY_SUCCESS` and `TREASURY_CANCELLED`,
+ * lists sources for `TREASURY_REQUIRE_SOURCES`,
+ * lists pairs of source and target for `TREASURY_REQUIRE_DEPENDENCIES`
+ * and contains message for `TREASURY_OTHER_ERROR` and `TREASURY_PANICKED`.
+ * Diagnostic is `uint8_t` severity, optional code, message
+ * and optional location of optional source, optional `uint32_t` line and column.
+ *
+ * Returns `TREASURY_BUFFER_IS_TOO_SMALL` with required length in `*result_len`
+ * if result does not fit, importer is called again with larger buffer.
+ */
+typedef int32_t (*treasury_importer_import_fn)(
+    const treasury_importer *importer,
+    const treasury_os_char *source_ptr,
+    uint32_t source_len,
+    const treasury_os_char *output_ptr,
+    uint32_t output_len,
+    const uint8_t *options_ptr,
+    uint32_t options_len,
+    treasury_sources *sources,
+    treasury_sources_get_fn sources_get,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    const treasury_cancellation *cancellation,
+    treasury_cancellation_is_cancelled_fn cancellation_is_cancelled,
+    treasury_progress *progress,
+    treasury_progress_report_fn progress_report,
+    uint8_t *result_ptr,
+    uint32_t *result_len);
+
+/*
+ * Same as `treasury_importer_import_fn`, but reads `source` and writes `output` streams.
+ * `source` and streams opened with `streams_open` are read with `reader_read`.
+ * Streams are consumed by the time result does not fit,
+ * so importer keeps the result when it returns `TREASURY_BUFFER_IS_TOO_SMALL`.
+ * It is then called again on the same thread with NULL `source` and larger buffer to write it.
+ * Importer that keeps no result fails such call.
+ */
+typedef int32_t (*treasury_importer_import_stream_fn)(
+    const treasury_importer *importer,
+    treasury_stream_reader *source,
+    treasury_stream_read_fn reader_read,
+    treasury_stream_writer *output,
+    treasury_stream_write_fn writer_write,
+    const uint8_t *options_ptr,
+    uint32_t options_len,
+    treasury_source_streams *streams,
+    treasury_source_streams_open_fn streams_open,
+    treasury_source_streams_close_fn streams_close,
+    treasury_dependencies *dependencies,
+    treasury_dependencies_get_fn dependencies_get,
+    const treasury_cancellation *cancellation,
+    treasury_cancellation_is_cancelled_fn cancellation_is_cancelled,
+    treasury_progress *progress,
+    treasury_progress_report_fn progress_report,
+    uint8_t *result_ptr,
+    uint32_t *result_len);
+
+/* Returns `TREASURY_SUCCESS` if importer recognizes the source by its head and `TREASURY_NOT_FOUND` otherwise. */
+typedef int32_t (*treasury_importer_probe_fn)(
+    const treasury_importer *importer,
+    const uint8_t *head_ptr,
+    uint32_t head_len);
+
+/*
+ * Writes description of the importer: name, list of formats, list of extensions, target,
+ * list of magic byte sequences, description, optional version, optional package
+ * and optional options schema.
+ * Returns required length, buffer is ignored if it exceeds `cap`. Returns zero on failure.
+ */
+typedef uint32_t (*treasury_importer_describe_fn)(
+    const treasury_importer *importer,
+    uint8_t *buffer,
+    uint32_t cap);
+
+typedef struct treasury_importer_ffi {
+    const treasury_importer *importer;
+    treasury_importer_import_fn import;
+    treasury_importer_probe_fn probe;
+    treasury_importer_describe_fn describe;
+    /* NULL if importer does not support streams. */
+    treasury_importer_import_stream_fn import_stream;
+} treasury_importer_ffi;
+
+/* Must be equal to `TREASURY_MAGIC`. */
+TREASURY_EXPORT extern const uint32_t @MAGIC_NAME@;
+
+/* Writes range of supported revisions, both `TREASURY_FFI_REVISION`.
+ * Revision 3 is not part of the range, it is negotiated through its own symbols. */
+TREASURY_EXPORT void @REVISIONS_FN_NAME@(uint32_t *min, uint32_t *max);
+
+/* Returns `TREASURY_FFI_LAYOUT` for `TREASURY_FFI_REVISION` and zero otherwise. Optional. */
+TREASURY_EXPORT uint64_t @LAYOUT_FN_NAME@(uint32_t revision);
+
+/* Writes up to `cap` importers and returns their total number. */
+TREASURY_EXPORT uint32_t @EXPORT_IMPORTERS_FN_NAME@(treasury_importer_ffi *buffer, uint32_t cap);
+
+/*
+ * Importers factory, exported instead of `@EXPORT_IMPORTERS_FN_NAME@`.
+ *
+ * Creates importers from configuration, JSON document. `config_ptr` is NULL without configuration.
+ * Returns NULL on failure and writes up to `*error_len` bytes of message into `error_ptr`,
+ * setting `*error_len` to the number of bytes written.
+ */
+TREASURY_EXPORT treasury_importers *@CREATE_IMPORTERS_FN_NAME@(
+    const uint8_t *config_ptr,
+    uint32_t config_len,
+    uint8_t *error_ptr,
+    uint32_t *error_len);
+
+/* Writes up to `cap` created importers and returns their total number. */
+TREASURY_EXPORT uint32_t @EXPORT_CREATED_IMPORTERS_FN_NAME@(
+    const treasury_importers *importers,
+    treasury_importer_ffi *buffer,
+    uint32_t cap);
+
+/* Drops created importers before the library is unloaded. */
+TREASURY_EXPORT void @DROP_IMPORTERS_FN_NAME@(treasury_importers *importers);
+
+/*
+ * Encodes values into buffer of `cap` bytes.
+ * `len` keeps growing when buffer is full, so after all writes it is the required length.
+ * Writer with zero capacity only measures.
+ */
+typedef struct treasury_writer {
+    uint8_t *ptr;
+    uint32_t cap;
+    uint32_t len;
+} treasury_writer;
+
+static inline treasury_writer treasury_writer_new(uint8_t *ptr, uint32_t cap) {
+    treasury_writer writer;
+    writer.ptr = ptr;
+    writer.cap = cap;
+    writer.len = 0;
+    return writer;
+}
+
+static inline void treasury_write_u8(treasury_writer *writer, uint8_t value) {
+    if (writer->len < writer->cap) {
+        writer->ptr[writer->len] = value;
+    }
+    writer->len += 1;
+}
+
+static inline void treasury_write_u32(treasury_writer *writer, uint32_t value) {
+    treasury_write_u8(writer, (uint8_t)value);
+    treasury_write_u8(writer, (uint8_t)(value >> 8));
+    treasury_write_u8(writer, (uint8_t)(value >> 16));
+    treasury_write_u8(writer, (uint8_t)(value >> 24));
+}
+
+/* Writes length-prefixed bytes. */
+static inline void treasury_write_bytes(treasury_writer *writer, const void *data, uint32_t len) {
+    uint32_t i;
+    treasury_write_u32(writer, len);
+    for (i = 0; i < len; ++i) {
+        treasury_write_u8(writer, ((const uint8_t *)data)[i]);
+    }
+}
+
+/* Writes length-prefixed null-terminated string. */
+static inline void treasury_write_str(treasury_writer *writer, const char *str) {
+    treasury_write_bytes(writer, str, (uint32_t)strlen(str));
+}
+
+/* Writes optional string, absent if `str` is NULL. */
+static inline void treasury_write_optional_str(treasury_writer *writer, const char *str) {
+    if (str == NULL) {
+        treasury_write_u8(writer, 0);
+    } else {
+        treasury_write_u8(writer, 1);
+        treasury_write_str(writer, str);
+    }
+}
+
+/* Writes diagnostic without location. `code` may be NULL. */
+static inline void treasury_write_diagnostic(
+    treasury_writer *writer,
+    uint8_t severity,
+    const char *code,
+    const char *message) {
+    treasury_write_u8(writer, severity);
+    treasury_write_optional_str(writer, code);
+    treasury_write_str(writer, message);
+    treasury_write_u8(writer, 0);
+}
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TREASURY_IMPORT_H */
+"#;
@@ -0,0 +1,25 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Flag that importer polls to stop early.
+///
+/// Import is cancelled when caller abandons it or when import timeout expires.
+/// Importer should return [`ImportError::Cancelled`] soon after cancellation is observed.
+///
+/// [`ImportError::Cancelled`]: crate::ImportError::Cancelled
+pub trait Cancellation {
+    /// Returns `true` if import is cancelled.
+    fn is_cancelled(&self) -> bool;
+}
+
+/// Import that is never cancelled.
+impl Cancellation for () {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl Cancellation for AtomicBool {
+    fn is_cancelled(&self) -> bool {
+        self.load(Ordering::Relaxed)
+    }
+}
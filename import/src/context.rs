@@ -0,0 +1,269 @@
+use std::{
+    fmt,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use treasury_id::AssetId;
+
+use crate::{
+    ensure_dependencies, ensure_sources, Cancellation, Dependencies, Dependency, Diagnostic,
+    Diagnostics, ImportError, Progress, Sources,
+};
+
+/// Arguments of [`Importer::import`] with helpers for common operations.
+///
+/// Helpers fail with [`ContextError`] that names the file or requirement,
+/// so importer functions may return any error that implements `Display`.
+///
+/// Sources and dependencies that are not available yet are recorded
+/// and [`ImportContext::finish`] requires them whatever importer function returns.
+///
+/// [`Importer::import`]: crate::Importer::import
+pub struct ImportContext<'a> {
+    source: &'a Path,
+    output: &'a Path,
+    options: Option<&'a str>,
+    sources: &'a mut dyn Sources,
+    dependencies: &'a mut dyn Dependencies,
+    cancellation: &'a dyn Cancellation,
+    diagnostics: &'a mut dyn Diagnostics,
+    progress: &'a mut dyn Progress,
+    missing_sources: Vec<String>,
+    missing_dependencies: Vec<Dependency>,
+}
+
+impl<'a> ImportContext<'a> {
+    /// Wraps arguments of [`Importer::import`](crate::Importer::import).
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        source: &'a Path,
+        output: &'a Path,
+        options: Option<&'a str>,
+        sources: &'a mut dyn Sources,
+        dependencies: &'a mut dyn Dependencies,
+        cancellation: &'a dyn Cancellation,
+        diagnostics: &'a mut dyn Diagnostics,
+        progress: &'a mut dyn Progress,
+    ) -> Self {
+        ImportContext {
+            source,
+            output,
+            options,
+            sources,
+            dependencies,
+            cancellation,
+            diagnostics,
+            progress,
+            missing_sources: Vec::new(),
+            missing_dependencies: Vec::new(),
+        }
+    }
+
+    /// Returns path of the source file.
+    pub fn source(&self) -> &Path {
+        self.source
+    }
+
+    /// Returns path where output must be written.
+    pub fn output(&self) -> &Path {
+        self.output
+    }
+
+    /// Returns import options, JSON document valid against importer options schema.
+    pub fn options(&self) -> Option<&str> {
+        self.options
+    }
+
+    /// Opens the source file for reading.
+    pub fn open_source(&self) -> Result<File, ContextError> {
+        File::open(self.source)
+            .map_err(|error| ContextError::io("open source file", self.source, error))
+    }
+
+    /// Reads the whole source file.
+    pub fn read_source(&self) -> Result<Vec<u8>, ContextError> {
+        std::fs::read(self.source)
+            .map_err(|error| ContextError::io("read source file", self.source, error))
+    }
+
+    /// Reads the whole source file as UTF-8 string.
+    pub fn read_source_to_string(&self) -> Result<String, ContextError> {
+        std::fs::read_to_string(self.source)
+            .map_err(|error| ContextError::io("read source file", self.source, error))
+    }
+
+    /// Creates the output file for writing.
+    pub fn create_output(&self) -> Result<File, ContextError> {
+        File::create(self.output)
+            .map_err(|error| ContextError::io("create output file", self.output, error))
+    }
+
+    /// Writes the whole output file.
+    pub fn write_output(&self, data: impl AsRef<[u8]>) -> Result<(), ContextError> {
+        std::fs::write(self.output, data)
+            .map_err(|error| ContextError::io("write output file", self.output, error))
+    }
+
+    /// Returns path to data of another source.
+    /// `source` is URL relative to the source.
+    ///
+    /// Fails if source is not available yet.
+    /// It is required when import finishes and importer runs again after it is fetched.
+    pub fn get_source(&mut self, source: &str) -> Result<PathBuf, ContextError> {
+        match self
+            .sources
+            .get_or_append(source, &mut self.missing_sources)
+        {
+            Ok(Some(path)) => Ok(path),
+            Ok(None) => Err(ContextError::MissingSource(source.to_owned())),
+            Err(reason) => Err(ContextError::Sources {
+                source: source.to_owned(),
+                reason,
+            }),
+        }
+    }
+
+    /// Returns id of asset imported from `source` to `target` format.
+    /// `source` is URL relative to the source.
+    ///
+    /// Fails if asset is not stored yet.
+    /// It is required when import finishes and importer runs again after it is stored.
+    pub fn get_dependency(&mut self, source: &str, target: &str) -> Result<AssetId, ContextError> {
+        match self
+            .dependencies
+            .get_or_append(source, target, &mut self.missing_dependencies)
+        {
+            Ok(Some(id)) => Ok(id),
+            Ok(None) => Err(ContextError::MissingDependency(Dependency {
+                source: source.to_owned(),
+                target: target.to_owned(),
+            })),
+            Err(reason) => Err(ContextError::Dependencies {
+                dependency: Dependency {
+                    source: source.to_owned(),
+                    target: target.to_owned(),
+                },
+                reason,
+            }),
+        }
+    }
+
+    /// Returns `true` if import is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Fails if import is cancelled.
+    /// Long running importers should call this periodically.
+    pub fn check_cancelled(&self) -> Result<(), ContextError> {
+        if self.cancellation.is_cancelled() {
+            Err(ContextError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reports diagnostic about the import.
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.report(diagnostic);
+    }
+
+    /// Reports that `fraction` of the import is done.
+    pub fn progress(&mut self, fraction: f32, stage: &str) {
+        self.progress.report(fraction, stage);
+    }
+
+    /// Turns result of importer function into result of [`Importer::import`](crate::Importer::import).
+    ///
+    /// Missing sources and dependencies are required first.
+    /// Errors of cancelled imports become [`ImportError::Cancelled`].
+    pub fn finish<E: fmt::Display>(self, result: Result<(), E>) -> Result<(), ImportError> {
+        ensure_sources(self.missing_sources)?;
+        ensure_dependencies(self.missing_dependencies)?;
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(_) if self.cancellation.is_cancelled() => Err(ImportError::Cancelled),
+            Err(err) => Err(ImportError::Other {
+                reason: err.to_string(),
+            }),
+        }
+    }
+}
+
+/// Error of [`ImportContext`] helpers.
+#[derive(Debug)]
+pub enum ContextError {
+    /// Failed to access source or output file.
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        error: io::Error,
+    },
+
+    /// Source is not available yet.
+    MissingSource(String),
+
+    /// Dependency is not stored yet.
+    MissingDependency(Dependency),
+
+    /// Failed to get source.
+    Sources { source: String, reason: String },
+
+    /// Failed to get dependency.
+    Dependencies {
+        dependency: Dependency,
+        reason: String,
+    },
+
+    /// Import is cancelled.
+    Cancelled,
+}
+
+impl ContextError {
+    fn io(action: &'static str, path: &Path, error: io::Error) -> Self {
+        ContextError::Io {
+            action,
+            path: path.to_owned(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextError::Io {
+                action,
+                path,
+                error,
+            } => write!(f, "Failed to {} '{}'. {}", action, path.display(), error),
+            ContextError::MissingSource(source) => write!(f, "Source '{}' is required", source),
+            ContextError::MissingDependency(dependency) => write!(
+                f,
+                "Dependency '{}' as '{}' is required",
+                dependency.source, dependency.target
+            ),
+            ContextError::Sources { source, reason } => {
+                write!(f, "Failed to get source '{}'. {}", source, reason)
+            }
+            ContextError::Dependencies { dependency, reason } => write!(
+                f,
+                "Failed to get dependency '{}' as '{}'. {}",
+                dependency.source, dependency.target, reason
+            ),
+            ContextError::Cancelled => f.write_str("Import is cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for ContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ContextError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use treasury_id::AssetId;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Dependency {
     pub source: String,
     pub target: String,
@@ -0,0 +1,140 @@
+//! Importers created by the library when it is loaded.
+//!
+//! Library defined with `make_treasury_importers_library! { factory = <expr>; }`
+//! builds its importers with [`ImportersFactory`] from configuration passed by the store,
+//! instead of exporting `&'static` importers.
+//!
+//! Store creates importers with [`CREATE_IMPORTERS_FN_NAME`], exports them with [`EXPORT_CREATED_IMPORTERS_FN_NAME`]
+//! and drops them with [`DROP_IMPORTERS_FN_NAME`] before the library is unloaded.
+
+use crate::{
+    ffi::{catch_panic, ImporterFFI},
+    importer::Importer,
+};
+
+/// Creates importers of the library.
+///
+/// `config` is JSON document built from `[importers.<name>]` table of `Treasury.toml`,
+/// `None` if the library is listed without configuration.
+/// Returned error is reported to the store and the library fails to load.
+pub type ImportersFactory = fn(config: Option<&str>) -> Result<Vec<Box<dyn Importer>>, String>;
+
+#[repr(transparent)]
+pub struct ImportersOpaque(u8);
+
+/// Creates importers from configuration.
+/// `config_ptr` is null if there is no configuration.
+///
+/// Returns null on failure and writes up to `*error_len` bytes of error message into `error_ptr`,
+/// setting `*error_len` to the number of bytes written.
+pub type CreateImportersFn = unsafe extern "C" fn(
+    config_ptr: *const u8,
+    config_len: u32,
+    error_ptr: *mut u8,
+    error_len: *mut u32,
+) -> *mut ImportersOpaque;
+
+pub const CREATE_IMPORTERS_FN_NAME: &str = "treasury_create_importers";
+
+/// Same as exporting static importers, but for importers created with [`CreateImportersFn`].
+/// Returns `u32::MAX` if the library panics.
+pub type ExportCreatedImportersFn = unsafe extern "C" fn(
+    importers: *const ImportersOpaque,
+    buffer: *mut ImporterFFI,
+    cap: u32,
+) -> u32;
+
+pub const EXPORT_CREATED_IMPORTERS_FN_NAME: &str = "treasury_export_created_importers";
+
+/// Drops importers created with [`CreateImportersFn`].
+/// Exported importers must not be used afterwards.
+pub type DropImportersFn = unsafe extern "C" fn(importers: *mut ImportersOpaque);
+
+pub const DROP_IMPORTERS_FN_NAME: &str = "treasury_drop_importers";
+
+/// # Safety
+///
+/// `config_ptr` must be null or valid for `config_len` bytes.
+/// `error_ptr` must be valid for writes of `*error_len` bytes.
+pub unsafe fn create_importers_ffi(
+    factory: ImportersFactory,
+    config_ptr: *const u8,
+    config_len: u32,
+    error_ptr: *mut u8,
+    error_len: *mut u32,
+) -> *mut ImportersOpaque {
+    let result = catch_panic(|| factory(decode_config(config_ptr, config_len)?))
+        .unwrap_or_else(|message| Err(format!("Importers factory panicked. {}", message)));
+
+    match result {
+        Ok(importers) => Box::into_raw(Box::new(importers)) as *mut ImportersOpaque,
+        Err(reason) => {
+            write_error(&reason, error_ptr, error_len);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// `importers` must be returned by [`create_importers_ffi`] and not dropped yet.
+/// `buffer` must be valid for writes of `cap` importers.
+pub unsafe fn export_created_importers_ffi(
+    importers: *const ImportersOpaque,
+    buffer: *mut ImporterFFI,
+    cap: u32,
+) -> u32 {
+    catch_panic(|| {
+        let importers = &*(importers as *const Vec<Box<dyn Importer>>);
+        for (idx, importer) in importers.iter().enumerate().take(cap as usize) {
+            // Store keeps exported importers only until it drops created ones.
+            let importer = &*(importer as *const Box<dyn Importer>);
+            std::ptr::write(buffer.add(idx), ImporterFFI::new(importer));
+        }
+        importers.len() as u32
+    })
+    .unwrap_or(u32::MAX)
+}
+
+/// # Safety
+///
+/// `importers` must be returned by [`create_importers_ffi`] and not dropped yet.
+pub unsafe fn drop_importers_ffi(importers: *mut ImportersOpaque) {
+    let result = catch_panic(|| drop(Box::from_raw(importers as *mut Vec<Box<dyn Importer>>)));
+    if let Err(message) = result {
+        tracing::error!("Importers panicked while dropped. {}", message);
+    }
+}
+
+/// # Safety
+///
+/// `config_ptr` must be null or valid for `config_len` bytes.
+pub(crate) unsafe fn decode_config<'a>(
+    config_ptr: *const u8,
+    config_len: u32,
+) -> Result<Option<&'a str>, String> {
+    if config_ptr.is_null() {
+        return Ok(None);
+    }
+
+    let config = std::slice::from_raw_parts(config_ptr, config_len as usize);
+    match std::str::from_utf8(config) {
+        Ok(config) => Ok(Some(config)),
+        Err(_) => Err("Importers configuration is not UTF-8".to_owned()),
+    }
+}
+
+/// Writes as much of error message as fits, cutting at char boundary.
+///
+/// # Safety
+///
+/// `error_ptr` must be valid for writes of `*error_len` bytes.
+pub(crate) unsafe fn write_error(reason: &str, error_ptr: *mut u8, error_len: *mut u32) {
+    let mut len = reason.len().min(*error_len as usize);
+    while !reason.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    std::ptr::copy_nonoverlapping(reason.as_ptr(), error_ptr, len);
+    *error_len = len as u32;
+}
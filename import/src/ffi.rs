@@ -1,45 +1,128 @@
-use std::{mem::size_of, path::PathBuf};
+//! C ABI between the store and importers libraries.
+//!
+//! Everything here is stable within [`FFI_REVISION`], incompatible changes increment it.
+//! Integers are little-endian, strings are UTF-8 and not null-terminated.
+//! Paths are bytes on Unix and UTF-16 code units on Windows, see [`OsChar`].
+//!
+//! Importers library exports [`MAGIC_NAME`] static, [`REVISIONS_FN_NAME`] and [`EXPORT_IMPORTERS_FN_NAME`] functions.
+//! [`LAYOUT_FN_NAME`] and [`SET_LOGGER_FN_NAME`] are optional.
+//! Libraries written in C or C++ include header generated by [`c_api::header`](crate::c_api::header).
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::OsString,
+    io::{self, Read, Write},
+    mem::{align_of, offset_of, size_of},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+};
 
 #[cfg(unix)]
-use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+use std::{
+    ffi::OsStr,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+};
 
 #[cfg(target_os = "wasi")]
-use std::{ffi::OsStr, os::wasi::ffi::OsStrExt};
-
-#[cfg(windows)]
 use std::{
-    ffi::OsString,
-    os::windows::ffi::{OsStrExt, OsStringExt},
+    ffi::OsStr,
+    os::wasi::ffi::{OsStrExt, OsStringExt},
 };
 
+#[cfg(windows)]
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
 use treasury_id::AssetId;
 
 use crate::{
-    dependencies::Dependencies,
+    cancellation::Cancellation,
+    dependencies::{Dependencies, Dependency},
+    diagnostics::{Diagnostic, Diagnostics, Location, Severity},
     importer::{ImportError, Importer},
+    progress::Progress,
     sources::Sources,
+    streams::SourceStreams,
 };
 
 const PATH_BUF_LEN_START: usize = 1024;
 pub const ANY_BUF_LEN_LIMIT: usize = 65536;
 
+/// Import requires sources listed in the result payload.
 pub const REQUIRE_SOURCES: i32 = 2;
+
+/// Import requires dependencies listed in the result payload.
 pub const REQUIRE_DEPENDENCIES: i32 = 1;
+
 pub const SUCCESS: i32 = 0;
+
+/// Requested source or dependency is not available yet,
+/// or importer does not recognize the source.
 pub const NOT_FOUND: i32 = -1;
+
 pub const NOT_UTF8: i32 = -2;
+
+/// Output does not fit into the buffer, required length is written instead.
 pub const BUFFER_IS_TOO_SMALL: i32 = -3;
+
+/// Import failed with error message in the result payload.
 pub const OTHER_ERROR: i32 = -6;
 
+pub const CANCELLED: i32 = -7;
+
+/// Importer panicked, panic message is in the result payload.
+pub const PANICKED: i32 = -8;
+
+/// Exported `u32` static with [`MAGIC`](crate::MAGIC) value.
+pub const MAGIC_NAME: &str = "TREASURY_DYLIB_MAGIC";
+
+/// Exported `fn(min: *mut u32, max: *mut u32)` that writes range of supported revisions.
+///
+/// Revision 3 predates this function and is not part of the range.
+/// Libraries that still support it export its own symbols, see [`legacy`](crate::legacy).
+pub const REVISIONS_FN_NAME: &str = "treasury_importer_ffi_revisions";
+
+/// Exported `fn(revision: u32) -> u64` that returns [`ffi_layout`] of the revision.
+/// Loader skips layout check if it is missing.
+pub const LAYOUT_FN_NAME: &str = "treasury_importer_ffi_layout";
+
+/// Exported `fn(buffer: *mut ImporterFFI, cap: u32) -> u32`.
+/// Writes up to `cap` importers and returns their total number, `u32::MAX` if library panics.
+pub const EXPORT_IMPORTERS_FN_NAME: &str = "treasury_export_importers_rev5";
+
+/// Exported [`SetLoggerFn`].
+pub const SET_LOGGER_FN_NAME: &str = "treasury_set_logger";
+
+/// Runs `f` catching panic, so that it does not unwind across FFI boundary.
+/// Returns panic message if `f` panics.
+#[doc(hidden)]
+pub fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(&*payload))
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+/// Unit of paths passed across FFI.
 #[cfg(any(unix, target_os = "wasi"))]
-type OsChar = u8;
+pub type OsChar = u8;
 
+/// Unit of paths passed across FFI.
 #[cfg(windows)]
-type OsChar = u16;
+pub type OsChar = u16;
 
 #[repr(transparent)]
 pub struct DependenciesOpaque(u8);
 
+/// Writes id of asset imported from `source` to `target` format into `id_ptr`.
+/// Returns `SUCCESS`, `NOT_FOUND` if asset is not stored yet, `NOT_UTF8` or `OTHER_ERROR`.
 pub type DependenciesGetFn = unsafe extern "C" fn(
     dependencies: *mut DependenciesOpaque,
     source_ptr: *const u8,
@@ -72,12 +155,12 @@ unsafe extern "C" fn dependencies_get_ffi(
     let f = dependencies as *mut DynDependencies;
     let f = &mut *f;
 
-    match f.get(source, target) {
-        Err(_) => return OTHER_ERROR,
-        Ok(None) => return NOT_FOUND,
-        Ok(Some(id)) => {
+    match catch_panic(|| f.get(source, target)) {
+        Err(_) | Ok(Err(_)) => OTHER_ERROR,
+        Ok(Ok(None)) => NOT_FOUND,
+        Ok(Ok(Some(id))) => {
             std::ptr::write(id_ptr, id.value().get());
-            return SUCCESS;
+            SUCCESS
         }
     }
 }
@@ -126,11 +209,11 @@ impl Dependencies for DependenciesFFI {
 
         match result {
             SUCCESS => match AssetId::new(id) {
-                None => Err(format!("Null AssetId returned from `Dependencies::get`")),
+                None => Err("Null AssetId returned from `Dependencies::get`".to_string()),
                 Some(id) => Ok(Some(id)),
             },
             NOT_FOUND => Ok(None),
-            NOT_UTF8 => Err(format!("Source is not UTF8 while stored in `str`")),
+            NOT_UTF8 => Err("Source is not UTF8 while stored in `str`".to_string()),
 
             _ => Err(format!(
                 "Unexpected return code from `Sources::get` FFI: {}",
@@ -143,15 +226,21 @@ impl Dependencies for DependenciesFFI {
 #[repr(transparent)]
 pub struct SourcesOpaque(u8);
 
-pub type SourcesGetFn = unsafe extern "C" fn(
+/// Writes path to data of `source` into `path_ptr` and its length into `*path_len`.
+/// Returns `SUCCESS`, `NOT_FOUND` if source is not available yet, `NOT_UTF8` or `OTHER_ERROR`.
+/// Returns `BUFFER_IS_TOO_SMALL` with required length in `*path_len` if path does not fit.
+pub type SourcesGetFn = SourcesGetFnOf<OsChar>;
+
+/// [`SourcesGetFn`] with paths of `C` characters.
+pub(crate) type SourcesGetFnOf<C> = unsafe extern "C" fn(
     sources: *mut SourcesOpaque,
     source_ptr: *const u8,
     source_len: u32,
-    path_ptr: *mut OsChar,
+    path_ptr: *mut C,
     path_len: *mut u32,
 ) -> i32;
 
-unsafe extern "C" fn sources_get_ffi<'a>(
+unsafe extern "C" fn sources_get_ffi(
     sources: *mut SourcesOpaque,
     source_ptr: *const u8,
     source_len: u32,
@@ -167,10 +256,10 @@ unsafe extern "C" fn sources_get_ffi<'a>(
     let f = sources as *mut DynSource;
     let f = &mut *f;
 
-    match f.get(source) {
-        Err(_) => return OTHER_ERROR,
-        Ok(None) => return NOT_FOUND,
-        Ok(Some(path)) => {
+    match catch_panic(|| f.get(source)) {
+        Err(_) | Ok(Err(_)) => OTHER_ERROR,
+        Ok(Ok(None)) => NOT_FOUND,
+        Ok(Ok(Some(path))) => {
             let os_str = path.as_os_str();
 
             #[cfg(any(unix, target_os = "wasi"))]
@@ -190,7 +279,7 @@ unsafe extern "C" fn sources_get_ffi<'a>(
             std::ptr::copy_nonoverlapping(path.as_ptr(), path_ptr, path.len() as u32 as usize);
             *path_len = path.len() as u32;
 
-            return SUCCESS;
+            SUCCESS
         }
     }
 }
@@ -215,7 +304,7 @@ impl<'a> DynSource<'a> {
 }
 
 impl SourcesFFI {
-    pub fn new<'a>(sources: &mut DynSource) -> Self {
+    pub fn new(sources: &mut DynSource) -> Self {
         SourcesFFI {
             opaque: sources as *const DynSource as _,
             get: sources_get_ffi,
@@ -253,6 +342,8 @@ impl Sources for SourcesFFI {
 
             return match result {
                 SUCCESS => {
+                    path_buf.truncate(path_len as usize);
+
                     #[cfg(any(unix, target_os = "wasi"))]
                     let path = OsString::from_vec(path_buf).into();
 
@@ -262,7 +353,7 @@ impl Sources for SourcesFFI {
                     Ok(Some(path))
                 }
                 NOT_FOUND => return Ok(None),
-                NOT_UTF8 => Err(format!("Source is not UTF8 while stored in `str`")),
+                NOT_UTF8 => Err("Source is not UTF8 while stored in `str`".to_string()),
                 _ => Err(format!(
                     "Unexpected return code from `Sources::get` FFI: {}",
                     result
@@ -272,33 +363,471 @@ impl Sources for SourcesFFI {
     }
 }
 
+#[repr(transparent)]
+pub struct CancellationOpaque(u8);
+
+/// Returns `CANCELLED` if import is cancelled and `SUCCESS` otherwise.
+pub type CancellationIsCancelledFn =
+    unsafe extern "C" fn(cancellation: *const CancellationOpaque) -> i32;
+
+unsafe extern "C" fn cancellation_is_cancelled_ffi(cancellation: *const CancellationOpaque) -> i32 {
+    let f = cancellation as *const DynCancellation;
+    let f = &*f;
+
+    // Import continues if cancellation check panics.
+    if catch_panic(|| f.cancellation.is_cancelled()).unwrap_or(false) {
+        CANCELLED
+    } else {
+        SUCCESS
+    }
+}
+
+pub struct CancellationFFI {
+    pub opaque: *const CancellationOpaque,
+    pub is_cancelled: CancellationIsCancelledFn,
+}
+
+pub struct DynCancellation<'a> {
+    cancellation: &'a dyn Cancellation,
+}
+
+impl<'a> DynCancellation<'a> {
+    pub fn new(cancellation: &'a dyn Cancellation) -> Self {
+        DynCancellation { cancellation }
+    }
+}
+
+impl CancellationFFI {
+    pub fn new(cancellation: &DynCancellation) -> Self {
+        CancellationFFI {
+            opaque: cancellation as *const DynCancellation as _,
+            is_cancelled: cancellation_is_cancelled_ffi,
+        }
+    }
+}
+
+impl Cancellation for CancellationFFI {
+    fn is_cancelled(&self) -> bool {
+        unsafe { (self.is_cancelled)(self.opaque) == CANCELLED }
+    }
+}
+
+#[repr(transparent)]
+pub struct ProgressOpaque(u8);
+
+/// Reports that `fraction` of the import is done, `stage` is UTF-8.
+pub type ProgressReportFn = unsafe extern "C" fn(
+    progress: *mut ProgressOpaque,
+    fraction: f32,
+    stage_ptr: *const u8,
+    stage_len: u32,
+);
+
+unsafe extern "C" fn progress_report_ffi(
+    progress: *mut ProgressOpaque,
+    fraction: f32,
+    stage_ptr: *const u8,
+    stage_len: u32,
+) {
+    let stage = std::slice::from_raw_parts(stage_ptr, stage_len as usize);
+    let stage = String::from_utf8_lossy(stage);
+
+    let f = progress as *mut DynProgress;
+    let f = &mut *f;
+
+    let _ = catch_panic(|| f.progress.report(fraction, &stage));
+}
+
+pub struct ProgressFFI {
+    pub opaque: *mut ProgressOpaque,
+    pub report: ProgressReportFn,
+}
+
+pub struct DynProgress<'a> {
+    progress: &'a mut dyn Progress,
+}
+
+impl<'a> DynProgress<'a> {
+    pub fn new(progress: &'a mut dyn Progress) -> Self {
+        DynProgress { progress }
+    }
+}
+
+impl ProgressFFI {
+    pub fn new(progress: &mut DynProgress) -> Self {
+        ProgressFFI {
+            opaque: progress as *mut DynProgress as _,
+            report: progress_report_ffi,
+        }
+    }
+}
+
+impl Progress for ProgressFFI {
+    fn report(&mut self, fraction: f32, stage: &str) {
+        unsafe { (self.report)(self.opaque, fraction, stage.as_ptr(), stage.len() as u32) }
+    }
+}
+
+#[repr(transparent)]
+pub struct ReaderOpaque(u8);
+
+/// Reads up to `*buf_len` bytes into `buf_ptr` and writes number of bytes read into `*buf_len`.
+/// Zero bytes are read only at the end of the stream.
+/// Returns `SUCCESS` or `OTHER_ERROR`.
+pub type ReaderReadFn =
+    unsafe extern "C" fn(reader: *mut ReaderOpaque, buf_ptr: *mut u8, buf_len: *mut u32) -> i32;
+
+unsafe extern "C" fn reader_read_ffi(
+    reader: *mut ReaderOpaque,
+    buf_ptr: *mut u8,
+    buf_len: *mut u32,
+) -> i32 {
+    let buf = std::slice::from_raw_parts_mut(buf_ptr, *buf_len as usize);
+
+    let f = reader as *mut DynReader;
+    let f = &mut *f;
+
+    let result = catch_panic(|| loop {
+        match f.reader.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => break result,
+        }
+    });
+
+    match result {
+        Err(_) | Ok(Err(_)) => OTHER_ERROR,
+        Ok(Ok(read)) => {
+            *buf_len = read as u32;
+            SUCCESS
+        }
+    }
+}
+
+pub struct ReaderFFI {
+    pub opaque: *mut ReaderOpaque,
+    pub read: ReaderReadFn,
+}
+
+pub struct DynReader<'a> {
+    reader: Box<dyn Read + 'a>,
+}
+
+impl<'a> DynReader<'a> {
+    pub fn new(reader: impl Read + 'a) -> Self {
+        DynReader {
+            reader: Box::new(reader),
+        }
+    }
+}
+
+impl ReaderFFI {
+    pub fn new(reader: &mut DynReader) -> Self {
+        ReaderFFI {
+            opaque: reader as *mut DynReader as _,
+            read: reader_read_ffi,
+        }
+    }
+}
+
+impl Read for ReaderFFI {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        let result = unsafe { (self.read)(self.opaque, buf.as_mut_ptr(), &mut len) };
+
+        match result {
+            SUCCESS if len as usize <= buf.len() => Ok(len as usize),
+            SUCCESS => Err(io::Error::other("Stream read more bytes than buffer holds")),
+            _ => Err(io::Error::other(format!(
+                "Unexpected return code from stream read FFI: {}",
+                result
+            ))),
+        }
+    }
+}
+
+#[repr(transparent)]
+pub struct WriterOpaque(u8);
+
+/// Writes all `buf_len` bytes from `buf_ptr`.
+/// Returns `SUCCESS` or `OTHER_ERROR`.
+pub type WriterWriteFn =
+    unsafe extern "C" fn(writer: *mut WriterOpaque, buf_ptr: *const u8, buf_len: u32) -> i32;
+
+unsafe extern "C" fn writer_write_ffi(
+    writer: *mut WriterOpaque,
+    buf_ptr: *const u8,
+    buf_len: u32,
+) -> i32 {
+    let buf = std::slice::from_raw_parts(buf_ptr, buf_len as usize);
+
+    let f = writer as *mut DynWriter;
+    let f = &mut *f;
+
+    match catch_panic(|| f.writer.write_all(buf)) {
+        Ok(Ok(())) => SUCCESS,
+        Err(_) | Ok(Err(_)) => OTHER_ERROR,
+    }
+}
+
+pub struct WriterFFI {
+    pub opaque: *mut WriterOpaque,
+    pub write: WriterWriteFn,
+}
+
+pub struct DynWriter<'a> {
+    writer: &'a mut dyn Write,
+}
+
+impl<'a> DynWriter<'a> {
+    pub fn new(writer: &'a mut dyn Write) -> Self {
+        DynWriter { writer }
+    }
+}
+
+impl WriterFFI {
+    pub fn new(writer: &mut DynWriter) -> Self {
+        WriterFFI {
+            opaque: writer as *mut DynWriter as _,
+            write: writer_write_ffi,
+        }
+    }
+}
+
+impl Write for WriterFFI {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize);
+        let result = unsafe { (self.write)(self.opaque, buf.as_ptr(), len as u32) };
+
+        match result {
+            SUCCESS => Ok(len),
+            _ => Err(io::Error::other(format!(
+                "Unexpected return code from stream write FFI: {}",
+                result
+            ))),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[repr(transparent)]
+pub struct SourceStreamsOpaque(u8);
+
+/// Opens stream of data from `source` and writes it into `*reader`.
+/// Opened stream is read with `ReaderReadFn` passed to the import
+/// and closed with `SourceStreamsCloseFn` before the import returns.
+/// At most one source stream is open at a time.
+/// Returns `SUCCESS`, `NOT_FOUND` if source is not available yet, `NOT_UTF8` or `OTHER_ERROR`.
+pub type SourceStreamsOpenFn = unsafe extern "C" fn(
+    streams: *mut SourceStreamsOpaque,
+    source_ptr: *const u8,
+    source_len: u32,
+    reader: *mut *mut ReaderOpaque,
+) -> i32;
+
+/// Closes stream opened with `SourceStreamsOpenFn`.
+pub type SourceStreamsCloseFn =
+    unsafe extern "C" fn(streams: *mut SourceStreamsOpaque, reader: *mut ReaderOpaque);
+
+unsafe extern "C" fn source_streams_open_ffi(
+    streams: *mut SourceStreamsOpaque,
+    source_ptr: *const u8,
+    source_len: u32,
+    reader: *mut *mut ReaderOpaque,
+) -> i32 {
+    let source =
+        match std::str::from_utf8(std::slice::from_raw_parts(source_ptr, source_len as usize)) {
+            Ok(source) => source,
+            Err(_) => return NOT_UTF8,
+        };
+
+    let f = streams as *mut DynSourceStreams;
+    let f = &mut *f;
+
+    if f.opened {
+        return OTHER_ERROR;
+    }
+
+    let result = catch_panic(|| {
+        f.streams.open(source).map(|stream| {
+            // Stream is closed before the import returns and no other stream is opened meanwhile.
+            stream.map(|stream| unsafe {
+                std::mem::transmute::<Box<dyn Read + '_>, Box<dyn Read + 'static>>(stream)
+            })
+        })
+    });
+
+    match result {
+        Err(_) | Ok(Err(_)) => OTHER_ERROR,
+        Ok(Ok(None)) => NOT_FOUND,
+        Ok(Ok(Some(stream))) => {
+            f.opened = true;
+            let stream = Box::new(DynReader { reader: stream });
+            std::ptr::write(reader, Box::into_raw(stream) as *mut ReaderOpaque);
+            SUCCESS
+        }
+    }
+}
+
+unsafe extern "C" fn source_streams_close_ffi(
+    streams: *mut SourceStreamsOpaque,
+    reader: *mut ReaderOpaque,
+) {
+    let f = streams as *mut DynSourceStreams;
+    let f = &mut *f;
+
+    f.opened = false;
+    let _ = catch_panic(|| drop(Box::from_raw(reader as *mut DynReader<'static>)));
+}
+
+pub struct SourceStreamsFFI {
+    pub opaque: *mut SourceStreamsOpaque,
+    pub open: SourceStreamsOpenFn,
+    pub close: SourceStreamsCloseFn,
+    pub read: ReaderReadFn,
+}
+
+pub struct DynSourceStreams<'a> {
+    streams: &'a mut dyn SourceStreams,
+    opened: bool,
+}
+
+impl<'a> DynSourceStreams<'a> {
+    pub fn new(streams: &'a mut dyn SourceStreams) -> Self {
+        DynSourceStreams {
+            streams,
+            opened: false,
+        }
+    }
+}
+
+impl SourceStreamsFFI {
+    pub fn new(streams: &mut DynSourceStreams) -> Self {
+        SourceStreamsFFI {
+            opaque: streams as *mut DynSourceStreams as _,
+            open: source_streams_open_ffi,
+            close: source_streams_close_ffi,
+            read: reader_read_ffi,
+        }
+    }
+}
+
+impl SourceStreams for SourceStreamsFFI {
+    fn open(&mut self, source: &str) -> Result<Option<Box<dyn Read + '_>>, String> {
+        let mut reader = std::ptr::null_mut();
+        let result = unsafe {
+            (self.open)(
+                self.opaque,
+                source.as_ptr(),
+                source.len() as u32,
+                &mut reader,
+            )
+        };
+
+        match result {
+            SUCCESS => Ok(Some(Box::new(SourceStreamFFI {
+                reader: ReaderFFI {
+                    opaque: reader,
+                    read: self.read,
+                },
+                streams: self,
+            }))),
+            NOT_FOUND => Ok(None),
+            NOT_UTF8 => Err("Source is not UTF8 while stored in `str`".to_string()),
+            _ => Err(format!(
+                "Unexpected return code from `SourceStreams::open` FFI: {}",
+                result
+            )),
+        }
+    }
+}
+
+/// Stream opened with [`SourceStreamsFFI`], closed on drop.
+struct SourceStreamFFI<'a> {
+    reader: ReaderFFI,
+    streams: &'a SourceStreamsFFI,
+}
+
+impl Read for SourceStreamFFI<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Drop for SourceStreamFFI<'_> {
+    fn drop(&mut self) {
+        unsafe { (self.streams.close)(self.streams.opaque, self.reader.opaque) }
+    }
+}
+
+#[repr(transparent)]
+pub struct LoggerOpaque(u8);
+
+/// Receives log record encoded with `LogRecord::encode`.
+/// Must be callable from any thread.
+pub type LogFn =
+    unsafe extern "C" fn(logger: *const LoggerOpaque, record_ptr: *const u8, record_len: u32);
+
+/// Exported by importers library to install subscriber that forwards records to `log`.
+/// `max_level` is encoded with `encode_level_filter`.
+pub type SetLoggerFn =
+    unsafe extern "C" fn(logger: *const LoggerOpaque, log: LogFn, max_level: u32);
+
 #[repr(transparent)]
 pub struct ImporterOpaque(u8);
 
-pub type ImporterImportFn = unsafe extern "C" fn(
+/// Writes to the result buffer length-prefixed payload specific to the returned code
+/// followed by the list of diagnostics reported by the importer.
+/// Diagnostics that do not fit into the buffer are dropped.
+/// Returns `BUFFER_IS_TOO_SMALL` with required length in `*result_len` if result does not fit,
+/// importer is called again with larger buffer.
+///
+/// Payload lists sources for `REQUIRE_SOURCES`, pairs of source and target for `REQUIRE_DEPENDENCIES`
+/// and contains message for `OTHER_ERROR` and `PANICKED`.
+/// Diagnostic is `u8` severity, optional code, message and optional location of optional source, line and column.
+///
+/// `options_ptr` is null if no options are set.
+pub type ImporterImportFn = ImporterImportFnOf<OsChar>;
+
+/// [`ImporterImportFn`] with paths of `C` characters.
+pub(crate) type ImporterImportFnOf<C> = unsafe extern "C" fn(
     importer: *const ImporterOpaque,
-    source_ptr: *const OsChar,
+    source_ptr: *const C,
     source_len: u32,
-    output_ptr: *const OsChar,
+    output_ptr: *const C,
     output_len: u32,
+    options_ptr: *const u8,
+    options_len: u32,
     sources: *mut SourcesOpaque,
-    sources_get: SourcesGetFn,
+    sources_get: SourcesGetFnOf<C>,
     dependencies: *mut DependenciesOpaque,
     dependencies_get: DependenciesGetFn,
+    cancellation: *const CancellationOpaque,
+    cancellation_is_cancelled: CancellationIsCancelledFn,
+    progress: *mut ProgressOpaque,
+    progress_report: ProgressReportFn,
     result_ptr: *mut u8,
     result_len: *mut u32,
 ) -> i32;
 
-unsafe extern "C" fn importer_import_ffi<I>(
+pub(crate) unsafe extern "C" fn importer_import_ffi<I>(
     importer: *const ImporterOpaque,
     source_ptr: *const OsChar,
     source_len: u32,
     output_ptr: *const OsChar,
     output_len: u32,
+    options_ptr: *const u8,
+    options_len: u32,
     sources: *mut SourcesOpaque,
     sources_get: SourcesGetFn,
     dependencies: *mut DependenciesOpaque,
     dependencies_get: DependenciesGetFn,
+    cancellation: *const CancellationOpaque,
+    cancellation_is_cancelled: CancellationIsCancelledFn,
+    progress: *mut ProgressOpaque,
+    progress_report: ProgressReportFn,
     result_ptr: *mut u8,
     result_len: *mut u32,
 ) -> i32
@@ -328,132 +857,785 @@ where
         get: dependencies_get,
     };
 
-    let importer = &*(importer as *const I);
-    let result = importer.import(
-        source.as_ref(),
-        output.as_ref(),
-        &mut sources,
-        &mut dependencies,
-    );
+    let cancellation = CancellationFFI {
+        opaque: cancellation,
+        is_cancelled: cancellation_is_cancelled,
+    };
 
-    match result {
-        Ok(()) => SUCCESS,
-        Err(ImportError::RequireSources { sources }) => {
-            let len_required = sources
-                .iter()
-                .fold(0, |acc, p| acc + p.len() + size_of::<u32>())
-                + size_of::<u32>();
+    let mut progress = ProgressFFI {
+        opaque: progress,
+        report: progress_report,
+    };
 
-            assert!(u32::try_from(len_required).is_ok());
+    let options = decode_options(options_ptr, options_len);
 
-            if *result_len < len_required as u32 {
-                *result_len = len_required as u32;
-                return BUFFER_IS_TOO_SMALL;
-            }
+    let importer = &*(importer as *const I);
+    let mut diagnostics = Vec::new();
+    let result = catch_panic(|| {
+        importer.import(
+            source.as_ref(),
+            output.as_ref(),
+            options?,
+            &mut sources,
+            &mut dependencies,
+            &cancellation,
+            &mut diagnostics,
+            &mut progress,
+        )
+    });
+
+    write_import_result(result, &diagnostics, result_ptr, result_len)
+}
+
+/// Same as [`ImporterImportFn`], but reads `source` and writes `output` streams.
+/// `source` and streams opened with `streams_open` are read with `reader_read`.
+///
+/// Streams are consumed by the time result does not fit,
+/// so importer keeps the result when it returns `BUFFER_IS_TOO_SMALL`.
+/// It is then called again on the same thread with null `source` and larger buffer to write it.
+/// Importer that keeps no result fails such call.
+pub type ImporterImportStreamFn = unsafe extern "C" fn(
+    importer: *const ImporterOpaque,
+    source: *mut ReaderOpaque,
+    reader_read: ReaderReadFn,
+    output: *mut WriterOpaque,
+    writer_write: WriterWriteFn,
+    options_ptr: *const u8,
+    options_len: u32,
+    streams: *mut SourceStreamsOpaque,
+    streams_open: SourceStreamsOpenFn,
+    streams_close: SourceStreamsCloseFn,
+    dependencies: *mut DependenciesOpaque,
+    dependencies_get: DependenciesGetFn,
+    cancellation: *const CancellationOpaque,
+    cancellation_is_cancelled: CancellationIsCancelledFn,
+    progress: *mut ProgressOpaque,
+    progress_report: ProgressReportFn,
+    result_ptr: *mut u8,
+    result_len: *mut u32,
+) -> i32;
 
-            std::ptr::copy_nonoverlapping(
-                (sources.len() as u32).to_le_bytes().as_ptr(),
-                result_ptr,
-                size_of::<u32>(),
-            );
+pub(crate) unsafe extern "C" fn importer_import_stream_ffi<I>(
+    importer: *const ImporterOpaque,
+    source: *mut ReaderOpaque,
+    reader_read: ReaderReadFn,
+    output: *mut WriterOpaque,
+    writer_write: WriterWriteFn,
+    options_ptr: *const u8,
+    options_len: u32,
+    streams: *mut SourceStreamsOpaque,
+    streams_open: SourceStreamsOpenFn,
+    streams_close: SourceStreamsCloseFn,
+    dependencies: *mut DependenciesOpaque,
+    dependencies_get: DependenciesGetFn,
+    cancellation: *const CancellationOpaque,
+    cancellation_is_cancelled: CancellationIsCancelledFn,
+    progress: *mut ProgressOpaque,
+    progress_report: ProgressReportFn,
+    result_ptr: *mut u8,
+    result_len: *mut u32,
+) -> i32
+where
+    I: Importer,
+{
+    let mut source = ReaderFFI {
+        opaque: source,
+        read: reader_read,
+    };
 
-            let mut offset = size_of::<u32>();
+    let mut output = WriterFFI {
+        opaque: output,
+        write: writer_write,
+    };
 
-            for url in &sources {
-                let len = url.len();
-
-                std::ptr::copy_nonoverlapping(
-                    (len as u32).to_le_bytes().as_ptr(),
-                    result_ptr.add(offset),
-                    size_of::<u32>(),
-                );
-                offset += size_of::<u32>();
-
-                std::ptr::copy_nonoverlapping(
-                    url.as_ptr(),
-                    result_ptr.add(offset),
-                    len as u32 as usize,
-                );
-                offset += len;
-            }
+    let mut streams = SourceStreamsFFI {
+        opaque: streams,
+        open: streams_open,
+        close: streams_close,
+        read: reader_read,
+    };
 
-            debug_assert_eq!(len_required, offset);
+    let mut dependencies = DependenciesFFI {
+        opaque: dependencies,
+        get: dependencies_get,
+    };
 
-            *result_len = len_required as u32;
-            REQUIRE_SOURCES
-        }
-        Err(ImportError::RequireDependencies { dependencies }) => {
-            let len_required = dependencies.iter().fold(0, |acc, dep| {
-                acc + dep.source.len() + dep.target.len() + size_of::<u32>() * 2
-            }) + size_of::<u32>();
+    let cancellation = CancellationFFI {
+        opaque: cancellation,
+        is_cancelled: cancellation_is_cancelled,
+    };
 
-            assert!(u32::try_from(len_required).is_ok());
+    let mut progress = ProgressFFI {
+        opaque: progress,
+        report: progress_report,
+    };
 
-            if *result_len < len_required as u32 {
-                *result_len = len_required as u32;
-                return BUFFER_IS_TOO_SMALL;
+    // Result kept by previous call is dropped unless this call asks for it.
+    let kept = KEPT_STREAM_RESULT.with(|kept| kept.borrow_mut().take());
+
+    let (code, payload, diagnostics) = if source.opaque.is_null() {
+        match kept.filter(|kept| kept.importer == importer) {
+            Some(kept) => (kept.code, kept.payload, kept.diagnostics),
+            None => {
+                let (code, payload) = encode_import_result(Err(ImportError::Other {
+                    reason: "Importer kept no result".to_owned(),
+                }));
+                (code, payload, Vec::new())
             }
+        }
+    } else {
+        let options = decode_options(options_ptr, options_len);
+
+        let mut diagnostics = Vec::new();
+        let result = catch_panic(|| {
+            (*(importer as *const I)).import_stream(
+                &mut source,
+                &mut output,
+                options?,
+                &mut streams,
+                &mut dependencies,
+                &cancellation,
+                &mut diagnostics,
+                &mut progress,
+            )
+        });
 
-            std::ptr::copy_nonoverlapping(
-                (dependencies.len() as u32).to_le_bytes().as_ptr(),
-                result_ptr,
-                size_of::<u32>(),
-            );
+        let (code, payload) = encode_import_result(
+            result.unwrap_or_else(|message| Err(ImportError::Panicked { message })),
+        );
+        (code, payload, diagnostics)
+    };
 
-            let mut offset = size_of::<u32>();
+    let written = write_result(code, &payload, &diagnostics, result_ptr, result_len);
+    if written == BUFFER_IS_TOO_SMALL {
+        KEPT_STREAM_RESULT.with(|kept| {
+            *kept.borrow_mut() = Some(KeptResult {
+                importer,
+                code,
+                payload,
+                diagnostics,
+            })
+        });
+    }
+    written
+}
 
-            for dep in &dependencies {
-                for s in [&dep.source, &dep.target] {
-                    let len = s.len();
-
-                    std::ptr::copy_nonoverlapping(
-                        (len as u32).to_le_bytes().as_ptr(),
-                        result_ptr.add(offset),
-                        size_of::<u32>(),
-                    );
-                    offset += size_of::<u32>();
-
-                    std::ptr::copy_nonoverlapping(
-                        s.as_ptr(),
-                        result_ptr.add(offset),
-                        len as u32 as usize,
-                    );
-                    offset += len;
-                }
-            }
+thread_local! {
+    /// Result of streaming import that did not fit into the result buffer.
+    static KEPT_STREAM_RESULT: RefCell<Option<KeptResult>> = const { RefCell::new(None) };
+}
 
-            debug_assert_eq!(len_required, offset);
+struct KeptResult {
+    importer: *const ImporterOpaque,
+    code: i32,
+    payload: Vec<u8>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// # Safety
+///
+/// `options_ptr` must be null or valid for `options_len` bytes.
+unsafe fn decode_options<'a>(
+    options_ptr: *const u8,
+    options_len: u32,
+) -> Result<Option<&'a str>, ImportError> {
+    if options_ptr.is_null() {
+        return Ok(None);
+    }
+
+    match std::str::from_utf8(std::slice::from_raw_parts(
+        options_ptr,
+        options_len as usize,
+    )) {
+        Ok(options) => Ok(Some(options)),
+        Err(_) => Err(ImportError::Other {
+            reason: "Import options are not UTF-8".to_owned(),
+        }),
+    }
+}
+
+/// Writes result of the import into the result buffer and returns the code.
+///
+/// # Safety
+///
+/// `result_ptr` must be valid for writes of `*result_len` bytes.
+unsafe fn write_import_result(
+    result: Result<Result<(), ImportError>, String>,
+    diagnostics: &[Diagnostic],
+    result_ptr: *mut u8,
+    result_len: *mut u32,
+) -> i32 {
+    let result = match result {
+        Ok(result) => result,
+        Err(message) => Err(ImportError::Panicked { message }),
+    };
+
+    let (code, payload) = encode_import_result(result);
+    write_result(code, &payload, diagnostics, result_ptr, result_len)
+}
+
+/// Writes encoded result of the import into the result buffer and returns the code.
+///
+/// # Safety
+///
+/// `result_ptr` must be valid for writes of `*result_len` bytes.
+unsafe fn write_result(
+    code: i32,
+    payload: &[u8],
+    diagnostics: &[Diagnostic],
+    result_ptr: *mut u8,
+    result_len: *mut u32,
+) -> i32 {
+    // Payload must fit with at least empty list of diagnostics.
+    // Payload too large for `u32` length never fits.
+    let len_required = payload.len() + size_of::<u32>() * 2;
+    let len_required = u32::try_from(len_required).unwrap_or(u32::MAX);
+
+    if *result_len < len_required {
+        *result_len = len_required;
+        return BUFFER_IS_TOO_SMALL;
+    }
+
+    let cap = *result_len as usize;
+    let mut buf = Vec::with_capacity(cap);
+    encode_bytes(&mut buf, payload);
+    buf.extend(encode_diagnostics(diagnostics, cap - buf.len()));
+    debug_assert!(buf.len() <= cap);
+
+    std::ptr::copy_nonoverlapping(buf.as_ptr(), result_ptr, buf.len());
+    *result_len = buf.len() as u32;
+    code
+}
 
-            *result_len = len_required as u32;
+/// Encodes result of [`Importer::import`] into result code and payload.
+pub(crate) fn encode_import_result(result: Result<(), ImportError>) -> (i32, Vec<u8>) {
+    let mut payload = Vec::new();
+
+    let code = match result {
+        Ok(()) => SUCCESS,
+        Err(ImportError::RequireSources { sources }) => {
+            encode_u32(&mut payload, sources.len() as u32);
+            for url in &sources {
+                encode_bytes(&mut payload, url.as_bytes());
+            }
+            REQUIRE_SOURCES
+        }
+        Err(ImportError::RequireDependencies { dependencies }) => {
+            encode_u32(&mut payload, dependencies.len() as u32);
+            for dep in &dependencies {
+                encode_bytes(&mut payload, dep.source.as_bytes());
+                encode_bytes(&mut payload, dep.target.as_bytes());
+            }
             REQUIRE_DEPENDENCIES
         }
+        Err(ImportError::Cancelled) => CANCELLED,
         Err(ImportError::Other { reason }) => {
-            if *result_len < reason.len() as u32 {
-                *result_len = reason.len() as u32;
-                return BUFFER_IS_TOO_SMALL;
+            payload.extend_from_slice(reason.as_bytes());
+            OTHER_ERROR
+        }
+        Err(ImportError::Panicked { message }) => {
+            payload.extend_from_slice(message.as_bytes());
+            PANICKED
+        }
+    };
+
+    (code, payload)
+}
+
+/// Space kept for the warning about dropped diagnostics.
+const DIAGNOSTICS_DROPPED_RESERVE: usize = 128;
+
+fn encode_diagnostic(buf: &mut Vec<u8>, diagnostic: &Diagnostic) {
+    buf.push(match diagnostic.severity {
+        Severity::Info => 0,
+        Severity::Warning => 1,
+        Severity::Error => 2,
+    });
+
+    encode_optional(buf, diagnostic.code.as_deref(), |buf, code| {
+        encode_bytes(buf, code.as_bytes())
+    });
+    encode_bytes(buf, diagnostic.message.as_bytes());
+    encode_optional(buf, diagnostic.location.as_ref(), |buf, location| {
+        encode_optional(buf, location.source.as_deref(), |buf, source| {
+            encode_bytes(buf, source.as_bytes())
+        });
+        encode_optional(buf, location.line, encode_u32);
+        encode_optional(buf, location.column, encode_u32);
+    });
+}
+
+/// Encodes diagnostics into at most `cap` bytes.
+/// Diagnostics that do not fit are replaced with a warning with their number.
+fn encode_diagnostics(diagnostics: &[Diagnostic], cap: usize) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut ends = Vec::with_capacity(diagnostics.len());
+    for diagnostic in diagnostics {
+        encode_diagnostic(&mut encoded, diagnostic);
+        ends.push(encoded.len());
+    }
+
+    let mut count = diagnostics.len();
+
+    if size_of::<u32>() + encoded.len() > cap {
+        count = ends
+            .iter()
+            .take_while(|&&end| size_of::<u32>() + end + DIAGNOSTICS_DROPPED_RESERVE <= cap)
+            .count();
+
+        encoded.truncate(count.checked_sub(1).map_or(0, |last| ends[last]));
+
+        let mut dropped = Vec::new();
+        encode_diagnostic(
+            &mut dropped,
+            &Diagnostic::warning(format!(
+                "{} more diagnostics did not fit into the result buffer",
+                diagnostics.len() - count
+            )),
+        );
+
+        if size_of::<u32>() + encoded.len() + dropped.len() <= cap {
+            encoded.extend(dropped);
+            count += 1;
+        }
+    }
+
+    let mut buf = Vec::with_capacity(size_of::<u32>() + encoded.len());
+    encode_u32(&mut buf, count as u32);
+    buf.extend(encoded);
+    buf
+}
+
+fn decode_diagnostic(decoder: &mut Decoder) -> Option<Diagnostic> {
+    let severity = match decoder.u8()? {
+        0 => Severity::Info,
+        1 => Severity::Warning,
+        2 => Severity::Error,
+        _ => return None,
+    };
+
+    Some(Diagnostic {
+        severity,
+        code: decoder.optional(Decoder::string)?,
+        message: decoder.string()?,
+        location: decoder.optional(|decoder| {
+            Some(Location {
+                source: decoder.optional(Decoder::string)?,
+                line: decoder.optional(Decoder::u32)?,
+                column: decoder.optional(Decoder::u32)?,
+            })
+        })?,
+    })
+}
+
+/// Decodes result of [`ImporterImportFn`] call.
+/// `result_buf` contains `result_len` bytes written by the call.
+///
+/// Diagnostics reported by the importer are passed to `diagnostics`.
+pub fn decode_import_result(
+    result: i32,
+    result_buf: &[u8],
+    diagnostics: &mut dyn Diagnostics,
+) -> Result<(), ImportError> {
+    match result {
+        SUCCESS | REQUIRE_SOURCES | REQUIRE_DEPENDENCIES | CANCELLED | OTHER_ERROR | PANICKED => {}
+        _ => {
+            return Err(ImportError::Other {
+                reason: format!(
+                    "Unexpected return code from `Importer::import` FFI: {}",
+                    result
+                ),
+            })
+        }
+    }
+
+    let mut decoder = Decoder { data: result_buf };
+    let decoded = (|| {
+        let payload = decoder.bytes()?;
+        let reported = decoder.list(decode_diagnostic)?;
+        decoder.data.is_empty().then_some((payload, reported))
+    })();
+
+    let (payload, reported) = match decoded {
+        None => {
+            return Err(ImportError::Other {
+                reason: "Malformed result of `Importer::import` FFI".to_owned(),
+            })
+        }
+        Some(decoded) => decoded,
+    };
+
+    for diagnostic in reported {
+        diagnostics.report(diagnostic);
+    }
+
+    decode_import_payload(result, payload)
+}
+
+/// Decodes payload specific to the result code.
+pub(crate) fn decode_import_payload(result: i32, payload: &[u8]) -> Result<(), ImportError> {
+    let mut payload = Decoder { data: payload };
+
+    match result {
+        SUCCESS => Ok(()),
+        REQUIRE_SOURCES => match payload.list(Decoder::string) {
+            Some(sources) => Err(ImportError::RequireSources { sources }),
+            None => Err(ImportError::Other {
+                reason: "`Importer::import` requires sources, but one of the sources is malformed or not UTF-8"
+                    .to_owned(),
+            }),
+        },
+        REQUIRE_DEPENDENCIES => {
+            let dependencies = payload.list(|decoder| {
+                Some(Dependency {
+                    source: decoder.string()?,
+                    target: decoder.string()?,
+                })
+            });
+
+            match dependencies {
+                Some(dependencies) => Err(ImportError::RequireDependencies { dependencies }),
+                None => Err(ImportError::Other {
+                    reason: "`Importer::import` requires dependencies, but one of the strings is malformed or not UTF-8"
+                        .to_owned(),
+                }),
             }
+        }
+        CANCELLED => Err(ImportError::Cancelled),
+        PANICKED => Err(ImportError::Panicked {
+            message: String::from_utf8_lossy(payload.data).into_owned(),
+        }),
+        _ => Err(ImportError::Other {
+            reason: String::from_utf8_lossy(payload.data).into_owned(),
+        }),
+    }
+}
 
-            let error_buf = std::slice::from_raw_parts_mut(result_ptr, reason.len());
-            error_buf.copy_from_slice(reason.as_bytes());
-            *result_len = reason.len() as u32;
-            OTHER_ERROR
+pub(crate) fn encode_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    encode_u32(buf, bytes.len() as u32);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_optional<T>(buf: &mut Vec<u8>, value: Option<T>, f: impl FnOnce(&mut Vec<u8>, T)) {
+    match value {
+        None => buf.push(0),
+        Some(value) => {
+            buf.push(1);
+            f(buf, value);
+        }
+    }
+}
+
+/// Reads values written with `encode_*` functions.
+pub(crate) struct Decoder<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn u8(&mut self) -> Option<u8> {
+        let (&head, tail) = self.data.split_first()?;
+        self.data = tail;
+        Some(head)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        let (head, tail) = self.data.split_first_chunk::<4>()?;
+        self.data = tail;
+        Some(u32::from_le_bytes(*head))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        let (head, tail) = self.data.split_first_chunk::<8>()?;
+        self.data = tail;
+        Some(u64::from_le_bytes(*head))
+    }
+
+    pub fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.data.len() < len {
+            return None;
         }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Some(head)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_owned()).ok()
+    }
+
+    pub fn list<T>(&mut self, mut f: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let count = self.u32()?;
+        (0..count).map(|_| f(self)).collect()
+    }
+
+    /// Returns `Some(None)` for absent value and `None` if data is malformed.
+    pub fn optional<T>(&mut self, f: impl FnOnce(&mut Self) -> Option<T>) -> Option<Option<T>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => f(self).map(Some),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `SUCCESS` if importer recognizes the source by its head and `NOT_FOUND` otherwise.
+pub type ImporterProbeFn = unsafe extern "C" fn(
+    importer: *const ImporterOpaque,
+    head_ptr: *const u8,
+    head_len: u32,
+) -> i32;
+
+/// Returns `PANICKED` if importer panics.
+pub(crate) unsafe extern "C" fn importer_probe_ffi<I>(
+    importer: *const ImporterOpaque,
+    head_ptr: *const u8,
+    head_len: u32,
+) -> i32
+where
+    I: Importer,
+{
+    let head = std::slice::from_raw_parts(head_ptr, head_len as usize);
+
+    let importer = &*(importer as *const I);
+    match catch_panic(|| importer.probe(head)) {
+        Ok(true) => SUCCESS,
+        Ok(false) => NOT_FOUND,
+        Err(_) => PANICKED,
+    }
+}
+
+/// Writes description of the importer: name, formats, extensions, target, magic,
+/// description, version, package and options schema.
+/// Strings are prefixed with `u32` length, lists with `u32` count and optional values with `u8` flag.
+///
+/// Returns required length, nothing is written if it exceeds `cap`.
+/// Returns zero if importer panics.
+pub type ImporterDescribeFn =
+    unsafe extern "C" fn(importer: *const ImporterOpaque, buffer: *mut u8, cap: u32) -> u32;
+
+pub(crate) unsafe extern "C" fn importer_describe_ffi<I>(
+    importer: *const ImporterOpaque,
+    buffer: *mut u8,
+    cap: u32,
+) -> u32
+where
+    I: Importer,
+{
+    let importer = &*(importer as *const I);
+
+    let desc = match catch_panic(|| {
+        let mut desc = Vec::new();
+        encode_importer_desc(&mut desc, importer);
+        desc
+    }) {
+        Ok(desc) => desc,
+        Err(_) => return 0,
+    };
+
+    // Description too large for `u32` length never fits.
+    let len = u32::try_from(desc.len()).unwrap_or(u32::MAX);
+    if len <= cap {
+        std::ptr::copy_nonoverlapping(desc.as_ptr(), buffer, desc.len());
+    }
+    len
+}
+
+/// Description of an importer exported by importers library.
+#[derive(Clone, Debug)]
+pub struct ImporterDesc {
+    pub name: String,
+    pub formats: Vec<String>,
+    pub extensions: Vec<String>,
+    pub target: String,
+    pub magic: Vec<Vec<u8>>,
+    pub description: String,
+    pub version: Option<String>,
+    pub package: Option<String>,
+    pub options_schema: Option<String>,
+}
+
+impl ImporterDesc {
+    /// Checks values that importers are required to provide.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("Importer name is empty".to_owned());
+        }
+        if self.formats.is_empty() {
+            return Err(format!("Importer '{}' has no formats", self.name));
+        }
+        if self.target.is_empty() {
+            return Err(format!("Importer '{}' target is empty", self.name));
+        }
+        Ok(())
+    }
+}
+
+fn encode_list<'a>(buf: &mut Vec<u8>, list: impl ExactSizeIterator<Item = &'a [u8]>) {
+    encode_u32(buf, list.len() as u32);
+    for bytes in list {
+        encode_bytes(buf, bytes);
     }
 }
 
-pub const MAX_EXTENSION_LEN: usize = 16;
-pub const MAX_EXTENSION_COUNT: usize = 16;
-pub const MAX_FFI_NAME_LEN: usize = 64;
-pub const MAX_FORMATS_COUNT: usize = 32;
+pub(crate) fn encode_importer_desc(buf: &mut Vec<u8>, importer: &dyn Importer) {
+    encode_bytes(buf, importer.name().as_bytes());
+    encode_list(buf, importer.formats().iter().map(|f| f.as_bytes()));
+    encode_list(buf, importer.extensions().iter().map(|e| e.as_bytes()));
+    encode_bytes(buf, importer.target().as_bytes());
+    encode_list(buf, importer.magic().iter().copied());
+    encode_bytes(buf, importer.description().as_bytes());
+    encode_optional(buf, importer.version(), |buf, s| {
+        encode_bytes(buf, s.as_bytes())
+    });
+    encode_optional(buf, importer.package(), |buf, s| {
+        encode_bytes(buf, s.as_bytes())
+    });
+    encode_optional(buf, importer.options_schema(), |buf, s| {
+        encode_bytes(buf, s.as_bytes())
+    });
+}
+
+pub(crate) fn decode_importer_desc(decoder: &mut Decoder) -> Option<ImporterDesc> {
+    Some(ImporterDesc {
+        name: decoder.string()?,
+        formats: decoder.list(Decoder::string)?,
+        extensions: decoder.list(Decoder::string)?,
+        target: decoder.string()?,
+        magic: decoder.list(|decoder| decoder.bytes().map(<[u8]>::to_vec))?,
+        description: decoder.string()?,
+        version: decoder.optional(Decoder::string)?,
+        package: decoder.optional(Decoder::string)?,
+        options_schema: decoder.optional(Decoder::string)?,
+    })
+}
+
+/// Revision of the importers library ABI.
+/// Incremented on any change of the exports, [`ImporterFFI`] layout or signatures of its functions.
+pub const FFI_REVISION: u32 = 5;
+
+/// Oldest revision that libraries export and loader has adapters for.
+pub const FFI_REVISION_MIN: u32 = crate::legacy::REVISION;
+
+/// Hashes sizes and offsets of FFI structure.
+/// Libraries compiled with different layout produce different fingerprint.
+pub(crate) const fn layout_fingerprint(values: &[u64]) -> u64 {
+    // FNV-1a
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut i = 0;
+    while i < values.len() {
+        hash ^= values[i];
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Hashes name of FFI type.
+const fn name_id(name: &str) -> u64 {
+    let bytes = name.as_bytes();
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Type of FFI function arguments.
+/// Fingerprint covers ids of function pointers, so changing signature changes the fingerprint.
+pub(crate) trait FfiType {
+    const ID: u64;
+}
+
+macro_rules! ffi_types {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl FfiType for $ty {
+                const ID: u64 = name_id(stringify!($ty));
+            }
+        )*
+    };
+}
+
+ffi_types!(
+    (),
+    u8,
+    u16,
+    u32,
+    i32,
+    u64,
+    f32,
+    ImporterOpaque,
+    SourcesOpaque,
+    DependenciesOpaque,
+    CancellationOpaque,
+    ProgressOpaque,
+    ReaderOpaque,
+    WriterOpaque,
+    SourceStreamsOpaque,
+);
+
+impl<T: FfiType> FfiType for *const T {
+    const ID: u64 = layout_fingerprint(&[name_id("*const"), T::ID]);
+}
+
+impl<T: FfiType> FfiType for *mut T {
+    const ID: u64 = layout_fingerprint(&[name_id("*mut"), T::ID]);
+}
+
+/// Nullable function pointer.
+impl<T: FfiType> FfiType for Option<T> {
+    const ID: u64 = layout_fingerprint(&[name_id("Option"), T::ID]);
+}
+
+macro_rules! ffi_fn_types {
+    () => {
+        impl<R: FfiType> FfiType for unsafe extern "C" fn() -> R {
+            const ID: u64 = layout_fingerprint(&[name_id("fn"), R::ID]);
+        }
+    };
+    ($first:ident $(, $rest:ident)*) => {
+        impl<R: FfiType, $first: FfiType $(, $rest: FfiType)*> FfiType
+            for unsafe extern "C" fn($first $(, $rest)*) -> R
+        {
+            const ID: u64 = layout_fingerprint(&[name_id("fn"), R::ID, $first::ID $(, $rest::ID)*]);
+        }
 
+        ffi_fn_types!($($rest),*);
+    };
+}
+
+ffi_fn_types!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12, A13, A14, A15, A16, A17, A18);
+
+/// Returns fingerprint of `ImporterFFI` layout of the revision.
+/// Returns zero for unknown revisions.
+pub fn ffi_layout(revision: u32) -> u64 {
+    match revision {
+        FFI_REVISION => ImporterFFI::FINGERPRINT,
+        crate::legacy::REVISION => crate::legacy::ImporterFFI::FINGERPRINT,
+        _ => 0,
+    }
+}
+
+/// Importer exported by importers library.
+/// Must be callable from any thread while the library is loaded.
 #[repr(C)]
 pub struct ImporterFFI {
+    /// Passed back to the functions.
     pub importer: *const ImporterOpaque,
     pub import: ImporterImportFn,
-    pub name: [u8; MAX_FFI_NAME_LEN],
-    pub formats: [[u8; MAX_FFI_NAME_LEN]; MAX_FORMATS_COUNT],
-    pub target: [u8; MAX_FFI_NAME_LEN],
-    pub extensions: [[u8; MAX_EXTENSION_LEN]; MAX_EXTENSION_COUNT],
+    pub probe: ImporterProbeFn,
+    pub describe: ImporterDescribeFn,
+
+    /// Null if importer does not support streams.
+    pub import_stream: Option<ImporterImportStreamFn>,
 }
 
 /// Exporting non thread-safe importers breaks the contract of the FFI.
@@ -464,93 +1646,387 @@ unsafe impl Send for ImporterFFI {}
 unsafe impl Sync for ImporterFFI {}
 
 impl ImporterFFI {
-    pub fn new<'a, I>(importer: &'static I) -> Self
+    pub const FINGERPRINT: u64 = {
+        let [importer, import, probe, describe, import_stream] = Self::signatures::<OsChar>();
+
+        layout_fingerprint(&[
+            FFI_REVISION as u64,
+            size_of::<ImporterFFI>() as u64,
+            align_of::<ImporterFFI>() as u64,
+            offset_of!(ImporterFFI, importer) as u64,
+            offset_of!(ImporterFFI, import) as u64,
+            offset_of!(ImporterFFI, probe) as u64,
+            offset_of!(ImporterFFI, describe) as u64,
+            offset_of!(ImporterFFI, import_stream) as u64,
+            size_of::<OsChar>() as u64,
+            importer,
+            import,
+            probe,
+            describe,
+            import_stream,
+        ])
+    };
+
+    /// Returns ids of field types with paths of `C` characters.
+    pub(crate) const fn signatures<C: FfiType>() -> [u64; 5] {
+        [
+            <*const ImporterOpaque>::ID,
+            ImporterImportFnOf::<C>::ID,
+            ImporterProbeFn::ID,
+            ImporterDescribeFn::ID,
+            Option::<ImporterImportStreamFn>::ID,
+        ]
+    }
+
+    pub fn new<I>(importer: &'static I) -> Self
     where
         I: Importer,
     {
-        let name = importer.name();
-        let formats = importer.formats();
-        let target = importer.target();
-        let extensions = importer.extensions();
+        ImporterFFI {
+            importer: importer as *const I as *const ImporterOpaque,
+            import: importer_import_ffi::<I>,
+            probe: importer_probe_ffi::<I>,
+            describe: importer_describe_ffi::<I>,
+            import_stream: importer
+                .supports_streams()
+                .then_some(importer_import_stream_ffi::<I> as ImporterImportStreamFn),
+        }
+    }
+}
 
-        let importer = importer as *const I as *const ImporterOpaque;
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
 
-        assert!(
-            name.len() <= MAX_FFI_NAME_LEN,
-            "Importer name should fit into {} bytes",
-            MAX_FFI_NAME_LEN
-        );
-        assert!(
-            formats.len() <= MAX_FORMATS_COUNT,
-            "Importer should support no more than {} formats",
-            MAX_FORMATS_COUNT
-        );
-        assert!(
-            formats.iter().all(|f| f.len() <= MAX_FFI_NAME_LEN),
-            "Importer formats should fit into {} bytes",
-            MAX_FFI_NAME_LEN
-        );
-        assert!(
-            target.len() <= MAX_FFI_NAME_LEN,
-            "Importer target should fit into {} bytes",
-            MAX_FFI_NAME_LEN
-        );
-        assert!(
-            extensions.len() < MAX_EXTENSION_COUNT,
-            "Importer should support no more than {} extensions",
-            MAX_EXTENSION_COUNT,
-        );
-        assert!(
-            extensions.iter().all(|e| e.len() < MAX_EXTENSION_LEN),
-            "Importer extensions should fit into {} bytes",
-            MAX_EXTENSION_LEN,
-        );
+    use super::*;
 
-        assert!(!name.is_empty(), "Importer name should not be empty");
-        assert!(!formats.is_empty(), "Importer formats should not be empty");
-        assert!(!target.is_empty(), "Importer target should not be empty");
+    struct PanickingImporter;
 
-        assert!(
-            !name.contains('\0'),
-            "Importer name should not contain '\\0' byte"
-        );
-        assert!(
-            formats.iter().all(|f| !f.contains('\0')),
-            "Importer formats should not contain '\\0' byte"
-        );
-        assert!(
-            !target.contains('\0'),
-            "Importer target should not contain '\\0' byte"
-        );
-        assert!(
-            extensions.iter().all(|e| !e.contains('\0')),
-            "Importer extensions should not contain '\\0' byte"
-        );
+    impl Importer for PanickingImporter {
+        fn name(&self) -> &str {
+            "Panicking importer"
+        }
 
-        let mut name_buf = [0; MAX_FFI_NAME_LEN];
-        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        fn formats(&self) -> &[&str] {
+            &["panic"]
+        }
 
-        let mut formats_buf = [[0; MAX_FFI_NAME_LEN]; MAX_FORMATS_COUNT];
-        for (i, &format) in formats.iter().enumerate() {
-            formats_buf[i][..format.len()].copy_from_slice(format.as_bytes());
+        fn extensions(&self) -> &[&str] {
+            &[]
         }
 
-        let mut target_buf = [0; MAX_FFI_NAME_LEN];
-        target_buf[..target.len()].copy_from_slice(target.as_bytes());
+        fn target(&self) -> &str {
+            "panic"
+        }
 
-        let mut extensions_buf = [[0; MAX_EXTENSION_LEN]; MAX_EXTENSION_COUNT];
+        fn probe(&self, _head: &[u8]) -> bool {
+            panic!("probe boom")
+        }
 
-        for (i, &extension) in extensions.iter().enumerate() {
-            extensions_buf[i][..extension.len()].copy_from_slice(extension.as_bytes());
+        fn import(
+            &self,
+            _source: &Path,
+            _output: &Path,
+            _options: Option<&str>,
+            _sources: &mut dyn Sources,
+            _dependencies: &mut dyn Dependencies,
+            _cancellation: &dyn Cancellation,
+            diagnostics: &mut dyn Diagnostics,
+            _progress: &mut dyn Progress,
+        ) -> Result<(), ImportError> {
+            diagnostics.report(Diagnostic::warning("about to panic"));
+            panic!("boom")
         }
+    }
 
-        ImporterFFI {
-            importer,
-            import: importer_import_ffi::<I>,
-            name: name_buf,
-            formats: formats_buf,
-            target: target_buf,
-            extensions: extensions_buf,
+    struct NoSources;
+
+    impl Sources for NoSources {
+        fn get(&mut self, _source: &str) -> Result<Option<PathBuf>, String> {
+            Ok(None)
+        }
+    }
+
+    struct NoDependencies;
+
+    impl Dependencies for NoDependencies {
+        fn get(&mut self, _source: &str, _target: &str) -> Result<Option<AssetId>, String> {
+            Ok(None)
         }
     }
+
+    /// Calls `import` of the importer through its FFI and decodes the result.
+    fn import_ffi(importer: &ImporterFFI) -> (Result<(), ImportError>, Vec<Diagnostic>) {
+        let source: Vec<OsChar> = b"source".iter().map(|&c| OsChar::from(c)).collect();
+        let output: Vec<OsChar> = b"output".iter().map(|&c| OsChar::from(c)).collect();
+
+        let mut sources = NoSources;
+        let mut sources = DynSource::new(&mut sources);
+        let sources = SourcesFFI::new(&mut sources);
+
+        let mut dependencies = NoDependencies;
+        let mut dependencies = DynDependencies::new(&mut dependencies);
+        let dependencies = DependenciesFFI::new(&mut dependencies);
+
+        let cancellation = DynCancellation::new(&());
+        let cancellation = CancellationFFI::new(&cancellation);
+
+        let mut progress = ();
+        let mut progress = DynProgress::new(&mut progress);
+        let progress = ProgressFFI::new(&mut progress);
+
+        let mut result_buf = vec![0; ANY_BUF_LEN_LIMIT];
+        let mut result_len = result_buf.len() as u32;
+
+        let result = unsafe {
+            (importer.import)(
+                importer.importer,
+                source.as_ptr(),
+                source.len() as u32,
+                output.as_ptr(),
+                output.len() as u32,
+                std::ptr::null(),
+                0,
+                sources.opaque,
+                sources.get,
+                dependencies.opaque,
+                dependencies.get,
+                cancellation.opaque,
+                cancellation.is_cancelled,
+                progress.opaque,
+                progress.report,
+                result_buf.as_mut_ptr(),
+                &mut result_len,
+            )
+        };
+        assert_eq!(result, PANICKED);
+
+        let mut diagnostics = Vec::new();
+        let result =
+            decode_import_result(result, &result_buf[..result_len as usize], &mut diagnostics);
+        (result, diagnostics)
+    }
+
+    #[test]
+    fn reports_import_panic() {
+        let importer = ImporterFFI::new(&PanickingImporter);
+        let (result, diagnostics) = import_ffi(&importer);
+
+        match result {
+            Err(ImportError::Panicked { message }) => assert_eq!(message, "boom"),
+            _ => panic!("Expected `ImportError::Panicked`"),
+        }
+
+        // Diagnostics reported before the panic are kept.
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "about to panic");
+    }
+
+    /// Streaming importer which result is larger than the source.
+    struct VerboseStreamImporter;
+
+    impl Importer for VerboseStreamImporter {
+        fn name(&self) -> &str {
+            "Verbose stream importer"
+        }
+
+        fn formats(&self) -> &[&str] {
+            &["verbose"]
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &[]
+        }
+
+        fn target(&self) -> &str {
+            "verbose"
+        }
+
+        fn import(
+            &self,
+            _source: &Path,
+            _output: &Path,
+            _options: Option<&str>,
+            _sources: &mut dyn Sources,
+            _dependencies: &mut dyn Dependencies,
+            _cancellation: &dyn Cancellation,
+            _diagnostics: &mut dyn Diagnostics,
+            _progress: &mut dyn Progress,
+        ) -> Result<(), ImportError> {
+            unreachable!()
+        }
+
+        fn supports_streams(&self) -> bool {
+            true
+        }
+
+        fn import_stream(
+            &self,
+            source: &mut dyn Read,
+            _output: &mut dyn Write,
+            _options: Option<&str>,
+            _sources: &mut dyn SourceStreams,
+            _dependencies: &mut dyn Dependencies,
+            _cancellation: &dyn Cancellation,
+            diagnostics: &mut dyn Diagnostics,
+            _progress: &mut dyn Progress,
+        ) -> Result<(), ImportError> {
+            let mut data = String::new();
+            source.read_to_string(&mut data).unwrap();
+            diagnostics.report(Diagnostic::warning("read the source"));
+            Err(ImportError::Other {
+                reason: data.repeat(64),
+            })
+        }
+    }
+
+    struct NoSourceStreams;
+
+    impl SourceStreams for NoSourceStreams {
+        fn open(&mut self, _source: &str) -> Result<Option<Box<dyn Read + '_>>, String> {
+            Ok(None)
+        }
+    }
+
+    /// Calls `import_stream` of the importer through its FFI with result buffer of `cap` bytes.
+    /// Source is null if `source` is `None`.
+    fn import_stream_ffi(
+        importer: &ImporterFFI,
+        source: Option<&[u8]>,
+        cap: usize,
+    ) -> (i32, Vec<u8>) {
+        let mut reader = DynReader::new(source.unwrap_or_default());
+        let mut reader = ReaderFFI::new(&mut reader);
+        if source.is_none() {
+            reader.opaque = std::ptr::null_mut();
+        }
+
+        let mut output = Vec::new();
+        let mut output = DynWriter::new(&mut output);
+        let output = WriterFFI::new(&mut output);
+
+        let mut streams = NoSourceStreams;
+        let mut streams = DynSourceStreams::new(&mut streams);
+        let streams = SourceStreamsFFI::new(&mut streams);
+
+        let mut dependencies = NoDependencies;
+        let mut dependencies = DynDependencies::new(&mut dependencies);
+        let dependencies = DependenciesFFI::new(&mut dependencies);
+
+        let cancellation = DynCancellation::new(&());
+        let cancellation = CancellationFFI::new(&cancellation);
+
+        let mut progress = ();
+        let mut progress = DynProgress::new(&mut progress);
+        let progress = ProgressFFI::new(&mut progress);
+
+        let mut result_buf = vec![0; cap];
+        let mut result_len = result_buf.len() as u32;
+
+        let result = unsafe {
+            (importer.import_stream.unwrap())(
+                importer.importer,
+                reader.opaque,
+                reader.read,
+                output.opaque,
+                output.write,
+                std::ptr::null(),
+                0,
+                streams.opaque,
+                streams.open,
+                streams.close,
+                dependencies.opaque,
+                dependencies.get,
+                cancellation.opaque,
+                cancellation.is_cancelled,
+                progress.opaque,
+                progress.report,
+                result_buf.as_mut_ptr(),
+                &mut result_len,
+            )
+        };
+
+        if result == BUFFER_IS_TOO_SMALL {
+            result_buf.clear();
+            result_buf.resize(result_len as usize, 0);
+        } else {
+            result_buf.truncate(result_len as usize);
+        }
+        (result, result_buf)
+    }
+
+    #[test]
+    fn keeps_stream_result_that_does_not_fit() {
+        let importer = ImporterFFI::new(&VerboseStreamImporter);
+
+        let (result, buf) = import_stream_ffi(&importer, Some(b"source"), 16);
+        assert_eq!(result, BUFFER_IS_TOO_SMALL);
+        assert!(buf.len() > "source".len() * 64);
+
+        // Importer called again with null source writes the kept result.
+        let (result, buf) = import_stream_ffi(&importer, None, ANY_BUF_LEN_LIMIT);
+        let mut diagnostics = Vec::new();
+        match decode_import_result(result, &buf, &mut diagnostics) {
+            Err(ImportError::Other { reason }) => assert_eq!(reason, "source".repeat(64)),
+            _ => panic!("Expected `ImportError::Other`"),
+        }
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "read the source");
+
+        // Result is written only once.
+        let (result, buf) = import_stream_ffi(&importer, None, ANY_BUF_LEN_LIMIT);
+        match decode_import_result(result, &buf, &mut Vec::new()) {
+            Err(ImportError::Other { reason }) => assert_eq!(reason, "Importer kept no result"),
+            _ => panic!("Expected `ImportError::Other`"),
+        }
+    }
+
+    #[test]
+    fn reports_probe_panic() {
+        let importer = ImporterFFI::new(&PanickingImporter);
+        let head = b"head";
+        let result =
+            unsafe { (importer.probe)(importer.importer, head.as_ptr(), head.len() as u32) };
+        assert_eq!(result, PANICKED);
+    }
+
+    #[test]
+    fn fingerprint_covers_signatures() {
+        // Import function without options.
+        type OptionlessImportFn = unsafe extern "C" fn(
+            *const ImporterOpaque,
+            *const OsChar,
+            u32,
+            *const OsChar,
+            u32,
+            *mut SourcesOpaque,
+            SourcesGetFn,
+            *mut DependenciesOpaque,
+            DependenciesGetFn,
+            *const CancellationOpaque,
+            CancellationIsCancelledFn,
+            *mut ProgressOpaque,
+            ProgressReportFn,
+            *mut u8,
+            *mut u32,
+        ) -> i32;
+
+        assert_ne!(ImporterImportFn::ID, OptionlessImportFn::ID);
+        assert_ne!(ImporterImportFn::ID, ImporterImportFnOf::<u16>::ID);
+        assert_ne!(<*const u8>::ID, <*mut u8>::ID);
+        assert_ne!(
+            ImporterFFI::signatures::<u8>(),
+            ImporterFFI::signatures::<u16>()
+        );
+    }
+
+    /// Layouts of released revisions must never change.
+    /// Changes of `ImporterFFI` and its functions require new revision
+    /// and an adapter for the old one, which keeps its fingerprint here.
+    #[test]
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    fn revision_fingerprints_are_pinned() {
+        assert_eq!(ffi_layout(3), 0xd30455b2d1b04019);
+        assert_eq!(ffi_layout(5), 0x1abb40464d02d0d4);
+    }
 }
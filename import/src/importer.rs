@@ -1,6 +1,14 @@
-use std::path::Path;
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
 
-use crate::{Dependencies, Dependency, Sources};
+use crate::{
+    Cancellation, Dependencies, Dependency, Diagnostics, Progress, SourceStreams, Sources,
+};
+
+/// Maximum number of first bytes of the source passed to [`Importer::probe`].
+pub const PROBE_LEN: usize = 4096;
 
 /// Result of `Importer::import` method.
 pub enum ImportError {
@@ -13,11 +21,20 @@ pub enum ImportError {
     /// Importer requires following dependencies.
     RequireDependencies { dependencies: Vec<Dependency> },
 
+    /// Import was stopped after [`Cancellation::is_cancelled`] returned `true`.
+    Cancelled,
+
     /// Importer failed to import the asset.
     Other {
         /// Failure reason.
         reason: String,
     },
+
+    /// Importer panicked during import.
+    Panicked {
+        /// Panic message.
+        message: String,
+    },
 }
 
 /// Trait for an importer.
@@ -34,12 +51,203 @@ pub trait Importer: Send + Sync {
     /// Returns target format importer produces.
     fn target(&self) -> &str;
 
+    /// Returns human readable description of the importer.
+    fn description(&self) -> &str {
+        ""
+    }
+
+    /// Returns semver version of the importer.
+    fn version(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns name of the crate or library the importer comes from.
+    fn package(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns JSON Schema of per-asset options accepted by [`Importer::import`].
+    ///
+    /// Importers without schema accept no options.
+    fn options_schema(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns byte sequences that sources of supported formats start with.
+    /// Used to pick importer by content when format and extension are missing or ambiguous.
+    fn magic(&self) -> &[&[u8]] {
+        &[]
+    }
+
+    /// Checks if source that starts with `head` can be imported.
+    /// `head` contains up to [`PROBE_LEN`] first bytes of the source.
+    ///
+    /// Used along with [`Importer::magic`] to pick importer by content.
+    /// Should be cheap.
+    fn probe(&self, head: &[u8]) -> bool {
+        let _ = head;
+        false
+    }
+
     /// Reads data from `source` path and writes result at `output` path.
+    ///
+    /// Long running importers should poll `cancellation`
+    /// and return [`ImportError::Cancelled`] when it is set.
+    ///
+    /// Warnings and other messages about the import are reported to `diagnostics`.
+    /// They are stored along with imported asset.
+    ///
+    /// Long running importers should report how much is done to `progress`.
+    ///
+    /// `options` is JSON document valid against [`Importer::options_schema`],
+    /// `None` if no options are set for the asset.
+    #[allow(clippy::too_many_arguments)]
     fn import(
         &self,
         source: &Path,
         output: &Path,
+        options: Option<&str>,
         sources: &mut dyn Sources,
         dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
     ) -> Result<(), ImportError>;
+
+    /// Returns `true` if importer implements [`Importer::import_stream`].
+    ///
+    /// Store then imports with streams, so sources may stay in memory
+    /// and output is hashed while it is written.
+    fn supports_streams(&self) -> bool {
+        false
+    }
+
+    /// Reads data from `source` stream and writes result into `output` stream.
+    /// Other sources are opened with `sources`.
+    ///
+    /// Called only if [`Importer::supports_streams`] returns `true`.
+    /// Otherwise the same as [`Importer::import`].
+    /// Streaming importers may implement [`Importer::import`] with [`import_with_streams`](crate::import_with_streams).
+    #[allow(clippy::too_many_arguments)]
+    fn import_stream(
+        &self,
+        source: &mut dyn Read,
+        output: &mut dyn Write,
+        options: Option<&str>,
+        sources: &mut dyn SourceStreams,
+        dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        let _ = (
+            source,
+            output,
+            options,
+            sources,
+            dependencies,
+            cancellation,
+            diagnostics,
+            progress,
+        );
+        Err(ImportError::Other {
+            reason: format!("Importer '{}' does not support streams", self.name()),
+        })
+    }
+}
+
+/// Importers created by library factory are boxed.
+impl<I> Importer for Box<I>
+where
+    I: Importer + ?Sized,
+{
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn formats(&self) -> &[&str] {
+        (**self).formats()
+    }
+
+    fn extensions(&self) -> &[&str] {
+        (**self).extensions()
+    }
+
+    fn target(&self) -> &str {
+        (**self).target()
+    }
+
+    fn description(&self) -> &str {
+        (**self).description()
+    }
+
+    fn version(&self) -> Option<&str> {
+        (**self).version()
+    }
+
+    fn package(&self) -> Option<&str> {
+        (**self).package()
+    }
+
+    fn options_schema(&self) -> Option<&str> {
+        (**self).options_schema()
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        (**self).magic()
+    }
+
+    fn probe(&self, head: &[u8]) -> bool {
+        (**self).probe(head)
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        options: Option<&str>,
+        sources: &mut dyn Sources,
+        dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        (**self).import(
+            source,
+            output,
+            options,
+            sources,
+            dependencies,
+            cancellation,
+            diagnostics,
+            progress,
+        )
+    }
+
+    fn supports_streams(&self) -> bool {
+        (**self).supports_streams()
+    }
+
+    fn import_stream(
+        &self,
+        source: &mut dyn Read,
+        output: &mut dyn Write,
+        options: Option<&str>,
+        sources: &mut dyn SourceStreams,
+        dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        (**self).import_stream(
+            source,
+            output,
+            options,
+            sources,
+            dependencies,
+            cancellation,
+            diagnostics,
+            progress,
+        )
+    }
 }
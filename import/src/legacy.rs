@@ -0,0 +1,221 @@
+//! Importer ABI revision 3, used by `treasury-import` 0.3.
+//!
+//! Libraries still export this revision under the old symbol names,
+//! so stores built with 0.3 can load them.
+//! Loader adapts libraries built with 0.3 that export nothing else.
+//!
+//! Importers of this revision are never cancelled, do not report progress and diagnostics,
+//! and can't be picked by content.
+//! Importers with names and extensions that do not fit the layout are not exported.
+
+#[cfg(unix)]
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+#[cfg(target_os = "wasi")]
+use std::{ffi::OsStr, os::wasi::ffi::OsStrExt};
+
+#[cfg(windows)]
+use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+use std::mem::{align_of, offset_of, size_of};
+
+use crate::{
+    diagnostics::Diagnostic,
+    ffi::{
+        catch_panic, encode_import_result, layout_fingerprint, DependenciesFFI, DependenciesGetFn,
+        DependenciesOpaque, ImporterOpaque, OsChar, SourcesFFI, SourcesGetFn, SourcesOpaque,
+        BUFFER_IS_TOO_SMALL, CANCELLED, OTHER_ERROR, PANICKED,
+    },
+    importer::{ImportError, Importer},
+};
+
+/// Revision of this ABI.
+pub const REVISION: u32 = 3;
+
+/// `fn() -> u32`, returns [`REVISION`].
+pub const VERSION_FN_NAME: &str = "treasury_importer_ffi_version_minor";
+
+/// `fn(buffer: *mut ImporterFFI, cap: u32) -> u32`
+pub const EXPORT_IMPORTERS_FN_NAME: &str = "treasury_export_importers";
+
+pub const MAX_EXTENSION_LEN: usize = 16;
+pub const MAX_EXTENSION_COUNT: usize = 16;
+pub const MAX_FFI_NAME_LEN: usize = 64;
+pub const MAX_FORMATS_COUNT: usize = 32;
+
+/// Writes to the result buffer payload specific to the returned code.
+pub type ImporterImportFn = unsafe extern "C" fn(
+    importer: *const ImporterOpaque,
+    source_ptr: *const OsChar,
+    source_len: u32,
+    output_ptr: *const OsChar,
+    output_len: u32,
+    sources: *mut SourcesOpaque,
+    sources_get: SourcesGetFn,
+    dependencies: *mut DependenciesOpaque,
+    dependencies_get: DependenciesGetFn,
+    result_ptr: *mut u8,
+    result_len: *mut u32,
+) -> i32;
+
+#[repr(C)]
+pub struct ImporterFFI {
+    pub importer: *const ImporterOpaque,
+    pub import: ImporterImportFn,
+    pub name: [u8; MAX_FFI_NAME_LEN],
+    pub formats: [[u8; MAX_FFI_NAME_LEN]; MAX_FORMATS_COUNT],
+    pub target: [u8; MAX_FFI_NAME_LEN],
+    pub extensions: [[u8; MAX_EXTENSION_LEN]; MAX_EXTENSION_COUNT],
+}
+
+/// Exporting non thread-safe importers breaks the contract of the FFI.
+/// The potential unsoundness is covered by `load_dylib_importers` unsafety.
+unsafe impl Send for ImporterFFI {}
+unsafe impl Sync for ImporterFFI {}
+
+impl ImporterFFI {
+    pub const FINGERPRINT: u64 = layout_fingerprint(&[
+        REVISION as u64,
+        size_of::<ImporterFFI>() as u64,
+        align_of::<ImporterFFI>() as u64,
+        offset_of!(ImporterFFI, importer) as u64,
+        offset_of!(ImporterFFI, import) as u64,
+        offset_of!(ImporterFFI, name) as u64,
+        offset_of!(ImporterFFI, formats) as u64,
+        offset_of!(ImporterFFI, target) as u64,
+        offset_of!(ImporterFFI, extensions) as u64,
+        size_of::<OsChar>() as u64,
+    ]);
+
+    /// Returns `None` if importer does not fit into the layout.
+    pub fn new<I>(importer: &'static I) -> Option<Self>
+    where
+        I: Importer,
+    {
+        let name = importer.name();
+        let formats = importer.formats();
+        let target = importer.target();
+        let extensions = importer.extensions();
+
+        let fits = |s: &str, len: usize| !s.is_empty() && s.len() <= len && !s.contains('\0');
+
+        if !fits(name, MAX_FFI_NAME_LEN)
+            || !fits(target, MAX_FFI_NAME_LEN)
+            || formats.is_empty()
+            || formats.len() > MAX_FORMATS_COUNT
+            || !formats.iter().all(|f| fits(f, MAX_FFI_NAME_LEN))
+            || extensions.len() >= MAX_EXTENSION_COUNT
+            || !extensions.iter().all(|e| fits(e, MAX_EXTENSION_LEN - 1))
+        {
+            return None;
+        }
+
+        let mut name_buf = [0; MAX_FFI_NAME_LEN];
+        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+
+        let mut formats_buf = [[0; MAX_FFI_NAME_LEN]; MAX_FORMATS_COUNT];
+        for (i, &format) in formats.iter().enumerate() {
+            formats_buf[i][..format.len()].copy_from_slice(format.as_bytes());
+        }
+
+        let mut target_buf = [0; MAX_FFI_NAME_LEN];
+        target_buf[..target.len()].copy_from_slice(target.as_bytes());
+
+        let mut extensions_buf = [[0; MAX_EXTENSION_LEN]; MAX_EXTENSION_COUNT];
+        for (i, &extension) in extensions.iter().enumerate() {
+            extensions_buf[i][..extension.len()].copy_from_slice(extension.as_bytes());
+        }
+
+        Some(ImporterFFI {
+            importer: importer as *const I as *const ImporterOpaque,
+            import: importer_import_ffi::<I>,
+            name: name_buf,
+            formats: formats_buf,
+            target: target_buf,
+            extensions: extensions_buf,
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "C" fn importer_import_ffi<I>(
+    importer: *const ImporterOpaque,
+    source_ptr: *const OsChar,
+    source_len: u32,
+    output_ptr: *const OsChar,
+    output_len: u32,
+    sources: *mut SourcesOpaque,
+    sources_get: SourcesGetFn,
+    dependencies: *mut DependenciesOpaque,
+    dependencies_get: DependenciesGetFn,
+    result_ptr: *mut u8,
+    result_len: *mut u32,
+) -> i32
+where
+    I: Importer,
+{
+    let source = std::slice::from_raw_parts(source_ptr, source_len as usize);
+    let output = std::slice::from_raw_parts(output_ptr, output_len as usize);
+
+    #[cfg(any(unix, target_os = "wasi"))]
+    let source = OsStr::from_bytes(source);
+    #[cfg(any(unix, target_os = "wasi"))]
+    let output = OsStr::from_bytes(output);
+
+    #[cfg(windows)]
+    let source = OsString::from_wide(source);
+    #[cfg(windows)]
+    let output = OsString::from_wide(output);
+
+    let mut sources = SourcesFFI {
+        opaque: sources,
+        get: sources_get,
+    };
+
+    let mut dependencies = DependenciesFFI {
+        opaque: dependencies,
+        get: dependencies_get,
+    };
+
+    let importer = &*(importer as *const I);
+    let result = catch_panic(|| {
+        importer.import(
+            source.as_ref(),
+            output.as_ref(),
+            None,
+            &mut sources,
+            &mut dependencies,
+            &(),
+            &mut Vec::<Diagnostic>::new(),
+            &mut (),
+        )
+    });
+
+    let result = match result {
+        Ok(result) => result,
+        Err(message) => Err(ImportError::Panicked { message }),
+    };
+
+    let (code, payload) = encode_import_result(result);
+
+    // Codes added after this revision are reported as other errors.
+    let (code, payload) = match code {
+        CANCELLED => (OTHER_ERROR, b"Import cancelled".to_vec()),
+        PANICKED => {
+            let mut message = b"Importer panicked: ".to_vec();
+            message.extend(payload);
+            (OTHER_ERROR, message)
+        }
+        _ => (code, payload),
+    };
+
+    let len_required = u32::try_from(payload.len()).unwrap_or(u32::MAX);
+    if *result_len < len_required {
+        *result_len = len_required;
+        return BUFFER_IS_TOO_SMALL;
+    }
+
+    std::ptr::copy_nonoverlapping(payload.as_ptr(), result_ptr, payload.len());
+    *result_len = len_required;
+    code
+}
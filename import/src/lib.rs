@@ -27,8 +27,12 @@
 //!         &self,
 //!         source: &std::path::Path,
 //!         output: &std::path::Path,
+//!         _options: Option<&str>,
 //!         _sources: &mut dyn treasury_import::Sources,
 //!         _dependencies: &mut dyn treasury_import::Dependencies,
+//!         _cancellation: &dyn treasury_import::Cancellation,
+//!         _diagnostics: &mut dyn treasury_import::Diagnostics,
+//!         _progress: &mut dyn treasury_import::Progress,
 //!     ) -> Result<(), treasury_import::ImportError> {
 //!         match std::fs::copy(source, output) {
 //!           Ok(_) => Ok(()),
@@ -44,21 +48,60 @@
 //!     &FooImporter;
 //! }
 //! ```
+//!
+//! With `macros` feature, [`importer`] attribute turns a function that takes [`ImportContext`] into an importer.
+//!
+//! ```ignore
+//! #[treasury_import::importer(name = "Bar importer", formats = ["bar"], extensions = ["bar"], target = "bar")]
+//! fn bar(ctx: &mut treasury_import::ImportContext) -> Result<(), treasury_import::ContextError> {
+//!     let data = ctx.read_source()?;
+//!     ctx.write_output(data)
+//! }
+//!
+//! treasury_import::make_treasury_importers_library! {
+//!     &bar;
+//! }
+//! ```
+//!
+//! Importers that read the source and write the output as streams return `true` from [`Importer::supports_streams`]
+//! and implement [`Importer::import_stream`]. [`import_with_streams`] implements [`Importer::import`] for them.
 
+mod cancellation;
+mod context;
 mod dependencies;
-mod ffi;
+mod diagnostics;
 mod importer;
+mod progress;
 mod sources;
+mod streams;
+
+pub mod c_api;
+pub mod factory;
+pub mod ffi;
+pub mod legacy;
+pub mod logging;
+
+pub mod wasm;
 
 #[cfg(feature = "libloading")]
 pub mod loading;
 
-pub use ffi::ImporterFFI;
+pub use ffi::{
+    catch_panic, ffi_layout, ImporterFFI, LogFn, LoggerOpaque, FFI_REVISION, FFI_REVISION_MIN,
+};
+
+#[cfg(feature = "macros")]
+pub use treasury_import_macros::importer;
 
 pub use self::{
+    cancellation::Cancellation,
+    context::{ContextError, ImportContext},
     dependencies::{Dependencies, Dependency},
-    importer::{ImportError, Importer},
+    diagnostics::{Diagnostic, Diagnostics, Location, Severity},
+    importer::{ImportError, Importer, PROBE_LEN},
+    progress::Progress,
     sources::Sources,
+    streams::{import_with_streams, SourceStreams},
 };
 
 /// Helper function to emit an error if some dependencies are missing.
@@ -97,30 +140,209 @@ pub const MAGIC: u32 = u32::from_le_bytes(*b"TRES");
 /// Defines exports required for an importers library.
 /// Accepts repetition of importer expressions of type [`&'static impl Importer`] delimited by ';'.
 ///
+/// Alternatively accepts `factory = <expr>;` where expression has type [`ImportersFactory`](factory::ImportersFactory).
+/// Factory creates importers from configuration when the library is loaded
+/// and they are dropped before the library is unloaded.
+///
 /// This macro must be used exactly once in a library crate.
 /// The library must be compiled as a dynamic library to be loaded by the treasury.
+/// When compiled for `wasm32-wasip1` it also defines exports described in [`wasm`] module.
 #[macro_export]
 macro_rules! make_treasury_importers_library {
-    ($($importer:expr);* $(;)?) => {
+    // Exports shared by both forms.
+    (@common) => {
         #[no_mangle]
         pub static TREASURY_DYLIB_MAGIC: u32 = $crate::MAGIC;
 
         #[no_mangle]
-        pub unsafe extern "C" fn treasury_importer_ffi_version_minor() -> u32 {
-            $crate::version()
+        pub unsafe extern "C" fn treasury_importer_ffi_revisions(min: *mut u32, max: *mut u32) {
+            // Revision 3 is exported under its own symbols for stores that predate revisions.
+            *min = $crate::FFI_REVISION;
+            *max = $crate::FFI_REVISION;
+        }
+
+        #[no_mangle]
+        pub extern "C" fn treasury_importer_ffi_layout(revision: u32) -> u64 {
+            $crate::ffi_layout(revision)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_set_logger(logger: *const $crate::LoggerOpaque, log: $crate::LogFn, max_level: u32) {
+            $crate::logging::set_logger_ffi(logger, log, max_level)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub extern "C" fn treasury_wasm_alloc(len: u32) -> *mut u8 {
+            $crate::wasm::alloc(len)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_wasm_dealloc(ptr: *mut u8, len: u32) {
+            $crate::wasm::dealloc(ptr, len)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub extern "C" fn treasury_wasm_init_log(max_level: u32) {
+            $crate::wasm::init_log(max_level)
+        }
+    };
+
+    (factory = $factory:expr $(;)?) => {
+        // Stores that predate factories cannot create importers, so revision 3 is not exported.
+        $crate::make_treasury_importers_library!(@common);
+
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_create_importers(
+            config_ptr: *const u8,
+            config_len: u32,
+            error_ptr: *mut u8,
+            error_len: *mut u32,
+        ) -> *mut $crate::factory::ImportersOpaque {
+            $crate::factory::create_importers_ffi($factory, config_ptr, config_len, error_ptr, error_len)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_export_created_importers(
+            importers: *const $crate::factory::ImportersOpaque,
+            buffer: *mut $crate::ImporterFFI,
+            cap: u32,
+        ) -> u32 {
+            $crate::factory::export_created_importers_ffi(importers, buffer, cap)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_drop_importers(importers: *mut $crate::factory::ImportersOpaque) {
+            $crate::factory::drop_importers_ffi(importers)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_wasm_create_importers(
+            config_ptr: *const u8,
+            config_len: u32,
+            error_ptr: *mut u8,
+            error_len: *mut u32,
+        ) -> i32 {
+            $crate::wasm::create_importers($factory, config_ptr, config_len, error_ptr, error_len)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_wasm_importers(buffer: *mut u8, cap: u32) -> u32 {
+            $crate::wasm::export_created_importers(buffer, cap)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_wasm_import(
+            index: u32,
+            source_ptr: *const u8,
+            source_len: u32,
+            output_ptr: *const u8,
+            output_len: u32,
+            options_ptr: *const u8,
+            options_len: u32,
+            result_ptr: *mut u8,
+            result_len: *mut u32,
+        ) -> i32 {
+            $crate::wasm::import_created(index, source_ptr, source_len, output_ptr, output_len, options_ptr, options_len, result_ptr, result_len)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_wasm_probe(index: u32, head_ptr: *const u8, head_len: u32) -> i32 {
+            $crate::wasm::probe_created(index, head_ptr, head_len)
+        }
+    };
+
+    ($($importer:expr);* $(;)?) => {
+        $crate::make_treasury_importers_library!(@common);
+
+        // Exports of revision 3 for stores that do not know about revisions.
+        #[no_mangle]
+        pub extern "C" fn treasury_importer_ffi_version_minor() -> u32 {
+            $crate::legacy::REVISION
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_export_importers(buffer: *mut $crate::legacy::ImporterFFI, mut cap: u32) -> u32 {
+            $crate::catch_panic(|| {
+                let mut len = 0;
+                $(
+                    if let Some(importer) = $crate::legacy::ImporterFFI::new($importer) {
+                        if cap > 0 {
+                            core::ptr::write(buffer.add(len as usize), importer);
+                            cap -= 1;
+                        }
+                        len += 1;
+                    }
+                )*
+                len
+            })
+            .unwrap_or(0)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_export_importers_rev5(buffer: *mut $crate::ImporterFFI, mut cap: u32) -> u32 {
+            $crate::catch_panic(|| {
+                let mut len = 0;
+                $(
+                    if cap > 0 {
+                        core::ptr::write(buffer.add(len as usize), $crate::ImporterFFI::new($importer));
+                        cap -= 1;
+                    }
+                    len += 1;
+                )*
+                len
+            })
+            .unwrap_or(u32::MAX)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn treasury_wasm_importers(buffer: *mut u8, cap: u32) -> u32 {
+            $crate::wasm::export_importers(&[$($importer as &dyn $crate::Importer),*], buffer, cap)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        #[allow(unused_assignments)]
+        pub unsafe extern "C" fn treasury_wasm_import(
+            index: u32,
+            source_ptr: *const u8,
+            source_len: u32,
+            output_ptr: *const u8,
+            output_len: u32,
+            options_ptr: *const u8,
+            options_len: u32,
+            result_ptr: *mut u8,
+            result_len: *mut u32,
+        ) -> i32 {
+            let mut i = 0;
+            $(
+                if i == index {
+                    return $crate::wasm::import($importer, source_ptr, source_len, output_ptr, output_len, options_ptr, options_len, result_ptr, result_len);
+                }
+                i += 1;
+            )*
+            $crate::wasm::NOT_FOUND
         }
 
+        #[cfg(target_arch = "wasm32")]
         #[no_mangle]
-        pub unsafe extern "C" fn treasury_export_importers(buffer: *mut $crate::ImporterFFI, mut cap: u32) -> u32 {
-            let mut len = 0;
+        #[allow(unused_assignments)]
+        pub unsafe extern "C" fn treasury_wasm_probe(index: u32, head_ptr: *const u8, head_len: u32) -> i32 {
+            let mut i = 0;
             $(
-                if cap > 0 {
-                    core::ptr::write(buffer.add(len as usize), $crate::ImporterFFI::new($importer));
-                    cap -= 1;
+                if i == index {
+                    return $crate::wasm::probe($importer, head_ptr, head_len);
                 }
-                len += 1;
+                i += 1;
             )*
-            len
+            $crate::wasm::NOT_FOUND
         }
     };
 }
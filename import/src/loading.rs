@@ -1,13 +1,16 @@
 use std::{
     error::Error,
     fmt::{self, Display},
-    mem::{size_of, MaybeUninit},
+    io::{Read, Write},
+    mem::{ManuallyDrop, MaybeUninit},
     path::Path,
-    sync::Arc,
+    sync::{Arc, Mutex, Weak},
 };
 
+use tracing::level_filters::LevelFilter;
+
 #[cfg(unix)]
-use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+use std::os::unix::ffi::OsStrExt;
 
 #[cfg(target_os = "wasi")]
 use std::{ffi::OsStr, os::wasi::ffi::OsStrExt};
@@ -16,35 +19,191 @@ use std::{ffi::OsStr, os::wasi::ffi::OsStrExt};
 use std::os::windows::ffi::OsStrExt;
 
 use crate::{
+    factory::{
+        CreateImportersFn, DropImportersFn, ExportCreatedImportersFn, ImportersOpaque,
+        CREATE_IMPORTERS_FN_NAME, DROP_IMPORTERS_FN_NAME, EXPORT_CREATED_IMPORTERS_FN_NAME,
+    },
     ffi::{
-        DependenciesFFI, DynDependencies, DynSource, ImporterFFI, ImporterImportFn, ImporterOpaque,
-        SourcesFFI, ANY_BUF_LEN_LIMIT, BUFFER_IS_TOO_SMALL, MAX_EXTENSION_COUNT, MAX_FFI_NAME_LEN,
-        MAX_FORMATS_COUNT, OTHER_ERROR, REQUIRE_DEPENDENCIES, REQUIRE_SOURCES, SUCCESS,
+        catch_panic, decode_import_payload, decode_import_result, decode_importer_desc, ffi_layout,
+        CancellationFFI, Decoder, DependenciesFFI, DynCancellation, DynDependencies, DynProgress,
+        DynReader, DynSource, DynSourceStreams, DynWriter, ImporterDesc, ImporterFFI,
+        ImporterImportFn, ImporterImportStreamFn, ImporterOpaque, ImporterProbeFn, LoggerOpaque,
+        ProgressFFI, ReaderFFI, SetLoggerFn, SourceStreamsFFI, SourcesFFI, WriterFFI,
+        ANY_BUF_LEN_LIMIT, BUFFER_IS_TOO_SMALL, EXPORT_IMPORTERS_FN_NAME, FFI_REVISION,
+        FFI_REVISION_MIN, LAYOUT_FN_NAME, MAGIC_NAME, PANICKED, REVISIONS_FN_NAME,
+        SET_LOGGER_FN_NAME, SUCCESS,
     },
     importer::Importer,
-    version, Dependencies, Dependency, ImportError, Sources, MAGIC,
+    legacy,
+    logging::{encode_level_filter, LogSink, Logger},
+    Cancellation, Dependencies, Diagnostics, ImportError, Progress, SourceStreams, Sources, MAGIC,
 };
 
-const RESULT_BUF_LEN_START: usize = 1024;
+/// Importer is not called again when result does not fit,
+/// so buffer starts with enough space for diagnostics.
+const RESULT_BUF_LEN_START: usize = ANY_BUF_LEN_LIMIT;
+
+const DESC_BUF_LEN_START: usize = 1024;
+
+/// Longer error messages of importers factory are truncated.
+const FACTORY_ERROR_BUF_LEN: usize = 4096;
 
 type MagicType = u32;
-const MAGIC_NAME: &'static str = "TREASURY_DYLIB_MAGIC";
 
+type RevisionsFnType = unsafe extern "C" fn(min: *mut u32, max: *mut u32);
+
+type LayoutFnType = unsafe extern "C" fn(revision: u32) -> u64;
+
+/// Libraries that predate revisions export only this revision.
 type VersionFnType = unsafe extern "C" fn() -> u32;
-const VERSION_FN_NAME: &'static str = "treasury_importer_ffi_version_minor";
 
+/// Returns `u32::MAX` if the library panics.
 type ExportImportersFnType = unsafe extern "C" fn(buffer: *mut ImporterFFI, count: u32) -> u32;
-const EXPORT_IMPORTERS_FN_NAME: &'static str = "treasury_export_importers";
+
+type LegacyExportImportersFnType =
+    unsafe extern "C" fn(buffer: *mut legacy::ImporterFFI, count: u32) -> u32;
+
+/// Shared sinks of loaded libraries, keyed by address of their magic value.
+///
+/// Libraries are loaded and unloaded under this lock,
+/// so library found here stays loaded until its shared sink is dropped.
+static SHARED_SINKS: Mutex<Vec<(usize, Weak<SharedSink>)>> = Mutex::new(Vec::new());
+
+/// Sinks of all loads of the same library.
+///
+/// Library keeps the first logger it is given while it is loaded,
+/// so its loads share one and records go to the sink of the latest load still alive.
+#[derive(Default)]
+struct SharedSink {
+    sinks: Mutex<Vec<Arc<dyn LogSink>>>,
+}
+
+impl SharedSink {
+    fn add(&self, sink: Arc<dyn LogSink>) {
+        self.sinks.lock().unwrap().push(sink);
+    }
+
+    fn remove(&self, sink: &Arc<dyn LogSink>) {
+        let mut sinks = self.sinks.lock().unwrap();
+        if let Some(idx) = sinks.iter().rposition(|s| Arc::ptr_eq(s, sink)) {
+            sinks.remove(idx);
+        }
+    }
+}
+
+impl LogSink for SharedSink {
+    fn log(&self, record: &[u8]) {
+        let sink = self.sinks.lock().unwrap().last().cloned();
+        if let Some(sink) = sink {
+            sink.log(record);
+        }
+    }
+}
+
+/// Logs record passed through the FFI with the [`SharedSink`] `logger` points to.
+unsafe extern "C" fn log_ffi(logger: *const LoggerOpaque, record_ptr: *const u8, record_len: u32) {
+    let sink = &*(logger as *const SharedSink);
+    let record = std::slice::from_raw_parts(record_ptr, record_len as usize);
+    let _ = catch_panic(|| sink.log(record));
+}
+
+/// Loaded library along with the sink it forwards log records to.
+/// Library is unloaded before the sink is dropped.
+struct Library {
+    /// Importers created by the library factory.
+    /// Dropped before the library is unloaded.
+    created: Option<(*mut ImportersOpaque, DropImportersFn)>,
+    library: ManuallyDrop<libloading::Library>,
+    sink: Arc<dyn LogSink>,
+    /// Passed to the library, shared with other loads of the same library.
+    shared_sink: ManuallyDrop<Arc<SharedSink>>,
+}
+
+/// Created importers are `Send` and `Sync` as required by `Importer`.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        if let Some((importers, drop_importers)) = self.created.take() {
+            unsafe { drop_importers(importers) }
+        }
+
+        self.shared_sink.remove(&self.sink);
+
+        let _shared_sinks = SHARED_SINKS.lock().unwrap();
+        unsafe {
+            ManuallyDrop::drop(&mut self.library);
+            ManuallyDrop::drop(&mut self.shared_sink);
+        }
+    }
+}
+
+impl Library {
+    /// Loads library and finds sink shared with its other loads.
+    unsafe fn load(lib_path: &Path, sink: Arc<dyn LogSink>) -> Result<Self, LoadingError> {
+        let mut shared_sinks = SHARED_SINKS.lock().unwrap();
+
+        let lib = libloading::Library::new(lib_path).map_err(LoadingError::LibLoading)?;
+
+        // First check the magic value. It must be both present and equal the constant.
+        let magic = *lib
+            .get::<*const MagicType>(MAGIC_NAME.as_bytes())
+            .map_err(|_| LoadingError::MagicSymbolNotFound)?;
+
+        if *magic != MAGIC {
+            return Err(LoadingError::MagicValueMismatch);
+        }
+
+        // Magic value has the same address in all loads of the library.
+        let key = magic as usize;
+        shared_sinks.retain(|(_, shared)| shared.strong_count() > 0);
+        let shared_sink = match shared_sinks
+            .iter()
+            .find(|(k, _)| *k == key)
+            .and_then(|(_, shared)| shared.upgrade())
+        {
+            Some(shared_sink) => shared_sink,
+            None => {
+                let shared_sink = Arc::new(SharedSink::default());
+                shared_sinks.push((key, Arc::downgrade(&shared_sink)));
+                shared_sink
+            }
+        };
+        shared_sink.add(sink.clone());
+
+        Ok(Library {
+            created: None,
+            library: ManuallyDrop::new(lib),
+            sink,
+            shared_sink: ManuallyDrop::new(shared_sink),
+        })
+    }
+}
+
+/// Import function of the negotiated revision.
+#[derive(Clone, Copy)]
+enum ImportFn {
+    Current(ImporterImportFn),
+    Legacy(legacy::ImporterImportFn),
+}
 
 pub struct DylibImporter {
     _path: Arc<Path>,
-    _library: Arc<libloading::Library>,
+    _library: Arc<Library>,
     importer: *const ImporterOpaque,
-    import: ImporterImportFn,
-    name: [u8; MAX_FFI_NAME_LEN],
-    formats: [Box<str>; MAX_FORMATS_COUNT],
-    target: [u8; MAX_FFI_NAME_LEN],
-    extensions: [Box<str>; MAX_EXTENSION_COUNT],
+    import: ImportFn,
+    import_stream: Option<ImporterImportStreamFn>,
+    name: Box<str>,
+    formats: Vec<Box<str>>,
+    target: Box<str>,
+    extensions: Vec<Box<str>>,
+    magic: Vec<Box<[u8]>>,
+    description: Box<str>,
+    version: Option<Box<str>>,
+    package: Option<Box<str>>,
+    options_schema: Option<Box<str>>,
+    probe: Option<ImporterProbeFn>,
 }
 
 /// Exporting non thread-safe importers breaks the contract of the FFI.
@@ -55,27 +214,134 @@ unsafe impl Send for DylibImporter {}
 unsafe impl Sync for DylibImporter {}
 
 impl DylibImporter {
-    fn new(importer: ImporterFFI, path: Arc<Path>, library: Arc<libloading::Library>) -> Self {
-        DylibImporter {
+    fn new(
+        importer: ImporterFFI,
+        path: Arc<Path>,
+        library: Arc<Library>,
+    ) -> Result<Self, LoadingError> {
+        let import_stream = importer.import_stream;
+        let mut buf = vec![0; DESC_BUF_LEN_START];
+
+        loop {
+            let len = unsafe {
+                (importer.describe)(importer.importer, buf.as_mut_ptr(), buf.len() as u32)
+            };
+
+            if len == 0 {
+                return Err(LoadingError::Panicked);
+            }
+
+            if len as usize > buf.len() {
+                buf.resize(len as usize, 0);
+                continue;
+            }
+
+            buf.truncate(len as usize);
+            break;
+        }
+
+        let mut decoder = Decoder { data: &buf };
+        let desc = decode_importer_desc(&mut decoder)
+            .filter(|_| decoder.data.is_empty())
+            .ok_or_else(|| {
+                LoadingError::InvalidDescription("Malformed importer description".to_owned())
+            })?;
+
+        let mut importer = Self::with_desc(
+            importer.importer,
+            ImportFn::Current(importer.import),
+            Some(importer.probe),
+            desc,
+            path,
+            library,
+        )?;
+        importer.import_stream = import_stream;
+        Ok(importer)
+    }
+
+    fn legacy(
+        importer: legacy::ImporterFFI,
+        path: Arc<Path>,
+        library: Arc<Library>,
+    ) -> Result<Self, LoadingError> {
+        let desc = ImporterDesc {
+            name: ffi_str(&importer.name)?,
+            formats: ffi_str_list(&importer.formats)?,
+            extensions: ffi_str_list(&importer.extensions)?,
+            target: ffi_str(&importer.target)?,
+            magic: Vec::new(),
+            description: String::new(),
+            version: None,
+            package: None,
+            options_schema: None,
+        };
+
+        Self::with_desc(
+            importer.importer,
+            ImportFn::Legacy(importer.import),
+            None,
+            desc,
+            path,
+            library,
+        )
+    }
+
+    fn with_desc(
+        importer: *const ImporterOpaque,
+        import: ImportFn,
+        probe: Option<ImporterProbeFn>,
+        desc: ImporterDesc,
+        path: Arc<Path>,
+        library: Arc<Library>,
+    ) -> Result<Self, LoadingError> {
+        desc.validate().map_err(LoadingError::InvalidDescription)?;
+
+        Ok(DylibImporter {
             _path: path,
             _library: library,
-            importer: importer.importer,
-            import: importer.import,
-            name: importer.name,
-            formats: importer
-                .formats
-                .map(|format| unsafe { std::str::from_utf8_unchecked(&format).into() }),
-            target: importer.target,
-            extensions: importer
-                .extensions
-                .map(|extension| unsafe { std::str::from_utf8_unchecked(&extension).into() }),
+            importer,
+            import,
+            import_stream: None,
+            name: desc.name.into(),
+            formats: desc.formats.into_iter().map(Into::into).collect(),
+            target: desc.target.into(),
+            extensions: desc.extensions.into_iter().map(Into::into).collect(),
+            magic: desc.magic.into_iter().map(Into::into).collect(),
+            description: desc.description.into(),
+            version: desc.version.map(Into::into),
+            package: desc.package.map(Into::into),
+            options_schema: desc.options_schema.map(Into::into),
+            probe,
+        })
+    }
+}
+
+/// Returns string stored in zero-padded buffer of revision 3 layout.
+fn ffi_str(buf: &[u8]) -> Result<String, LoadingError> {
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match std::str::from_utf8(&buf[..len]) {
+        Ok(s) => Ok(s.to_owned()),
+        Err(_) => Err(LoadingError::InvalidDescription(
+            "Importer description is not UTF-8".to_owned(),
+        )),
+    }
+}
+
+/// Returns non-empty strings stored in zero-padded buffers of revision 3 layout.
+fn ffi_str_list<const N: usize>(bufs: &[[u8; N]]) -> Result<Vec<String>, LoadingError> {
+    let mut list = Vec::new();
+    for buf in bufs {
+        let s = ffi_str(buf)?;
+        if !s.is_empty() {
+            list.push(s);
         }
     }
+    Ok(list)
 }
 
 impl Importer for DylibImporter {
     fn name(&self) -> &str {
-        unsafe { std::str::from_utf8_unchecked(&self.name) }
+        &self.name
     }
 
     fn formats(&self) -> &[&str] {
@@ -85,7 +351,7 @@ impl Importer for DylibImporter {
     }
 
     fn target(&self) -> &str {
-        unsafe { std::str::from_utf8_unchecked(&self.target) }
+        &self.target
     }
 
     fn extensions(&self) -> &[&str] {
@@ -97,12 +363,48 @@ impl Importer for DylibImporter {
         }
     }
 
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    fn package(&self) -> Option<&str> {
+        self.package.as_deref()
+    }
+
+    fn options_schema(&self) -> Option<&str> {
+        self.options_schema.as_deref()
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        unsafe { std::slice::from_raw_parts(self.magic.as_ptr() as *const &[u8], self.magic.len()) }
+    }
+
+    fn probe(&self, head: &[u8]) -> bool {
+        let Some(probe) = self.probe else {
+            return false;
+        };
+
+        let result = unsafe { probe(self.importer, head.as_ptr(), head.len() as u32) };
+        if result == PANICKED {
+            tracing::error!("Importer '{}' panicked while probing", self.name);
+        }
+        result == SUCCESS
+    }
+
     fn import(
         &self,
         source: &Path,
         output: &Path,
+        options: Option<&str>,
         sources: &mut dyn Sources,
         dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
     ) -> Result<(), ImportError> {
         let os_str = source.as_os_str();
 
@@ -132,21 +434,83 @@ impl Importer for DylibImporter {
         let mut dependencies = DynDependencies::new(dependencies);
         let dependencies = DependenciesFFI::new(&mut dependencies);
 
+        let import = match self.import {
+            ImportFn::Current(import) => import,
+            // Revision 3 importers declare no options schema, so store never sets options for them.
+            ImportFn::Legacy(import) => {
+                let mut result_buf = vec![0; RESULT_BUF_LEN_START];
+                let mut result_len = result_buf.len() as u32;
+
+                let result = loop {
+                    let result = unsafe {
+                        import(
+                            self.importer,
+                            source.as_ptr(),
+                            source.len() as u32,
+                            output.as_ptr(),
+                            output.len() as u32,
+                            sources.opaque,
+                            sources.get,
+                            dependencies.opaque,
+                            dependencies.get,
+                            result_buf.as_mut_ptr(),
+                            &mut result_len,
+                        )
+                    };
+
+                    if result == BUFFER_IS_TOO_SMALL {
+                        if result_len > ANY_BUF_LEN_LIMIT as u32 {
+                            return Err(ImportError::Other {
+                                reason: format!(
+                                    "Result does not fit into limit '{}', '{}' required",
+                                    ANY_BUF_LEN_LIMIT, result_len
+                                ),
+                            });
+                        }
+
+                        result_buf.resize(result_len as usize, 0);
+                        continue;
+                    }
+                    break result;
+                };
+
+                debug_assert!(result_len <= result_buf.len() as u32);
+                return decode_import_payload(result, &result_buf[..result_len as usize]);
+            }
+        };
+
+        let cancellation = DynCancellation::new(cancellation);
+        let cancellation = CancellationFFI::new(&cancellation);
+
+        let mut progress = DynProgress::new(progress);
+        let progress = ProgressFFI::new(&mut progress);
+
+        let (options_ptr, options_len) = match options {
+            None => (std::ptr::null(), 0),
+            Some(options) => (options.as_ptr(), options.len() as u32),
+        };
+
         let mut result_buf = vec![0; RESULT_BUF_LEN_START];
         let mut result_len = result_buf.len() as u32;
 
         let result = loop {
             let result = unsafe {
-                (self.import)(
+                import(
                     self.importer,
                     source.as_ptr(),
                     source.len() as u32,
                     output.as_ptr(),
                     output.len() as u32,
+                    options_ptr,
+                    options_len,
                     sources.opaque,
                     sources.get,
                     dependencies.opaque,
                     dependencies.get,
+                    cancellation.opaque,
+                    cancellation.is_cancelled,
+                    progress.opaque,
+                    progress.report,
                     result_buf.as_mut_ptr(),
                     &mut result_len,
                 )
@@ -163,140 +527,161 @@ impl Importer for DylibImporter {
                 }
 
                 result_buf.resize(result_len as usize, 0);
+                continue;
             }
             break result;
         };
 
-        match result {
-            SUCCESS => Ok(()),
-            REQUIRE_SOURCES => unsafe {
-                let mut u32buf = [0; size_of::<u32>()];
-                std::ptr::copy_nonoverlapping(
-                    result_buf[..size_of::<u32>()].as_ptr(),
-                    u32buf.as_mut_ptr(),
-                    size_of::<u32>(),
-                );
-                let count = u32::from_le_bytes(u32buf);
-
-                let mut offset = size_of::<u32>();
-
-                let mut sources = Vec::new();
-                for _ in 0..count {
-                    std::ptr::copy_nonoverlapping(
-                        result_buf[offset..][..size_of::<u32>()].as_ptr(),
-                        u32buf.as_mut_ptr(),
-                        size_of::<u32>(),
-                    );
-                    offset += size_of::<u32>();
-                    let len = u32::from_le_bytes(u32buf);
-                    let mut source = vec![0; len as usize];
-                    std::ptr::copy_nonoverlapping(
-                        result_buf[offset..][..len as usize].as_ptr(),
-                        source.as_mut_ptr(),
-                        len as usize,
-                    );
-                    offset += len as usize;
-                    match String::from_utf8(source) {
-                            Ok(source) => sources.push(source),
-                            Err(_) => return Err(ImportError::Other {
-                                reason: "`Importer::import` requires sources, but one of the sources is not UTF-8"
-                                    .to_owned(),
-                            }),
-                        }
-                }
+        debug_assert!(result_len <= result_buf.len() as u32);
+        decode_import_result(result, &result_buf[..result_len as usize], diagnostics)
+    }
 
-                Err(ImportError::RequireSources { sources })
-            },
-            REQUIRE_DEPENDENCIES => unsafe {
-                let mut u32buf = [0; size_of::<u32>()];
-                std::ptr::copy_nonoverlapping(
-                    result_buf[..size_of::<u32>()].as_ptr(),
-                    u32buf.as_mut_ptr(),
-                    size_of::<u32>(),
-                );
-                let count = u32::from_le_bytes(u32buf);
-                let mut offset = size_of::<u32>();
-
-                let mut dependencies = Vec::new();
-                for _ in 0..count {
-                    let mut decode_string = || {
-                        std::ptr::copy_nonoverlapping(
-                            result_buf[offset..][..size_of::<u32>()].as_ptr(),
-                            u32buf.as_mut_ptr(),
-                            size_of::<u32>(),
-                        );
-                        offset += size_of::<u32>();
-                        let len = u32::from_le_bytes(u32buf);
-
-                        let mut string = vec![0; len as usize];
-                        std::ptr::copy_nonoverlapping(
-                            result_buf[offset..][..len as usize].as_ptr(),
-                            string.as_mut_ptr(),
-                            len as usize,
-                        );
-                        offset += len as usize;
-
-                        match String::from_utf8(string) {
-                                Ok(string) => Ok(string),
-                                Err(_) => return Err(ImportError::Other { reason: "`Importer::import` requires dependencies, but one of the strings is not UTF-8".to_owned() }),
-                            }
-                    };
+    fn supports_streams(&self) -> bool {
+        self.import_stream.is_some()
+    }
+
+    fn import_stream(
+        &self,
+        source: &mut dyn Read,
+        output: &mut dyn Write,
+        options: Option<&str>,
+        sources: &mut dyn SourceStreams,
+        dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        let Some(import_stream) = self.import_stream else {
+            return Err(ImportError::Other {
+                reason: format!("Importer '{}' does not support streams", self.name),
+            });
+        };
 
-                    let source = decode_string()?;
-                    let target = decode_string()?;
+        let mut source = DynReader::new(source);
+        let source = ReaderFFI::new(&mut source);
 
-                    dependencies.push(Dependency { source, target });
-                }
+        let mut output = DynWriter::new(output);
+        let output = WriterFFI::new(&mut output);
 
-                Err(ImportError::RequireDependencies { dependencies })
-            },
-            OTHER_ERROR => {
-                debug_assert!(result_len <= result_buf.len() as u32);
+        let mut sources = DynSourceStreams::new(sources);
+        let sources = SourceStreamsFFI::new(&mut sources);
 
-                let error = &result_buf[..result_len as usize];
-                let error_lossy = String::from_utf8_lossy(error);
+        let mut dependencies = DynDependencies::new(dependencies);
+        let dependencies = DependenciesFFI::new(&mut dependencies);
+
+        let cancellation = DynCancellation::new(cancellation);
+        let cancellation = CancellationFFI::new(&cancellation);
+
+        let mut progress = DynProgress::new(progress);
+        let progress = ProgressFFI::new(&mut progress);
+
+        let (options_ptr, options_len) = match options {
+            None => (std::ptr::null(), 0),
+            Some(options) => (options.as_ptr(), options.len() as u32),
+        };
+
+        let mut result_buf = vec![0; RESULT_BUF_LEN_START];
+        let mut result_len = result_buf.len() as u32;
+        let mut source_opaque = source.opaque;
+
+        let result = loop {
+            let result = unsafe {
+                import_stream(
+                    self.importer,
+                    source_opaque,
+                    source.read,
+                    output.opaque,
+                    output.write,
+                    options_ptr,
+                    options_len,
+                    sources.opaque,
+                    sources.open,
+                    sources.close,
+                    dependencies.opaque,
+                    dependencies.get,
+                    cancellation.opaque,
+                    cancellation.is_cancelled,
+                    progress.opaque,
+                    progress.report,
+                    result_buf.as_mut_ptr(),
+                    &mut result_len,
+                )
+            };
+
+            if result == BUFFER_IS_TOO_SMALL {
+                if result_len > ANY_BUF_LEN_LIMIT as u32 {
+                    return Err(ImportError::Other {
+                        reason: format!(
+                            "Result does not fit into limit '{}', '{}' required",
+                            ANY_BUF_LEN_LIMIT, result_len
+                        ),
+                    });
+                }
 
-                Err(ImportError::Other {
-                    reason: error_lossy.into_owned(),
-                })
+                // Streams are consumed, importer called with null source writes the result it kept.
+                source_opaque = std::ptr::null_mut();
+                result_buf.resize(result_len as usize, 0);
+                continue;
             }
-            _ => Err(ImportError::Other {
-                reason: format!(
-                    "Unexpected return code from `Importer::import` FFI: {}",
-                    result
-                ),
-            }),
-        }
+            break result;
+        };
+
+        debug_assert!(result_len <= result_buf.len() as u32);
+        decode_import_result(result, &result_buf[..result_len as usize], diagnostics)
     }
 }
 
 #[derive(Debug)]
 pub enum LoadingError {
     LibLoading(libloading::Error),
+    Io(std::io::Error),
     FailedToOpenLibrary,
     MagicSymbolNotFound,
     MagicValueMismatch,
     VersionSymbolNotFound,
-    VersionMismatch,
+    UnsupportedRevisions { min: u32, max: u32 },
+    LayoutMismatch { revision: u32 },
     ExportImportersSymbolNotFound,
+    CreateImporters(String),
+    InvalidDescription(String),
+    Panicked,
+    Wasm(String),
+    Host(String),
 }
 
 impl Display for LoadingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             LoadingError::LibLoading(err) => write!(f, "libloading error: {}", err),
+            LoadingError::Io(err) => write!(f, "Failed to read library file: {}", err),
             LoadingError::FailedToOpenLibrary => write!(f, "Failed to open library"),
             LoadingError::MagicSymbolNotFound => {
                 write!(f, "'TREASURY_DYLIB_MAGIC' symbol not found")
             }
             LoadingError::MagicValueMismatch => write!(f, "'TREASURY_DYLIB_MAGIC' value mismatch"),
             LoadingError::VersionSymbolNotFound => {
-                write!(f, "'treasury_importer_ffi_version_minor' symbol not found")
+                write!(f, "'treasury_importer_ffi_revisions' symbol not found")
+            }
+            LoadingError::UnsupportedRevisions { min, max } => write!(
+                f,
+                "Library supports ABI revisions {}..={}, loader supports {} and {}",
+                min, max, FFI_REVISION_MIN, FFI_REVISION
+            ),
+            LoadingError::LayoutMismatch { revision } => {
+                write!(f, "Layout of ABI revision {} mismatch", revision)
             }
-            LoadingError::VersionMismatch => write!(f, "Version mismatch"),
             LoadingError::ExportImportersSymbolNotFound => {
-                write!(f, "'treasury_export_importers' symbol not found")
+                write!(f, "Importers export symbol not found")
+            }
+            LoadingError::CreateImporters(reason) => {
+                write!(f, "Library failed to create importers: {}", reason)
+            }
+            LoadingError::InvalidDescription(reason) => {
+                write!(f, "Invalid importer description: {}", reason)
             }
+            LoadingError::Panicked => write!(f, "Importers library panicked"),
+            LoadingError::Wasm(err) => write!(f, "WebAssembly error: {}", err),
+            LoadingError::Host(err) => write!(f, "Importer host error: {}", err),
         }
     }
 }
@@ -305,53 +690,215 @@ impl Error for LoadingError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             LoadingError::LibLoading(err) => Some(err),
+            LoadingError::Io(err) => Some(err),
             _ => None,
         }
     }
 }
 
 /// Load importers from dynamic library at specified path.
+///
+/// # Safety
+///
+/// Loading a dynamic library runs arbitrary code and trusts exported symbols.
+/// Library at `lib_path` must be produced with `make_treasury_importers_library!` macro.
 pub unsafe fn load_importers(
     lib_path: &Path,
 ) -> Result<impl Iterator<Item = DylibImporter>, LoadingError> {
-    tracing::info!("Loading importers from '{}'", lib_path.display());
+    load_importers_with_config(lib_path, None)
+}
 
-    let lib = libloading::Library::new(lib_path).map_err(|_| LoadingError::FailedToOpenLibrary)?;
+/// Load importers from dynamic library at specified path.
+/// Library that defines importers factory creates them from `config`, JSON document.
+/// Created importers are dropped when all returned importers are dropped.
+///
+/// # Safety
+///
+/// Same as [`load_importers`].
+pub unsafe fn load_importers_with_config(
+    lib_path: &Path,
+    config: Option<&str>,
+) -> Result<impl Iterator<Item = DylibImporter>, LoadingError> {
+    load_importers_with_sink(
+        lib_path,
+        config,
+        Arc::new(Logger::new()),
+        LevelFilter::current(),
+    )
+}
 
-    // First check the magic value. It must be both present and equal the constant.
-    let magic = lib
-        .get::<*const MagicType>(MAGIC_NAME.as_bytes())
-        .map_err(|_| LoadingError::MagicSymbolNotFound)?;
+/// Load importers from dynamic library at specified path, see [`load_importers_with_config`].
+/// Events and spans of the library up to `max_level` are passed to `sink`.
+///
+/// # Safety
+///
+/// Same as [`load_importers`].
+pub unsafe fn load_importers_with_sink(
+    lib_path: &Path,
+    config: Option<&str>,
+    sink: Arc<dyn LogSink>,
+    max_level: LevelFilter,
+) -> Result<impl Iterator<Item = DylibImporter>, LoadingError> {
+    tracing::info!("Loading importers from '{}'", lib_path.display());
 
-    if **magic != MAGIC {
-        return Err(LoadingError::MagicValueMismatch);
+    let mut lib = Library::load(lib_path, sink)?;
+
+    // Then pick the newest revision supported by both sides.
+    // Loader has no adapter for revision 4, its import function takes no options.
+    let (lib_min, lib_max) = match lib
+        .library
+        .get::<RevisionsFnType>(REVISIONS_FN_NAME.as_bytes())
+    {
+        Ok(revisions) => {
+            let (mut min, mut max) = (0, 0);
+            revisions(&mut min, &mut max);
+            (min, max)
+        }
+        Err(_) => {
+            let version = lib
+                .library
+                .get::<VersionFnType>(legacy::VERSION_FN_NAME.as_bytes())
+                .map_err(|_| LoadingError::VersionSymbolNotFound)?;
+            let version = version();
+            (version, version)
+        }
+    };
+
+    let revision = [FFI_REVISION, legacy::REVISION]
+        .into_iter()
+        .find(|revision| (lib_min..=lib_max).contains(revision))
+        .ok_or(LoadingError::UnsupportedRevisions {
+            min: lib_min,
+            max: lib_max,
+        })?;
+
+    tracing::debug!("Using ABI revision {}", revision);
+
+    match lib.library.get::<LayoutFnType>(LAYOUT_FN_NAME.as_bytes()) {
+        Ok(layout) => {
+            if layout(revision) != ffi_layout(revision) {
+                return Err(LoadingError::LayoutMismatch { revision });
+            }
+        }
+        Err(_) => tracing::debug!("'{}' symbol not found", LAYOUT_FN_NAME),
+    }
+
+    // Factory may log while creating importers, so logger is set first.
+    // Library that is already loaded keeps the shared sink it was given.
+    match lib
+        .library
+        .get::<SetLoggerFn>(SET_LOGGER_FN_NAME.as_bytes())
+    {
+        Ok(set_logger) => set_logger(
+            &**lib.shared_sink as *const SharedSink as *const LoggerOpaque,
+            log_ffi,
+            encode_level_filter(max_level),
+        ),
+        Err(_) => tracing::debug!("'{}' symbol not found", SET_LOGGER_FN_NAME),
     }
 
-    // First check the magic value. It must be both present and equal the constant.
-    let lib_ffi_version = lib
-        .get::<VersionFnType>(VERSION_FN_NAME.as_bytes())
-        .map_err(|_| LoadingError::VersionSymbolNotFound)?;
+    let importers = if revision == legacy::REVISION {
+        let export_importers = *lib
+            .library
+            .get::<LegacyExportImportersFnType>(legacy::EXPORT_IMPORTERS_FN_NAME.as_bytes())
+            .map_err(|_| LoadingError::ExportImportersSymbolNotFound)?;
+
+        // Legacy export reports panic as no importers.
+        Exported::Legacy(export(|buffer, cap| Some(export_importers(buffer, cap)))?)
+    } else if let Ok(create_importers) = lib
+        .library
+        .get::<CreateImportersFn>(CREATE_IMPORTERS_FN_NAME.as_bytes())
+    {
+        let create_importers = *create_importers;
+
+        let export_importers = *lib
+            .library
+            .get::<ExportCreatedImportersFn>(EXPORT_CREATED_IMPORTERS_FN_NAME.as_bytes())
+            .map_err(|_| LoadingError::ExportImportersSymbolNotFound)?;
+
+        let drop_importers = *lib
+            .library
+            .get::<DropImportersFn>(DROP_IMPORTERS_FN_NAME.as_bytes())
+            .map_err(|_| LoadingError::ExportImportersSymbolNotFound)?;
+
+        let (config_ptr, config_len) = match config {
+            None => (std::ptr::null(), 0),
+            Some(config) => (config.as_ptr(), config.len() as u32),
+        };
 
-    let lib_ffi_version = lib_ffi_version();
+        let mut error = vec![0; FACTORY_ERROR_BUF_LEN];
+        let mut error_len = error.len() as u32;
 
-    let ffi_version = version();
+        let importers =
+            create_importers(config_ptr, config_len, error.as_mut_ptr(), &mut error_len);
 
-    if lib_ffi_version != ffi_version {
-        return Err(LoadingError::VersionMismatch);
-    }
+        if importers.is_null() {
+            error.truncate(error_len as usize);
+            return Err(LoadingError::CreateImporters(
+                String::from_utf8_lossy(&error).into_owned(),
+            ));
+        }
+
+        lib.created = Some((importers, drop_importers));
+
+        Exported::Current(export(|buffer, cap| {
+            match export_importers(importers, buffer, cap) {
+                u32::MAX => None,
+                count => Some(count),
+            }
+        })?)
+    } else {
+        let export_importers = *lib
+            .library
+            .get::<ExportImportersFnType>(EXPORT_IMPORTERS_FN_NAME.as_bytes())
+            .map_err(|_| LoadingError::ExportImportersSymbolNotFound)?;
+
+        if config.is_some() {
+            tracing::warn!(
+                "Library '{}' does not create importers from configuration, configuration is ignored",
+                lib_path.display()
+            );
+        }
 
-    let export_importers = lib
-        .get::<ExportImportersFnType>(EXPORT_IMPORTERS_FN_NAME.as_bytes())
-        .map_err(|_| LoadingError::ExportImportersSymbolNotFound)?;
+        Exported::Current(export(|buffer, cap| match export_importers(buffer, cap) {
+            u32::MAX => None,
+            count => Some(count),
+        })?)
+    };
 
+    let lib = Arc::new(lib);
+    let lib_path: Arc<Path> = Arc::from(lib_path);
+
+    let importers = match importers {
+        Exported::Current(importers) => importers
+            .into_iter()
+            .map(|ffi| DylibImporter::new(ffi, lib_path.clone(), lib.clone()))
+            .collect::<Result<Vec<_>, _>>()?,
+        Exported::Legacy(importers) => importers
+            .into_iter()
+            .map(|ffi| DylibImporter::legacy(ffi, lib_path.clone(), lib.clone()))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    Ok(importers.into_iter())
+}
+
+enum Exported {
+    Current(Vec<ImporterFFI>),
+    Legacy(Vec<legacy::ImporterFFI>),
+}
+
+/// Calls export function with growing buffer until all importers fit.
+/// `export_importers` returns `None` if the library panics.
+unsafe fn export<T>(
+    mut export_importers: impl FnMut(*mut T, u32) -> Option<u32>,
+) -> Result<Vec<T>, LoadingError> {
     let mut importers = Vec::new();
     importers.resize_with(64, MaybeUninit::uninit);
 
     loop {
-        let count = export_importers(
-            importers.as_mut_ptr() as *mut ImporterFFI,
-            importers.len() as u32,
-        );
+        let count = export_importers(importers.as_mut_ptr() as *mut T, importers.len() as u32)
+            .ok_or(LoadingError::Panicked)?;
 
         if count > importers.len() as u32 {
             importers.resize_with(count as usize, MaybeUninit::uninit);
@@ -362,11 +909,8 @@ pub unsafe fn load_importers(
         break;
     }
 
-    let lib = Arc::new(lib);
-    let lib_path: Arc<Path> = Arc::from(lib_path);
-
-    Ok(importers.into_iter().map(move |importer| {
-        let ffi: ImporterFFI = importer.assume_init();
-        DylibImporter::new(ffi, lib_path.clone(), lib.clone())
-    }))
+    Ok(importers
+        .into_iter()
+        .map(|importer| importer.assume_init())
+        .collect())
 }
@@ -0,0 +1,433 @@
+//! Forwarding of `tracing` events and spans from importers libraries to the store.
+//!
+//! Importers library links its own copy of `tracing`, so its events never reach subscriber of the store.
+//! [`make_treasury_importers_library!`] installs subscriber in the library
+//! that encodes events and spans into [`LogRecord`]s and passes them to the store.
+//! Store emits them with its own `tracing`, inside the span of the import.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span, Event, Level, Metadata, Subscriber,
+};
+
+use crate::ffi::{catch_panic, encode_bytes, Decoder, LogFn, LoggerOpaque};
+
+const EVENT: u8 = 0;
+const NEW_SPAN: u8 = 1;
+const ENTER: u8 = 2;
+const EXIT: u8 = 3;
+const CLOSE: u8 = 4;
+
+/// Event or span change forwarded from importers library.
+#[derive(Clone, Debug)]
+pub enum LogRecord {
+    Event {
+        level: Level,
+        target: String,
+        message: String,
+    },
+    NewSpan {
+        id: u64,
+        level: Level,
+        target: String,
+        name: String,
+        fields: String,
+    },
+    Enter {
+        id: u64,
+    },
+    Exit {
+        id: u64,
+    },
+    Close {
+        id: u64,
+    },
+}
+
+fn encode_level(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 1,
+        Level::WARN => 2,
+        Level::INFO => 3,
+        Level::DEBUG => 4,
+        Level::TRACE => 5,
+    }
+}
+
+fn decode_level(level: u8) -> Option<Level> {
+    match level {
+        1 => Some(Level::ERROR),
+        2 => Some(Level::WARN),
+        3 => Some(Level::INFO),
+        4 => Some(Level::DEBUG),
+        5 => Some(Level::TRACE),
+        _ => None,
+    }
+}
+
+/// Encodes level filter passed to the library. Zero turns forwarding off.
+pub fn encode_level_filter(filter: LevelFilter) -> u32 {
+    filter
+        .into_level()
+        .map_or(0, |level| encode_level(level).into())
+}
+
+pub fn decode_level_filter(filter: u32) -> LevelFilter {
+    u8::try_from(filter)
+        .ok()
+        .and_then(decode_level)
+        .map_or(LevelFilter::OFF, LevelFilter::from_level)
+}
+
+impl LogRecord {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            LogRecord::Event {
+                level,
+                target,
+                message,
+            } => {
+                buf.push(EVENT);
+                buf.push(encode_level(*level));
+                encode_bytes(&mut buf, target.as_bytes());
+                encode_bytes(&mut buf, message.as_bytes());
+            }
+            LogRecord::NewSpan {
+                id,
+                level,
+                target,
+                name,
+                fields,
+            } => {
+                buf.push(NEW_SPAN);
+                buf.extend_from_slice(&id.to_le_bytes());
+                buf.push(encode_level(*level));
+                encode_bytes(&mut buf, target.as_bytes());
+                encode_bytes(&mut buf, name.as_bytes());
+                encode_bytes(&mut buf, fields.as_bytes());
+            }
+            LogRecord::Enter { id } => {
+                buf.push(ENTER);
+                buf.extend_from_slice(&id.to_le_bytes());
+            }
+            LogRecord::Exit { id } => {
+                buf.push(EXIT);
+                buf.extend_from_slice(&id.to_le_bytes());
+            }
+            LogRecord::Close { id } => {
+                buf.push(CLOSE);
+                buf.extend_from_slice(&id.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    /// Returns `None` if record is malformed.
+    pub fn decode(record: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder { data: record };
+
+        let record = match decoder.u8()? {
+            EVENT => LogRecord::Event {
+                level: decode_level(decoder.u8()?)?,
+                target: decoder.string()?,
+                message: decoder.string()?,
+            },
+            NEW_SPAN => LogRecord::NewSpan {
+                id: decoder.u64()?,
+                level: decode_level(decoder.u8()?)?,
+                target: decoder.string()?,
+                name: decoder.string()?,
+                fields: decoder.string()?,
+            },
+            ENTER => LogRecord::Enter { id: decoder.u64()? },
+            EXIT => LogRecord::Exit { id: decoder.u64()? },
+            CLOSE => LogRecord::Close { id: decoder.u64()? },
+            _ => return None,
+        };
+
+        if !decoder.data.is_empty() {
+            return None;
+        }
+
+        Some(record)
+    }
+}
+
+/// Receiver of records encoded with [`LogRecord::encode`].
+pub trait LogSink: Send + Sync {
+    fn log(&self, record: &[u8]);
+}
+
+/// Emits forwarded records with `tracing` of the store.
+///
+/// Spans of the library become children of the span current when they are created.
+/// Events are emitted with `importers` target, original target is recorded in `target` field.
+#[derive(Default)]
+pub struct Logger {
+    spans: Mutex<HashMap<u64, tracing::Span>>,
+}
+
+impl Logger {
+    pub fn new() -> Self {
+        Logger::default()
+    }
+
+    fn with_span(&self, id: u64, f: impl FnOnce(&span::Id, &tracing::Dispatch)) {
+        let spans = self.spans.lock().unwrap();
+        if let Some(span) = spans.get(&id) {
+            span.with_subscriber(|(id, dispatch)| f(id, dispatch));
+        }
+    }
+}
+
+/// Expands `tracing` macro with level known only at runtime.
+macro_rules! dyn_level {
+    ($level:expr, $macro:ident!(target: $target:expr, $($args:tt)*)) => {
+        match $level {
+            Level::ERROR => tracing::$macro!(target: $target, Level::ERROR, $($args)*),
+            Level::WARN => tracing::$macro!(target: $target, Level::WARN, $($args)*),
+            Level::INFO => tracing::$macro!(target: $target, Level::INFO, $($args)*),
+            Level::DEBUG => tracing::$macro!(target: $target, Level::DEBUG, $($args)*),
+            Level::TRACE => tracing::$macro!(target: $target, Level::TRACE, $($args)*),
+        }
+    };
+}
+
+impl LogSink for Logger {
+    fn log(&self, record: &[u8]) {
+        let record = match LogRecord::decode(record) {
+            None => {
+                tracing::error!("Malformed log record from importers library");
+                return;
+            }
+            Some(record) => record,
+        };
+
+        match record {
+            LogRecord::Event {
+                level,
+                target,
+                message,
+            } => {
+                dyn_level!(
+                    level,
+                    event!(target: "importers", target = %target, "{}", message)
+                )
+            }
+            LogRecord::NewSpan {
+                id,
+                level,
+                target,
+                name,
+                fields,
+            } => {
+                let span = dyn_level!(
+                    level,
+                    span!(target: "importers", "importer", name = %name, target = %target, fields = %fields)
+                );
+                self.spans.lock().unwrap().insert(id, span);
+            }
+            LogRecord::Enter { id } => self.with_span(id, |id, dispatch| dispatch.enter(id)),
+            LogRecord::Exit { id } => self.with_span(id, |id, dispatch| dispatch.exit(id)),
+            LogRecord::Close { id } => {
+                self.spans.lock().unwrap().remove(&id);
+            }
+        }
+    }
+}
+
+/// Formats fields of events and spans.
+struct FieldsWriter {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldsWriter {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl FieldsWriter {
+    fn new() -> Self {
+        FieldsWriter {
+            message: String::new(),
+            fields: String::new(),
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.fields.is_empty() {
+            if !self.message.is_empty() {
+                self.message.push(' ');
+            }
+            self.message.push_str(&self.fields);
+        }
+        self.message
+    }
+}
+
+/// Subscriber of importers library that passes records to the store.
+struct ForwardSubscriber<F> {
+    log: F,
+    max_level: LevelFilter,
+    next_id: AtomicU64,
+
+    /// Reference counts of open spans.
+    spans: Mutex<HashMap<u64, usize>>,
+}
+
+impl<F> ForwardSubscriber<F>
+where
+    F: Fn(&[u8]) + Send + Sync + 'static,
+{
+    fn send(&self, record: LogRecord) {
+        (self.log)(&record.encode());
+    }
+}
+
+impl<F> Subscriber for ForwardSubscriber<F>
+where
+    F: Fn(&[u8]) + Send + Sync + 'static,
+{
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.max_level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.max_level)
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.spans.lock().unwrap().insert(id, 1);
+
+        let mut fields = FieldsWriter::new();
+        attrs.record(&mut fields);
+
+        let metadata = attrs.metadata();
+        self.send(LogRecord::NewSpan {
+            id,
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            name: metadata.name().to_owned(),
+            fields: fields.finish(),
+        });
+
+        span::Id::from_u64(id)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = FieldsWriter::new();
+        event.record(&mut message);
+
+        let metadata = event.metadata();
+        self.send(LogRecord::Event {
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            message: message.finish(),
+        });
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.send(LogRecord::Enter {
+            id: span.into_u64(),
+        });
+    }
+
+    fn exit(&self, span: &span::Id) {
+        self.send(LogRecord::Exit {
+            id: span.into_u64(),
+        });
+    }
+
+    fn clone_span(&self, span: &span::Id) -> span::Id {
+        if let Some(refs) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            *refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: span::Id) -> bool {
+        let id = span.into_u64();
+        let mut spans = self.spans.lock().unwrap();
+        let closed = match spans.get_mut(&id) {
+            None => false,
+            Some(refs) => {
+                *refs -= 1;
+                *refs == 0
+            }
+        };
+
+        if closed {
+            spans.remove(&id);
+            drop(spans);
+            self.send(LogRecord::Close { id });
+        }
+        closed
+    }
+}
+
+/// Installs global subscriber that passes records to `log`.
+/// Does nothing if global subscriber is already set.
+pub fn install(log: impl Fn(&[u8]) + Send + Sync + 'static, max_level: LevelFilter) {
+    let subscriber = ForwardSubscriber {
+        log,
+        max_level,
+        next_id: AtomicU64::new(1),
+        spans: Mutex::new(HashMap::new()),
+    };
+
+    if tracing::subscriber::set_global_default(subscriber).is_err() {
+        tracing::debug!("Importers library already has global subscriber");
+    }
+}
+
+/// Called from export generated by [`make_treasury_importers_library!`].
+///
+/// # Safety
+///
+/// `log` must stay callable with `logger` while the library is loaded.
+#[doc(hidden)]
+pub unsafe fn set_logger_ffi(logger: *const LoggerOpaque, log: LogFn, max_level: u32) {
+    struct Callback(*const LoggerOpaque, LogFn);
+
+    // Logger is required to be thread-safe by the FFI contract.
+    unsafe impl Send for Callback {}
+    unsafe impl Sync for Callback {}
+
+    let callback = Callback(logger, log);
+
+    // Library runs without forwarding if installation panics.
+    let _ = catch_panic(move || {
+        install(
+            move |record| {
+                let Callback(logger, log) = &callback;
+                unsafe { log(*logger, record.as_ptr(), record.len() as u32) }
+            },
+            decode_level_filter(max_level),
+        )
+    });
+}
@@ -0,0 +1,14 @@
+/// Receiver of import progress.
+///
+/// Long running importers should report progress as they go,
+/// so callers can tell the import is advancing.
+pub trait Progress {
+    /// Reports that `fraction` of the import is done, in range `0.0..=1.0`.
+    /// `stage` describes work importer does now.
+    fn report(&mut self, fraction: f32, stage: &str);
+}
+
+/// Progress that is ignored.
+impl Progress for () {
+    fn report(&mut self, _fraction: f32, _stage: &str) {}
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{Cancellation, Dependencies, Diagnostics, ImportError, Importer, Progress, Sources};
+
+pub trait SourceStreams {
+    /// Opens stream of data from specified source.
+    /// Returns `None` if source is not available yet.
+    fn open(&mut self, source: &str) -> Result<Option<Box<dyn Read + '_>>, String>;
+
+    fn open_or_append(
+        &mut self,
+        source: &str,
+        missing: &mut Vec<String>,
+    ) -> Result<Option<Box<dyn Read + '_>>, String> {
+        match self.open(source) {
+            Err(err) => Err(err),
+            Ok(Some(stream)) => Ok(Some(stream)),
+            Ok(None) => {
+                missing.push(source.to_owned());
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Opens files of [`Sources`].
+struct SourceFiles<'a> {
+    sources: &'a mut dyn Sources,
+}
+
+impl SourceStreams for SourceFiles<'_> {
+    fn open(&mut self, source: &str) -> Result<Option<Box<dyn Read + '_>>, String> {
+        match self.sources.get(source)? {
+            None => Ok(None),
+            Some(path) => match File::open(&path) {
+                Ok(file) => Ok(Some(Box::new(BufReader::new(file)))),
+                Err(err) => Err(format!(
+                    "Failed to open source file '{}'. {:#}",
+                    path.display(),
+                    err
+                )),
+            },
+        }
+    }
+}
+
+/// Implements [`Importer::import`] with [`Importer::import_stream`]
+/// by opening `source` and `output` files and sources returned by `sources`.
+#[allow(clippy::too_many_arguments)]
+pub fn import_with_streams(
+    importer: &(impl Importer + ?Sized),
+    source: &Path,
+    output: &Path,
+    options: Option<&str>,
+    sources: &mut dyn Sources,
+    dependencies: &mut dyn Dependencies,
+    cancellation: &dyn Cancellation,
+    diagnostics: &mut dyn Diagnostics,
+    progress: &mut dyn Progress,
+) -> Result<(), ImportError> {
+    let mut source = match File::open(source) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => {
+            return Err(ImportError::Other {
+                reason: format!(
+                    "Failed to open source file '{}'. {:#}",
+                    source.display(),
+                    err
+                ),
+            })
+        }
+    };
+
+    let mut output_file = match File::create(output) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => {
+            return Err(ImportError::Other {
+                reason: format!(
+                    "Failed to create output file '{}'. {:#}",
+                    output.display(),
+                    err
+                ),
+            })
+        }
+    };
+
+    importer.import_stream(
+        &mut source,
+        &mut output_file,
+        options,
+        &mut SourceFiles { sources },
+        dependencies,
+        cancellation,
+        diagnostics,
+        progress,
+    )?;
+
+    match output_file.flush() {
+        Ok(()) => Ok(()),
+        Err(err) => Err(ImportError::Other {
+            reason: format!(
+                "Failed to write output file '{}'. {:#}",
+                output.display(),
+                err
+            ),
+        }),
+    }
+}
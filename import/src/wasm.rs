@@ -0,0 +1,385 @@
+//! ABI of importers libraries compiled to WebAssembly.
+//!
+//! Library built for `wasm32-wasip1` with [`make_treasury_importers_library!`]
+//! exports functions listed here and imports `Sources::get`, `Dependencies::get`,
+//! `Cancellation::is_cancelled`, `Progress::report` and log sink from the [`HOST_MODULE`] provided by the store.
+//!
+//! Paths passed to wasm importers are paths inside WASI sandbox.
+//!
+//! Panics abort wasm importers instead of unwinding.
+//! Panic message is passed to the store with [`PANIC_NAME`] before the module traps.
+
+use crate::{
+    ffi::{decode_importer_desc, encode_importer_desc, encode_u32, Decoder},
+    importer::Importer,
+};
+
+pub use crate::ffi::{
+    decode_import_result, ImporterDesc, BUFFER_IS_TOO_SMALL, CANCELLED, NOT_FOUND, NOT_UTF8,
+    OTHER_ERROR, SUCCESS,
+};
+
+/// Module name of functions imported from the store.
+pub const HOST_MODULE: &str = "treasury";
+
+/// Imported `Sources::get`.
+/// `fn(source_ptr: u32, source_len: u32, path_ptr: u32, path_len_ptr: u32) -> i32`
+pub const SOURCES_GET_NAME: &str = "sources_get";
+
+/// Imported `Dependencies::get`.
+/// `fn(source_ptr: u32, source_len: u32, target_ptr: u32, target_len: u32, id_ptr: u32) -> i32`
+pub const DEPENDENCIES_GET_NAME: &str = "dependencies_get";
+
+/// Imported `Cancellation::is_cancelled`.
+/// `fn() -> i32`, returns [`CANCELLED`] if import is cancelled.
+pub const IS_CANCELLED_NAME: &str = "is_cancelled";
+
+/// Imported `Progress::report`.
+/// `fn(fraction: f32, stage_ptr: u32, stage_len: u32)`
+pub const PROGRESS_REPORT_NAME: &str = "progress_report";
+
+/// Imported log sink, receives records encoded with `LogRecord::encode`.
+/// `fn(record_ptr: u32, record_len: u32)`
+pub const LOG_NAME: &str = "log";
+
+/// Imported panic report, receives panic message.
+/// `fn(message_ptr: u32, message_len: u32)`
+pub const PANIC_NAME: &str = "panic";
+
+/// Writes range of supported ABI revisions.
+/// `fn(min_ptr: u32, max_ptr: u32)`
+pub const REVISIONS_FN_NAME: &str = "treasury_importer_ffi_revisions";
+
+/// `fn(len: u32) -> u32`
+pub const ALLOC_FN_NAME: &str = "treasury_wasm_alloc";
+
+/// `fn(ptr: u32, len: u32)`
+pub const DEALLOC_FN_NAME: &str = "treasury_wasm_dealloc";
+
+/// Writes description of importers encoded with [`encode_importers`].
+/// Returns required length, nothing is written if it exceeds `cap`.
+/// `fn(buffer: u32, cap: u32) -> u32`
+pub const IMPORTERS_FN_NAME: &str = "treasury_wasm_importers";
+
+/// `fn(importer: u32, source_ptr: u32, source_len: u32, output_ptr: u32, output_len: u32, options_ptr: u32, options_len: u32, result_ptr: u32, result_len_ptr: u32) -> i32`
+///
+/// `options_ptr` is zero if no options are set.
+pub const IMPORT_FN_NAME: &str = "treasury_wasm_import";
+
+/// `fn(importer: u32, head_ptr: u32, head_len: u32) -> i32`
+pub const PROBE_FN_NAME: &str = "treasury_wasm_probe";
+
+/// Creates importers from configuration, exported by libraries defined with importers factory.
+/// Called after instantiation, before other exports.
+/// `fn(config_ptr: u32, config_len: u32, error_ptr: u32, error_len_ptr: u32) -> i32`
+///
+/// `config_ptr` is zero if there is no configuration.
+/// On failure up to `*error_len_ptr` bytes of error message are written into `error_ptr`
+/// and `*error_len_ptr` is set to the number of bytes written.
+pub const CREATE_IMPORTERS_FN_NAME: &str = "treasury_wasm_create_importers";
+
+/// Installs subscriber that passes records to imported [`LOG_NAME`].
+/// `fn(max_level: u32)`, `max_level` is encoded with `encode_level_filter`.
+pub const INIT_LOG_FN_NAME: &str = "treasury_wasm_init_log";
+
+/// Encodes importers description.
+pub fn encode_importers(importers: &[&dyn Importer]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_u32(&mut buf, importers.len() as u32);
+
+    for importer in importers {
+        encode_importer_desc(&mut buf, *importer);
+    }
+
+    buf
+}
+
+/// Decodes importers description encoded with [`encode_importers`].
+/// Returns `None` if data is malformed.
+pub fn decode_importers(data: &[u8]) -> Option<Vec<ImporterDesc>> {
+    let mut decoder = Decoder { data };
+
+    let importers = decoder.list(decode_importer_desc)?;
+
+    if !decoder.data.is_empty() {
+        return None;
+    }
+
+    Some(importers)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use self::guest::*;
+
+/// Functions called from exports generated by [`make_treasury_importers_library!`].
+#[cfg(target_arch = "wasm32")]
+mod guest {
+    use std::{
+        alloc::Layout,
+        sync::{Once, OnceLock},
+    };
+
+    use crate::{
+        factory::{decode_config, write_error, ImportersFactory},
+        ffi::{
+            importer_import_ffi, importer_probe_ffi, panic_message, CancellationOpaque,
+            DependenciesOpaque, ImporterOpaque, ProgressOpaque, SourcesOpaque, NOT_FOUND,
+            OTHER_ERROR, SUCCESS,
+        },
+        importer::Importer,
+    };
+
+    #[link(wasm_import_module = "treasury")]
+    extern "C" {
+        #[link_name = "sources_get"]
+        fn host_sources_get(
+            source_ptr: *const u8,
+            source_len: u32,
+            path_ptr: *mut u8,
+            path_len: *mut u32,
+        ) -> i32;
+
+        #[link_name = "dependencies_get"]
+        fn host_dependencies_get(
+            source_ptr: *const u8,
+            source_len: u32,
+            target_ptr: *const u8,
+            target_len: u32,
+            id_ptr: *mut u64,
+        ) -> i32;
+
+        #[link_name = "is_cancelled"]
+        fn host_is_cancelled() -> i32;
+
+        #[link_name = "progress_report"]
+        fn host_progress_report(fraction: f32, stage_ptr: *const u8, stage_len: u32);
+
+        #[link_name = "log"]
+        fn host_log(record_ptr: *const u8, record_len: u32);
+
+        #[link_name = "panic"]
+        fn host_panic(message_ptr: *const u8, message_len: u32);
+    }
+
+    /// Reports panics to the store, as they abort the module before `catch_unwind` sees them.
+    fn set_panic_hook() {
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let default = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                let message = panic_message(info.payload());
+                unsafe { host_panic(message.as_ptr(), message.len() as u32) }
+                default(info);
+            }));
+        });
+    }
+
+    unsafe extern "C" fn sources_get(
+        _sources: *mut SourcesOpaque,
+        source_ptr: *const u8,
+        source_len: u32,
+        path_ptr: *mut u8,
+        path_len: *mut u32,
+    ) -> i32 {
+        host_sources_get(source_ptr, source_len, path_ptr, path_len)
+    }
+
+    unsafe extern "C" fn dependencies_get(
+        _dependencies: *mut DependenciesOpaque,
+        source_ptr: *const u8,
+        source_len: u32,
+        target_ptr: *const u8,
+        target_len: u32,
+        id_ptr: *mut u64,
+    ) -> i32 {
+        host_dependencies_get(source_ptr, source_len, target_ptr, target_len, id_ptr)
+    }
+
+    unsafe extern "C" fn is_cancelled(_cancellation: *const CancellationOpaque) -> i32 {
+        host_is_cancelled()
+    }
+
+    unsafe extern "C" fn progress_report(
+        _progress: *mut ProgressOpaque,
+        fraction: f32,
+        stage_ptr: *const u8,
+        stage_len: u32,
+    ) {
+        host_progress_report(fraction, stage_ptr, stage_len)
+    }
+
+    pub fn init_log(max_level: u32) {
+        crate::logging::install(
+            |record| unsafe { host_log(record.as_ptr(), record.len() as u32) },
+            crate::logging::decode_level_filter(max_level),
+        );
+    }
+
+    fn layout(len: u32) -> Layout {
+        Layout::array::<u8>(len.max(1) as usize).unwrap()
+    }
+
+    pub fn alloc(len: u32) -> *mut u8 {
+        unsafe { std::alloc::alloc(layout(len)) }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be allocated with [`alloc`] with the same `len`.
+    pub unsafe fn dealloc(ptr: *mut u8, len: u32) {
+        std::alloc::dealloc(ptr, layout(len))
+    }
+
+    /// # Safety
+    ///
+    /// `buffer` must be valid for writes of `cap` bytes.
+    pub unsafe fn export_importers(importers: &[&dyn Importer], buffer: *mut u8, cap: u32) -> u32 {
+        let encoded = super::encode_importers(importers);
+        if encoded.len() <= cap as usize {
+            std::ptr::copy_nonoverlapping(encoded.as_ptr(), buffer, encoded.len());
+        }
+        encoded.len() as u32
+    }
+
+    /// # Safety
+    ///
+    /// Pointers must be valid for their lengths.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn import<I>(
+        importer: &'static I,
+        source_ptr: *const u8,
+        source_len: u32,
+        output_ptr: *const u8,
+        output_len: u32,
+        options_ptr: *const u8,
+        options_len: u32,
+        result_ptr: *mut u8,
+        result_len: *mut u32,
+    ) -> i32
+    where
+        I: Importer,
+    {
+        set_panic_hook();
+
+        importer_import_ffi::<I>(
+            importer as *const I as *const ImporterOpaque,
+            source_ptr,
+            source_len,
+            output_ptr,
+            output_len,
+            options_ptr,
+            options_len,
+            std::ptr::null_mut(),
+            sources_get,
+            std::ptr::null_mut(),
+            dependencies_get,
+            std::ptr::null(),
+            is_cancelled,
+            std::ptr::null_mut(),
+            progress_report,
+            result_ptr,
+            result_len,
+        )
+    }
+
+    /// # Safety
+    ///
+    /// `head_ptr` must be valid for `head_len` bytes.
+    pub unsafe fn probe<I>(importer: &'static I, head_ptr: *const u8, head_len: u32) -> i32
+    where
+        I: Importer,
+    {
+        importer_probe_ffi::<I>(
+            importer as *const I as *const ImporterOpaque,
+            head_ptr,
+            head_len,
+        )
+    }
+
+    /// Importers created by the library factory.
+    /// Each import runs in fresh instance, so they are created once per instance.
+    static CREATED: OnceLock<Vec<Box<dyn Importer>>> = OnceLock::new();
+
+    fn created() -> &'static [Box<dyn Importer>] {
+        CREATED.get().map_or(&[], |importers| importers)
+    }
+
+    /// # Safety
+    ///
+    /// Pointers must be valid for their lengths.
+    pub unsafe fn create_importers(
+        factory: ImportersFactory,
+        config_ptr: *const u8,
+        config_len: u32,
+        error_ptr: *mut u8,
+        error_len: *mut u32,
+    ) -> i32 {
+        set_panic_hook();
+
+        match decode_config(config_ptr, config_len).and_then(factory) {
+            Ok(importers) => {
+                let _ = CREATED.set(importers);
+                SUCCESS
+            }
+            Err(reason) => {
+                write_error(&reason, error_ptr, error_len);
+                OTHER_ERROR
+            }
+        }
+    }
+
+    /// Same as [`export_importers`] for importers created by the library factory.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for writes of `cap` bytes.
+    pub unsafe fn export_created_importers(buffer: *mut u8, cap: u32) -> u32 {
+        let importers: Vec<&dyn Importer> = created().iter().map(|importer| &**importer).collect();
+        export_importers(&importers, buffer, cap)
+    }
+
+    /// Same as [`import`] for importer created by the library factory.
+    ///
+    /// # Safety
+    ///
+    /// Pointers must be valid for their lengths.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn import_created(
+        index: u32,
+        source_ptr: *const u8,
+        source_len: u32,
+        output_ptr: *const u8,
+        output_len: u32,
+        options_ptr: *const u8,
+        options_len: u32,
+        result_ptr: *mut u8,
+        result_len: *mut u32,
+    ) -> i32 {
+        match created().get(index as usize) {
+            None => NOT_FOUND,
+            Some(importer) => import(
+                importer,
+                source_ptr,
+                source_len,
+                output_ptr,
+                output_len,
+                options_ptr,
+                options_len,
+                result_ptr,
+                result_len,
+            ),
+        }
+    }
+
+    /// Same as [`probe`] for importer created by the library factory.
+    ///
+    /// # Safety
+    ///
+    /// `head_ptr` must be valid for `head_len` bytes.
+    pub unsafe fn probe_created(index: u32, head_ptr: *const u8, head_len: u32) -> i32 {
+        match created().get(index as usize) {
+            None => NOT_FOUND,
+            Some(importer) => probe(importer, head_ptr, head_len),
+        }
+    }
+}
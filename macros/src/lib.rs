@@ -0,0 +1,342 @@
+//! Attribute macro that turns a function into treasury importer.
+//!
+//! Use it through `treasury_import::importer` with `macros` feature enabled.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parse::Parser, parse_macro_input, punctuated::Punctuated, spanned::Spanned, Expr, FnArg,
+    ItemFn, MetaNameValue, Token, Visibility,
+};
+
+/// Turns function into unit struct that implements `Importer`.
+///
+/// Function takes `&mut ImportContext` and returns `Result<(), E>` where `E: Display`.
+/// Struct has the function's name, so it can be listed in `make_treasury_importers_library!`
+/// as `&function_name`.
+///
+/// Required arguments are `name`, `formats` and `target`.
+/// Optional arguments are `extensions`, `description`, `version`, `package`,
+/// `options_schema`, `magic` and `probe`, a path to `fn(&[u8]) -> bool`.
+#[proc_macro_attribute]
+pub fn importer(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let parser = Punctuated::<MetaNameValue, Token![,]>::parse_terminated;
+    let args = match parser.parse(attr) {
+        Ok(args) => args,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let function = parse_macro_input!(item as ItemFn);
+
+    match expand(args, function) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct Args {
+    name: Option<Expr>,
+    formats: Option<Expr>,
+    extensions: Option<Expr>,
+    target: Option<Expr>,
+    description: Option<Expr>,
+    version: Option<Expr>,
+    package: Option<Expr>,
+    options_schema: Option<Expr>,
+    magic: Option<Expr>,
+    probe: Option<Expr>,
+}
+
+impl Args {
+    fn parse(args: Punctuated<MetaNameValue, Token![,]>) -> syn::Result<Self> {
+        let mut parsed = Args::default();
+
+        for arg in args {
+            let key = match arg.path.get_ident() {
+                Some(ident) => ident.to_string(),
+                None => return Err(syn::Error::new(arg.path.span(), "Expected argument name")),
+            };
+
+            let slot = match &*key {
+                "name" => &mut parsed.name,
+                "formats" => &mut parsed.formats,
+                "extensions" => &mut parsed.extensions,
+                "target" => &mut parsed.target,
+                "description" => &mut parsed.description,
+                "version" => &mut parsed.version,
+                "package" => &mut parsed.package,
+                "options_schema" => &mut parsed.options_schema,
+                "magic" => &mut parsed.magic,
+                "probe" => &mut parsed.probe,
+                _ => {
+                    return Err(syn::Error::new(
+                        arg.path.span(),
+                        format!("Unknown importer argument '{}'", key),
+                    ))
+                }
+            };
+
+            if slot.is_some() {
+                return Err(syn::Error::new(
+                    arg.path.span(),
+                    format!("Duplicate importer argument '{}'", key),
+                ));
+            }
+            *slot = Some(arg.value);
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn required(arg: Option<Expr>, key: &str) -> syn::Result<Expr> {
+    arg.ok_or_else(|| {
+        syn::Error::new(
+            Span::call_site(),
+            format!("Missing importer argument '{}'", key),
+        )
+    })
+}
+
+fn expand(
+    args: Punctuated<MetaNameValue, Token![,]>,
+    function: ItemFn,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let args = Args::parse(args)?;
+
+    let sig = &function.sig;
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(syn::Error::new(
+            asyncness.span(),
+            "Importer function cannot be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "Importer function cannot be generic",
+        ));
+    }
+    if sig.inputs.len() != 1 || matches!(sig.inputs.first(), Some(FnArg::Receiver(_))) {
+        return Err(syn::Error::new(
+            sig.inputs.span(),
+            "Importer function must take single `&mut ImportContext` argument",
+        ));
+    }
+
+    let name = required(args.name, "name")?;
+    let formats = required(args.formats, "formats")?;
+    let target = required(args.target, "target")?;
+
+    let extensions = match args.extensions {
+        None => quote!(&[]),
+        Some(extensions) => quote!(&#extensions),
+    };
+
+    let description = args.description.map(|description| {
+        quote! {
+            fn description(&self) -> &str {
+                #description
+            }
+        }
+    });
+
+    let version = args.version.map(|version| {
+        quote! {
+            fn version(&self) -> ::core::option::Option<&str> {
+                ::core::option::Option::Some(#version)
+            }
+        }
+    });
+
+    let package = args.package.map(|package| {
+        quote! {
+            fn package(&self) -> ::core::option::Option<&str> {
+                ::core::option::Option::Some(#package)
+            }
+        }
+    });
+
+    let options_schema = args.options_schema.map(|options_schema| {
+        quote! {
+            fn options_schema(&self) -> ::core::option::Option<&str> {
+                ::core::option::Option::Some(#options_schema)
+            }
+        }
+    });
+
+    let magic = args.magic.map(|magic| {
+        quote! {
+            fn magic(&self) -> &[&[u8]] {
+                &#magic
+            }
+        }
+    });
+
+    let probe = args.probe.map(|probe| {
+        quote! {
+            fn probe(&self, head: &[u8]) -> bool {
+                (#probe)(head)
+            }
+        }
+    });
+
+    // Docs and visibility go to the struct, function is nested into `Importer::import`.
+    let mut function = function;
+    let vis = std::mem::replace(&mut function.vis, Visibility::Inherited);
+    let (docs, attrs): (Vec<_>, Vec<_>) = function
+        .attrs
+        .drain(..)
+        .partition(|attr| attr.path().is_ident("doc"));
+    function.attrs = attrs;
+    let ident = &function.sig.ident;
+
+    Ok(quote! {
+        #(#docs)*
+        #[allow(non_camel_case_types)]
+        #vis struct #ident;
+
+        impl ::treasury_import::Importer for #ident {
+            fn name(&self) -> &str {
+                #name
+            }
+
+            fn formats(&self) -> &[&str] {
+                &#formats
+            }
+
+            fn extensions(&self) -> &[&str] {
+                #extensions
+            }
+
+            fn target(&self) -> &str {
+                #target
+            }
+
+            #description
+            #version
+            #package
+            #options_schema
+            #magic
+            #probe
+
+            fn import(
+                &self,
+                source: &::std::path::Path,
+                output: &::std::path::Path,
+                options: ::core::option::Option<&str>,
+                sources: &mut dyn ::treasury_import::Sources,
+                dependencies: &mut dyn ::treasury_import::Dependencies,
+                cancellation: &dyn ::treasury_import::Cancellation,
+                diagnostics: &mut dyn ::treasury_import::Diagnostics,
+                progress: &mut dyn ::treasury_import::Progress,
+            ) -> ::core::result::Result<(), ::treasury_import::ImportError> {
+                #function
+
+                let mut ctx = ::treasury_import::ImportContext::new(
+                    source,
+                    output,
+                    options,
+                    sources,
+                    dependencies,
+                    cancellation,
+                    diagnostics,
+                    progress,
+                );
+                let result = #ident(&mut ctx);
+                ctx.finish(result)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_error(args: proc_macro2::TokenStream, function: ItemFn) -> String {
+        let args = Punctuated::<MetaNameValue, Token![,]>::parse_terminated
+            .parse2(args)
+            .unwrap();
+        match expand(args, function) {
+            Ok(tokens) => panic!("Expected error, expanded to {}", tokens),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn importer_fn() -> ItemFn {
+        syn::parse_quote! {
+            fn foo(ctx: &mut ImportContext) -> Result<(), String> {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn expands_struct_with_function_name() {
+        let args = Punctuated::<MetaNameValue, Token![,]>::parse_terminated
+            .parse2(quote!(name = "Foo", formats = ["foo"], target = "foo"))
+            .unwrap();
+        let tokens = expand(args, importer_fn()).unwrap().to_string();
+        assert!(tokens.contains("struct foo"), "{}", tokens);
+        assert!(
+            tokens.contains("impl :: treasury_import :: Importer for foo"),
+            "{}",
+            tokens
+        );
+    }
+
+    #[test]
+    fn rejects_missing_arguments() {
+        assert_eq!(
+            expand_error(quote!(name = "Foo", formats = ["foo"]), importer_fn()),
+            "Missing importer argument 'target'"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_duplicate_arguments() {
+        assert_eq!(
+            expand_error(quote!(name = "Foo", color = "red"), importer_fn()),
+            "Unknown importer argument 'color'"
+        );
+        assert_eq!(
+            expand_error(quote!(name = "Foo", name = "Bar"), importer_fn()),
+            "Duplicate importer argument 'name'"
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_functions() {
+        let args = quote!(name = "Foo", formats = ["foo"], target = "foo");
+
+        assert_eq!(
+            expand_error(
+                args.clone(),
+                syn::parse_quote!(
+                    async fn foo(ctx: &mut ImportContext) -> Result<(), String> {}
+                )
+            ),
+            "Importer function cannot be async"
+        );
+        assert_eq!(
+            expand_error(
+                args.clone(),
+                syn::parse_quote!(
+                    fn foo<T>(ctx: &mut ImportContext) -> Result<(), String> {}
+                )
+            ),
+            "Importer function cannot be generic"
+        );
+        assert_eq!(
+            expand_error(
+                args,
+                syn::parse_quote!(
+                    fn foo() -> Result<(), String> {}
+                )
+            ),
+            "Importer function must take single `&mut ImportContext` argument"
+        );
+    }
+}
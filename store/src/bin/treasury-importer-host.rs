@@ -0,0 +1,21 @@
+//! Importer host process.
+//! Loads importers library given as the only argument
+//! and serves import requests from the store over stdin and stdout.
+
+use std::path::PathBuf;
+
+fn main() -> eyre::Result<()> {
+    // Stdout is reserved for messages to the store.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    let lib_path = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .ok_or_else(|| eyre::eyre!("Usage: treasury-importer-host <importers-library>"))?;
+
+    // # Safety: Same as loading importers library into the store.
+    unsafe { treasury_store::host::run(&lib_path) }
+}
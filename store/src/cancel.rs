@@ -0,0 +1,117 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use hashbrown::HashMap;
+use treasury_import::Cancellation;
+
+/// Limits on time importers may spend on single import, in seconds.
+///
+/// Limits are advisory for importers loaded into the store process.
+/// Hosted importers are killed with the host and WebAssembly importers are interrupted.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ImportTimeouts {
+    /// Limit for importers without their own limit.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub default: Option<u64>,
+
+    /// Limits for importers by name.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub importers: HashMap<String, u64>,
+}
+
+impl ImportTimeouts {
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.importers.is_empty()
+    }
+
+    /// Returns time limit for the importer.
+    pub fn get(&self, importer: &str) -> Option<Duration> {
+        self.importers
+            .get(importer)
+            .copied()
+            .or(self.default)
+            .map(Duration::from_secs)
+    }
+}
+
+/// Cancels imports it is passed to.
+/// Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Requests imports to stop.
+    /// Running importer observes cancellation through [`Cancellation`].
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Error returned when import is stopped before completion.
+/// Nothing is written to artifacts directory or `.treasure` files for assets that were not completed.
+#[derive(Debug, thiserror::Error)]
+pub enum Interrupted {
+    #[error("Import was cancelled")]
+    Cancelled,
+
+    #[error("Importer '{importer}' exceeded timeout of {} seconds", timeout.as_secs())]
+    TimedOut { importer: String, timeout: Duration },
+}
+
+/// Cancellation of a single importer call.
+pub(crate) struct ImportCancellation<'a> {
+    token: Option<&'a CancelToken>,
+    deadline: Option<Instant>,
+    timeout: Option<Duration>,
+}
+
+impl<'a> ImportCancellation<'a> {
+    pub fn new(token: Option<&'a CancelToken>, timeout: Option<Duration>) -> Self {
+        ImportCancellation {
+            token,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            timeout,
+        }
+    }
+
+    /// Returns reason why import must be stopped, if any.
+    pub fn interrupted(&self, importer: &str) -> Option<Interrupted> {
+        if self.token.is_some_and(CancelToken::is_cancelled) {
+            return Some(Interrupted::Cancelled);
+        }
+
+        match (self.deadline, self.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                Some(Interrupted::TimedOut {
+                    importer: importer.to_owned(),
+                    timeout,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Cancellation for ImportCancellation<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.token.is_some_and(CancelToken::is_cancelled)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
@@ -0,0 +1,114 @@
+//! Framing of messages exchanged with remote clients and importer host.
+//!
+//! Every message is serialized with `bincode`
+//! and prefixed with its length as little-endian `u32`.
+
+use std::io::{ErrorKind, Read, Write};
+
+use eyre::WrapErr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest message that can be sent or received.
+const MAX_MESSAGE_LEN: u32 = 1 << 20;
+
+/// Serializes message prefixed with its length.
+fn encode<M>(message: &M) -> eyre::Result<Vec<u8>>
+where
+    M: serde::Serialize,
+{
+    let len = bincode::serialized_size(message).wrap_err("Failed to serialize message")?;
+
+    let len = u32::try_from(len)
+        .ok()
+        .filter(|len| *len <= MAX_MESSAGE_LEN)
+        .ok_or_else(|| eyre::eyre!("Message is too large. {} bytes", len))?;
+
+    let mut data = Vec::with_capacity(4 + len as usize);
+    data.extend_from_slice(&len.to_le_bytes());
+    bincode::serialize_into(&mut data, message).wrap_err("Failed to serialize message")?;
+    Ok(data)
+}
+
+/// Checks length prefix of received message.
+fn decode_len(len: [u8; 4]) -> eyre::Result<usize> {
+    let len = u32::from_le_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        return Err(eyre::eyre!("Message is too large. {} bytes", len));
+    }
+    Ok(len as usize)
+}
+
+/// Sends single message.
+pub async fn send<S, M>(stream: &mut S, message: &M) -> eyre::Result<()>
+where
+    S: AsyncWrite + Unpin,
+    M: serde::Serialize,
+{
+    let data = encode(message)?;
+
+    stream
+        .write_all(&data)
+        .await
+        .wrap_err("Failed to send message")?;
+    stream.flush().await.wrap_err("Failed to send message")?;
+    Ok(())
+}
+
+/// Receives single message.
+/// Returns `None` if stream is closed before the message.
+pub async fn recv<S, M>(stream: &mut S) -> eyre::Result<Option<M>>
+where
+    S: AsyncRead + Unpin,
+    M: serde::de::DeserializeOwned,
+{
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).wrap_err("Failed to receive message"),
+    }
+
+    let mut data = vec![0; decode_len(len)?];
+    stream
+        .read_exact(&mut data)
+        .await
+        .wrap_err("Failed to receive message")?;
+
+    let message = bincode::deserialize(&data).wrap_err("Failed to deserialize message")?;
+    Ok(Some(message))
+}
+
+/// Blocking version of [`send`].
+pub(crate) fn write_message<W, M>(stream: &mut W, message: &M) -> eyre::Result<()>
+where
+    W: Write,
+    M: serde::Serialize,
+{
+    let data = encode(message)?;
+
+    stream.write_all(&data).wrap_err("Failed to send message")?;
+    stream.flush().wrap_err("Failed to send message")?;
+    Ok(())
+}
+
+/// Blocking version of [`recv`].
+pub(crate) fn read_message<R, M>(stream: &mut R) -> eyre::Result<Option<M>>
+where
+    R: Read,
+    M: serde::de::DeserializeOwned,
+{
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).wrap_err("Failed to receive message"),
+    }
+
+    let mut data = vec![0; decode_len(len)?];
+    stream
+        .read_exact(&mut data)
+        .wrap_err("Failed to receive message")?;
+
+    let message = bincode::deserialize(&data).wrap_err("Failed to deserialize message")?;
+    Ok(Some(message))
+}
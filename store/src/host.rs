@@ -0,0 +1,985 @@
+//! Out-of-process execution of importers.
+//!
+//! Importers libraries are loaded into a child process - importer host.
+//! Store talks to it over the child's stdin and stdout,
+//! forwarding source and dependency requests and import results.
+//! Host crash or hang fails the import instead of taking the store down.
+//! Host is killed if importer does not stop soon after import is cancelled or times out.
+//! Host is restarted on next use.
+//!
+//! Host redirects its stdout to stderr before loading importers,
+//! so importers that print do not corrupt messages to the store.
+//! Their events and spans are forwarded to the store.
+
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::fd::AsFd;
+
+#[cfg(windows)]
+use std::os::windows::io::AsHandle;
+
+use eyre::WrapErr;
+use parking_lot::Mutex;
+use tracing::level_filters::LevelFilter;
+use treasury_id::AssetId;
+use treasury_import::{
+    logging::{LogSink, Logger},
+    Cancellation, Dependencies, Dependency, Diagnostic, Diagnostics, ImportError, Importer,
+    Progress, Sources,
+};
+
+use crate::framing::{read_message, write_message};
+
+/// Name of the importer host executable.
+pub const HOST_EXECUTABLE: &str = "treasury-importer-host";
+
+const DEFAULT_TIMEOUT: u64 = 300;
+
+/// Environment variable with the store's maximum log level passed to the host.
+const LOG_LEVEL_ENV: &str = "TREASURY_IMPORTER_HOST_LOG_LEVEL";
+
+/// Environment variable with configuration of the library passed to the host.
+const CONFIG_ENV: &str = "TREASURY_IMPORTER_HOST_CONFIG";
+
+/// Time host has to drop importers and exit after the store closes its stdin.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time importer has to stop after import is cancelled or timed out before the host is killed.
+const CANCELLATION_GRACE: Duration = Duration::from_secs(1);
+
+/// Minimal interval between cancellation requests from host to store.
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Configuration of the importer host.
+/// When set importers libraries are loaded into a child process.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ImporterHost {
+    /// Path to importer host executable.
+    /// Relative to treasury base directory.
+    /// By default it is searched next to current executable and then in `PATH`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub executable: Option<PathBuf>,
+
+    /// Seconds to wait for the host to respond before considering it hung.
+    /// Default is 300.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub timeout: Option<u64>,
+}
+
+/// Importer host configuration with resolved paths.
+pub(crate) struct HostSettings {
+    executable: PathBuf,
+    timeout: Duration,
+}
+
+impl HostSettings {
+    pub fn new(host: &ImporterHost, base: &Path) -> Self {
+        let executable = match &host.executable {
+            Some(executable) => base.join(executable),
+            None => default_executable(),
+        };
+
+        HostSettings {
+            executable,
+            timeout: Duration::from_secs(host.timeout.unwrap_or(DEFAULT_TIMEOUT)),
+        }
+    }
+}
+
+fn default_executable() -> PathBuf {
+    let file_name = format!("{}{}", HOST_EXECUTABLE, std::env::consts::EXE_SUFFIX);
+
+    if let Ok(current) = std::env::current_exe() {
+        let path = current.with_file_name(&file_name);
+        if path.is_file() {
+            return path;
+        }
+    }
+
+    // Let the system search in `PATH`.
+    PathBuf::from(file_name)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ImporterDesc {
+    name: String,
+    formats: Vec<String>,
+    extensions: Vec<String>,
+    target: String,
+    magic: Vec<Vec<u8>>,
+    description: String,
+    version: Option<String>,
+    package: Option<String>,
+    options_schema: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+enum HostError {
+    RequireSources { sources: Vec<String> },
+    RequireDependencies { dependencies: Vec<(String, String)> },
+    Cancelled,
+    Other { reason: String },
+    Panicked { message: String },
+}
+
+/// Messages from host to store.
+#[derive(serde::Serialize, serde::Deserialize)]
+enum HostMessage {
+    Loaded {
+        importers: Vec<ImporterDesc>,
+    },
+    LoadFailed {
+        reason: String,
+    },
+    Probed {
+        recognized: bool,
+    },
+    GetSource {
+        source: String,
+    },
+    GetDependency {
+        source: String,
+        target: String,
+    },
+    IsCancelled,
+    /// Diagnostic reported by the importer. Store does not reply.
+    Diagnostic {
+        diagnostic: Diagnostic,
+    },
+    /// Progress reported by the importer. Store does not reply.
+    Progress {
+        fraction: f32,
+        stage: String,
+    },
+    /// Log record of the importers library. May arrive at any time.
+    Log {
+        record: Vec<u8>,
+    },
+    Imported {
+        result: Result<(), HostError>,
+    },
+}
+
+/// Messages from store to host.
+#[derive(serde::Serialize, serde::Deserialize)]
+enum StoreMessage {
+    Probe {
+        importer: u32,
+        head: Vec<u8>,
+    },
+    Import {
+        importer: u32,
+        source: PathBuf,
+        output: PathBuf,
+        options: Option<String>,
+    },
+    Source {
+        result: Result<Option<PathBuf>, String>,
+    },
+    Dependency {
+        result: Result<Option<AssetId>, String>,
+    },
+    Cancelled {
+        cancelled: bool,
+    },
+}
+
+/// Returns duplicate of stdout for messages to the store
+/// and redirects stdout to stderr.
+fn take_stdout() -> eyre::Result<File> {
+    let stdout = std::io::stdout();
+    stdout.lock().flush().wrap_err("Failed to flush stdout")?;
+
+    #[cfg(unix)]
+    let output = stdout.as_fd().try_clone_to_owned();
+
+    #[cfg(windows)]
+    let output = stdout.as_handle().try_clone_to_owned();
+
+    let output = File::from(output.wrap_err("Failed to duplicate stdout")?);
+
+    // Safety: Standard descriptors are open for the whole life of the process.
+    if unsafe { libc::dup2(2, 1) } < 0 {
+        return Err(std::io::Error::last_os_error())
+            .wrap_err("Failed to redirect stdout to stderr");
+    }
+
+    Ok(output)
+}
+
+/// Runs importer host.
+/// Loads importers from the library and serves requests from the store
+/// on stdin and stdout until stdin is closed.
+/// Configuration of the library is passed by the store in environment.
+///
+/// # Safety
+///
+/// Library at `lib_path` must be produced with `make_treasury_importers_library!` macro.
+pub unsafe fn run(lib_path: &Path) -> eyre::Result<()> {
+    let stdin = RefCell::new(std::io::stdin().lock());
+
+    // Output is locked per message, as log records are sent from importer threads.
+    let output = Arc::new(Mutex::new(take_stdout()?));
+    let send = |message: &HostMessage| write_message(&mut *output.lock(), message);
+    let recv = || -> eyre::Result<Option<StoreMessage>> { read_message(&mut *stdin.borrow_mut()) };
+
+    struct LogForward {
+        output: Arc<Mutex<File>>,
+    }
+
+    impl LogSink for LogForward {
+        fn log(&self, record: &[u8]) {
+            let message = HostMessage::Log {
+                record: record.to_vec(),
+            };
+            if let Err(err) = write_message(&mut *self.output.lock(), &message) {
+                tracing::error!("Failed to forward log record. {:#}", err);
+            }
+        }
+    }
+
+    let max_level = std::env::var(LOG_LEVEL_ENV)
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or_else(LevelFilter::current);
+
+    let config = std::env::var(CONFIG_ENV).ok();
+
+    let importers: Vec<_> = match treasury_import::loading::load_importers_with_sink(
+        lib_path,
+        config.as_deref(),
+        Arc::new(LogForward {
+            output: output.clone(),
+        }),
+        max_level,
+    ) {
+        Err(err) => {
+            send(&HostMessage::LoadFailed {
+                reason: err.to_string(),
+            })?;
+            return Err(eyre::eyre!(
+                "Failed to load importers from '{}'. {}",
+                lib_path.display(),
+                err
+            ));
+        }
+        Ok(iter) => iter.collect(),
+    };
+
+    send(&HostMessage::Loaded {
+        importers: importers
+            .iter()
+            .map(|importer| ImporterDesc {
+                name: importer.name().to_owned(),
+                formats: importer.formats().iter().map(|&f| f.to_owned()).collect(),
+                extensions: importer
+                    .extensions()
+                    .iter()
+                    .map(|&e| e.to_owned())
+                    .collect(),
+                target: importer.target().to_owned(),
+                magic: importer.magic().iter().map(|&m| m.to_owned()).collect(),
+                description: importer.description().to_owned(),
+                version: importer.version().map(str::to_owned),
+                package: importer.package().map(str::to_owned),
+                options_schema: importer.options_schema().map(str::to_owned),
+            })
+            .collect(),
+    })?;
+
+    struct Requests<'a, S, R> {
+        send: &'a S,
+        recv: &'a R,
+    }
+
+    /// Asks store whether import is cancelled,
+    /// at most once per `CANCELLATION_POLL_INTERVAL`.
+    struct CancellationRequests<'a, S, R> {
+        requests: Requests<'a, S, R>,
+        polled: Cell<Option<Instant>>,
+        cancelled: Cell<bool>,
+    }
+
+    impl<S, R> Requests<'_, S, R>
+    where
+        S: Fn(&HostMessage) -> eyre::Result<()>,
+        R: Fn() -> eyre::Result<Option<StoreMessage>>,
+    {
+        fn request(&self, message: HostMessage) -> Result<StoreMessage, String> {
+            (self.send)(&message).map_err(|err| format!("{:#}", err))?;
+            match (self.recv)() {
+                Ok(Some(reply)) => Ok(reply),
+                Ok(None) => Err("Store disconnected".to_owned()),
+                Err(err) => Err(format!("{:#}", err)),
+            }
+        }
+    }
+
+    impl<S, R> Sources for Requests<'_, S, R>
+    where
+        S: Fn(&HostMessage) -> eyre::Result<()>,
+        R: Fn() -> eyre::Result<Option<StoreMessage>>,
+    {
+        fn get(&mut self, source: &str) -> Result<Option<PathBuf>, String> {
+            match self.request(HostMessage::GetSource {
+                source: source.to_owned(),
+            })? {
+                StoreMessage::Source { result } => result,
+                _ => Err("Unexpected reply from store".to_owned()),
+            }
+        }
+    }
+
+    impl<S, R> Dependencies for Requests<'_, S, R>
+    where
+        S: Fn(&HostMessage) -> eyre::Result<()>,
+        R: Fn() -> eyre::Result<Option<StoreMessage>>,
+    {
+        fn get(&mut self, source: &str, target: &str) -> Result<Option<AssetId>, String> {
+            match self.request(HostMessage::GetDependency {
+                source: source.to_owned(),
+                target: target.to_owned(),
+            })? {
+                StoreMessage::Dependency { result } => result,
+                _ => Err("Unexpected reply from store".to_owned()),
+            }
+        }
+    }
+
+    impl<S, R> Diagnostics for Requests<'_, S, R>
+    where
+        S: Fn(&HostMessage) -> eyre::Result<()>,
+    {
+        fn report(&mut self, diagnostic: Diagnostic) {
+            if let Err(err) = (self.send)(&HostMessage::Diagnostic { diagnostic }) {
+                tracing::error!("Failed to send diagnostic. {:#}", err);
+            }
+        }
+    }
+
+    impl<S, R> Progress for Requests<'_, S, R>
+    where
+        S: Fn(&HostMessage) -> eyre::Result<()>,
+    {
+        fn report(&mut self, fraction: f32, stage: &str) {
+            let message = HostMessage::Progress {
+                fraction,
+                stage: stage.to_owned(),
+            };
+            if let Err(err) = (self.send)(&message) {
+                tracing::error!("Failed to send progress. {:#}", err);
+            }
+        }
+    }
+
+    impl<S, R> Cancellation for CancellationRequests<'_, S, R>
+    where
+        S: Fn(&HostMessage) -> eyre::Result<()>,
+        R: Fn() -> eyre::Result<Option<StoreMessage>>,
+    {
+        fn is_cancelled(&self) -> bool {
+            if self.cancelled.get() {
+                return true;
+            }
+
+            let now = Instant::now();
+            if let Some(polled) = self.polled.get() {
+                if now < polled + CANCELLATION_POLL_INTERVAL {
+                    return false;
+                }
+            }
+            self.polled.set(Some(now));
+
+            let cancelled = match self.requests.request(HostMessage::IsCancelled) {
+                Ok(StoreMessage::Cancelled { cancelled }) => cancelled,
+                Ok(_) => {
+                    tracing::error!("Unexpected reply from store");
+                    false
+                }
+                Err(err) => {
+                    tracing::error!("Failed to request cancellation status. {}", err);
+                    false
+                }
+            };
+
+            self.cancelled.set(cancelled);
+            cancelled
+        }
+    }
+
+    while let Some(message) = recv()? {
+        match message {
+            StoreMessage::Probe { importer, head } => {
+                let importer = importers
+                    .get(importer as usize)
+                    .ok_or_else(|| eyre::eyre!("Importer index is out of bounds"))?;
+
+                send(&HostMessage::Probed {
+                    recognized: importer.probe(&head),
+                })?;
+            }
+            StoreMessage::Import {
+                importer,
+                source,
+                output,
+                options,
+            } => {
+                let importer = importers
+                    .get(importer as usize)
+                    .ok_or_else(|| eyre::eyre!("Importer index is out of bounds"))?;
+
+                let result = importer.import(
+                    &source,
+                    &output,
+                    options.as_deref(),
+                    &mut Requests {
+                        send: &send,
+                        recv: &recv,
+                    },
+                    &mut Requests {
+                        send: &send,
+                        recv: &recv,
+                    },
+                    &CancellationRequests {
+                        requests: Requests {
+                            send: &send,
+                            recv: &recv,
+                        },
+                        polled: Cell::new(None),
+                        cancelled: Cell::new(false),
+                    },
+                    &mut Requests {
+                        send: &send,
+                        recv: &recv,
+                    },
+                    &mut Requests {
+                        send: &send,
+                        recv: &recv,
+                    },
+                );
+
+                let result = result.map_err(|err| match err {
+                    ImportError::RequireSources { sources } => {
+                        HostError::RequireSources { sources }
+                    }
+                    ImportError::RequireDependencies { dependencies } => {
+                        HostError::RequireDependencies {
+                            dependencies: dependencies
+                                .into_iter()
+                                .map(|dep| (dep.source, dep.target))
+                                .collect(),
+                        }
+                    }
+                    ImportError::Cancelled => HostError::Cancelled,
+                    ImportError::Other { reason } => HostError::Other { reason },
+                    ImportError::Panicked { message } => HostError::Panicked { message },
+                });
+
+                send(&HostMessage::Imported { result })?;
+            }
+            StoreMessage::Source { .. }
+            | StoreMessage::Dependency { .. }
+            | StoreMessage::Cancelled { .. } => {
+                return Err(eyre::eyre!("Unexpected message from store"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Running importer host process.
+struct Worker {
+    child: Child,
+
+    /// Closed on drop to let the host drop importers and exit.
+    stdin: Option<ChildStdin>,
+
+    /// Messages read from child's stdout by reader thread.
+    /// Disconnected when stdout is closed.
+    messages: Receiver<HostMessage>,
+
+    /// Emits log records forwarded from the host.
+    logger: Logger,
+}
+
+impl Worker {
+    fn spawn(
+        settings: &HostSettings,
+        lib_path: &Path,
+        config: Option<&str>,
+    ) -> eyre::Result<(Self, Vec<ImporterDesc>)> {
+        let mut command = Command::new(&settings.executable);
+        command
+            .arg(lib_path)
+            .env(LOG_LEVEL_ENV, LevelFilter::current().to_string());
+
+        if let Some(config) = config {
+            command.env(CONFIG_ENV, config);
+        }
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to spawn importer host '{}'",
+                    settings.executable.display()
+                )
+            })?;
+
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+
+        let (tx, messages) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            match read_message(&mut stdout) {
+                Ok(Some(message)) => {
+                    if tx.send(message).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(err) => {
+                    tracing::error!("Importer host sent invalid message. {:#}", err);
+                    return;
+                }
+            }
+        });
+
+        let mut worker = Worker {
+            child,
+            stdin: Some(stdin),
+            messages,
+            logger: Logger::new(),
+        };
+
+        match worker.recv(settings.timeout)? {
+            HostMessage::Loaded { importers } => Ok((worker, importers)),
+            HostMessage::LoadFailed { reason } => Err(eyre::eyre!(reason)),
+            _ => Err(eyre::eyre!("Unexpected message from importer host")),
+        }
+    }
+
+    fn send(&mut self, message: &StoreMessage) -> eyre::Result<()> {
+        let stdin = self.stdin.as_mut().expect("Stdin is closed only on drop");
+        write_message(stdin, message)
+    }
+
+    /// Receives next message other than log record.
+    /// Log records are emitted on the calling thread.
+    fn recv(&mut self, timeout: Duration) -> eyre::Result<HostMessage> {
+        loop {
+            match self.messages.recv_timeout(timeout) {
+                Ok(HostMessage::Log { record }) => self.logger.log(&record),
+                result => return self.received(result, timeout),
+            }
+        }
+    }
+
+    /// Same as [`Worker::recv`], but returns `None` on timeout.
+    fn try_recv(&mut self, timeout: Duration) -> eyre::Result<Option<HostMessage>> {
+        loop {
+            match self.messages.recv_timeout(timeout) {
+                Ok(HostMessage::Log { record }) => self.logger.log(&record),
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                result => return self.received(result, timeout).map(Some),
+            }
+        }
+    }
+
+    /// Kills the host without waiting for it to drop importers.
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    fn received(
+        &mut self,
+        result: Result<HostMessage, RecvTimeoutError>,
+        timeout: Duration,
+    ) -> eyre::Result<HostMessage> {
+        match result {
+            Ok(message) => Ok(message),
+            Err(RecvTimeoutError::Timeout) => Err(eyre::eyre!(
+                "Importer host did not respond in {} seconds",
+                timeout.as_secs()
+            )),
+            Err(RecvTimeoutError::Disconnected) => match self.child.wait() {
+                Ok(status) => Err(eyre::eyre!("Importer host exited. {}", status)),
+                Err(err) => Err(eyre::eyre!("Importer host exited. {}", err)),
+            },
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // Host exits when stdin is closed, dropping created importers.
+        // Hung host is killed.
+        drop(self.stdin.take());
+
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+                _ => return,
+            }
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Importers library loaded into importer host.
+pub(crate) struct HostedLibrary {
+    lib_path: PathBuf,
+
+    /// Passed to the library when host is restarted.
+    config: Option<String>,
+    settings: Arc<HostSettings>,
+
+    /// Running host. `None` after host failed, until next use.
+    worker: Mutex<Option<Worker>>,
+}
+
+impl HostedLibrary {
+    /// Runs `f` with running worker.
+    /// Starts new worker if there is none.
+    /// Stops worker on failure.
+    fn with_worker<R>(&self, f: impl FnOnce(&mut Worker) -> eyre::Result<R>) -> eyre::Result<R> {
+        let mut worker = self.worker.lock();
+
+        let running = match &mut *worker {
+            Some(running) => running,
+            None => {
+                tracing::info!("Restarting importer host for '{}'", self.lib_path.display());
+                let (running, _) =
+                    Worker::spawn(&self.settings, &self.lib_path, self.config.as_deref())?;
+                worker.insert(running)
+            }
+        };
+
+        let result = f(running);
+        if result.is_err() {
+            *worker = None;
+        }
+        result
+    }
+}
+
+/// Loads importers from the library into new importer host.
+pub(crate) fn load_importers(
+    settings: &Arc<HostSettings>,
+    lib_path: &Path,
+    config: Option<&str>,
+) -> eyre::Result<Vec<ProcessImporter>> {
+    let (worker, descs) = Worker::spawn(settings, lib_path, config)?;
+
+    let library = Arc::new(HostedLibrary {
+        lib_path: lib_path.to_owned(),
+        config: config.map(str::to_owned),
+        settings: settings.clone(),
+        worker: Mutex::new(Some(worker)),
+    });
+
+    Ok(descs
+        .into_iter()
+        .enumerate()
+        .map(|(idx, desc)| ProcessImporter {
+            library: library.clone(),
+            index: idx as u32,
+            name: desc.name.into(),
+            formats: desc.formats.into_iter().map(Into::into).collect(),
+            extensions: desc.extensions.into_iter().map(Into::into).collect(),
+            target: desc.target.into(),
+            magic: desc.magic.into_iter().map(Into::into).collect(),
+            description: desc.description.into(),
+            version: desc.version.map(Into::into),
+            package: desc.package.map(Into::into),
+            options_schema: desc.options_schema.map(Into::into),
+        })
+        .collect())
+}
+
+/// Importer that runs in importer host.
+pub(crate) struct ProcessImporter {
+    library: Arc<HostedLibrary>,
+    index: u32,
+    name: Box<str>,
+    formats: Vec<Box<str>>,
+    extensions: Vec<Box<str>>,
+    target: Box<str>,
+    magic: Vec<Box<[u8]>>,
+    description: Box<str>,
+    version: Option<Box<str>>,
+    package: Option<Box<str>>,
+    options_schema: Option<Box<str>>,
+}
+
+impl Importer for ProcessImporter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formats(&self) -> &[&str] {
+        unsafe {
+            std::slice::from_raw_parts(self.formats.as_ptr() as *const &str, self.formats.len())
+        }
+    }
+
+    fn extensions(&self) -> &[&str] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.extensions.as_ptr() as *const &str,
+                self.extensions.len(),
+            )
+        }
+    }
+
+    fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    fn package(&self) -> Option<&str> {
+        self.package.as_deref()
+    }
+
+    fn options_schema(&self) -> Option<&str> {
+        self.options_schema.as_deref()
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        unsafe { std::slice::from_raw_parts(self.magic.as_ptr() as *const &[u8], self.magic.len()) }
+    }
+
+    fn probe(&self, head: &[u8]) -> bool {
+        let timeout = self.library.settings.timeout;
+
+        let result = self.library.with_worker(|worker| {
+            worker.send(&StoreMessage::Probe {
+                importer: self.index,
+                head: head.to_owned(),
+            })?;
+
+            match worker.recv(timeout)? {
+                HostMessage::Probed { recognized } => Ok(recognized),
+                _ => Err(eyre::eyre!("Unexpected message from importer host")),
+            }
+        });
+
+        match result {
+            Ok(recognized) => recognized,
+            Err(err) => {
+                tracing::error!("Importer '{}' failed to probe. {:#}", self.name, err);
+                false
+            }
+        }
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        options: Option<&str>,
+        sources: &mut dyn Sources,
+        dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        let timeout = self.library.settings.timeout;
+        let mut killed = false;
+
+        let result = self.library.with_worker(|worker| {
+            worker.send(&StoreMessage::Import {
+                importer: self.index,
+                source: source.to_owned(),
+                output: output.to_owned(),
+                options: options.map(str::to_owned),
+            })?;
+
+            // Host is killed if importer does not stop soon after cancellation,
+            // so that timeouts are enforced.
+            let mut responded = Instant::now();
+            let mut cancelled = None;
+
+            loop {
+                let message = worker.try_recv(CANCELLATION_POLL_INTERVAL)?;
+
+                if cancelled.is_none() && cancellation.is_cancelled() {
+                    cancelled = Some(Instant::now());
+                }
+
+                if cancelled.is_some_and(|at| at.elapsed() >= CANCELLATION_GRACE) {
+                    worker.kill();
+                    killed = true;
+                    return Err(eyre::eyre!("Importer did not stop after cancellation"));
+                }
+
+                let Some(message) = message else {
+                    if responded.elapsed() >= timeout {
+                        return Err(eyre::eyre!(
+                            "Importer host did not respond in {} seconds",
+                            timeout.as_secs()
+                        ));
+                    }
+                    continue;
+                };
+                responded = Instant::now();
+
+                match message {
+                    HostMessage::GetSource { source } => {
+                        let result = sources.get(&source);
+                        worker.send(&StoreMessage::Source { result })?;
+                    }
+                    HostMessage::GetDependency { source, target } => {
+                        let result = dependencies.get(&source, &target);
+                        worker.send(&StoreMessage::Dependency { result })?;
+                    }
+                    HostMessage::IsCancelled => {
+                        let cancelled = cancellation.is_cancelled();
+                        worker.send(&StoreMessage::Cancelled { cancelled })?;
+                    }
+                    HostMessage::Diagnostic { diagnostic } => diagnostics.report(diagnostic),
+                    HostMessage::Progress { fraction, stage } => progress.report(fraction, &stage),
+                    HostMessage::Imported { result } => return Ok(result),
+                    _ => return Err(eyre::eyre!("Unexpected message from importer host")),
+                }
+            }
+        });
+
+        if killed {
+            tracing::warn!(
+                "Importer '{}' did not stop after cancellation, importer host for '{}' is killed",
+                self.name,
+                self.library.lib_path.display()
+            );
+            return Err(ImportError::Cancelled);
+        }
+
+        match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(HostError::RequireSources { sources })) => {
+                Err(ImportError::RequireSources { sources })
+            }
+            Ok(Err(HostError::RequireDependencies { dependencies })) => {
+                Err(ImportError::RequireDependencies {
+                    dependencies: dependencies
+                        .into_iter()
+                        .map(|(source, target)| Dependency { source, target })
+                        .collect(),
+                })
+            }
+            Ok(Err(HostError::Cancelled)) => Err(ImportError::Cancelled),
+            Ok(Err(HostError::Other { reason })) => Err(ImportError::Other { reason }),
+            Ok(Err(HostError::Panicked { message })) => Err(ImportError::Panicked { message }),
+            Err(err) => Err(ImportError::Other {
+                reason: format!(
+                    "Importer host for '{}' failed. {:#}",
+                    self.library.lib_path.display(),
+                    err
+                ),
+            }),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs::File, os::unix::fs::PermissionsExt};
+
+    use treasury_import_testing::{FakeDependencies, FakeSources};
+
+    use super::*;
+
+    struct Cancelled;
+
+    impl Cancellation for Cancelled {
+        fn is_cancelled(&self) -> bool {
+            true
+        }
+    }
+
+    /// Writes host that reports loaded importer and hangs.
+    fn hanging_host(dir: &Path) -> PathBuf {
+        let loaded = dir.join("loaded");
+        let message = HostMessage::Loaded {
+            importers: vec![ImporterDesc {
+                name: "Hanging".to_owned(),
+                formats: vec!["hang".to_owned()],
+                extensions: Vec::new(),
+                target: "hang".to_owned(),
+                magic: Vec::new(),
+                description: String::new(),
+                version: None,
+                package: None,
+                options_schema: None,
+            }],
+        };
+        write_message(&mut File::create(&loaded).unwrap(), &message).unwrap();
+
+        let executable = dir.join("host.sh");
+        std::fs::write(
+            &executable,
+            format!("#!/bin/sh\ncat '{}'\nexec sleep 60\n", loaded.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+        executable
+    }
+
+    #[test]
+    fn kills_host_after_cancellation() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = Arc::new(HostSettings {
+            executable: hanging_host(dir.path()),
+            timeout: Duration::from_secs(60),
+        });
+
+        let importers = load_importers(&settings, &dir.path().join("lib.so"), None).unwrap();
+        assert_eq!(importers.len(), 1);
+
+        let source = dir.path().join("source.hang");
+        std::fs::write(&source, "source").unwrap();
+
+        let started = Instant::now();
+        let result = importers[0].import(
+            &source,
+            &dir.path().join("output"),
+            None,
+            &mut FakeSources::new().unwrap(),
+            &mut FakeDependencies::new(),
+            &Cancelled,
+            &mut Vec::new(),
+            &mut (),
+        );
+
+        assert!(matches!(result, Err(ImportError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(importers[0].library.worker.lock().is_none());
+    }
+}
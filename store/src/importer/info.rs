@@ -0,0 +1,67 @@
+use std::{path::PathBuf, sync::Arc};
+
+use treasury_import::loading::LoadingError;
+
+/// Where importer comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImporterOrigin {
+    /// Registered with `Treasury::register_importer`.
+    Static,
+
+    /// Loaded from dynamic library at the path.
+    Library(PathBuf),
+}
+
+/// Format or extension claimed by an importer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Claim {
+    Format(String),
+    Extension(String),
+}
+
+/// Format or extension to the same target claimed by several importers.
+#[derive(Clone, Debug)]
+pub struct ImporterConflict {
+    pub claim: Claim,
+
+    /// Other importers that claim the same format or extension.
+    pub importers: Vec<String>,
+
+    /// Importer chosen by preferences and priorities.
+    /// `None` if they cannot decide.
+    /// Source overrides may still choose differently for particular sources.
+    pub preferred: Option<String>,
+}
+
+/// Information about registered importer.
+#[derive(Clone, Debug)]
+pub struct ImporterInfo {
+    pub name: String,
+    pub description: String,
+
+    /// Semver version declared by the importer.
+    pub version: Option<String>,
+
+    /// Crate or library the importer declares it comes from.
+    pub package: Option<String>,
+
+    /// JSON Schema of per-asset options.
+    pub options_schema: Option<String>,
+
+    pub origin: ImporterOrigin,
+    pub formats: Vec<String>,
+    pub extensions: Vec<String>,
+    pub target: String,
+    pub priority: i32,
+    pub conflicts: Vec<ImporterConflict>,
+}
+
+/// Importers library that failed to load.
+#[derive(Clone, Debug, thiserror::Error)]
+#[error("Failed to load importers from '{}'", .path.display())]
+pub struct LibraryError {
+    pub path: PathBuf,
+
+    #[source]
+    pub error: Arc<LoadingError>,
+}
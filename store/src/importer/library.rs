@@ -0,0 +1,55 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Importers libraries listed in `Treasury.toml`.
+///
+/// Either list of paths or `[importers.<name>]` tables.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ImportersLibs {
+    /// Libraries without configuration.
+    Paths(Vec<PathBuf>),
+
+    /// Libraries with configuration by name.
+    /// Loaded in order of names.
+    Named(BTreeMap<String, ImportersLib>),
+}
+
+/// Importers library with configuration.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ImportersLib {
+    pub path: PathBuf,
+
+    /// All other keys of the table.
+    /// Passed to the library as JSON object when it creates importers.
+    #[serde(flatten)]
+    pub config: toml::value::Table,
+}
+
+impl Default for ImportersLibs {
+    fn default() -> Self {
+        ImportersLibs::Paths(Vec::new())
+    }
+}
+
+impl ImportersLibs {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            ImportersLibs::Paths(paths) => paths.is_empty(),
+            ImportersLibs::Named(libs) => libs.is_empty(),
+        }
+    }
+
+    /// Returns paths of all libraries along with configuration for those that have it.
+    pub fn libraries(&self) -> Vec<(&Path, Option<&toml::value::Table>)> {
+        match self {
+            ImportersLibs::Paths(paths) => paths.iter().map(|path| (&**path, None)).collect(),
+            ImportersLibs::Named(libs) => libs
+                .values()
+                .map(|lib| (&*lib.path, Some(&lib.config)))
+                .collect(),
+        }
+    }
+}
@@ -1,97 +1,804 @@
-use std::path::Path;
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use hashbrown::{hash_map::RawEntryMut, HashMap};
+use hashbrown::{hash_map::Entry, HashMap};
 use treasury_import::{loading::LoadingError, Importer};
 
+use crate::{host::HostSettings, sha256::Sha256Hash};
+
+pub use self::{
+    info::{Claim, ImporterConflict, ImporterInfo, ImporterOrigin, LibraryError},
+    library::{ImportersLib, ImportersLibs},
+    selection::{ImporterPreferences, SourceOverride},
+};
+
+use self::{
+    options::{validate_metadata, validate_options},
+    selection::Preferences,
+};
+
+mod info;
+mod library;
+mod options;
+mod selection;
+
 #[derive(Debug, thiserror::Error)]
-#[error("Multiple importers may import from different formats '{formats:?}' to target '{target}'")]
+#[error(
+    "Cannot decide on importer to target '{target}'. Candidates: {}. Set importer preference or priority in `Treasury.toml`",
+    .candidates.join(", ")
+)]
 pub struct CannotDecideOnImporter {
-    formats: Vec<String>,
+    candidates: Vec<String>,
+    target: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Multiple import chains of the same length lead to target '{target}'. Candidates: {}. Set importer preference or priority in `Treasury.toml`",
+    .candidates.join(", ")
+)]
+pub struct AmbiguousImportChain {
+    candidates: Vec<String>,
     target: String,
 }
 
+/// Maximum number of chains listed in [`AmbiguousImportChain`].
+const MAX_LISTED_CHAINS: usize = 8;
+
+/// Importer with its registration details.
+#[derive(Clone)]
+struct Registered {
+    importer: Arc<dyn Importer>,
+
+    /// Library importer was loaded from.
+    /// `None` for statically registered importers.
+    library: Option<Arc<Path>>,
+
+    /// Hash of the library file and its configuration.
+    /// `None` for statically registered importers.
+    revision: Option<Sha256Hash>,
+
+    /// Options schema compiled when importer is registered.
+    options_schema: Option<Arc<jsonschema::Validator>>,
+}
+
+impl Registered {
+    fn describe(&self, priority: i32) -> String {
+        match &self.library {
+            None => format!("'{}' (static, priority {})", self.importer.name(), priority),
+            Some(library) => format!(
+                "'{}' (from '{}', priority {})",
+                self.importer.name(),
+                library.display(),
+                priority
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
 struct ToTarget {
-    importers: Vec<Box<dyn Importer>>,
-    formats: HashMap<String, usize>,
-    extensions: HashMap<String, usize>,
+    importers: Vec<Registered>,
+
+    /// Indices of importers that claim each format, in registration order.
+    formats: HashMap<String, Vec<usize>>,
+
+    /// Indices of importers that claim each extension, in registration order.
+    extensions: HashMap<String, Vec<usize>>,
 }
 
+impl ToTarget {
+    /// Finds importers by media type.
+    /// Tries full media type, then subtype and then subtype without structured syntax suffix,
+    /// e.g. `image/svg+xml`, `svg+xml` and `svg`.
+    fn by_media_type(&self, media_type: &str) -> Option<&[usize]> {
+        let subtype = media_type.split_once('/').map(|(_, subtype)| subtype);
+        let base = subtype
+            .and_then(|subtype| subtype.split_once('+'))
+            .map(|(base, _)| base);
+
+        std::iter::once(media_type)
+            .chain(subtype)
+            .chain(base)
+            .find_map(|name| {
+                self.formats
+                    .get(name)
+                    .or_else(|| self.extensions.get(name))
+                    .map(|candidates| &**candidates)
+            })
+    }
+
+    fn find_by_name(&self, candidates: &[usize], name: &str) -> Option<usize> {
+        candidates
+            .iter()
+            .copied()
+            .find(|&idx| self.importers[idx].importer.name() == name)
+    }
+}
+
+#[derive(Clone)]
 pub struct Importers {
     targets: HashMap<String, ToTarget>,
+    preferences: Preferences,
+
+    /// Importer host to load libraries into.
+    /// Libraries are loaded into this process if `None`.
+    host: Option<Arc<HostSettings>>,
+
+    /// Configuration passed to libraries when they create importers.
+    /// Kept for libraries that are not loaded yet, to use it on reload.
+    configs: HashMap<PathBuf, Arc<str>>,
 }
 
 impl Importers {
-    pub fn new() -> Self {
-        Importers {
+    /// Returns error if preferences are invalid.
+    pub fn new(preferences: &ImporterPreferences) -> eyre::Result<Self> {
+        Ok(Importers {
             targets: HashMap::new(),
-        }
+            preferences: Preferences::new(preferences)?,
+            host: None,
+            configs: HashMap::new(),
+        })
+    }
+
+    /// Sets configuration of the library, JSON document.
+    /// Used when library is loaded or reloaded afterwards.
+    pub fn set_library_config(&mut self, lib_path: &Path, config: Option<&str>) {
+        match config {
+            None => self.configs.remove(lib_path),
+            Some(config) => self.configs.insert(lib_path.to_owned(), Arc::from(config)),
+        };
+    }
+
+    /// Sets importer host for libraries loaded afterwards.
+    pub fn set_host(&mut self, host: Option<HostSettings>) {
+        self.host = host.map(Arc::new);
+    }
+
+    /// Sets rules to choose between importers that claim the same source.
+    pub fn set_preferences(&mut self, preferences: &ImporterPreferences) -> eyre::Result<()> {
+        self.preferences = Preferences::new(preferences)?;
+        Ok(())
     }
 
     pub fn register_importer(&mut self, importer: impl treasury_import::Importer + 'static) {
-        self.add_importer(importer);
+        let options_schema = validate_metadata(&importer).unwrap_or_else(|err| {
+            tracing::warn!("{}", err);
+            None
+        });
+
+        self.add_importer(Registered {
+            importer: Arc::new(importer),
+            library: None,
+            revision: None,
+            options_schema,
+        });
     }
 
     /// Loads importers from dylib.
     /// There is no possible way to guarantee that dylib does not break safety contracts.
     /// Some measures to ensure safety are taken.
     /// Providing dylib from which importers will be successfully imported and then cause an UB should possible only on purpose.
-    pub unsafe fn load_dylib_importers(&mut self, lib_path: &Path) -> Result<(), LoadingError> {
-        let iter = treasury_import::loading::load_importers(lib_path)?;
+    ///
+    /// Library is loaded from a copy placed into `shadow` directory,
+    /// so that original file can be rebuilt while library is loaded
+    /// and new version is not confused with the old one by the system loader.
+    ///
+    /// Importers previously loaded from the same library are replaced.
+    /// Returns `false` if neither library nor its configuration changed since it was loaded.
+    pub unsafe fn load_dylib_importers(
+        &mut self,
+        lib_path: &Path,
+        shadow: &Path,
+    ) -> Result<bool, LoadingError> {
+        let file_hash = Sha256Hash::file_hash(lib_path).map_err(LoadingError::Io)?;
+        let config = self.configs.get(lib_path).cloned();
 
-        for importer in iter {
-            self.add_importer(importer);
+        // Configuration changes what importers produce as much as their code.
+        let revision = match &config {
+            None => file_hash,
+            Some(config) => Sha256Hash::new([&file_hash[..], config.as_bytes()].concat()),
+        };
+
+        if self.library_revision(lib_path) == Some(revision) {
+            return Ok(false);
         }
 
-        Ok(())
+        let importers: Vec<Arc<dyn Importer>> = if is_wasm(lib_path) {
+            // WebAssembly modules are sandboxed and loaded from memory,
+            // no need for shadow copy or importer host.
+            load_wasm_importers(lib_path, config.as_deref())?
+        } else {
+            let shadow_path =
+                shadow_copy(lib_path, shadow, &file_hash).map_err(LoadingError::Io)?;
+
+            self.load_native_importers(&shadow_path, config.as_deref())?
+        };
+
+        let options_schemas = importers
+            .iter()
+            .map(|importer| validate_metadata(&**importer))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(LoadingError::InvalidDescription)?;
+
+        let library: Arc<Path> = Arc::from(lib_path);
+
+        self.remove_library(lib_path);
+
+        for (importer, options_schema) in importers.into_iter().zip(options_schemas) {
+            self.add_importer(Registered {
+                importer,
+                library: Some(library.clone()),
+                revision: Some(revision),
+                options_schema,
+            });
+        }
+
+        Ok(true)
+    }
+
+    /// Loads importers from native library, in importer host if configured.
+    unsafe fn load_native_importers(
+        &self,
+        lib_path: &Path,
+        config: Option<&str>,
+    ) -> Result<Vec<Arc<dyn Importer>>, LoadingError> {
+        let importers = match &self.host {
+            None => treasury_import::loading::load_importers_with_config(lib_path, config)?
+                .map(|importer| Arc::new(importer) as Arc<dyn Importer>)
+                .collect(),
+            Some(host) => crate::host::load_importers(host, lib_path, config)
+                .map_err(|err| LoadingError::Host(format!("{:#}", err)))?
+                .into_iter()
+                .map(|importer| Arc::new(importer) as Arc<dyn Importer>)
+                .collect(),
+        };
+        Ok(importers)
+    }
+
+    /// Returns paths of all libraries importers were loaded from.
+    pub fn libraries(&self) -> Vec<Arc<Path>> {
+        let mut libraries: Vec<Arc<Path>> = Vec::new();
+        for to_target in self.targets.values() {
+            for registered in &to_target.importers {
+                if let Some(library) = &registered.library {
+                    if !libraries.contains(library) {
+                        libraries.push(library.clone());
+                    }
+                }
+            }
+        }
+        libraries
+    }
+
+    /// Returns hash of the library file importers were loaded from.
+    fn library_revision(&self, lib_path: &Path) -> Option<Sha256Hash> {
+        self.targets
+            .values()
+            .flat_map(|to_target| &to_target.importers)
+            .find(|registered| registered.library.as_deref() == Some(lib_path))
+            .and_then(|registered| registered.revision)
+    }
+
+    /// Removes all importers loaded from the library.
+    fn remove_library(&mut self, lib_path: &Path) {
+        for to_target in self.targets.values_mut() {
+            to_target
+                .importers
+                .retain(|registered| registered.library.as_deref() != Some(lib_path));
+
+            // Indices are shifted, rebuild claims.
+            to_target.formats.clear();
+            to_target.extensions.clear();
+
+            for (idx, registered) in to_target.importers.iter().enumerate() {
+                for &format in registered.importer.formats() {
+                    to_target
+                        .formats
+                        .entry(format.to_owned())
+                        .or_default()
+                        .push(idx);
+                }
+                for &extension in registered.importer.extensions() {
+                    to_target
+                        .extensions
+                        .entry(extension.to_owned())
+                        .or_default()
+                        .push(idx);
+                }
+            }
+        }
+
+        self.targets
+            .retain(|_, to_target| !to_target.importers.is_empty());
+    }
+
+    /// Returns revision of the importers chain to the target.
+    /// It is `None` when all importers are registered statically,
+    /// otherwise changes whenever any library used in the chain changes.
+    ///
+    /// Returns `None` if some importer from the chain is not registered anymore.
+    pub fn chain_revision(&self, chain: &[String], target: &str) -> Option<Option<Sha256Hash>> {
+        let mut revisions = Vec::with_capacity(chain.len());
+        let mut targets = vec![target];
+
+        // Walk the chain backwards, each step produces one of the formats consumed by the next one.
+        for name in chain.iter().rev() {
+            let registered = targets.iter().find_map(|&target| {
+                self.targets
+                    .get(target)?
+                    .importers
+                    .iter()
+                    .find(|registered| registered.importer.name() == name)
+            })?;
+
+            revisions.push(registered.revision);
+            targets = registered.importer.formats().to_vec();
+        }
+
+        if revisions.iter().all(Option::is_none) {
+            return Some(None);
+        }
+
+        let mut bytes = Vec::with_capacity(revisions.len() * 32);
+        for revision in revisions.iter().rev() {
+            match revision {
+                None => bytes.extend_from_slice(&[0; 32]),
+                Some(revision) => bytes.extend_from_slice(&revision[..]),
+            }
+        }
+        Some(Some(Sha256Hash::new(bytes)))
+    }
+
+    /// Returns information about all registered importers.
+    /// Sorted by target and name.
+    pub fn infos(&self) -> Vec<ImporterInfo> {
+        let mut infos = Vec::new();
+
+        for (target, to_target) in &self.targets {
+            for (idx, registered) in to_target.importers.iter().enumerate() {
+                let importer = &*registered.importer;
+
+                let claims = importer
+                    .formats()
+                    .iter()
+                    .map(|&format| {
+                        (
+                            Claim::Format(format.to_owned()),
+                            &to_target.formats[format],
+                            None,
+                        )
+                    })
+                    .chain(importer.extensions().iter().map(|&extension| {
+                        (
+                            Claim::Extension(extension.to_owned()),
+                            &to_target.extensions[extension],
+                            Some(extension),
+                        )
+                    }));
+
+                let conflicts = claims
+                    .filter(|(_, candidates, _)| candidates.len() > 1)
+                    .map(|(claim, candidates, extension)| ImporterConflict {
+                        claim,
+                        importers: candidates
+                            .iter()
+                            .filter(|&&other| other != idx)
+                            .map(|&other| to_target.importers[other].importer.name().to_owned())
+                            .collect(),
+                        preferred: self
+                            .select(to_target, candidates, extension, target)
+                            .ok()
+                            .map(|importer| importer.name().to_owned()),
+                    })
+                    .collect();
+
+                infos.push(ImporterInfo {
+                    name: importer.name().to_owned(),
+                    description: importer.description().to_owned(),
+                    version: importer.version().map(str::to_owned),
+                    package: importer.package().map(str::to_owned),
+                    options_schema: importer.options_schema().map(str::to_owned),
+                    origin: match &registered.library {
+                        None => ImporterOrigin::Static,
+                        Some(library) => ImporterOrigin::Library(library.to_path_buf()),
+                    },
+                    formats: importer.formats().iter().map(|&f| f.to_owned()).collect(),
+                    extensions: importer
+                        .extensions()
+                        .iter()
+                        .map(|&e| e.to_owned())
+                        .collect(),
+                    target: target.clone(),
+                    priority: self.preferences.priority(importer.name()),
+                    conflicts,
+                });
+            }
+        }
+
+        infos.sort_by(|a, b| (&a.target, &a.name).cmp(&(&b.target, &b.name)));
+        infos
     }
 
     /// Try to guess importer by optionally provided format and extension or by target alone.
+    ///
+    /// Media type, such as one declared in `data:` URL, is used as a hint when extension is not available.
+    /// It matches importers that declare either full media type or its subtype as format or extension.
+    ///
+    /// Candidates are narrowed down to importers that recognize `head` of the source, if any does,
+    /// before preferences and priorities are applied.
+    ///
+    /// `source` is path relative to treasury base directory or full URL of the source.
+    /// It is matched against source overrides that take precedence over everything else.
     pub fn guess(
         &self,
+        source: &str,
         format: Option<&str>,
         extension: Option<&str>,
+        media_type: Option<&str>,
+        head: &[u8],
         target: &str,
     ) -> Result<Option<&dyn Importer>, CannotDecideOnImporter> {
         tracing::debug!("Guessing importer to '{}'", target);
 
-        let to_target = self.targets.get(target);
-
-        match to_target {
+        let to_target = match self.targets.get(target) {
             None => {
                 tracing::debug!("No importers to '{}' found", target);
-                Ok(None)
+                return Ok(None);
             }
-            Some(to_target) => match format {
-                None => match extension {
-                    None => match to_target.importers.len() {
-                        0 => {
-                            unreachable!()
-                        }
-                        1 => Ok(Some(&*to_target.importers[0])),
-                        _ => {
-                            tracing::debug!("Multiple importers to '{}' found", target);
-                            Err(CannotDecideOnImporter {
-                                target: target.to_owned(),
-                                formats: to_target.formats.keys().cloned().collect(),
-                            })
+            Some(to_target) => to_target,
+        };
+
+        if let Some(name) = self.preferences.source_override(source, target) {
+            let all = (0..to_target.importers.len()).collect::<Vec<_>>();
+            match to_target.find_by_name(&all, name) {
+                Some(idx) => return Ok(Some(&*to_target.importers[idx].importer)),
+                None => tracing::warn!(
+                    "Importer '{}' set for source '{}' does not import to '{}'",
+                    name,
+                    source,
+                    target
+                ),
+            }
+        }
+
+        match format {
+            None => match extension {
+                None => {
+                    if let Some(media_type) = media_type {
+                        if let Some(candidates) = to_target.by_media_type(media_type) {
+                            let candidates = recognized(to_target, candidates, head);
+                            return self.select(to_target, &candidates, None, target).map(Some);
                         }
-                    },
-                    Some(extension) => match to_target.extensions.get(extension) {
-                        None => Ok(None),
-                        Some(&idx) => Ok(Some(&*to_target.importers[idx])),
-                    },
-                },
-                Some(format) => match to_target.formats.get(format) {
+                        tracing::debug!(
+                            "No importers to '{}' match media type '{}'",
+                            target,
+                            media_type
+                        );
+                    }
+
+                    let all = (0..to_target.importers.len()).collect::<Vec<_>>();
+                    let candidates = recognized(to_target, &all, head);
+                    self.select(to_target, &candidates, None, target).map(Some)
+                }
+                Some(extension) => match to_target.extensions.get(extension) {
                     None => Ok(None),
-                    Some(&idx) => Ok(Some(&*to_target.importers[idx])),
+                    Some(candidates) => {
+                        let candidates = recognized(to_target, candidates, head);
+                        self.select(to_target, &candidates, Some(extension), target)
+                            .map(Some)
+                    }
                 },
             },
+            Some(format) => match to_target.formats.get(format) {
+                None => Ok(None),
+                Some(candidates) => {
+                    let candidates = recognized(to_target, candidates, head);
+                    self.select(to_target, &candidates, None, target).map(Some)
+                }
+            },
+        }
+    }
+
+    /// Try to pick importer to `target` by content of the source.
+    /// `head` should contain first bytes of the source, up to [`treasury_import::PROBE_LEN`].
+    ///
+    /// Importers that either declare magic bytes `head` starts with or whose probe function accepts `head` are candidates.
+    pub fn sniff(
+        &self,
+        head: &[u8],
+        target: &str,
+    ) -> Result<Option<&dyn Importer>, CannotDecideOnImporter> {
+        tracing::debug!("Sniffing importer to '{}'", target);
+
+        let to_target = match self.targets.get(target) {
+            None => return Ok(None),
+            Some(to_target) => to_target,
+        };
+
+        let candidates = (0..to_target.importers.len())
+            .filter(|&idx| recognizes(&*to_target.importers[idx].importer, head))
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        self.select(to_target, &candidates, None, target).map(Some)
+    }
+
+    /// Checks `options` against options schema of registered `importer`.
+    pub fn validate_options(&self, importer: &dyn Importer, options: &str) -> eyre::Result<()> {
+        let schema = self
+            .targets
+            .get(importer.target())
+            .and_then(|to_target| {
+                to_target
+                    .importers
+                    .iter()
+                    .find(|registered| std::ptr::addr_eq(&*registered.importer, importer))
+            })
+            .and_then(|registered| registered.options_schema.as_deref());
+
+        validate_options(importer, schema, options)
+    }
+
+    /// Finds shortest chain of importers that converts source to `target` through intermediate formats.
+    ///
+    /// First importer in the chain is picked by `format` if provided.
+    /// Otherwise by extension, media type or by content of the source, in that order.
+    /// Each next importer accepts target format of the previous one.
+    pub fn find_chain(
+        &self,
+        format: Option<&str>,
+        extension: Option<&str>,
+        media_type: Option<&str>,
+        head: &[u8],
+        target: &str,
+    ) -> Result<Option<Vec<&dyn Importer>>, AmbiguousImportChain> {
+        tracing::debug!("Searching import chain to '{}'", target);
+
+        struct Reached<'a> {
+            distance: usize,
+
+            /// Last steps of all shortest chains to the format.
+            /// Previous format, `None` for the first step, and importer.
+            via: Vec<(Option<&'a str>, &'a dyn Importer)>,
+        }
+
+        // Breadth-first search over formats.
+        let mut reached: HashMap<&str, Reached> = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        for importer in self.first_steps(format, extension, media_type, head) {
+            match reached.entry(importer.target()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(Reached {
+                        distance: 1,
+                        via: vec![(None, importer)],
+                    });
+                    queue.push_back(importer.target());
+                }
+                Entry::Occupied(mut entry) => entry.get_mut().via.push((None, importer)),
+            }
+        }
+
+        while let Some(node) = queue.pop_front() {
+            if node == target {
+                break;
+            }
+
+            let distance = reached[node].distance + 1;
+
+            for (next, to_target) in &self.targets {
+                let candidates = match to_target.formats.get(node) {
+                    None => continue,
+                    Some(candidates) => candidates,
+                };
+
+                // Every candidate continues a chain when preferences cannot decide.
+                let steps = match self.select(to_target, candidates, None, next) {
+                    Ok(importer) => vec![importer],
+                    Err(_) => candidates
+                        .iter()
+                        .map(|&idx| &*to_target.importers[idx].importer)
+                        .collect(),
+                };
+
+                for importer in steps {
+                    match reached.entry(next.as_str()) {
+                        Entry::Vacant(entry) => {
+                            entry.insert(Reached {
+                                distance,
+                                via: vec![(Some(node), importer)],
+                            });
+                            queue.push_back(next.as_str());
+                        }
+                        Entry::Occupied(mut entry) => {
+                            if entry.get().distance == distance {
+                                entry.get_mut().via.push((Some(node), importer));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if !reached.contains_key(target) {
+            tracing::debug!("No import chain to '{}' found", target);
+            return Ok(None);
+        }
+
+        // Collects shortest chains to the format, up to `limit`.
+        fn chains<'a>(
+            reached: &HashMap<&str, Reached<'a>>,
+            node: &str,
+            limit: usize,
+        ) -> Vec<Vec<&'a dyn Importer>> {
+            let mut found = Vec::new();
+            for &(prev, importer) in &reached[node].via {
+                match prev {
+                    None => found.push(vec![importer]),
+                    Some(prev) => {
+                        for mut chain in chains(reached, prev, limit - found.len()) {
+                            chain.push(importer);
+                            found.push(chain);
+                        }
+                    }
+                }
+                if found.len() >= limit {
+                    found.truncate(limit);
+                    break;
+                }
+            }
+            found
+        }
+
+        let mut chains = chains(&reached, target, MAX_LISTED_CHAINS);
+        if chains.len() > 1 {
+            return Err(AmbiguousImportChain {
+                candidates: chains
+                    .iter()
+                    .map(|chain| {
+                        chain
+                            .iter()
+                            .map(|importer| format!("'{}'", importer.name()))
+                            .collect::<Vec<_>>()
+                            .join(" -> ")
+                    })
+                    .collect(),
+                target: target.to_owned(),
+            });
         }
+
+        Ok(chains.pop())
     }
 
-    fn add_importer(&mut self, importer: impl Importer + 'static) {
-        let importer = Box::new(importer);
+    /// Returns importers to any target that accept the source.
+    /// Importers to the same target are returned together when preferences cannot decide between them.
+    fn first_steps(
+        &self,
+        format: Option<&str>,
+        extension: Option<&str>,
+        media_type: Option<&str>,
+        head: &[u8],
+    ) -> Vec<&dyn Importer> {
+        let find = |f: &dyn Fn(&ToTarget) -> Vec<usize>| -> Vec<&dyn Importer> {
+            let mut steps = Vec::new();
+            for (target, to_target) in &self.targets {
+                let candidates = f(to_target);
+                if candidates.is_empty() {
+                    continue;
+                }
+
+                let candidates = recognized(to_target, &candidates, head);
+
+                match self.select(to_target, &candidates, extension, target) {
+                    Ok(importer) => steps.push(importer),
+                    Err(_) => steps.extend(
+                        candidates
+                            .iter()
+                            .map(|&idx| &*to_target.importers[idx].importer),
+                    ),
+                }
+            }
+            steps
+        };
+
+        if let Some(format) = format {
+            return find(&|to_target| to_target.formats.get(format).cloned().unwrap_or_default());
+        }
+
+        if let Some(extension) = extension {
+            let steps = find(&|to_target| {
+                to_target
+                    .extensions
+                    .get(extension)
+                    .cloned()
+                    .unwrap_or_default()
+            });
+            if !steps.is_empty() {
+                return steps;
+            }
+        }
 
+        if let Some(media_type) = media_type {
+            let steps = find(&|to_target| {
+                to_target
+                    .by_media_type(media_type)
+                    .map(<[usize]>::to_vec)
+                    .unwrap_or_default()
+            });
+            if !steps.is_empty() {
+                return steps;
+            }
+        }
+
+        find(&|to_target| {
+            (0..to_target.importers.len())
+                .filter(|&idx| recognizes(&*to_target.importers[idx].importer, head))
+                .collect()
+        })
+    }
+
+    /// Chooses one of the candidate importers to `target`.
+    ///
+    /// Importer preferred for the extension wins, then importer preferred for the target,
+    /// then the one with highest priority.
+    fn select<'a>(
+        &self,
+        to_target: &'a ToTarget,
+        candidates: &[usize],
+        extension: Option<&str>,
+        target: &str,
+    ) -> Result<&'a dyn Importer, CannotDecideOnImporter> {
+        if let [idx] = *candidates {
+            return Ok(&*to_target.importers[idx].importer);
+        }
+
+        let preferred = extension
+            .and_then(|extension| self.preferences.for_extension(extension))
+            .into_iter()
+            .chain(self.preferences.for_target(target));
+
+        for name in preferred {
+            if let Some(idx) = to_target.find_by_name(candidates, name) {
+                return Ok(&*to_target.importers[idx].importer);
+            }
+        }
+
+        let priority = |idx: usize| {
+            self.preferences
+                .priority(to_target.importers[idx].importer.name())
+        };
+
+        let max_priority = candidates.iter().map(|&idx| priority(idx)).max();
+
+        let mut top = candidates
+            .iter()
+            .copied()
+            .filter(|&idx| Some(priority(idx)) == max_priority);
+
+        if let (Some(idx), None) = (top.next(), top.next()) {
+            return Ok(&*to_target.importers[idx].importer);
+        }
+
+        tracing::debug!("Multiple importers to '{}' found", target);
+
+        Err(CannotDecideOnImporter {
+            candidates: candidates
+                .iter()
+                .map(|&idx| to_target.importers[idx].describe(priority(idx)))
+                .collect(),
+            target: target.to_owned(),
+        })
+    }
+
+    fn add_importer(&mut self, registered: Registered) {
+        let importer = &*registered.importer;
         let name = importer.name();
         let target = importer.target();
         let formats = importer.formats();
@@ -105,66 +812,377 @@ impl Importers {
             extensions,
         );
 
-        match self.targets.raw_entry_mut().from_key(target) {
-            RawEntryMut::Vacant(entry) => {
-                let to_target = entry
-                    .insert(
-                        target.to_owned(),
-                        ToTarget {
-                            importers: Vec::new(),
-                            formats: HashMap::new(),
-                            extensions: HashMap::new(),
-                        },
-                    )
-                    .1;
-
-                for &format in &*formats {
-                    to_target.formats.insert(format.to_owned(), 0);
-                }
+        let to_target = self
+            .targets
+            .entry(target.to_owned())
+            .or_insert_with(|| ToTarget {
+                importers: Vec::new(),
+                formats: HashMap::new(),
+                extensions: HashMap::new(),
+            });
 
-                for &extension in &*extensions {
-                    to_target.extensions.insert(extension.to_owned(), 0);
-                }
-                to_target.importers.push(importer);
+        let idx = to_target.importers.len();
+
+        for &format in formats {
+            let candidates = to_target.formats.entry(format.to_owned()).or_default();
+            if !candidates.is_empty() {
+                tracing::debug!(
+                    "'{}' -> '{}' is also claimed by {} other importers",
+                    format,
+                    target,
+                    candidates.len(),
+                );
             }
-            RawEntryMut::Occupied(entry) => {
-                let to_target = entry.into_mut();
-                let idx = to_target.importers.len();
+            candidates.push(idx);
+        }
 
-                for &format in &*formats {
-                    match to_target.formats.raw_entry_mut().from_key(format) {
-                        RawEntryMut::Vacant(entry) => {
-                            entry.insert(format.to_owned(), idx);
-                        }
-                        RawEntryMut::Occupied(entry) => {
-                            tracing::error!(
-                                "'{}' -> '{}' importer already registered: {:#?}",
-                                format,
-                                target,
-                                entry.get(),
-                            );
-                        }
-                    }
-                }
+        for &extension in extensions {
+            let candidates = to_target
+                .extensions
+                .entry(extension.to_owned())
+                .or_default();
+            if !candidates.is_empty() {
+                tracing::debug!(
+                    "'.{}' -> '{}' is also claimed by {} other importers",
+                    extension,
+                    target,
+                    candidates.len(),
+                );
+            }
+            candidates.push(idx);
+        }
 
-                for &extension in &*extensions {
-                    match to_target.extensions.raw_entry_mut().from_key(extension) {
-                        RawEntryMut::Vacant(entry) => {
-                            entry.insert(extension.to_owned(), idx);
-                        }
-                        RawEntryMut::Occupied(entry) => {
-                            tracing::error!(
-                                "'.{}' -> '{}' importer already registered: {:#?}",
-                                extension,
-                                target,
-                                entry.get(),
-                            );
-                        }
-                    }
-                }
+        to_target.importers.push(registered);
+    }
+}
+
+/// Checks if library is a WebAssembly module.
+fn is_wasm(lib_path: &Path) -> bool {
+    lib_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("wasm"))
+}
+
+#[cfg(feature = "wasm")]
+fn load_wasm_importers(
+    lib_path: &Path,
+    config: Option<&str>,
+) -> Result<Vec<Arc<dyn Importer>>, LoadingError> {
+    Ok(crate::wasm::load_importers(lib_path, config)?
+        .into_iter()
+        .map(|importer| Arc::new(importer) as Arc<dyn Importer>)
+        .collect())
+}
+
+#[cfg(not(feature = "wasm"))]
+fn load_wasm_importers(
+    _lib_path: &Path,
+    _config: Option<&str>,
+) -> Result<Vec<Arc<dyn Importer>>, LoadingError> {
+    Err(LoadingError::Wasm(
+        "WebAssembly importers require `wasm` feature of `treasury-store`".to_owned(),
+    ))
+}
+
+/// Copies library into `shadow` directory under a name unique for its content.
+fn shadow_copy(lib_path: &Path, shadow: &Path, revision: &Sha256Hash) -> std::io::Result<PathBuf> {
+    let stem = lib_path.file_stem().unwrap_or_default().to_string_lossy();
+    let hex = format!("{:x}", revision);
 
-                to_target.importers.push(importer);
+    let mut file_name = format!("{}-{}", stem, &hex[..16]);
+    if let Some(extension) = lib_path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+
+    let shadow_path = shadow.join(file_name);
+
+    // Same content is already there and may be loaded. Overwriting it could break running code.
+    if !shadow_path.exists() {
+        if !shadow.exists() {
+            std::fs::create_dir_all(shadow)?;
+
+            if let Err(err) = std::fs::write(shadow.join(".gitignore"), "*") {
+                tracing::error!(
+                    "Failed to place .gitignore into importers directory. {:#}",
+                    err
+                );
             }
         }
+
+        // Copy under temporary name first, so that partially written file is never loaded.
+        let partial = shadow_path.with_extension("partial");
+        std::fs::copy(lib_path, &partial)?;
+        std::fs::rename(&partial, &shadow_path)?;
+    }
+
+    Ok(shadow_path)
+}
+
+/// Checks if importer recognizes the source by its first bytes.
+fn recognizes(importer: &dyn Importer, head: &[u8]) -> bool {
+    importer.magic().iter().any(|magic| head.starts_with(magic)) || importer.probe(head)
+}
+
+/// Narrows candidates down to importers that recognize the source content.
+/// Keeps all candidates if none does.
+fn recognized(to_target: &ToTarget, candidates: &[usize], head: &[u8]) -> Vec<usize> {
+    if candidates.len() < 2 {
+        return candidates.to_vec();
+    }
+
+    let recognized: Vec<usize> = candidates
+        .iter()
+        .copied()
+        .filter(|&idx| recognizes(&*to_target.importers[idx].importer, head))
+        .collect();
+
+    if recognized.is_empty() {
+        candidates.to_vec()
+    } else {
+        recognized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_importer::TestImporter;
+
+    use super::*;
+
+    fn importers(preferences: ImporterPreferences) -> Importers {
+        let mut importers = Importers::new(&preferences).unwrap();
+        importers.register_importer(
+            TestImporter::new("a", "image")
+                .extension("bin")
+                .magic(b"AA"),
+        );
+        importers.register_importer(
+            TestImporter::new("b", "image")
+                .extension("bin")
+                .magic(b"BB"),
+        );
+        importers
+    }
+
+    fn guess(importers: &Importers, source: &str, head: &[u8]) -> Result<String, String> {
+        match importers.guess(source, None, Some("bin"), None, head, "image") {
+            Ok(Some(importer)) => Ok(importer.name().to_owned()),
+            Ok(None) => Err("none".to_owned()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    #[test]
+    fn content_picks_among_extension_candidates() {
+        let importers = importers(ImporterPreferences::default());
+
+        assert_eq!(guess(&importers, "x.bin", b"AA..").unwrap(), "a");
+        assert_eq!(guess(&importers, "x.bin", b"BB..").unwrap(), "b");
+
+        let err = guess(&importers, "x.bin", b"CC..").unwrap_err();
+        assert!(err.contains("'a'") && err.contains("'b'"), "{}", err);
+    }
+
+    #[test]
+    fn content_takes_precedence_over_priority_and_preferences() {
+        let mut preferences = ImporterPreferences::default();
+        preferences.priority.insert("a".to_owned(), 10);
+        preferences
+            .extensions
+            .insert("bin".to_owned(), "a".to_owned());
+        preferences
+            .targets
+            .insert("image".to_owned(), "a".to_owned());
+        let importers = importers(preferences);
+
+        assert_eq!(guess(&importers, "x.bin", b"BB..").unwrap(), "b");
+
+        // Preferences decide when content is not recognized.
+        assert_eq!(guess(&importers, "x.bin", b"CC..").unwrap(), "a");
+    }
+
+    #[test]
+    fn source_override_takes_precedence_over_content() {
+        let mut preferences = ImporterPreferences::default();
+        preferences.sources.push(SourceOverride {
+            glob: "forced/*.bin".to_owned(),
+            importer: "a".to_owned(),
+            target: None,
+        });
+        let importers = importers(preferences);
+
+        assert_eq!(guess(&importers, "forced/x.bin", b"BB..").unwrap(), "a");
+        assert_eq!(guess(&importers, "other/x.bin", b"BB..").unwrap(), "b");
+    }
+
+    fn chain(importers: &Importers) -> Result<Vec<String>, String> {
+        match importers.find_chain(None, Some("psd"), None, b"", "ktx2") {
+            Ok(Some(chain)) => Ok(chain.iter().map(|i| i.name().to_owned()).collect()),
+            Ok(None) => Err("none".to_owned()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    #[test]
+    fn finds_shortest_chain() {
+        let mut importers = Importers::new(&ImporterPreferences::default()).unwrap();
+        importers.register_importer(TestImporter::new("psd2png", "png").extension("psd"));
+        importers.register_importer(TestImporter::new("png2ktx", "ktx2").format("png"));
+        importers.register_importer(TestImporter::new("png2tga", "tga").format("png"));
+        importers.register_importer(TestImporter::new("tga2ktx", "ktx2").format("tga"));
+
+        assert_eq!(chain(&importers).unwrap(), ["psd2png", "png2ktx"]);
+    }
+
+    #[test]
+    fn ambiguous_chain_lists_candidates() {
+        let mut importers = Importers::new(&ImporterPreferences::default()).unwrap();
+        importers.register_importer(TestImporter::new("psd2png", "png").extension("psd"));
+        importers.register_importer(TestImporter::new("psd2tga", "tga").extension("psd"));
+        importers.register_importer(TestImporter::new("png2ktx", "ktx2").format("png"));
+        importers.register_importer(TestImporter::new("tga2ktx", "ktx2").format("tga"));
+
+        let err = chain(&importers).unwrap_err();
+        assert!(err.contains("'psd2png' -> 'png2ktx'"), "{}", err);
+        assert!(err.contains("'psd2tga' -> 'tga2ktx'"), "{}", err);
+    }
+
+    #[test]
+    fn ambiguous_step_lists_candidates() {
+        let mut preferences = ImporterPreferences::default();
+        let mut importers = Importers::new(&ImporterPreferences::default()).unwrap();
+        importers.register_importer(TestImporter::new("psd2png", "png").extension("psd"));
+        importers.register_importer(TestImporter::new("png2ktx-a", "ktx2").format("png"));
+        importers.register_importer(TestImporter::new("png2ktx-b", "ktx2").format("png"));
+
+        let err = chain(&importers).unwrap_err();
+        assert!(err.contains("'psd2png' -> 'png2ktx-a'"), "{}", err);
+        assert!(err.contains("'psd2png' -> 'png2ktx-b'"), "{}", err);
+
+        // Priority resolves ambiguous step.
+        preferences.priority.insert("png2ktx-b".to_owned(), 1);
+        importers.set_preferences(&preferences).unwrap();
+        assert_eq!(chain(&importers).unwrap(), ["psd2png", "png2ktx-b"]);
+    }
+
+    /// Three importers to `image` claiming `png` extension.
+    fn png_importers(preferences: &str) -> Importers {
+        let preferences: ImporterPreferences = toml::from_str(preferences).unwrap();
+
+        let mut importers = Importers::new(&preferences).unwrap();
+        for name in ["a", "b", "c"] {
+            importers.register_importer(TestImporter::new(name, "image").extension("png"));
+        }
+        importers
+    }
+
+    fn guess_png(importers: &Importers, source: &str) -> Result<String, String> {
+        match importers.guess(source, None, Some("png"), None, b"", "image") {
+            Ok(Some(importer)) => Ok(importer.name().to_owned()),
+            Ok(None) => Err("none".to_owned()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    #[test]
+    fn undecided_without_preferences() {
+        let importers = png_importers("");
+
+        let err = guess_png(&importers, "x.png").unwrap_err();
+        assert!(err.contains("'a' (static, priority 0)"), "{}", err);
+        assert!(err.contains("'b' (static, priority 0)"), "{}", err);
+        assert!(err.contains("'c' (static, priority 0)"), "{}", err);
+    }
+
+    #[test]
+    fn highest_priority_wins() {
+        let importers = png_importers(
+            r#"
+            priority = { a = -1, c = 5 }
+            "#,
+        );
+        assert_eq!(guess_png(&importers, "x.png").unwrap(), "c");
+
+        let importers = png_importers(
+            r#"
+            priority = { b = 5, c = 5 }
+            "#,
+        );
+        let err = guess_png(&importers, "x.png").unwrap_err();
+        assert!(err.contains("'b' (static, priority 5)"), "{}", err);
+        assert!(err.contains("'c' (static, priority 5)"), "{}", err);
+    }
+
+    #[test]
+    fn target_preference_beats_priority() {
+        let importers = png_importers(
+            r#"
+            targets = { image = "b" }
+            priority = { c = 5 }
+            "#,
+        );
+        assert_eq!(guess_png(&importers, "x.png").unwrap(), "b");
+    }
+
+    #[test]
+    fn extension_preference_beats_target_preference() {
+        let importers = png_importers(
+            r#"
+            extensions = { png = "a" }
+            targets = { image = "b" }
+            priority = { c = 5 }
+            "#,
+        );
+        assert_eq!(guess_png(&importers, "x.png").unwrap(), "a");
+    }
+
+    #[test]
+    fn preference_for_unknown_importer_is_ignored() {
+        let importers = png_importers(
+            r#"
+            extensions = { png = "missing" }
+            priority = { c = 5 }
+            "#,
+        );
+        assert_eq!(guess_png(&importers, "x.png").unwrap(), "c");
+    }
+
+    #[test]
+    fn source_overrides_match_glob_and_target() {
+        let importers = png_importers(
+            r#"
+            extensions = { png = "a" }
+
+            [[sources]]
+            glob = "legacy/**/*.png"
+            importer = "b"
+
+            [[sources]]
+            glob = "other/*.png"
+            importer = "c"
+            target = "mesh"
+            "#,
+        );
+
+        assert_eq!(guess_png(&importers, "legacy/x/y.png").unwrap(), "b");
+        assert_eq!(guess_png(&importers, "x.png").unwrap(), "a");
+
+        // Override for other target does not apply.
+        assert_eq!(guess_png(&importers, "other/x.png").unwrap(), "a");
+    }
+
+    #[test]
+    fn invalid_override_glob_is_rejected() {
+        let preferences: ImporterPreferences = toml::from_str(
+            r#"
+            [[sources]]
+            glob = "a/[b"
+            importer = "a"
+            "#,
+        )
+        .unwrap();
+
+        assert!(Importers::new(&preferences).is_err());
     }
 }
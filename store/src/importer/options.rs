@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use jsonschema::Validator;
+use treasury_import::Importer;
+
+/// Checks metadata declared by the importer.
+/// Version must be semver and options schema must be valid JSON Schema.
+///
+/// Returns compiled options schema to validate options with.
+pub fn validate_metadata(importer: &dyn Importer) -> Result<Option<Arc<Validator>>, String> {
+    if let Some(version) = importer.version() {
+        if let Err(err) = semver::Version::parse(version) {
+            return Err(format!(
+                "Importer '{}' version '{}' is not semver. {}",
+                importer.name(),
+                version,
+                err
+            ));
+        }
+    }
+
+    match importer.options_schema() {
+        None => Ok(None),
+        Some(schema) => {
+            compile_schema(importer.name(), schema).map(|schema| Some(Arc::new(schema)))
+        }
+    }
+}
+
+/// Checks `options` against `schema` compiled by [`validate_metadata`].
+/// Importers without schema accept no options.
+pub fn validate_options(
+    importer: &dyn Importer,
+    schema: Option<&Validator>,
+    options: &str,
+) -> eyre::Result<()> {
+    if importer.options_schema().is_none() {
+        return Err(eyre::eyre!(
+            "Importer '{}' accepts no options",
+            importer.name()
+        ));
+    }
+
+    let Some(validator) = schema else {
+        return Err(eyre::eyre!(
+            "Importer '{}' options schema is invalid",
+            importer.name()
+        ));
+    };
+
+    let options: serde_json::Value = serde_json::from_str(options)
+        .map_err(|err| eyre::eyre!("Import options are not valid JSON. {}", err))?;
+
+    let errors = validator
+        .iter_errors(&options)
+        .map(|err| match err.instance_path.to_string() {
+            path if path.is_empty() => err.to_string(),
+            path => format!("{} at '{}'", err, path),
+        })
+        .collect::<Vec<_>>();
+
+    if !errors.is_empty() {
+        return Err(eyre::eyre!(
+            "Import options are invalid for importer '{}'. {}",
+            importer.name(),
+            errors.join("; ")
+        ));
+    }
+
+    Ok(())
+}
+
+fn compile_schema(name: &str, schema: &str) -> Result<Validator, String> {
+    let schema: serde_json::Value = serde_json::from_str(schema).map_err(|err| {
+        format!(
+            "Importer '{}' options schema is not valid JSON. {}",
+            name, err
+        )
+    })?;
+
+    jsonschema::validator_for(&schema)
+        .map_err(|err| format!("Importer '{}' options schema is invalid. {}", name, err))
+}
@@ -0,0 +1,112 @@
+use eyre::WrapErr;
+use globset::{Glob, GlobMatcher};
+use hashbrown::HashMap;
+
+/// Rules to choose between importers that claim the same source.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ImporterPreferences {
+    /// Preferred importer name for each target.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub targets: HashMap<String, String>,
+
+    /// Preferred importer name for each source extension.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub extensions: HashMap<String, String>,
+
+    /// Priority for each importer name.
+    /// Importer with highest priority wins between candidates when no other preference applies.
+    /// Default priority is 0.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub priority: HashMap<String, i32>,
+
+    /// Importers forced for sources which paths match glob patterns.
+    /// First matching override wins.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub sources: Vec<SourceOverride>,
+}
+
+impl ImporterPreferences {
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+            && self.extensions.is_empty()
+            && self.priority.is_empty()
+            && self.sources.is_empty()
+    }
+}
+
+/// Importer forced for sources that match glob pattern.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SourceOverride {
+    /// Glob pattern.
+    /// Matched against source path relative to treasury base directory
+    /// or against full URL for sources outside of it.
+    pub glob: String,
+
+    /// Name of the importer to use.
+    pub importer: String,
+
+    /// Target this override applies to.
+    /// Applies to all targets if not specified.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub target: Option<String>,
+}
+
+/// Preferences with compiled glob patterns.
+#[derive(Clone)]
+pub(super) struct Preferences {
+    targets: HashMap<String, String>,
+    extensions: HashMap<String, String>,
+    priority: HashMap<String, i32>,
+    sources: Vec<(GlobMatcher, SourceOverride)>,
+}
+
+impl Preferences {
+    pub fn new(preferences: &ImporterPreferences) -> eyre::Result<Self> {
+        let sources = preferences
+            .sources
+            .iter()
+            .map(|source| {
+                let glob = Glob::new(&source.glob).wrap_err_with(|| {
+                    format!(
+                        "Invalid glob pattern '{}' in importer overrides",
+                        source.glob
+                    )
+                })?;
+                Ok((glob.compile_matcher(), source.clone()))
+            })
+            .collect::<eyre::Result<_>>()?;
+
+        Ok(Preferences {
+            targets: preferences.targets.clone(),
+            extensions: preferences.extensions.clone(),
+            priority: preferences.priority.clone(),
+            sources,
+        })
+    }
+
+    /// Returns name of the importer forced for the source and target.
+    pub fn source_override(&self, source: &str, target: &str) -> Option<&str> {
+        self.sources
+            .iter()
+            .find(|(matcher, source_override)| {
+                source_override
+                    .target
+                    .as_deref()
+                    .is_none_or(|t| t == target)
+                    && matcher.is_match(source)
+            })
+            .map(|(_, source_override)| &*source_override.importer)
+    }
+
+    pub fn for_extension(&self, extension: &str) -> Option<&str> {
+        self.extensions.get(extension).map(|name| &**name)
+    }
+
+    pub fn for_target(&self, target: &str) -> Option<&str> {
+        self.targets.get(target).map(|name| &**name)
+    }
+
+    pub fn priority(&self, importer: &str) -> i32 {
+        self.priority.get(importer).copied().unwrap_or(0)
+    }
+}
@@ -1,34 +1,69 @@
 use std::{
     collections::VecDeque,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
-    time::SystemTime,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
+use cancel::ImportCancellation;
 use eyre::WrapErr;
 use hashbrown::{HashMap, HashSet};
+use host::{HostSettings, ImporterHost};
 use importer::Importers;
 use meta::{AssetMeta, SourceMeta};
 use parking_lot::RwLock;
+use progress::StepProgress;
+use provider::SourceProviders;
+use remote::RemoteSources;
+use sha256::Sha256Writer;
 use sources::Sources;
 use temp::Temporaries;
+use tokio::runtime::RuntimeFlavor;
 use treasury_id::AssetId;
-use treasury_import::{loading::LoadingError, ImportError, Importer};
+use treasury_import::{loading::LoadingError, Diagnostic, ImportError, Importer, Severity};
 use url::Url;
 
+mod cancel;
+mod framing;
+pub mod host;
 mod importer;
 mod meta;
-mod scheme;
+mod progress;
+mod provider;
+pub mod remote;
 mod sha256;
 mod sources;
 mod temp;
 
-pub const TREASURY_META_NAME: &'static str = "Treasury.toml";
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(test)]
+mod test_importer;
+
+pub use self::{
+    cancel::{CancelToken, ImportTimeouts, Interrupted},
+    importer::{
+        Claim, ImporterConflict, ImporterInfo, ImporterOrigin, ImporterPreferences, ImportersLib,
+        ImportersLibs, LibraryError, SourceOverride,
+    },
+    meta::SourceVersion,
+    progress::{progress_stream, ImportProgress, ProgressSender, ProgressStream},
+    provider::{FetchedSource, HttpValidators, SourceContent, SourceProvider},
+};
+
+pub const TREASURY_META_NAME: &str = "Treasury.toml";
 
-const DEFAULT_AUX: &'static str = "treasury";
-const DEFAULT_ARTIFACTS: &'static str = "artifacts";
-const DEFAULT_EXTERNAL: &'static str = "external";
+const DEFAULT_AUX: &str = "treasury";
+const DEFAULT_ARTIFACTS: &str = "artifacts";
+const DEFAULT_EXTERNAL: &str = "external";
 const MAX_ITEM_ATTEMPTS: u32 = 1024;
 
+/// Directory where copies of loaded importers libraries are placed.
+const DEFAULT_SHADOW: &str = "importers";
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct TreasuryInfo {
     #[serde(skip_serializing_if = "Option::is_none", default)]
@@ -37,8 +72,21 @@ pub struct TreasuryInfo {
     pub external: Option<PathBuf>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub temp: Option<PathBuf>,
-    #[serde(skip_serializing_if = "Vec::is_empty", default)]
-    pub importers: Vec<PathBuf>,
+    /// Importers libraries, list of paths or `[importers.<name>]` tables with configuration.
+    #[serde(skip_serializing_if = "ImportersLibs::is_empty", default)]
+    pub importers: ImportersLibs,
+
+    /// Rules to choose between importers that claim the same source.
+    #[serde(skip_serializing_if = "ImporterPreferences::is_empty", default)]
+    pub prefer: ImporterPreferences,
+
+    /// Runs importers from libraries in a separate process when set.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub host: Option<ImporterHost>,
+
+    /// Time limits for importers.
+    #[serde(skip_serializing_if = "ImportTimeouts::is_empty", default)]
+    pub timeouts: ImportTimeouts,
 }
 
 impl Default for TreasuryInfo {
@@ -72,13 +120,17 @@ impl TreasuryInfo {
         let artifacts = artifacts.map(Path::to_owned);
         let external = external.map(Path::to_owned);
         let temp = temp.map(Path::to_owned);
-        let importers = importers.iter().copied().map(|p| p.to_owned()).collect();
+        let importers =
+            ImportersLibs::Paths(importers.iter().copied().map(|p| p.to_owned()).collect());
 
         TreasuryInfo {
             artifacts,
             external,
             temp,
             importers,
+            prefer: ImporterPreferences::default(),
+            host: None,
+            timeouts: ImportTimeouts::default(),
         }
     }
 }
@@ -90,13 +142,71 @@ struct AssetItem {
     target: String,
 }
 
+/// Parameters of [`Treasury::store_url_with`].
+#[derive(Default)]
+pub struct StoreOptions<'a> {
+    options: Option<&'a serde_json::Value>,
+    cancel: Option<&'a CancelToken>,
+    progress: Option<&'a ProgressSender>,
+    remote: Option<&'a mut (dyn RemoteSources + 'a)>,
+}
+
+impl<'a> StoreOptions<'a> {
+    pub fn new() -> Self {
+        StoreOptions::default()
+    }
+
+    /// Sets options for the importer that produces the asset.
+    /// `options` are validated against the importer's options schema before import
+    /// and recorded in asset metadata. Asset is reimported when options change.
+    ///
+    /// Other imports of the asset reuse recorded options.
+    pub fn options(mut self, options: &'a serde_json::Value) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// Import stops with [`Interrupted::Cancelled`] error once `cancel` is cancelled.
+    ///
+    /// Assets completed before cancellation, such as dependencies, are kept.
+    pub fn cancel(mut self, cancel: &'a CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Progress of the asset and each dependency imported on the way is sent to `progress`.
+    ///
+    /// Use [`progress_stream`] to create the sender and the stream that receives progress.
+    pub fn progress(mut self, progress: &'a ProgressSender) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Fetches `remote:` sources from remote client.
+    /// Use [`remote::remote_url`] to make URL of the source that lives on the client.
+    ///
+    /// Source data and all sources requested by importers are fetched through `remote`.
+    /// Asset metadata records client-relative `remote:` URLs.
+    pub fn remote(mut self, remote: &'a mut dyn RemoteSources) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+}
+
 pub struct Treasury {
     base: PathBuf,
     base_url: Url,
     artifacts_base: PathBuf,
     external: PathBuf,
     temp: PathBuf,
-    importers: Importers,
+    shadow: PathBuf,
+
+    /// Importers are replaced as a whole when library is reloaded.
+    /// Imports in progress keep using previous snapshot.
+    importers: RwLock<Arc<Importers>>,
+    library_errors: RwLock<Vec<LibraryError>>,
+    providers: SourceProviders,
+    timeouts: ImportTimeouts,
 
     artifacts: RwLock<HashMap<AssetId, AssetItem>>,
     scanned: RwLock<bool>,
@@ -153,21 +263,41 @@ impl Treasury {
             .temp
             .map_or_else(std::env::temp_dir, |path| base.join(path));
 
-        let mut importers = Importers::new();
+        let shadow = base.join(DEFAULT_AUX).join(DEFAULT_SHADOW);
 
-        for lib_path in &meta.importers {
+        let mut importers =
+            Importers::new(&meta.prefer).wrap_err("Invalid importer preferences")?;
+        importers.set_host(meta.host.map(|host| HostSettings::new(&host, &base)));
+
+        let mut library_errors = Vec::new();
+
+        for (lib_path, config) in meta.importers.libraries() {
             let lib_path = base.join(lib_path);
 
+            if let Some(config) = config {
+                let config = serde_json::to_string(config).wrap_err_with(|| {
+                    format!(
+                        "Failed to convert configuration of importers library '{}' to JSON",
+                        lib_path.display()
+                    )
+                })?;
+                importers.set_library_config(&lib_path, Some(&config));
+            }
+
             unsafe {
                 // # Safety: Nope.
                 // There is no way to make this safe.
                 // But it is unlikely to cause problems by accident.
-                if let Err(err) = importers.load_dylib_importers(&lib_path) {
+                if let Err(err) = importers.load_dylib_importers(&lib_path, &shadow) {
                     tracing::error!(
                         "Failed to load importers from '{}'. {:#}",
                         lib_path.display(),
                         err
                     );
+                    library_errors.push(LibraryError {
+                        path: lib_path,
+                        error: Arc::new(err),
+                    });
                 }
             }
         }
@@ -178,7 +308,11 @@ impl Treasury {
             artifacts_base: artifacts,
             external,
             temp,
-            importers,
+            shadow,
+            importers: RwLock::new(Arc::new(importers)),
+            library_errors: RwLock::new(library_errors),
+            providers: SourceProviders::new(),
+            timeouts: meta.timeouts,
             artifacts: RwLock::new(HashMap::new()),
             scanned: RwLock::new(false),
         })
@@ -188,15 +322,174 @@ impl Treasury {
     /// There is no possible way to guarantee that dylib does not break safety contracts.
     /// Some measures to ensure safety are taken.
     /// Providing dylib from which importers will be successfully loaded and then cause an UB should only be possible on purpose.
+    ///
+    /// # Safety
+    ///
+    /// Library at `lib_path` must be produced with `make_treasury_importers_library!` macro.
     #[tracing::instrument(skip(self))]
     pub unsafe fn register_importers_lib(&mut self, lib_path: &Path) -> Result<(), LoadingError> {
-        self.importers.load_dylib_importers(lib_path)
+        Arc::make_mut(self.importers.get_mut()).load_dylib_importers(lib_path, &self.shadow)?;
+        Ok(())
+    }
+
+    /// Loads importers from dylib, passing `config` to the library when it creates importers.
+    /// Configuration is kept for reloads of the library.
+    /// Importers already loaded from the library are replaced if configuration differs.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Treasury::register_importers_lib`].
+    #[tracing::instrument(skip(self, config))]
+    pub unsafe fn register_importers_lib_with_config(
+        &mut self,
+        lib_path: &Path,
+        config: &serde_json::Value,
+    ) -> Result<(), LoadingError> {
+        let importers = Arc::make_mut(self.importers.get_mut());
+        importers.set_library_config(lib_path, Some(&config.to_string()));
+        importers.load_dylib_importers(lib_path, &self.shadow)?;
+        Ok(())
+    }
+
+    /// Reloads importers from dylib after it was changed.
+    /// Importers previously loaded from the library are replaced with new ones.
+    /// Imports in progress finish with old importers.
+    /// Assets produced by importers from the library are reimported when requested next time.
+    ///
+    /// Returns `false` if library is not changed.
+    /// On failure previously loaded importers are kept.
+    ///
+    /// # Safety
+    ///
+    /// Library at `lib_path` must be produced with `make_treasury_importers_library!` macro.
+    #[tracing::instrument(skip(self))]
+    pub unsafe fn reload_importers_lib(&self, lib_path: &Path) -> Result<bool, LibraryError> {
+        // Hold the lock to not lose concurrent reload of another library.
+        let mut importers = self.importers.write();
+        let mut new_importers = Importers::clone(&importers);
+
+        let result = new_importers.load_dylib_importers(lib_path, &self.shadow);
+
+        let mut library_errors = self.library_errors.write();
+        library_errors.retain(|error| error.path != lib_path);
+
+        match result {
+            Ok(false) => Ok(false),
+            Ok(true) => {
+                tracing::info!("Reloaded importers from '{}'", lib_path.display());
+                *importers = Arc::new(new_importers);
+                Ok(true)
+            }
+            Err(err) => {
+                let err = LibraryError {
+                    path: lib_path.to_owned(),
+                    error: Arc::new(err),
+                };
+                library_errors.push(err.clone());
+                Err(err)
+            }
+        }
+    }
+
+    /// Sets rules to choose between importers that claim the same source.
+    /// Replaces preferences from `Treasury.toml`.
+    pub fn set_importer_preferences(
+        &mut self,
+        preferences: &ImporterPreferences,
+    ) -> eyre::Result<()> {
+        Arc::make_mut(self.importers.get_mut()).set_preferences(preferences)
+    }
+
+    /// Sets importer host to run importers from libraries registered afterwards.
+    /// Replaces host from `Treasury.toml`.
+    /// Libraries are loaded into this process if `None`.
+    pub fn set_importer_host(&mut self, host: Option<&ImporterHost>) {
+        let host = host.map(|host| HostSettings::new(host, &self.base));
+        Arc::make_mut(self.importers.get_mut()).set_host(host);
+    }
+
+    /// Sets time limits for importers.
+    /// Replaces timeouts from `Treasury.toml`.
+    pub fn set_import_timeouts(&mut self, timeouts: &ImportTimeouts) {
+        self.timeouts = timeouts.clone();
+    }
+
+    /// Returns information about all registered importers,
+    /// including formats and extensions they claim and conflicts between them.
+    pub fn importers(&self) -> Vec<ImporterInfo> {
+        self.importers.read().infos()
+    }
+
+    /// Returns importers libraries that failed to load or reload.
+    pub fn library_errors(&self) -> Vec<LibraryError> {
+        self.library_errors.read().clone()
+    }
+
+    /// Watches importers libraries and reloads them when they change.
+    /// Library is reloaded once its file stays unchanged for one `interval`,
+    /// so that partially written library is not picked up.
+    /// Libraries that failed to load are watched as well.
+    ///
+    /// This future never completes. Drop it to stop watching.
+    ///
+    /// # Safety
+    ///
+    /// Libraries must be produced with `make_treasury_importers_library!` macro.
+    pub async unsafe fn watch_importers_libs(&self, interval: Duration) {
+        let mut stamps: HashMap<PathBuf, Option<(SystemTime, u64)>> = HashMap::new();
+        let mut changed = HashSet::new();
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let mut libraries: Vec<PathBuf> = self
+                .importers
+                .read()
+                .libraries()
+                .iter()
+                .map(|library| library.to_path_buf())
+                .collect();
+
+            for error in self.library_errors.read().iter() {
+                if !libraries.contains(&error.path) {
+                    libraries.push(error.path.clone());
+                }
+            }
+
+            for lib_path in libraries {
+                let stamp = std::fs::metadata(&lib_path)
+                    .ok()
+                    .and_then(|meta| Some((meta.modified().ok()?, meta.len())));
+
+                match stamps.insert(lib_path.clone(), stamp) {
+                    None => {}
+                    Some(old) if old != stamp => {
+                        changed.insert(lib_path);
+                    }
+                    Some(_) if stamp.is_some() && changed.remove(&lib_path) => {
+                        if let Err(err) = self.reload_importers_lib(&lib_path) {
+                            tracing::error!("{:#}", eyre::Report::new(err));
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
     }
 
     /// Adds importer to the store.
     #[tracing::instrument(skip_all)]
     pub fn register_importer(&mut self, importer: impl Importer + 'static) {
-        self.importers.register_importer(importer)
+        Arc::make_mut(self.importers.get_mut()).register_importer(importer)
+    }
+
+    /// Adds source provider to the store.
+    /// Replaces providers previously registered for the same URL schemes,
+    /// including built-in ones.
+    #[tracing::instrument(skip_all)]
+    pub fn register_source_provider(&mut self, provider: impl SourceProvider + 'static) {
+        self.providers.register_provider(provider)
     }
 
     /// Import an asset.
@@ -208,12 +501,7 @@ impl Treasury {
         target: &str,
         new_id: impl FnMut() -> AssetId,
     ) -> eyre::Result<(AssetId, PathBuf)> {
-        let source = self.base_url.join(source).wrap_err_with(|| {
-            format!(
-                "Failed to construct URL from base '{}' and source '{}'",
-                self.base_url, source
-            )
-        })?;
+        let source = join_source(&self.base_url, source)?;
 
         self.store_url(source, format, target, new_id).await
     }
@@ -225,17 +513,53 @@ impl Treasury {
         source: Url,
         format: Option<&str>,
         target: &str,
+        new_id: impl FnMut() -> AssetId,
+    ) -> eyre::Result<(AssetId, PathBuf)> {
+        self.store_impl(source, format, target, StoreOptions::new(), new_id)
+            .await
+    }
+
+    /// Import an asset with options, cancellation, progress reporting
+    /// or from remote client, see [`StoreOptions`].
+    #[tracing::instrument(skip(self, options, new_id))]
+    pub async fn store_url_with(
+        &self,
+        source: Url,
+        format: Option<&str>,
+        target: &str,
+        options: StoreOptions<'_>,
+        new_id: impl FnMut() -> AssetId,
+    ) -> eyre::Result<(AssetId, PathBuf)> {
+        self.store_impl(source, format, target, options, new_id)
+            .await
+    }
+
+    async fn store_impl(
+        &self,
+        source: Url,
+        format: Option<&str>,
+        target: &str,
+        options: StoreOptions<'_>,
         mut new_id: impl FnMut() -> AssetId,
     ) -> eyre::Result<(AssetId, PathBuf)> {
+        let StoreOptions {
+            options,
+            cancel,
+            progress,
+            mut remote,
+        } = options;
+
         let mut temporaries = Temporaries::new(&self.temp);
-        let mut sources = Sources::new();
+        let mut sources = Sources::new(&self.providers);
 
         let base = &self.base;
         let artifacts = &self.artifacts_base;
         let external = &self.external;
-        let importers = &self.importers;
+        // Snapshot of importers. Reloaded libraries do not affect this import.
+        let importers = Arc::clone(&self.importers.read());
+        let importers = &*importers;
 
-        struct StackItem {
+        struct StackItem<'a> {
             /// Source URL.
             source: Url,
 
@@ -250,10 +574,27 @@ impl Treasury {
 
             /// Sources requested by importer.
             /// Relative to `source`.
-            sources: HashMap<Url, SystemTime>,
+            sources: HashMap<Url, SourceVersion>,
 
             /// Dependencies requested by importer.
             dependencies: HashSet<AssetId>,
+
+            /// Chain of importers that converts source to the target.
+            /// Empty until chosen.
+            chain: Vec<&'a dyn Importer>,
+
+            /// Number of completed steps in the chain.
+            step: usize,
+
+            /// Output of the last completed step.
+            intermediate: Option<PathBuf>,
+
+            /// Diagnostics reported by completed steps.
+            diagnostics: Vec<Diagnostic>,
+
+            /// Options for the last importer in the chain, as JSON document.
+            /// `None` reuses options recorded for existing asset.
+            options: Option<String>,
         }
 
         let mut stack = Vec::new();
@@ -264,11 +605,20 @@ impl Treasury {
             attempt: 0,
             sources: HashMap::new(),
             dependencies: HashSet::new(),
+            chain: Vec::new(),
+            step: 0,
+            intermediate: None,
+            diagnostics: Vec::new(),
+            options: options.map(serde_json::Value::to_string),
         });
 
-        loop {
+        'items: loop {
             // tokio::time::sleep(Duration::from_secs(1)).await;
 
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                return Err(Interrupted::Cancelled.into());
+            }
+
             let item = stack.last_mut().unwrap();
             item.attempt += 1;
 
@@ -276,7 +626,37 @@ impl Treasury {
                 .wrap_err("Failed to fetch source meta")?;
 
             if let Some(asset) = meta.get_asset(&item.target) {
-                if asset.needs_reimport(&self.base_url) {
+                let options_changed = match &item.options {
+                    None => {
+                        item.options = asset.options().map(str::to_owned);
+                        false
+                    }
+                    Some(options) => Some(&**options) != asset.options(),
+                };
+
+                let importers_changed = matches!(
+                    importers.chain_revision(asset.chain(), &item.target),
+                    Some(revision) if revision.as_ref() != asset.revision()
+                );
+
+                if importers_changed {
+                    tracing::debug!(
+                        "'{}' '{:?}' '{}' reimporting with changed importers",
+                        item.source,
+                        item.format,
+                        item.target
+                    );
+                } else if options_changed {
+                    tracing::debug!(
+                        "'{}' '{:?}' '{}' reimporting with changed options",
+                        item.source,
+                        item.format,
+                        item.target
+                    );
+                } else if asset
+                    .needs_reimport(&self.base_url, &self.providers, remote.as_deref_mut())
+                    .await
+                {
                     tracing::debug!(
                         "'{}' '{:?}' '{}' reimporting",
                         item.source,
@@ -299,32 +679,105 @@ impl Treasury {
                 }
             }
 
-            let importer =
-                importers.guess(item.format.as_deref(), url_ext(&item.source), &item.target)?;
+            if item.chain.is_empty() {
+                let media_type = provider::data::media_type(&item.source);
+                let relative_source = match make_relative(&self.base_url, &item.source) {
+                    None => item.source.to_string(),
+                    Some(relative) => percent_encoding::percent_decode_str(&relative)
+                        .decode_utf8_lossy()
+                        .into_owned(),
+                };
 
-            let importer = importer.ok_or_else(|| {
-                eyre::eyre!(
-                    "Failed to find importer '{} -> {}' for asset '{}'",
-                    item.format.as_deref().unwrap_or("<undefined>"),
-                    item.target,
-                    item.source,
-                )
-            })?;
+                // Content is used to pick among importers that claim the source.
+                let (content, _) = sources
+                    .fetch(&mut temporaries, &item.source, remote.as_deref_mut())
+                    .await?;
+
+                let head = read_head(content)
+                    .wrap_err_with(|| format!("Failed to read source '{}'", item.source))?;
+
+                let guessed = importers.guess(
+                    &relative_source,
+                    item.format.as_deref(),
+                    url_ext(&item.source),
+                    media_type.as_deref(),
+                    &head,
+                    &item.target,
+                );
 
-            // Fetch source file.
-            let (source_path, modified) = sources.fetch(&mut temporaries, &item.source).await?;
-            let source_path = source_path.to_owned();
+                let chain = match guessed {
+                    Ok(Some(importer)) => Some(vec![importer]),
+                    guessed => {
+                        // There is no importer picked by format, extension or media type.
+                        // Try to recognize the source by its content
+                        // or find a chain of importers through intermediate formats.
+                        let sniffed = match item.format {
+                            None => importers.sniff(&head, &item.target)?,
+                            Some(_) => None,
+                        };
 
-            let output_path = temporaries.make_temporary();
+                        match (sniffed, guessed) {
+                            (Some(importer), _) => Some(vec![importer]),
+                            (None, Err(err)) => return Err(err.into()),
+                            (None, Ok(_)) => importers.find_chain(
+                                item.format.as_deref(),
+                                url_ext(&item.source),
+                                media_type.as_deref(),
+                                &head,
+                                &item.target,
+                            )?,
+                        }
+                    }
+                };
+
+                item.chain = chain.ok_or_else(|| {
+                    eyre::eyre!(
+                        "Failed to find importer '{} -> {}' for asset '{}'",
+                        item.format.as_deref().unwrap_or("<undefined>"),
+                        item.target,
+                        item.source,
+                    )
+                })?;
+
+                // Only the last step receives options, intermediate steps are imported without them.
+                if let (Some(options), Some(last)) = (&item.options, item.chain.last()) {
+                    importers
+                        .validate_options(*last, options)
+                        .wrap_err_with(|| {
+                            format!(
+                                "Failed to import {}:{:?}->{}",
+                                item.source, item.format, item.target
+                            )
+                        })?;
+                }
+
+                if item.chain.len() > 1 {
+                    tracing::debug!(
+                        "Importing '{}' to '{}' through '{}'",
+                        item.source,
+                        item.target,
+                        item.chain
+                            .iter()
+                            .map(|importer| importer.target())
+                            .collect::<Vec<_>>()
+                            .join(" -> "),
+                    );
+                }
+            }
+
+            // Fetch source.
+            let (_, version) = sources
+                .fetch(&mut temporaries, &item.source, remote.as_deref_mut())
+                .await?;
 
             struct Fn<F>(F);
 
             impl<F> treasury_import::Sources for Fn<F>
             where
-                F: FnMut(&str) -> Option<PathBuf>,
+                F: FnMut(&str) -> Result<Option<PathBuf>, String>,
             {
                 fn get(&mut self, source: &str) -> Result<Option<PathBuf>, String> {
-                    Ok((self.0)(source))
+                    (self.0)(source)
                 }
             }
 
@@ -337,18 +790,71 @@ impl Treasury {
                 }
             }
 
-            let result = importer.import(
-                &source_path,
-                &output_path,
-                &mut Fn(|src: &str| {
-                    let src = item.source.join(src).ok()?; // If parsing fails - source will be listed in `ImportResult::RequireSources`.
-                    let (path, modified) = sources.get(&src)?;
-                    if let Some(modified) = modified {
-                        item.sources.insert(src, modified);
+            /// Opens fetched sources for streaming importers.
+            struct Streams<'a, 'b> {
+                source: &'a Url,
+                sources: &'a Sources<'b>,
+                versions: &'a mut HashMap<Url, SourceVersion>,
+            }
+
+            impl treasury_import::SourceStreams for Streams<'_, '_> {
+                fn open(&mut self, src: &str) -> Result<Option<Box<dyn Read + '_>>, String> {
+                    // If parsing fails - source will be listed in `ImportResult::RequireSources`.
+                    let Ok(src) = self.source.join(src) else {
+                        return Ok(None);
+                    };
+                    let Some((content, version)) = self.sources.get(&src) else {
+                        return Ok(None);
+                    };
+                    let stream = content
+                        .open()
+                        .map_err(|err| format!("Failed to open source '{}'. {:#}", src, err))?;
+                    if let Some(version) = version {
+                        self.versions.insert(src, version);
                     }
-                    Some(path.to_owned())
-                }),
-                &mut Fn(|src: &str, target: &str| {
+                    Ok(Some(stream))
+                }
+            }
+
+            // Zero for the requested asset.
+            let stack_depth = stack.len() - 1;
+            let item = stack.last_mut().unwrap();
+
+            let (output_path, output_hash) = loop {
+                let importer = item.chain[item.step];
+                let output_path = temporaries.make_temporary();
+
+                // Options are meant for the importer that produces the target.
+                let options = if item.step + 1 == item.chain.len() {
+                    item.options.as_deref()
+                } else {
+                    None
+                };
+
+                let cancellation =
+                    ImportCancellation::new(cancel, self.timeouts.get(importer.name()));
+                let mut diagnostics = Vec::new();
+
+                let mut step_progress = StepProgress {
+                    sender: progress,
+                    source: &item.source,
+                    target: &item.target,
+                    depth: stack_depth,
+                    importer: importer.name(),
+                    step: item.step,
+                    steps: item.chain.len(),
+                };
+                treasury_import::Progress::report(&mut step_progress, 0.0, "Started");
+
+                // Events and spans forwarded from importers libraries are emitted inside this span.
+                let span = tracing::info_span!(
+                    "import",
+                    importer = importer.name(),
+                    source = %item.source,
+                    target = %item.target,
+                );
+
+                let mut dependencies = Fn(|src: &str, target: &str| {
                     let src = item.source.join(src).ok()?;
 
                     match SourceMeta::new(&src, base, external) {
@@ -362,82 +868,237 @@ impl Treasury {
                             None
                         }
                     }
-                }),
-            );
+                });
+
+                // Output of streaming importer is hashed while it is written.
+                let mut output_hash = None;
+
+                let result = if importer.supports_streams() {
+                    let mut step_source: Box<dyn Read> = match &item.intermediate {
+                        Some(intermediate) => {
+                            let file = File::open(intermediate).wrap_err_with(|| {
+                                format!(
+                                    "Failed to open intermediate file '{}'",
+                                    intermediate.display()
+                                )
+                            })?;
+                            Box::new(BufReader::new(file))
+                        }
+                        None => {
+                            let (content, _) = sources.get(&item.source).ok_or_else(|| {
+                                eyre::eyre!("Source '{}' is not fetched", item.source)
+                            })?;
+                            content.open().wrap_err_with(|| {
+                                format!("Failed to open source '{}'", item.source)
+                            })?
+                        }
+                    };
+
+                    let output = File::create(&output_path).wrap_err_with(|| {
+                        format!("Failed to create output file '{}'", output_path.display())
+                    })?;
+                    let mut output = Sha256Writer::new(BufWriter::new(output));
+
+                    let result = span.in_scope(|| {
+                        blocking(|| {
+                            importer.import_stream(
+                                &mut step_source,
+                                &mut output,
+                                options,
+                                &mut Streams {
+                                    source: &item.source,
+                                    sources: &sources,
+                                    versions: &mut item.sources,
+                                },
+                                &mut dependencies,
+                                &cancellation,
+                                &mut diagnostics,
+                                &mut step_progress,
+                            )
+                        })
+                    });
+
+                    if result.is_ok() {
+                        output.flush().wrap_err_with(|| {
+                            format!("Failed to write output file '{}'", output_path.display())
+                        })?;
+                        output_hash = Some(output.finish());
+                    }
+                    result
+                } else {
+                    let step_source = match &item.intermediate {
+                        Some(intermediate) => intermediate.clone(),
+                        None => {
+                            let (path, _) = sources
+                                .path(&mut temporaries, &item.source)?
+                                .ok_or_else(|| {
+                                    eyre::eyre!("Source '{}' is not fetched", item.source)
+                                })?;
+                            path.to_owned()
+                        }
+                    };
+
+                    span.in_scope(|| {
+                        blocking(|| {
+                            importer.import(
+                                &step_source,
+                                &output_path,
+                                options,
+                                &mut Fn(|src: &str| {
+                                    // If parsing fails - source will be listed in `ImportResult::RequireSources`.
+                                    let Ok(src) = item.source.join(src) else {
+                                        return Ok(None);
+                                    };
+                                    match sources.path(&mut temporaries, &src) {
+                                        Err(err) => Err(format!("{:#}", err)),
+                                        Ok(None) => Ok(None),
+                                        Ok(Some((path, version))) => {
+                                            if let Some(version) = version {
+                                                item.sources.insert(src, version);
+                                            }
+                                            Ok(Some(path.to_owned()))
+                                        }
+                                    }
+                                }),
+                                &mut dependencies,
+                                &cancellation,
+                                &mut diagnostics,
+                                &mut step_progress,
+                            )
+                        })
+                    })
+                };
 
-            match result {
-                Ok(()) => {}
-                Err(ImportError::Other { reason }) => {
-                    return Err(eyre::eyre!(
-                        "Failed to import {}:{:?}->{}. {}",
-                        item.source,
-                        item.format,
-                        item.target,
-                        reason,
-                    ))
+                // Result of interrupted import is discarded even if importer did not notice.
+                if let Some(interrupted) = cancellation.interrupted(importer.name()) {
+                    return Err(interrupted.into());
+                }
+
+                // Diagnostics of attempts that require more sources or dependencies are discarded,
+                // as the importer runs again.
+                if matches!(
+                    result,
+                    Ok(()) | Err(ImportError::Other { .. } | ImportError::Panicked { .. })
+                ) {
+                    for diagnostic in &diagnostics {
+                        log_diagnostic(importer.name(), diagnostic);
+                    }
                 }
-                Err(ImportError::RequireSources { sources: srcs }) => {
-                    if item.attempt >= MAX_ITEM_ATTEMPTS {
+
+                match result {
+                    Ok(()) => {
+                        item.diagnostics.append(&mut diagnostics);
+                        item.step += 1;
+                        if item.step == item.chain.len() {
+                            break (output_path, output_hash);
+                        }
+
+                        // Output of this step is the source for the next one.
+                        item.intermediate = Some(output_path);
+                    }
+                    Err(ImportError::Cancelled) => {
                         return Err(eyre::eyre!(
-                            "Failed to import {}:{:?}->{}. Too many attempts",
+                            "Failed to import {}:{:?}->{} with '{}'. Importer cancelled the import",
                             item.source,
                             item.format,
                             item.target,
-                        ));
+                            importer.name(),
+                        ))
                     }
-
-                    let source = item.source.clone();
-                    for src in srcs {
-                        match source.join(&src) {
-                            Err(err) => {
-                                return Err(eyre::eyre!(
-                                    "Failed to join URL '{}' with '{}'. {:#}",
-                                    source,
-                                    src,
-                                    err,
-                                ))
-                            }
-                            Ok(url) => sources.fetch(&mut temporaries, &url).await?,
-                        };
+                    Err(ImportError::Other { reason }) => {
+                        return Err(eyre::eyre!(
+                            "Failed to import {}:{:?}->{} with '{}'. {}{}",
+                            item.source,
+                            item.format,
+                            item.target,
+                            importer.name(),
+                            reason,
+                            error_diagnostics(&diagnostics),
+                        ))
                     }
-                    continue;
-                }
-                Err(ImportError::RequireDependencies { dependencies }) => {
-                    if item.attempt >= MAX_ITEM_ATTEMPTS {
+                    Err(ImportError::Panicked { message }) => {
                         return Err(eyre::eyre!(
-                            "Failed to import {}:{:?}->{}. Too many attempts",
+                            "Failed to import {}:{:?}->{} with '{}'. Importer panicked: {}{}",
                             item.source,
                             item.format,
                             item.target,
-                        ));
+                            importer.name(),
+                            message,
+                            error_diagnostics(&diagnostics),
+                        ))
                     }
+                    Err(ImportError::RequireSources { sources: srcs }) => {
+                        if item.attempt >= MAX_ITEM_ATTEMPTS {
+                            return Err(eyre::eyre!(
+                                "Failed to import {}:{:?}->{}. Too many attempts",
+                                item.source,
+                                item.format,
+                                item.target,
+                            ));
+                        }
 
-                    let source = item.source.clone();
-                    for dep in dependencies.into_iter() {
-                        match source.join(&dep.source) {
-                            Err(err) => {
-                                return Err(eyre::eyre!(
-                                    "Failed to join URL '{}' with '{}'. {:#}",
-                                    source,
-                                    dep.source,
-                                    err,
-                                ))
-                            }
-                            Ok(url) => {
-                                stack.push(StackItem {
-                                    source: url,
-                                    format: None,
-                                    target: dep.target,
-                                    attempt: 0,
-                                    sources: HashMap::new(),
-                                    dependencies: HashSet::new(),
-                                });
-                            }
-                        };
+                        let source = item.source.clone();
+                        for src in srcs {
+                            match source.join(&src) {
+                                Err(err) => {
+                                    return Err(eyre::eyre!(
+                                        "Failed to join URL '{}' with '{}'. {:#}",
+                                        source,
+                                        src,
+                                        err,
+                                    ))
+                                }
+                                Ok(url) => {
+                                    sources
+                                        .fetch(&mut temporaries, &url, remote.as_deref_mut())
+                                        .await?
+                                }
+                            };
+                        }
+                        continue 'items;
+                    }
+                    Err(ImportError::RequireDependencies { dependencies }) => {
+                        if item.attempt >= MAX_ITEM_ATTEMPTS {
+                            return Err(eyre::eyre!(
+                                "Failed to import {}:{:?}->{}. Too many attempts",
+                                item.source,
+                                item.format,
+                                item.target,
+                            ));
+                        }
+
+                        let source = item.source.clone();
+                        for dep in dependencies.into_iter() {
+                            match source.join(&dep.source) {
+                                Err(err) => {
+                                    return Err(eyre::eyre!(
+                                        "Failed to join URL '{}' with '{}'. {:#}",
+                                        source,
+                                        dep.source,
+                                        err,
+                                    ))
+                                }
+                                Ok(url) => {
+                                    stack.push(StackItem {
+                                        source: url,
+                                        format: None,
+                                        target: dep.target,
+                                        attempt: 0,
+                                        sources: HashMap::new(),
+                                        dependencies: HashSet::new(),
+                                        chain: Vec::new(),
+                                        step: 0,
+                                        intermediate: None,
+                                        diagnostics: Vec::new(),
+                                        options: None,
+                                    });
+                                }
+                            };
+                        }
+                        continue 'items;
                     }
-                    continue;
                 }
-            }
+            };
 
             if !artifacts.exists() {
                 std::fs::create_dir_all(artifacts).wrap_err_with(|| {
@@ -459,27 +1120,40 @@ impl Treasury {
 
             let item = stack.pop().unwrap();
 
-            let make_relative_source = |source| match self.base_url.make_relative(source) {
-                None => item.source.to_string(),
+            let make_relative_source = |source: &Url| match make_relative(&self.base_url, source) {
+                None => source.to_string(),
                 Some(source) => source,
             };
 
             let mut sources = Vec::new();
-            if let Some(modified) = modified {
-                sources.push((make_relative_source(&item.source), modified));
+            if let Some(version) = version {
+                sources.push((make_relative_source(&item.source), version));
             }
             sources.extend(
                 item.sources
                     .iter()
-                    .map(|(url, modified)| (make_relative_source(url), *modified)),
+                    .map(|(url, version)| (make_relative_source(url), version.clone())),
             );
 
+            let chain: Vec<_> = item
+                .chain
+                .iter()
+                .map(|importer| importer.name().to_owned())
+                .collect();
+
+            let revision = importers.chain_revision(&chain, &item.target).flatten();
+
             let asset = AssetMeta::new(
                 new_id,
                 item.format.clone(),
+                chain,
+                revision,
                 sources,
                 item.dependencies.into_iter().collect(),
+                item.diagnostics,
+                item.options,
                 &output_path,
+                output_hash,
                 artifacts,
             )
             .wrap_err("Failed to prepare new asset")?;
@@ -488,6 +1162,17 @@ impl Treasury {
 
             meta.add_asset(item.target.clone(), asset, base, external)?;
 
+            if let Some(progress) = progress {
+                progress.send(ImportProgress {
+                    source: item.source.clone(),
+                    target: item.target.clone(),
+                    depth: stack.len(),
+                    importer: None,
+                    fraction: 1.0,
+                    stage: "Stored".to_owned(),
+                });
+            }
+
             self.artifacts.write().insert(
                 new_id,
                 AssetItem {
@@ -515,7 +1200,12 @@ impl Treasury {
 
             if !*scanned {
                 scan_local(&self.base, &existing_artifacts, &mut new_artifacts);
-                scan_external(&self.external, &existing_artifacts, &mut new_artifacts);
+                scan_external(
+                    &self.base,
+                    &self.external,
+                    &existing_artifacts,
+                    &mut new_artifacts,
+                );
 
                 let mut artifacts = self.artifacts.write();
                 for (id, item) in new_artifacts {
@@ -539,6 +1229,19 @@ impl Treasury {
         Some(path)
     }
 
+    /// Returns diagnostics reported by importers when the asset was imported.
+    /// Returns `None` if asset is not imported.
+    pub fn diagnostics(&self, source: &str, target: &str) -> eyre::Result<Option<Vec<Diagnostic>>> {
+        let source_url = join_source(&self.base_url, source)?;
+
+        let meta = SourceMeta::new(&source_url, &self.base, &self.external)
+            .wrap_err("Failed to fetch source meta")?;
+
+        Ok(meta
+            .get_asset(target)
+            .map(|asset| asset.diagnostics().to_vec()))
+    }
+
     /// Fetch asset data path.
     pub async fn find_asset(
         &self,
@@ -546,12 +1249,7 @@ impl Treasury {
         target: &str,
         new_id: impl FnMut() -> AssetId,
     ) -> eyre::Result<Option<(AssetId, PathBuf)>> {
-        let source_url = self.base_url.join(source).wrap_err_with(|| {
-            format!(
-                "Failed to construct URL from base '{}' and source '{}'",
-                self.base_url, source
-            )
-        })?;
+        let source_url = join_source(&self.base_url, source)?;
 
         let meta = SourceMeta::new(&source_url, &self.base, &self.external)
             .wrap_err("Failed to fetch source meta")?;
@@ -580,6 +1278,54 @@ impl Treasury {
     }
 }
 
+/// Formats error diagnostics to append to the import error message.
+fn error_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| format!("\n{}", diagnostic))
+        .collect()
+}
+
+fn log_diagnostic(importer: &str, diagnostic: &Diagnostic) {
+    match diagnostic.severity {
+        Severity::Info => tracing::info!("Importer '{}': {}", importer, diagnostic),
+        Severity::Warning => tracing::warn!("Importer '{}': {}", importer, diagnostic),
+        Severity::Error => tracing::error!("Importer '{}': {}", importer, diagnostic),
+    }
+}
+
+/// Joins source to base URL.
+/// Archive paths relative to base in `archive:` URLs are resolved as well.
+fn join_source(base: &Url, source: &str) -> eyre::Result<Url> {
+    let url = base.join(source).wrap_err_with(|| {
+        format!(
+            "Failed to construct URL from base '{}' and source '{}'",
+            base, source
+        )
+    })?;
+
+    provider::archive::resolve(base, url)
+}
+
+/// Makes source URL relative to base URL.
+/// `archive:` URLs keep the scheme with archive path made relative.
+fn make_relative(base: &Url, source: &Url) -> Option<String> {
+    base.make_relative(source)
+        .or_else(|| provider::archive::make_relative(base, source))
+}
+
+/// Runs importer call without stalling other tasks of multi-threaded runtime.
+/// Current-thread runtime cannot hand its tasks over, so the call blocks it.
+fn blocking<R>(f: impl FnOnce() -> R) -> R {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
 pub fn find_treasury_info(mut path: PathBuf) -> Option<PathBuf> {
     loop {
         path.push(TREASURY_META_NAME);
@@ -594,7 +1340,22 @@ pub fn find_treasury_info(mut path: PathBuf) -> Option<PathBuf> {
     }
 }
 
+/// Reads first bytes of the source for importers to probe.
+fn read_head(content: &SourceContent) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(treasury_import::PROBE_LEN);
+    content
+        .open()?
+        .take(treasury_import::PROBE_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
 fn url_ext(url: &Url) -> Option<&str> {
+    if url.scheme() == "data" {
+        // Path of data URL is its content.
+        return None;
+    }
+
     let path = url.path();
     let dot = path.rfind('.')?;
     let sep = path.rfind('/')?;
@@ -606,11 +1367,12 @@ fn url_ext(url: &Url) -> Option<&str> {
 }
 
 fn scan_external(
+    base: &Path,
     external: &Path,
     existing_artifacts: &HashSet<AssetId>,
     artifacts: &mut Vec<(AssetId, AssetItem)>,
 ) {
-    let dir = match std::fs::read_dir(&external) {
+    let dir = match std::fs::read_dir(external) {
         Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
             tracing::info!("External directory does not exists");
             return;
@@ -647,7 +1409,7 @@ fn scan_external(
             Ok(ft) => ft,
         };
         if ft.is_file() && !SourceMeta::is_local_meta_path(&path) {
-            let meta = match SourceMeta::open_external(&path) {
+            let meta = match SourceMeta::open_external(&path, base) {
                 Err(err) => {
                     tracing::error!("Failed to scan meta file '{}'. {:#}", path.display(), err);
                     continue;
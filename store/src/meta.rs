@@ -9,13 +9,33 @@ use std::{
 use eyre::WrapErr;
 use hashbrown::HashMap;
 use treasury_id::AssetId;
+use treasury_import::Diagnostic;
 use url::Url;
 
-use crate::{scheme::Scheme, sha256::Sha256Hash};
+use crate::{
+    provider::{archive, file, HttpValidators, SourceProviders},
+    remote::{remote_source_path, RemoteSources, REMOTE_SCHEME},
+    sha256::Sha256Hash,
+};
 
 const PREFIX_STARTING_LEN: usize = 8;
-const EXTENSION: &'static str = "treasure";
-const DOT_EXTENSION: &'static str = ".treasure";
+const EXTENSION: &str = "treasure";
+const DOT_EXTENSION: &str = ".treasure";
+
+/// Version of the source recorded when asset is imported.
+/// Used to check if source was modified since.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum SourceVersion {
+    /// Last modification time of the source.
+    Modified(SystemTime),
+
+    /// HTTP cache validators of the downloaded source.
+    Http(HttpValidators),
+
+    /// Opaque token produced by custom source provider.
+    Token(String),
+}
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct AssetMeta {
@@ -27,6 +47,18 @@ pub struct AssetMeta {
     #[serde(skip_serializing_if = "Option::is_none", default)]
     format: Option<String>,
 
+    // Names of importers that produced the asset, in order of application.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    chain: Vec<String>,
+
+    // Hash of importers libraries used in the chain.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    revision: Option<Sha256Hash>,
+
+    // Import options as JSON document.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    options: Option<String>,
+
     #[serde(skip_serializing_if = "prefix_is_default", default = "default_prefix")]
     prefix: usize,
 
@@ -37,9 +69,13 @@ pub struct AssetMeta {
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     dependencies: Vec<AssetId>,
 
-    // Key is URL, value is last modified time.
+    // Key is URL, value is source version.
     #[serde(skip_serializing_if = "HashMap::is_empty", default)]
-    sources: HashMap<String, SystemTime>,
+    sources: HashMap<String, SourceVersion>,
+
+    // Diagnostics reported by importers in the chain.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    diagnostics: Vec<Diagnostic>,
 }
 
 fn prefix_is_default(prefix: &usize) -> bool {
@@ -69,20 +105,36 @@ impl AssetMeta {
     ///
     /// If artifact with the same hash already exists in the `artifacts` directory,
     /// it will be shared between assets.
+    ///
+    /// `chain` lists names of importers applied to the source one after another.
+    /// `revision` identifies versions of importers libraries used in the chain.
+    /// `diagnostics` are reported by importers in the chain.
+    /// `options` are import options the asset was imported with.
+    /// `output_hash` is hash of the output computed while it was written,
+    /// `None` if output file is to be hashed.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: AssetId,
         format: Option<String>,
-        sources: Vec<(String, SystemTime)>,
+        chain: Vec<String>,
+        revision: Option<Sha256Hash>,
+        sources: Vec<(String, SourceVersion)>,
         dependencies: Vec<AssetId>,
+        diagnostics: Vec<Diagnostic>,
+        options: Option<String>,
         output: &Path,
+        output_hash: Option<Sha256Hash>,
         artifacts: &Path,
     ) -> eyre::Result<Self> {
-        let sha256 = Sha256Hash::file_hash(output).wrap_err_with(|| {
-            format!(
-                "Failed to calculate hash of the file '{}'",
-                output.display()
-            )
-        })?;
+        let sha256 = match output_hash {
+            Some(sha256) => sha256,
+            None => Sha256Hash::file_hash(output).wrap_err_with(|| {
+                format!(
+                    "Failed to calculate hash of the file '{}'",
+                    output.display()
+                )
+            })?,
+        };
 
         let hex = format!("{:x}", sha256);
 
@@ -156,6 +208,10 @@ impl AssetMeta {
             suffix,
             sources: sources.into_iter().collect(),
             dependencies,
+            chain,
+            revision,
+            diagnostics,
+            options,
         })
     }
 
@@ -167,9 +223,36 @@ impl AssetMeta {
         self.format.as_deref()
     }
 
-    pub fn needs_reimport(&self, base: &Url) -> bool {
-        for (url, last_modified) in &self.sources {
-            let url = match base.join(url) {
+    pub fn chain(&self) -> &[String] {
+        &self.chain
+    }
+
+    pub fn revision(&self) -> Option<&Sha256Hash> {
+        self.revision.as_ref()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn options(&self) -> Option<&str> {
+        self.options.as_deref()
+    }
+
+    /// Checks if any source was modified since asset was imported.
+    /// `remote` is used to check sources that live on remote client.
+    pub async fn needs_reimport(
+        &self,
+        base: &Url,
+        providers: &SourceProviders,
+        mut remote: Option<&mut (dyn RemoteSources + '_)>,
+    ) -> bool {
+        for (url, version) in &self.sources {
+            let url = match base
+                .join(url)
+                .map_err(eyre::Report::from)
+                .and_then(|url| archive::resolve(base, url))
+            {
                 Err(err) => {
                     tracing::error!(
                         "Failed to figure out source URL from base: {} and source: {}. {:#}. Asset can be outdated",
@@ -182,39 +265,62 @@ impl AssetMeta {
                 Ok(url) => url,
             };
 
-            match url.scheme().parse() {
-                Ok(Scheme::File) => {
-                    let path = match url.to_file_path() {
-                        Err(()) => {
-                            tracing::error!("Invalid file URL");
-                            continue;
-                        }
-                        Ok(path) => path,
-                    };
-
-                    let modified = match path.metadata().and_then(|meta| meta.modified()) {
-                        Err(err) => {
-                            tracing::error!(
-                                "Failed to check how new the source file is. {:#}",
-                                err
-                            );
-                            continue;
-                        }
-                        Ok(modified) => modified,
-                    };
+            if url.scheme() == REMOTE_SCHEME {
+                let remote = match remote.as_deref_mut() {
+                    None => {
+                        tracing::debug!(
+                            "Remote source '{}' cannot be checked without remote client",
+                            url
+                        );
+                        continue;
+                    }
+                    Some(remote) => remote,
+                };
 
-                    if modified < *last_modified {
-                        tracing::warn!("Source file is older than when asset was imported. Could be clock change. Reimort just in case");
-                        return true;
+                let path = match remote_source_path(&url) {
+                    Err(err) => {
+                        tracing::error!("{:#}", err);
+                        continue;
                     }
+                    Ok(path) => path,
+                };
 
-                    if modified > *last_modified {
-                        tracing::debug!("Source file was updated");
-                        return true;
+                let modified = match remote.modified(&path).await {
+                    Err(err) => {
+                        tracing::error!("Failed to check how new the remote source is. {:#}", err);
+                        continue;
+                    }
+                    Ok(None) => {
+                        tracing::warn!("Remote source '{}' is missing on client", url);
+                        continue;
                     }
+                    Ok(Some(modified)) => modified,
+                };
+
+                if file::is_modified(modified, version) {
+                    return true;
+                }
+                continue;
+            }
+
+            let provider = match providers.get(url.scheme()) {
+                None => {
+                    tracing::error!("Unsupported scheme: '{}'", url.scheme());
+                    continue;
+                }
+                Some(provider) => provider,
+            };
+
+            match provider.is_modified(&url, version).await {
+                Err(err) => {
+                    tracing::error!("Failed to check source '{}'. {:#}", url, err);
+                    continue;
+                }
+                Ok(false) => {}
+                Ok(true) => {
+                    tracing::debug!("Source '{}' was updated", url);
+                    return true;
                 }
-                Ok(Scheme::Data) => continue,
-                Err(_) => tracing::error!("Unsupported scheme: '{}'", url.scheme()),
             }
         }
 
@@ -259,12 +365,25 @@ struct FileError<E: Error> {
 /// It may include several assets.
 /// If attached to external source outside treasury directory
 /// then it is stored together with artifacts by URL hash.
-#[derive(serde::Serialize, serde::Deserialize)]
 pub struct SourceMeta {
     url: Url,
     assets: HashMap<String, AssetMeta>,
 }
 
+/// External meta file content.
+/// Archives inside treasury directory are recorded relative to it.
+#[derive(serde::Deserialize)]
+struct ExternalMeta {
+    url: String,
+    assets: HashMap<String, AssetMeta>,
+}
+
+#[derive(serde::Serialize)]
+struct ExternalMetaRef<'a> {
+    url: &'a str,
+    assets: &'a HashMap<String, AssetMeta>,
+}
+
 impl SourceMeta {
     /// Finds and returns meta for the source URL.
     /// Creates new file if needed.
@@ -283,7 +402,7 @@ impl SourceMeta {
     }
 
     pub fn is_local_meta_path(meta_path: &Path) -> bool {
-        meta_path.extension().map_or(false, |e| e == EXTENSION)
+        meta_path.extension().is_some_and(|e| e == EXTENSION)
     }
 
     pub fn new_local(meta_path: &Path) -> eyre::Result<SourceMeta> {
@@ -335,7 +454,8 @@ impl SourceMeta {
             })
             .wrap_err("Meta read failed"),
             Ok(data) => {
-                let assets = toml::from_slice(&data)
+                // External meta is written together with source URL.
+                let meta: ExternalMeta = toml::from_slice(&data)
                     .map_err(|err| FileError {
                         error: err,
                         path: meta_path.to_owned(),
@@ -343,13 +463,13 @@ impl SourceMeta {
                     .wrap_err("Meta read failed")?;
                 Ok(SourceMeta {
                     url: source.clone(),
-                    assets,
+                    assets: meta.assets,
                 })
             }
         }
     }
 
-    pub fn open_external(meta_path: &Path) -> eyre::Result<SourceMeta> {
+    pub fn open_external(meta_path: &Path, base: &Path) -> eyre::Result<SourceMeta> {
         match std::fs::read(meta_path) {
             Err(err) => Err(FileError {
                 error: err,
@@ -357,13 +477,30 @@ impl SourceMeta {
             })
             .wrap_err("Meta read failed"),
             Ok(data) => {
-                let meta = toml::from_slice(&data)
+                let meta: ExternalMeta = toml::from_slice(&data)
                     .map_err(|err| FileError {
                         error: err,
                         path: meta_path.to_owned(),
                     })
                     .wrap_err("Meta read failed")?;
-                Ok(meta)
+
+                let base = base_url(base)?;
+                let url = base
+                    .join(&meta.url)
+                    .map_err(eyre::Report::from)
+                    .and_then(|url| archive::resolve(&base, url))
+                    .wrap_err_with(|| {
+                        format!(
+                            "Invalid source URL '{}' in meta '{}'",
+                            meta.url,
+                            meta_path.display()
+                        )
+                    })?;
+
+                Ok(SourceMeta {
+                    url,
+                    assets: meta.assets,
+                })
             }
         }
     }
@@ -387,7 +524,7 @@ impl SourceMeta {
 
         let (meta_path, is_external) = get_meta_path(&self.url, base, external)?;
         if is_external {
-            self.write_with_url_to(&meta_path)?;
+            self.write_with_url_to(&meta_path, base)?;
         } else {
             self.write_to(&meta_path)?;
         }
@@ -401,7 +538,7 @@ impl SourceMeta {
                 path: path.to_owned(),
             })
             .wrap_err("Meta write failed")?;
-        std::fs::write(path, data.as_bytes())
+        write_atomic(path, data.as_bytes())
             .map_err(|err| FileError {
                 error: err,
                 path: path.to_owned(),
@@ -410,14 +547,20 @@ impl SourceMeta {
         Ok(())
     }
 
-    fn write_with_url_to(&self, path: &Path) -> eyre::Result<()> {
-        let data = toml::to_string_pretty(self)
+    fn write_with_url_to(&self, path: &Path, base: &Path) -> eyre::Result<()> {
+        let url = recorded_url(&self.url, base)?;
+        let meta = ExternalMetaRef {
+            url: &url,
+            assets: &self.assets,
+        };
+
+        let data = toml::to_string_pretty(&meta)
             .map_err(|err| FileError {
                 error: err,
                 path: path.to_owned(),
             })
             .wrap_err("Meta write failed")?;
-        std::fs::write(path, data.as_bytes())
+        write_atomic(path, data.as_bytes())
             .map_err(|err| FileError {
                 error: err,
                 path: path.to_owned(),
@@ -427,6 +570,21 @@ impl SourceMeta {
     }
 }
 
+/// Writes file through temporary sibling file,
+/// so that interrupted write does not leave partially written file.
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    std::fs::write(&partial, data)?;
+    if let Err(err) = std::fs::rename(&partial, path) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(err);
+    }
+    Ok(())
+}
+
 fn files_eq(lhs: &Path, rhs: &Path) -> std::io::Result<bool> {
     let mut lhs = File::open(lhs)?;
     let mut rhs = File::open(rhs)?;
@@ -457,26 +615,33 @@ fn files_eq(lhs: &Path, rhs: &Path) -> std::io::Result<bool> {
     }
 }
 
+fn base_url(base: &Path) -> eyre::Result<Url> {
+    Url::from_directory_path(base)
+        .map_err(|()| eyre::eyre!("'{}' is invalid base path", base.display()))
+}
+
+/// Returns source URL as it is recorded in external meta.
+fn recorded_url(source: &Url, base: &Path) -> eyre::Result<String> {
+    let base = base_url(base)?;
+    Ok(archive::make_relative(&base, source).unwrap_or_else(|| source.to_string()))
+}
+
 /// Finds and returns meta for the source URL.
 /// Creates new file if needed.
 fn get_meta_path(source: &Url, base: &Path, external: &Path) -> eyre::Result<(PathBuf, bool)> {
     if source.scheme() == "file" {
-        match source.to_file_path() {
-            Ok(path) => {
-                let path =
-                    dunce::canonicalize(&path).map_err(|err| CanonError { error: err, path })?;
+        if let Ok(path) = source.to_file_path() {
+            let path = dunce::canonicalize(&path).map_err(|err| CanonError { error: err, path })?;
 
-                if path.starts_with(base) {
-                    // Files inside `base` directory has meta attached to them as sibling file with `.treasure` extension added.
+            if path.starts_with(base) {
+                // Files inside `base` directory has meta attached to them as sibling file with `.treasure` extension added.
 
-                    let mut filename = path.file_name().unwrap_or("".as_ref()).to_owned();
-                    filename.push(DOT_EXTENSION);
+                let mut filename = path.file_name().unwrap_or("".as_ref()).to_owned();
+                filename.push(DOT_EXTENSION);
 
-                    let path = path.with_file_name(filename);
-                    return Ok((path, false));
-                }
+                let path = path.with_file_name(filename);
+                return Ok((path, false));
             }
-            Err(()) => {}
         }
     }
 
@@ -487,7 +652,8 @@ fn get_meta_path(source: &Url, base: &Path, external: &Path) -> eyre::Result<(Pa
         )
     })?;
 
-    let hash = Sha256Hash::new(source.as_str());
+    // Recorded URL does not change when treasury directory is moved.
+    let hash = Sha256Hash::new(recorded_url(source, base)?.as_bytes());
     let hex = format!("{:x}", hash);
 
     with_path_candidates(&hex, external, |_prefix, _suffix, path| {
@@ -498,7 +664,7 @@ fn get_meta_path(source: &Url, base: &Path, external: &Path) -> eyre::Result<(Pa
             }
             Ok(md) => {
                 if md.is_file() {
-                    match SourceMeta::open_external(&path) {
+                    match SourceMeta::open_external(&path, base) {
                         Err(_) => {
                             tracing::error!(
                                 "Failed to open existing source metadata at '{}'",
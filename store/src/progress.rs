@@ -0,0 +1,112 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use url::Url;
+
+/// Progress of an asset being stored.
+#[derive(Clone, Debug)]
+pub struct ImportProgress {
+    /// Source of the asset.
+    pub source: Url,
+
+    /// Target format of the asset.
+    pub target: String,
+
+    /// Position in the dependency stack.
+    /// Zero for the requested asset, dependencies are deeper.
+    pub depth: usize,
+
+    /// Importer that reports progress.
+    /// `None` for stages performed by the store itself.
+    pub importer: Option<String>,
+
+    /// Fraction of the asset import done, in range `0.0..=1.0`.
+    /// Accounts for all importers in the chain.
+    pub fraction: f32,
+
+    /// Description of the current stage.
+    pub stage: String,
+}
+
+/// Sending half of progress stream.
+/// Passed to `StoreOptions::progress`.
+#[derive(Clone, Debug)]
+pub struct ProgressSender {
+    sender: UnboundedSender<ImportProgress>,
+}
+
+impl ProgressSender {
+    pub(crate) fn send(&self, progress: ImportProgress) {
+        // Progress is not interesting if stream is dropped.
+        let _ = self.sender.send(progress);
+    }
+}
+
+/// Stream of import progress.
+/// Ends when all senders are dropped.
+#[derive(Debug)]
+pub struct ProgressStream {
+    receiver: UnboundedReceiver<ImportProgress>,
+}
+
+impl ProgressStream {
+    /// Returns next progress update.
+    pub async fn recv(&mut self) -> Option<ImportProgress> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for ProgressStream {
+    type Item = ImportProgress;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ImportProgress>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Creates connected progress sender and stream.
+pub fn progress_stream() -> (ProgressSender, ProgressStream) {
+    let (sender, receiver) = unbounded_channel();
+    (ProgressSender { sender }, ProgressStream { receiver })
+}
+
+/// Progress of a single importer call in the chain.
+pub(crate) struct StepProgress<'a> {
+    pub sender: Option<&'a ProgressSender>,
+    pub source: &'a Url,
+    pub target: &'a str,
+    pub depth: usize,
+    pub importer: &'a str,
+
+    /// Index of the step and number of steps in the chain.
+    pub step: usize,
+    pub steps: usize,
+}
+
+impl treasury_import::Progress for StepProgress<'_> {
+    fn report(&mut self, fraction: f32, stage: &str) {
+        let sender = match self.sender {
+            None => return,
+            Some(sender) => sender,
+        };
+
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+
+        sender.send(ImportProgress {
+            source: self.source.clone(),
+            target: self.target.to_owned(),
+            depth: self.depth,
+            importer: Some(self.importer.to_owned()),
+            fraction: (self.step as f32 + fraction) / self.steps as f32,
+            stage: stage.to_owned(),
+        });
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use eyre::WrapErr;
+use futures_util::future::BoxFuture;
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::meta::SourceVersion;
+
+use super::{file, FetchedSource, SourceContent, SourceProvider};
+
+/// Separates path to the archive from path of the entry inside it.
+const ENTRY_SEPARATOR: &str = "!/";
+
+/// Provider for sources inside archives.
+///
+/// URLs look like `archive:/path/to/pack.zip!/path/inside/archive.png`.
+/// Relative URLs resolve to siblings inside the same archive.
+/// Entry is extracted into temporary file, archive modification time is used as version.
+///
+/// Supports zip, tar and gzipped tar archives.
+pub struct ArchiveProvider;
+
+impl SourceProvider for ArchiveProvider {
+    fn schemes(&self) -> &[&str] {
+        &["archive"]
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        source: &'a Url,
+        temp: &'a Path,
+    ) -> BoxFuture<'a, eyre::Result<FetchedSource>> {
+        Box::pin(async move {
+            let (archive, entry) = split_archive_url(source)?;
+
+            tracing::debug!(
+                "Extracting '{}' from archive '{}'",
+                entry,
+                archive.display()
+            );
+
+            let modified = archive
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .wrap_err_with(|| format!("Failed to access archive '{}'", archive.display()))?;
+
+            let mut output = File::create(temp).wrap_err_with(|| {
+                format!("Failed to create temporary file '{}'", temp.display())
+            })?;
+
+            extract(&archive, &entry, &mut output).wrap_err_with(|| {
+                format!(
+                    "Failed to extract '{}' from archive '{}'",
+                    entry,
+                    archive.display()
+                )
+            })?;
+
+            Ok(FetchedSource {
+                content: SourceContent::File(temp.to_owned()),
+                version: Some(SourceVersion::Modified(modified)),
+            })
+        })
+    }
+
+    fn is_modified<'a>(
+        &'a self,
+        source: &'a Url,
+        version: &'a SourceVersion,
+    ) -> BoxFuture<'a, eyre::Result<bool>> {
+        Box::pin(async move {
+            let (archive, _) = split_archive_url(source)?;
+
+            let modified = archive
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .wrap_err("Failed to check how new the archive is")?;
+
+            Ok(file::is_modified(modified, version))
+        })
+    }
+}
+
+/// Splits archive URL into archive file path and entry path.
+fn split_archive_url(source: &Url) -> eyre::Result<(PathBuf, String)> {
+    let path = source.path();
+
+    let sep = path.find(ENTRY_SEPARATOR).ok_or_else(|| {
+        eyre::eyre!(
+            "Archive URL '{}' does not contain '{}' separator",
+            source,
+            ENTRY_SEPARATOR
+        )
+    })?;
+
+    let archive_url = Url::parse(&format!("file://{}", &path[..sep]))
+        .wrap_err_with(|| format!("Invalid archive path in URL '{}'", source))?;
+
+    let archive = archive_url
+        .to_file_path()
+        .map_err(|()| eyre::eyre!("Invalid archive path in URL '{}'", source))?;
+
+    let entry = percent_decode_str(&path[sep + ENTRY_SEPARATOR.len()..])
+        .decode_utf8()
+        .wrap_err_with(|| format!("Archive entry path in URL '{}' is not UTF-8", source))?
+        .into_owned();
+
+    Ok((archive, entry))
+}
+
+/// Returns `archive:` URL with archive path relative to `base` directory URL,
+/// e.g. `archive:packs/pack.zip!/a.png`.
+/// Returns `None` if `source` is not an archive URL or archive is outside of `base`.
+pub(crate) fn make_relative(base: &Url, source: &Url) -> Option<String> {
+    if source.scheme() != "archive" {
+        return None;
+    }
+
+    let path = source.path();
+    let sep = path.find(ENTRY_SEPARATOR)?;
+    let archive = Url::parse(&format!("file://{}", &path[..sep])).ok()?;
+
+    let relative = base.make_relative(&archive)?;
+    if relative.starts_with("../") {
+        return None;
+    }
+
+    Some(format!("archive:{}{}", relative, &path[sep..]))
+}
+
+/// Resolves `archive:` URL with archive path relative to `base` directory URL.
+/// Other URLs are returned as is.
+pub(crate) fn resolve(base: &Url, source: Url) -> eyre::Result<Url> {
+    if source.scheme() != "archive" || source.path().starts_with('/') {
+        return Ok(source);
+    }
+
+    let path = source.path();
+    let sep = path.find(ENTRY_SEPARATOR).ok_or_else(|| {
+        eyre::eyre!(
+            "Archive URL '{}' does not contain '{}' separator",
+            source,
+            ENTRY_SEPARATOR
+        )
+    })?;
+
+    let archive = base
+        .join(&path[..sep])
+        .wrap_err_with(|| format!("Invalid archive path in URL '{}'", source))?;
+
+    Url::parse(&format!("archive:{}{}", archive.path(), &path[sep..]))
+        .wrap_err_with(|| format!("Invalid archive path in URL '{}'", source))
+}
+
+fn extract(archive: &Path, entry: &str, output: &mut File) -> eyre::Result<()> {
+    let file = File::open(archive)?;
+    let name = archive
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+
+    if name.ends_with(".zip") {
+        let mut zip = zip::ZipArchive::new(file)?;
+        let mut entry = zip.by_name(entry)?;
+        std::io::copy(&mut entry, output)?;
+        return Ok(());
+    }
+
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        return extract_tar(flate2::read::GzDecoder::new(file), entry, output);
+    }
+
+    if name.ends_with(".tar") {
+        return extract_tar(file, entry, output);
+    }
+
+    Err(eyre::eyre!("Unsupported archive format"))
+}
+
+fn extract_tar(read: impl Read, entry: &str, output: &mut impl Write) -> eyre::Result<()> {
+    let mut tar = tar::Archive::new(read);
+    let entry = normalize_entry(Path::new(entry));
+
+    for e in tar.entries()? {
+        let mut e = e?;
+        if normalize_entry(&e.path()?) == entry {
+            std::io::copy(&mut e, output)?;
+            return Ok(());
+        }
+    }
+
+    Err(eyre::eyre!("Entry not found"))
+}
+
+/// Strips `.` components, so `./a/b` and `a/./b` match `a/b`.
+fn normalize_entry(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn tar(write: impl Write, entries: &[(&str, &[u8])]) {
+        let mut tar = tar::Builder::new(write);
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            // `append_data` would normalize the path, archives made by other tools may not.
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            tar.append(&header, *data).unwrap();
+        }
+        tar.into_inner().unwrap();
+    }
+
+    fn extract_to_vec(archive: &Path, entry: &str) -> eyre::Result<Vec<u8>> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entry");
+        extract(archive, entry, &mut File::create(&path).unwrap())?;
+        Ok(std::fs::read(&path).unwrap())
+    }
+
+    #[test]
+    fn splits_archive_url() {
+        let url = Url::parse("archive:/packs/pack%20a.zip!/dir/a%20b.png").unwrap();
+        let (archive, entry) = split_archive_url(&url).unwrap();
+
+        assert_eq!(archive, Path::new("/packs/pack a.zip"));
+        assert_eq!(entry, "dir/a b.png");
+
+        let url = Url::parse("archive:/packs/pack.zip").unwrap();
+        assert!(split_archive_url(&url).is_err());
+    }
+
+    #[test]
+    fn relative_sources_stay_in_archive() {
+        let url = Url::parse("archive:/packs/pack.zip!/dir/a.gltf").unwrap();
+        let sibling = url.join("b.bin").unwrap();
+
+        let (archive, entry) = split_archive_url(&sibling).unwrap();
+        assert_eq!(archive, Path::new("/packs/pack.zip"));
+        assert_eq!(entry, "dir/b.bin");
+    }
+
+    #[test]
+    fn makes_archive_path_relative_to_base() {
+        let base = Url::parse("file:///base/").unwrap();
+
+        let url = Url::parse("archive:/base/packs/pack.zip!/dir/a.png").unwrap();
+        let relative = make_relative(&base, &url).unwrap();
+        assert_eq!(relative, "archive:packs/pack.zip!/dir/a.png");
+
+        let resolved = resolve(&base, Url::parse(&relative).unwrap()).unwrap();
+        assert_eq!(resolved, url);
+
+        let outside = Url::parse("archive:/other/pack.zip!/a.png").unwrap();
+        assert_eq!(make_relative(&base, &outside), None);
+        assert_eq!(resolve(&base, outside.clone()).unwrap(), outside);
+    }
+
+    #[test]
+    fn extracts_zip_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.zip");
+        zip(&path, &[("a.txt", b"a"), ("dir/b.txt", b"b")]);
+
+        assert_eq!(extract_to_vec(&path, "a.txt").unwrap(), b"a");
+        assert_eq!(extract_to_vec(&path, "dir/b.txt").unwrap(), b"b");
+        assert!(extract_to_vec(&path, "c.txt").is_err());
+    }
+
+    #[test]
+    fn extracts_tar_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.tar");
+        tar(
+            File::create(&path).unwrap(),
+            &[("a.txt", b"a"), ("./dir/b.txt", b"b")],
+        );
+
+        assert_eq!(extract_to_vec(&path, "a.txt").unwrap(), b"a");
+        assert_eq!(extract_to_vec(&path, "dir/b.txt").unwrap(), b"b");
+        assert_eq!(extract_to_vec(&path, "./a.txt").unwrap(), b"a");
+        assert!(extract_to_vec(&path, "c.txt").is_err());
+    }
+
+    #[test]
+    fn extracts_gzipped_tar_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.tar.gz");
+        let gz = flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        tar(gz, &[("./a.txt", b"a")]);
+
+        assert_eq!(extract_to_vec(&path, "a.txt").unwrap(), b"a");
+    }
+
+    #[test]
+    fn rejects_unknown_archive_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.rar");
+        std::fs::write(&path, b"").unwrap();
+
+        assert!(extract_to_vec(&path, "a.txt").is_err());
+    }
+}
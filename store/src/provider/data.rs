@@ -0,0 +1,242 @@
+use std::{borrow::Cow, path::Path};
+
+use base64::{
+    alphabet::{STANDARD, URL_SAFE},
+    engine::{
+        fast_portable::{FastPortable, FastPortableConfig},
+        DecodePaddingMode,
+    },
+};
+use eyre::WrapErr;
+use futures_util::future::BoxFuture;
+use percent_encoding::percent_decode_str;
+use url::{Position, Url};
+
+use crate::meta::SourceVersion;
+
+use super::{FetchedSource, SourceContent, SourceProvider};
+
+/// Padding is optional when decoding base64 payload.
+const BASE64_CONFIG: FastPortableConfig =
+    FastPortableConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+
+/// Provider for `data:` sources.
+/// Content is decoded into memory. Data URLs never change.
+pub struct DataProvider;
+
+impl SourceProvider for DataProvider {
+    fn schemes(&self) -> &[&str] {
+        &["data"]
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        source: &'a Url,
+        _temp: &'a Path,
+    ) -> BoxFuture<'a, eyre::Result<FetchedSource>> {
+        Box::pin(async move {
+            let data_url = DataUrl::parse(source)?;
+            let decoded = data_url.decode()?;
+
+            Ok(FetchedSource {
+                content: SourceContent::Memory(decoded),
+                version: None,
+            })
+        })
+    }
+
+    fn is_modified<'a>(
+        &'a self,
+        _source: &'a Url,
+        _version: &'a SourceVersion,
+    ) -> BoxFuture<'a, eyre::Result<bool>> {
+        Box::pin(async move { Ok(false) })
+    }
+}
+
+/// Parsed `data:[<mediatype>][;base64],<data>` URL as defined by RFC 2397.
+struct DataUrl<'a> {
+    /// Media type without parameters, lowercased.
+    /// Empty if URL does not declare one.
+    media_type: String,
+    base64: bool,
+    data: &'a str,
+}
+
+impl<'a> DataUrl<'a> {
+    fn parse(source: &'a Url) -> eyre::Result<Self> {
+        if source.scheme() != "data" {
+            return Err(eyre::eyre!("'{}' is not a data URL", source));
+        }
+
+        // Fragment is not part of the data.
+        let content = &source[Position::BeforePath..Position::AfterQuery];
+
+        let comma = content
+            .find(',')
+            .ok_or_else(|| eyre::eyre!("Invalid data URL. Comma is missing"))?;
+
+        let (header, data) = (&content[..comma], &content[comma + 1..]);
+
+        let mut params = header.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or("");
+        let base64 = match params.next_back() {
+            Some(last) => last.eq_ignore_ascii_case("base64"),
+            None => false,
+        };
+
+        let media_type = percent_decode_str(media_type)
+            .decode_utf8_lossy()
+            .trim()
+            .to_ascii_lowercase();
+
+        Ok(DataUrl {
+            media_type,
+            base64,
+            data,
+        })
+    }
+
+    fn decode(&self) -> eyre::Result<Vec<u8>> {
+        let data: Cow<[u8]> = percent_decode_str(self.data).into();
+
+        if !self.base64 {
+            return Ok(data.into_owned());
+        }
+
+        let data: Vec<u8> = data
+            .iter()
+            .copied()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+
+        // RFC 2397 uses standard alphabet. URL-safe one is accepted as fallback.
+        match base64::decode_engine(&data, &FastPortable::from(&STANDARD, BASE64_CONFIG)) {
+            Ok(decoded) => Ok(decoded),
+            Err(err) => base64::decode_engine(&data, &FastPortable::from(&URL_SAFE, BASE64_CONFIG))
+                .map_err(|_| err)
+                .wrap_err("Failed to decode base64 data URL"),
+        }
+    }
+}
+
+/// Returns media type declared in `data:` URL.
+/// Parameters are stripped.
+pub(crate) fn media_type(source: &Url) -> Option<String> {
+    if source.scheme() != "data" {
+        return None;
+    }
+
+    let data_url = DataUrl::parse(source).ok()?;
+    if data_url.media_type.is_empty() {
+        None
+    } else {
+        Some(data_url.media_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        importer::{ImporterPreferences, Importers},
+        test_importer::TestImporter,
+    };
+
+    use super::*;
+
+    fn decode(url: &str) -> eyre::Result<Vec<u8>> {
+        let url = Url::parse(url).unwrap();
+        DataUrl::parse(&url)?.decode()
+    }
+
+    #[test]
+    fn decodes_plain_data() {
+        assert_eq!(decode("data:,hello").unwrap(), b"hello");
+        assert_eq!(
+            decode("data:text/plain;charset=utf-8,hello").unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn decodes_percent_encoded_data() {
+        assert_eq!(decode("data:,hello%20world%21").unwrap(), b"hello world!");
+
+        // `+` and `/` of standard alphabet may be percent-encoded.
+        assert_eq!(decode("data:;base64,%2B%2F8%3D").unwrap(), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn decodes_padded_and_unpadded_base64() {
+        assert_eq!(decode("data:;base64,aGk=").unwrap(), b"hi");
+        assert_eq!(decode("data:;base64,aGk").unwrap(), b"hi");
+        assert_eq!(decode("data:;base64,aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode("data:;base64,aGVsbG8").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn decodes_both_base64_alphabets() {
+        assert_eq!(decode("data:;base64,+/8=").unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode("data:;base64,-_8=").unwrap(), [0xfb, 0xff]);
+
+        // Valid in both alphabets.
+        assert_eq!(decode("data:;base64,AAEC").unwrap(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn ignores_whitespace_in_base64() {
+        assert_eq!(decode("data:;base64,aGVs%20bG8=").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn rejects_invalid_base64() {
+        assert!(decode("data:;base64,a*b=").is_err());
+        assert!(decode("data:;base64,+_8=").is_err());
+    }
+
+    #[test]
+    fn rejects_missing_comma() {
+        assert!(decode("data:text/plain").is_err());
+    }
+
+    #[test]
+    fn parses_media_type() {
+        let url = Url::parse("data:Image/PNG;name=a.png;base64,AAAA").unwrap();
+        assert_eq!(media_type(&url).as_deref(), Some("image/png"));
+
+        let url = Url::parse("data:;base64,AAAA").unwrap();
+        assert_eq!(media_type(&url), None);
+
+        let url = Url::parse("file:///a.png").unwrap();
+        assert_eq!(media_type(&url), None);
+    }
+
+    #[test]
+    fn guesses_importer_by_media_type() {
+        let mut importers = Importers::new(&ImporterPreferences::default()).unwrap();
+        importers.register_importer(TestImporter::new("png", "image").extension("png"));
+        importers.register_importer(TestImporter::new("svg", "image").format("svg"));
+
+        let guess = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            let media_type = media_type(&url);
+            importers
+                .guess(
+                    url.as_str(),
+                    None,
+                    None,
+                    media_type.as_deref(),
+                    b"",
+                    "image",
+                )
+                .unwrap()
+                .map(|importer| importer.name().to_owned())
+        };
+
+        assert_eq!(guess("data:image/png;base64,AAAA").as_deref(), Some("png"));
+        assert_eq!(
+            guess("data:image/svg+xml,%3Csvg%2F%3E").as_deref(),
+            Some("svg")
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::{path::Path, time::SystemTime};
+
+use eyre::WrapErr;
+use futures_util::future::BoxFuture;
+use url::Url;
+
+use crate::meta::SourceVersion;
+
+use super::{FetchedSource, SourceContent, SourceProvider};
+
+/// Provider for local `file:` sources.
+/// Sources are used in place, modification time is used as version.
+pub struct FileProvider;
+
+impl SourceProvider for FileProvider {
+    fn schemes(&self) -> &[&str] {
+        &["file"]
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        source: &'a Url,
+        _temp: &'a Path,
+    ) -> BoxFuture<'a, eyre::Result<FetchedSource>> {
+        Box::pin(async move {
+            let path = source
+                .to_file_path()
+                .map_err(|()| eyre::eyre!("Invalid file: URL"))?;
+
+            tracing::debug!("Fetching file '{}' ('{}')", source, path.display());
+
+            let modified = path
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .wrap_err_with(|| format!("Failed to access source file '{}'", path.display()))?;
+
+            Ok(FetchedSource {
+                content: SourceContent::File(path),
+                version: Some(SourceVersion::Modified(modified)),
+            })
+        })
+    }
+
+    fn is_modified<'a>(
+        &'a self,
+        source: &'a Url,
+        version: &'a SourceVersion,
+    ) -> BoxFuture<'a, eyre::Result<bool>> {
+        Box::pin(async move {
+            let path = source
+                .to_file_path()
+                .map_err(|()| eyre::eyre!("Invalid file URL"))?;
+
+            let modified = path
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .wrap_err("Failed to check how new the source file is")?;
+
+            Ok(is_modified(modified, version))
+        })
+    }
+}
+
+/// Compares modification time with recorded source version.
+pub(crate) fn is_modified(modified: SystemTime, version: &SourceVersion) -> bool {
+    let last_modified = match version {
+        SourceVersion::Modified(last_modified) => *last_modified,
+        _ => {
+            tracing::warn!("Unexpected version of source file. Reimport");
+            return true;
+        }
+    };
+
+    if modified < last_modified {
+        tracing::warn!("Source file is older than when asset was imported. Could be clock change. Reimort just in case");
+        return true;
+    }
+
+    if modified > last_modified {
+        tracing::debug!("Source file was updated");
+        return true;
+    }
+
+    false
+}
@@ -0,0 +1,371 @@
+use std::{fs::File, io::Write, path::Path};
+
+use eyre::WrapErr;
+use futures_util::future::BoxFuture;
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, StatusCode,
+};
+use url::Url;
+
+use crate::meta::SourceVersion;
+
+use super::{FetchedSource, SourceContent, SourceProvider};
+
+/// HTTP cache validators of the downloaded source.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HttpValidators {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub etag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub last_modified: Option<String>,
+}
+
+/// Provider for `http:` and `https:` sources.
+/// Sources are downloaded into temporary files and revalidated with conditional requests.
+pub struct HttpProvider {
+    client: Client,
+}
+
+impl HttpProvider {
+    pub fn new() -> Self {
+        HttpProvider {
+            client: Client::new(),
+        }
+    }
+}
+
+impl SourceProvider for HttpProvider {
+    fn schemes(&self) -> &[&str] {
+        &["http", "https"]
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        source: &'a Url,
+        temp: &'a Path,
+    ) -> BoxFuture<'a, eyre::Result<FetchedSource>> {
+        Box::pin(async move {
+            let validators = download(&self.client, source, temp).await?;
+
+            Ok(FetchedSource {
+                content: SourceContent::File(temp.to_owned()),
+                version: Some(SourceVersion::Http(validators)),
+            })
+        })
+    }
+
+    fn is_modified<'a>(
+        &'a self,
+        source: &'a Url,
+        version: &'a SourceVersion,
+    ) -> BoxFuture<'a, eyre::Result<bool>> {
+        Box::pin(async move {
+            match version {
+                SourceVersion::Http(validators) => {
+                    is_modified(&self.client, source, validators).await
+                }
+                _ => {
+                    tracing::warn!("Unexpected version of source '{}'. Reimport", source);
+                    Ok(true)
+                }
+            }
+        })
+    }
+}
+
+/// Downloads source into file at `path`.
+/// Returns validators to check if source was modified later.
+async fn download(client: &Client, url: &Url, path: &Path) -> eyre::Result<HttpValidators> {
+    tracing::debug!("Downloading '{}'", url);
+
+    let mut response = client
+        .get(url.clone())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .wrap_err_with(|| format!("Failed to download '{}'", url))?;
+
+    let validators = HttpValidators {
+        etag: header_value(&response, ETAG),
+        last_modified: header_value(&response, LAST_MODIFIED),
+    };
+
+    let mut file = File::create(path)
+        .wrap_err_with(|| format!("Failed to create temporary file '{}'", path.display()))?;
+
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .wrap_err_with(|| format!("Failed to download '{}'", url))?
+    {
+        file.write_all(&chunk).wrap_err_with(|| {
+            format!(
+                "Failed to write downloaded content to temporary file '{}'",
+                path.display()
+            )
+        })?;
+    }
+
+    Ok(validators)
+}
+
+/// Revalidates source with conditional request.
+/// Returns `true` if source was modified since validators were received.
+async fn is_modified(
+    client: &Client,
+    url: &Url,
+    validators: &HttpValidators,
+) -> eyre::Result<bool> {
+    if validators.etag.is_none() && validators.last_modified.is_none() {
+        tracing::debug!("No validators for '{}'. Assume modified", url);
+        return Ok(true);
+    }
+
+    let mut request = client.head(url.clone());
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = request
+        .send()
+        .await
+        .wrap_err_with(|| format!("Failed to revalidate '{}'", url))?;
+
+    match response.status() {
+        StatusCode::NOT_MODIFIED => Ok(false),
+        status if status.is_success() => {
+            // Server may ignore conditional headers.
+            let current = HttpValidators {
+                etag: header_value(&response, ETAG),
+                last_modified: header_value(&response, LAST_MODIFIED),
+            };
+            Ok(current != *validators)
+        }
+        status => Err(eyre::eyre!(
+            "Failed to revalidate '{}'. Status: {}",
+            url,
+            status
+        )),
+    }
+}
+
+fn header_value(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    let value = response.headers().get(name)?;
+    Some(value.to_str().ok()?.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const ETAG_VALUE: &str = "\"v1\"";
+    const LAST_MODIFIED_VALUE: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    /// Request received by the test server.
+    struct Request {
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    /// Response of the test server: status, headers and body.
+    type Response = (u16, Vec<(&'static str, &'static str)>, &'static [u8]);
+
+    /// Serves HTTP/1.1 requests on local port with `handler`.
+    /// Returns base URL and requests received so far.
+    async fn server(handler: fn(&Request) -> Response) -> (Url, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    if stream.read(&mut byte).await.unwrap() == 0 {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+
+                let head = String::from_utf8(head).unwrap();
+                let mut lines = head.lines();
+                let mut request_line = lines.next().unwrap().split(' ');
+                let request = Request {
+                    method: request_line.next().unwrap().to_owned(),
+                    path: request_line.next().unwrap().to_owned(),
+                    headers: lines
+                        .filter_map(|line| line.split_once(": "))
+                        .map(|(n, v)| (n.to_owned(), v.to_owned()))
+                        .collect(),
+                };
+
+                let (status, headers, body) = handler(&request);
+                let mut response = format!(
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    status,
+                    body.len()
+                );
+                for (name, value) in headers {
+                    response.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                response.push_str("\r\n");
+
+                let mut response = response.into_bytes();
+                if request.method != "HEAD" && status != 304 {
+                    response.extend_from_slice(body);
+                }
+
+                received.lock().unwrap().push(request);
+                stream.write_all(&response).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    /// Responds with validators and honors conditional headers.
+    fn conditional(request: &Request) -> Response {
+        let validators = vec![("ETag", ETAG_VALUE), ("Last-Modified", LAST_MODIFIED_VALUE)];
+
+        if request.header("If-None-Match") == Some(ETAG_VALUE) {
+            return (304, validators, b"");
+        }
+        (200, validators, b"content")
+    }
+
+    fn validators(etag: &str) -> HttpValidators {
+        HttpValidators {
+            etag: Some(etag.to_owned()),
+            last_modified: Some(LAST_MODIFIED_VALUE.to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_content_and_validators() {
+        let (url, requests) = server(conditional).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("source");
+
+        let validators = download(&Client::new(), &url.join("a.txt").unwrap(), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"content");
+        assert_eq!(validators, self::validators(ETAG_VALUE));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/a.txt");
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_head_request() {
+        let (url, requests) = server(conditional).await;
+
+        let modified = is_modified(&Client::new(), &url, &validators(ETAG_VALUE))
+            .await
+            .unwrap();
+        assert!(!modified);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].method, "HEAD");
+        assert_eq!(requests[0].header("If-None-Match"), Some(ETAG_VALUE));
+        assert_eq!(
+            requests[0].header("If-Modified-Since"),
+            Some(LAST_MODIFIED_VALUE)
+        );
+    }
+
+    #[tokio::test]
+    async fn modified_when_validators_change() {
+        let (url, _) = server(conditional).await;
+
+        let modified = is_modified(&Client::new(), &url, &validators("\"v0\""))
+            .await
+            .unwrap();
+        assert!(modified);
+    }
+
+    #[tokio::test]
+    async fn not_modified_when_conditional_headers_are_ignored() {
+        let (url, _) = server(|_| {
+            (
+                200,
+                vec![("ETag", ETAG_VALUE), ("Last-Modified", LAST_MODIFIED_VALUE)],
+                b"content",
+            )
+        })
+        .await;
+
+        let modified = is_modified(&Client::new(), &url, &validators(ETAG_VALUE))
+            .await
+            .unwrap();
+        assert!(!modified);
+    }
+
+    #[tokio::test]
+    async fn modified_without_validators() {
+        let no_validators = HttpValidators {
+            etag: None,
+            last_modified: None,
+        };
+
+        // No request is made, so unreachable URL is fine.
+        let url = Url::parse("http://127.0.0.1:1/").unwrap();
+        let modified = is_modified(&Client::new(), &url, &no_validators)
+            .await
+            .unwrap();
+        assert!(modified);
+    }
+
+    #[tokio::test]
+    async fn fails_on_error_status() {
+        let (url, _) = server(|request| match request.path.as_str() {
+            "/missing" => (404, vec![], b"not found"),
+            _ => (500, vec![], b"error"),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let err = download(
+            &Client::new(),
+            &url.join("missing").unwrap(),
+            &dir.path().join("source"),
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("404"), "{:#}", err);
+
+        let err = is_modified(&Client::new(), &url, &validators(ETAG_VALUE))
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("500"), "{:#}", err);
+    }
+}
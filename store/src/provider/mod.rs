@@ -0,0 +1,200 @@
+//! Source providers fetch sources by URL and detect their changes.
+//! Each provider handles URLs with specific schemes.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures_util::future::BoxFuture;
+use hashbrown::HashMap;
+use url::Url;
+
+use crate::meta::SourceVersion;
+
+pub(crate) mod archive;
+pub(crate) mod data;
+pub(crate) mod file;
+mod http;
+
+pub use self::http::HttpValidators;
+
+/// Content of the fetched source.
+pub enum SourceContent {
+    /// Path to the source content.
+    /// Either temporary path given to the provider or any other existing file.
+    File(PathBuf),
+
+    /// Source content kept in memory.
+    /// Streaming importers read it directly,
+    /// it is written to temporary file only for importers that take paths.
+    Memory(Vec<u8>),
+}
+
+impl SourceContent {
+    /// Opens stream of the content.
+    pub(crate) fn open(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        match self {
+            SourceContent::File(path) => Ok(Box::new(BufReader::new(File::open(path)?))),
+            SourceContent::Memory(data) => Ok(Box::new(&data[..])),
+        }
+    }
+}
+
+/// Source fetched by [`SourceProvider`].
+pub struct FetchedSource {
+    pub content: SourceContent,
+
+    /// Version of the fetched source used to detect changes later.
+    /// `None` if source never changes.
+    pub version: Option<SourceVersion>,
+}
+
+/// Trait for source providers.
+///
+/// Provider fetches sources with URL schemes it handles
+/// and checks if previously fetched sources were modified.
+pub trait SourceProvider: Send + Sync {
+    /// Returns list of URL schemes this provider handles.
+    fn schemes(&self) -> &[&str];
+
+    /// Fetches source.
+    /// Provider may write source content to `temp` path or keep it in memory.
+    fn fetch<'a>(
+        &'a self,
+        source: &'a Url,
+        temp: &'a Path,
+    ) -> BoxFuture<'a, eyre::Result<FetchedSource>>;
+
+    /// Checks if source was modified since `version` was fetched.
+    fn is_modified<'a>(
+        &'a self,
+        source: &'a Url,
+        version: &'a SourceVersion,
+    ) -> BoxFuture<'a, eyre::Result<bool>>;
+}
+
+/// Collection of source providers by scheme.
+pub struct SourceProviders {
+    schemes: HashMap<String, Arc<dyn SourceProvider>>,
+}
+
+impl SourceProviders {
+    /// Returns collection with built-in `file:`, `data:`, `http:`, `https:` and `archive:` providers.
+    pub fn new() -> Self {
+        let mut providers = SourceProviders {
+            schemes: HashMap::new(),
+        };
+
+        providers.register_provider(file::FileProvider);
+        providers.register_provider(data::DataProvider);
+        providers.register_provider(http::HttpProvider::new());
+        providers.register_provider(archive::ArchiveProvider);
+
+        providers
+    }
+
+    /// Adds source provider.
+    /// Replaces previously registered providers for the same schemes.
+    pub fn register_provider(&mut self, provider: impl SourceProvider + 'static) {
+        let provider: Arc<dyn SourceProvider> = Arc::new(provider);
+
+        for &scheme in provider.schemes() {
+            tracing::info!("Registering source provider for '{}:' scheme", scheme);
+
+            if self
+                .schemes
+                .insert(scheme.to_owned(), provider.clone())
+                .is_some()
+            {
+                tracing::debug!("Source provider for '{}:' scheme replaced", scheme);
+            }
+        }
+    }
+
+    /// Returns provider for the URL scheme.
+    pub fn get(&self, scheme: &str) -> Option<&dyn SourceProvider> {
+        self.schemes.get(scheme).map(|provider| &**provider)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named(&'static [&'static str]);
+
+    impl SourceProvider for Named {
+        fn schemes(&self) -> &[&str] {
+            self.0
+        }
+
+        fn fetch<'a>(
+            &'a self,
+            _source: &'a Url,
+            _temp: &'a Path,
+        ) -> BoxFuture<'a, eyre::Result<FetchedSource>> {
+            Box::pin(async move {
+                Ok(FetchedSource {
+                    content: SourceContent::Memory(self.0.join(",").into_bytes()),
+                    version: None,
+                })
+            })
+        }
+
+        fn is_modified<'a>(
+            &'a self,
+            _source: &'a Url,
+            _version: &'a SourceVersion,
+        ) -> BoxFuture<'a, eyre::Result<bool>> {
+            Box::pin(async move { Ok(false) })
+        }
+    }
+
+    fn fetch(providers: &SourceProviders, url: &str) -> Vec<u8> {
+        let url = Url::parse(url).unwrap();
+        let provider = providers.get(url.scheme()).unwrap();
+        let fetched = futures_util::FutureExt::now_or_never(provider.fetch(&url, Path::new("")))
+            .unwrap()
+            .unwrap();
+
+        match fetched.content {
+            SourceContent::Memory(data) => data,
+            SourceContent::File(_) => panic!("Unexpected file content"),
+        }
+    }
+
+    #[test]
+    fn registers_builtin_providers() {
+        let providers = SourceProviders::new();
+        for scheme in ["file", "data", "http", "https", "archive"] {
+            assert!(providers.get(scheme).is_some(), "{}", scheme);
+        }
+        assert!(providers.get("custom").is_none());
+    }
+
+    #[test]
+    fn dispatches_by_scheme() {
+        let mut providers = SourceProviders::new();
+        providers.register_provider(Named(&["a", "b"]));
+        providers.register_provider(Named(&["c"]));
+
+        assert_eq!(fetch(&providers, "a:x"), b"a,b");
+        assert_eq!(fetch(&providers, "b:x"), b"a,b");
+        assert_eq!(fetch(&providers, "c:x"), b"c");
+        assert_eq!(fetch(&providers, "data:,x"), b"x");
+    }
+
+    #[test]
+    fn replaces_provider_for_same_scheme() {
+        let mut providers = SourceProviders::new();
+        providers.register_provider(Named(&["a", "b"]));
+        providers.register_provider(Named(&["b", "data"]));
+
+        assert_eq!(fetch(&providers, "a:x"), b"a,b");
+        assert_eq!(fetch(&providers, "b:x"), b"b,data");
+        assert_eq!(fetch(&providers, "data:,x"), b"b,data");
+    }
+}
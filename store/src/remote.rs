@@ -0,0 +1,454 @@
+//! Remote store protocol.
+//!
+//! Remote client asks server to store a source that lives on client's machine.
+//! Server then asks client for the source data and for any source requested by importers,
+//! imports them from temporaries and records client-relative `remote:` URLs in metadata.
+//!
+//! Every message is prefixed with its length as little-endian `u32`.
+//! Source data is sent in chunks, so messages stay small.
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use eyre::WrapErr;
+use futures_util::future::BoxFuture;
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncRead, AsyncWrite};
+use treasury_id::AssetId;
+use url::Url;
+
+pub use crate::framing::{recv, send};
+
+use crate::{StoreOptions, Treasury};
+
+/// Scheme of URLs for sources that live on remote client.
+pub(crate) const REMOTE_SCHEME: &str = "remote";
+
+/// Largest chunk of source data sent in one message.
+const SOURCE_CHUNK_LEN: usize = 1 << 16;
+
+/// Source data uploaded by remote client.
+pub struct RemoteSource {
+    /// Last modification time of the source on client machine.
+    pub modified: SystemTime,
+
+    /// Source content.
+    pub data: Vec<u8>,
+}
+
+/// Access to sources that live on remote client machine.
+///
+/// `source` arguments are paths relative to client's base directory.
+pub trait RemoteSources: Send {
+    /// Fetches source data from the client.
+    /// Returns `None` if client does not have the source.
+    fn fetch<'a>(
+        &'a mut self,
+        source: &'a str,
+    ) -> BoxFuture<'a, eyre::Result<Option<RemoteSource>>>;
+
+    /// Returns last modification time of the source on the client.
+    /// Returns `None` if client does not have the source.
+    fn modified<'a>(
+        &'a mut self,
+        source: &'a str,
+    ) -> BoxFuture<'a, eyre::Result<Option<SystemTime>>>;
+}
+
+/// Messages sent by remote client.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum ClientMessage {
+    /// Request to store an asset.
+    Store {
+        /// Source path relative to client's base directory.
+        source: String,
+        format: Option<String>,
+        target: String,
+    },
+
+    /// Reply to [`ServerMessage::FetchSource`].
+    /// Unless `modified` is `None`, source data follows in [`ClientMessage::Chunk`] messages
+    /// terminated by [`ClientMessage::End`].
+    Source {
+        source: String,
+        modified: Option<SystemTime>,
+    },
+
+    /// Chunk of source data.
+    Chunk {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+
+    /// End of source data.
+    End,
+
+    /// Reply to [`ServerMessage::QueryModified`].
+    Modified {
+        source: String,
+        modified: Option<SystemTime>,
+    },
+
+    /// Reply to [`ServerMessage::FetchSource`] or [`ServerMessage::QueryModified`]
+    /// when client failed to access the source.
+    /// May follow chunks of source data if client failed to read the rest.
+    Failed { source: String, reason: String },
+}
+
+/// Messages sent by server to remote client.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum ServerMessage {
+    /// Server requires source data.
+    FetchSource { source: String },
+
+    /// Server checks if previously imported source was modified.
+    QueryModified { source: String },
+
+    /// Store request succeeded.
+    Stored { id: AssetId },
+
+    /// Store request failed.
+    Failed { reason: String },
+}
+
+/// Returns `remote:` URL for the source path relative to client's base directory.
+pub fn remote_url(source: &str) -> Result<Url, url::ParseError> {
+    Url::parse("remote:/")?.join(source)
+}
+
+/// Returns source path relative to client's base directory for `remote:` URL.
+pub(crate) fn remote_source_path(url: &Url) -> eyre::Result<Cow<'_, str>> {
+    percent_decode_str(url.path().trim_start_matches('/'))
+        .decode_utf8()
+        .wrap_err_with(|| format!("Remote source URL '{}' is not UTF-8", url))
+}
+
+/// Server side of the connection with remote client.
+struct Connection<S> {
+    stream: S,
+}
+
+impl<S> Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn recv_reply(&mut self) -> eyre::Result<ClientMessage> {
+        recv(&mut self.stream)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Remote client disconnected"))
+    }
+}
+
+impl<S> RemoteSources for Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn fetch<'a>(
+        &'a mut self,
+        source: &'a str,
+    ) -> BoxFuture<'a, eyre::Result<Option<RemoteSource>>> {
+        Box::pin(async move {
+            send(
+                &mut self.stream,
+                &ServerMessage::FetchSource {
+                    source: source.to_owned(),
+                },
+            )
+            .await?;
+
+            let modified = match self.recv_reply().await? {
+                ClientMessage::Source {
+                    source: src,
+                    modified,
+                } if src == source => modified,
+                reply => return Err(fetch_error(source, reply)),
+            };
+
+            let Some(modified) = modified else {
+                return Ok(None);
+            };
+
+            let mut data = Vec::new();
+            loop {
+                match self.recv_reply().await? {
+                    ClientMessage::Chunk { data: chunk } => data.extend_from_slice(&chunk),
+                    ClientMessage::End => return Ok(Some(RemoteSource { modified, data })),
+                    reply => return Err(fetch_error(source, reply)),
+                }
+            }
+        })
+    }
+
+    fn modified<'a>(
+        &'a mut self,
+        source: &'a str,
+    ) -> BoxFuture<'a, eyre::Result<Option<SystemTime>>> {
+        Box::pin(async move {
+            send(
+                &mut self.stream,
+                &ServerMessage::QueryModified {
+                    source: source.to_owned(),
+                },
+            )
+            .await?;
+
+            match self.recv_reply().await? {
+                ClientMessage::Modified {
+                    source: src,
+                    modified,
+                } if src == source => Ok(modified),
+                ClientMessage::Failed {
+                    source: src,
+                    reason,
+                } if src == source => Err(eyre::eyre!(
+                    "Remote client failed to check source '{}'. {}",
+                    source,
+                    reason
+                )),
+                _ => Err(eyre::eyre!(
+                    "Unexpected reply from remote client while checking source '{}'",
+                    source
+                )),
+            }
+        })
+    }
+}
+
+/// Returns error for reply to [`ServerMessage::FetchSource`] that carries no source data.
+fn fetch_error(source: &str, reply: ClientMessage) -> eyre::Report {
+    match reply {
+        ClientMessage::Failed {
+            source: src,
+            reason,
+        } if src == source => eyre::eyre!(
+            "Remote client failed to read source '{}'. {}",
+            source,
+            reason
+        ),
+        _ => eyre::eyre!(
+            "Unexpected reply from remote client while fetching source '{}'",
+            source
+        ),
+    }
+}
+
+/// Serves store requests from remote client until it disconnects.
+pub async fn serve<S>(
+    treasury: &Treasury,
+    stream: S,
+    mut new_id: impl FnMut() -> AssetId,
+) -> eyre::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut connection = Connection { stream };
+
+    loop {
+        let message = match recv(&mut connection.stream).await? {
+            None => return Ok(()),
+            Some(message) => message,
+        };
+
+        match message {
+            ClientMessage::Store {
+                source,
+                format,
+                target,
+            } => {
+                let result = match remote_url(&source) {
+                    Err(err) => Err(eyre::Report::new(err).wrap_err(format!(
+                        "Failed to construct remote URL for source '{}'",
+                        source
+                    ))),
+                    Ok(url) => {
+                        treasury
+                            .store_url_with(
+                                url,
+                                format.as_deref(),
+                                &target,
+                                StoreOptions::new().remote(&mut connection),
+                                &mut new_id,
+                            )
+                            .await
+                    }
+                };
+
+                let reply = match result {
+                    Ok((id, _)) => ServerMessage::Stored { id },
+                    Err(err) => {
+                        tracing::error!("Failed to store remote source '{}'. {:#}", source, err);
+                        ServerMessage::Failed {
+                            reason: format!("{:#}", err),
+                        }
+                    }
+                };
+
+                send(&mut connection.stream, &reply).await?;
+            }
+            _ => return Err(eyre::eyre!("Unexpected message from remote client")),
+        }
+    }
+}
+
+/// Client that stores sources from its own machine on remote treasury server.
+pub struct Client<S> {
+    stream: S,
+    base: PathBuf,
+}
+
+impl<S> Client<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Creates new client over connected stream.
+    /// Sources are looked up relative to `base` directory.
+    pub fn new(stream: S, base: &Path) -> Self {
+        Client {
+            stream,
+            base: base.to_owned(),
+        }
+    }
+
+    /// Stores an asset on the server.
+    /// `source` is path relative to client's base directory.
+    pub async fn store(
+        &mut self,
+        source: &str,
+        format: Option<&str>,
+        target: &str,
+    ) -> eyre::Result<AssetId> {
+        send(
+            &mut self.stream,
+            &ClientMessage::Store {
+                source: source.to_owned(),
+                format: format.map(str::to_owned),
+                target: target.to_owned(),
+            },
+        )
+        .await?;
+
+        loop {
+            let message = recv(&mut self.stream)
+                .await?
+                .ok_or_else(|| eyre::eyre!("Server disconnected"))?;
+
+            match message {
+                // Local errors are reported to the server, which is waiting for the reply.
+                ServerMessage::FetchSource { source } => self.send_source(source).await?,
+                ServerMessage::QueryModified { source } => {
+                    let reply = match self.source_modified(&source) {
+                        Ok(modified) => ClientMessage::Modified { source, modified },
+                        Err(err) => ClientMessage::Failed {
+                            source,
+                            reason: format!("{:#}", err),
+                        },
+                    };
+                    send(&mut self.stream, &reply).await?;
+                }
+                ServerMessage::Stored { id } => return Ok(id),
+                ServerMessage::Failed { reason } => {
+                    return Err(eyre::eyre!("Failed to store '{}'. {}", source, reason))
+                }
+            }
+        }
+    }
+
+    fn source_path(&self, source: &str) -> eyre::Result<PathBuf> {
+        let path = Path::new(source);
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(eyre::eyre!(
+                "Server requested source '{}' outside of base directory",
+                source
+            ));
+        }
+        Ok(self.base.join(path))
+    }
+
+    /// Replies to [`ServerMessage::FetchSource`] with source data split into chunks.
+    async fn send_source(&mut self, source: String) -> eyre::Result<()> {
+        let (path, mut file, modified) = match self.open_source(&source) {
+            Ok(Some(opened)) => opened,
+            Ok(None) => {
+                let reply = ClientMessage::Source {
+                    source,
+                    modified: None,
+                };
+                return send(&mut self.stream, &reply).await;
+            }
+            Err(err) => {
+                let reply = ClientMessage::Failed {
+                    source,
+                    reason: format!("{:#}", err),
+                };
+                return send(&mut self.stream, &reply).await;
+            }
+        };
+
+        let reply = ClientMessage::Source {
+            source: source.clone(),
+            modified: Some(modified),
+        };
+        send(&mut self.stream, &reply).await?;
+
+        let mut buf = vec![0; SOURCE_CHUNK_LEN];
+        loop {
+            let reply = match file
+                .read(&mut buf)
+                .wrap_err_with(|| format!("Failed to read source file '{}'", path.display()))
+            {
+                Ok(0) => return send(&mut self.stream, &ClientMessage::End).await,
+                Ok(len) => ClientMessage::Chunk {
+                    data: buf[..len].to_vec(),
+                },
+                Err(err) => {
+                    let reply = ClientMessage::Failed {
+                        source,
+                        reason: format!("{:#}", err),
+                    };
+                    return send(&mut self.stream, &reply).await;
+                }
+            };
+            send(&mut self.stream, &reply).await?;
+        }
+    }
+
+    /// Opens source file.
+    /// Returns `None` if client does not have the source.
+    fn open_source(&self, source: &str) -> eyre::Result<Option<(PathBuf, File, SystemTime)>> {
+        let path = self.source_path(source)?;
+
+        let modified = match self.source_modified(source)? {
+            None => return Ok(None),
+            Some(modified) => modified,
+        };
+
+        let file = File::open(&path)
+            .wrap_err_with(|| format!("Failed to read source file '{}'", path.display()))?;
+
+        Ok(Some((path, file, modified)))
+    }
+
+    fn source_modified(&self, source: &str) -> eyre::Result<Option<SystemTime>> {
+        let path = self.source_path(source)?;
+
+        match path.metadata() {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err)
+                .wrap_err_with(|| format!("Failed to check source file '{}'", path.display())),
+            Ok(meta) => {
+                let modified = meta.modified().wrap_err_with(|| {
+                    format!("Failed to check source file '{}'", path.display())
+                })?;
+                Ok(Some(modified))
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::{
     borrow::{Borrow, Cow},
     fmt::{self, Debug, LowerHex, UpperHex},
     fs::File,
+    io::{self, Write},
     num::ParseIntError,
     ops::Deref,
     path::Path,
@@ -110,7 +111,7 @@ impl Sha256Hash {
         // Check for a duplicate.
         let mut hasher = Sha256::new();
 
-        let mut file = File::open(&path)?;
+        let mut file = File::open(path)?;
         std::io::copy(&mut file, &mut hasher)?;
 
         let mut bytes = [0u8; 32];
@@ -119,6 +120,43 @@ impl Sha256Hash {
     }
 }
 
+/// Writer that hashes data written through it.
+pub struct Sha256Writer<W> {
+    writer: W,
+    hasher: Sha256,
+}
+
+impl<W> Sha256Writer<W> {
+    pub fn new(writer: W) -> Self {
+        Sha256Writer {
+            writer,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns hash of all data written so far.
+    pub fn finish(self) -> Sha256Hash {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&self.hasher.finalize());
+        Sha256Hash { bytes }
+    }
+}
+
+impl<W> Write for Sha256Writer<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
 impl Serialize for Sha256Hash {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -1,41 +1,62 @@
-use std::{
-    fs::File,
-    io::Write,
-    mem::size_of_val,
-    path::{Path, PathBuf},
-    time::SystemTime,
-};
+use std::path::Path;
 
-use base64::{
-    alphabet::URL_SAFE,
-    engine::fast_portable::{FastPortable, NO_PAD},
-};
 use eyre::WrapErr;
 use hashbrown::{hash_map::RawEntryMut, HashMap};
 use url::Url;
 
-use crate::{scheme::Scheme, temp::Temporaries};
+use crate::{
+    meta::SourceVersion,
+    provider::{SourceContent, SourceProviders},
+    remote::{remote_source_path, RemoteSources, REMOTE_SCHEME},
+    temp::Temporaries,
+};
 
 /// Fetches and caches sources.
-/// Saves remote sources to temporaries.
-pub struct Sources {
-    feched: HashMap<Url, (PathBuf, bool)>,
+/// Keeps remote sources in memory.
+pub struct Sources<'a> {
+    feched: HashMap<Url, (SourceContent, Option<SourceVersion>)>,
+    providers: &'a SourceProviders,
 }
 
-impl Sources {
-    pub fn new() -> Self {
+impl<'a> Sources<'a> {
+    pub fn new(providers: &'a SourceProviders) -> Self {
         Sources {
             feched: HashMap::new(),
+            providers,
         }
     }
 
-    pub fn get(&self, source: &Url) -> Option<(&Path, Option<SystemTime>)> {
-        let (path, local) = self.feched.get(source)?;
-        if *local {
-            let modified = path.metadata().ok()?.modified().ok()?;
-            Some((path, Some(modified)))
-        } else {
-            Some((path, None))
+    pub fn get(&self, source: &Url) -> Option<(&SourceContent, Option<SourceVersion>)> {
+        let (content, version) = self.feched.get(source)?;
+        Some((content, version.clone()))
+    }
+
+    /// Returns path to the content of fetched source.
+    /// Content kept in memory is written to temporary file first.
+    pub fn path(
+        &mut self,
+        temporaries: &mut Temporaries<'_>,
+        source: &Url,
+    ) -> eyre::Result<Option<(&Path, Option<SourceVersion>)>> {
+        let Some((content, version)) = self.feched.get_mut(source) else {
+            return Ok(None);
+        };
+
+        if let SourceContent::Memory(data) = content {
+            let temp = temporaries.make_temporary();
+            std::fs::write(&temp, data).wrap_err_with(|| {
+                format!(
+                    "Failed to write source '{}' content to temporary file '{}'",
+                    source,
+                    temp.display(),
+                )
+            })?;
+            *content = SourceContent::File(temp);
+        }
+
+        match content {
+            SourceContent::File(path) => Ok(Some((path, version.clone()))),
+            SourceContent::Memory(_) => unreachable!(),
         }
     }
 
@@ -43,67 +64,52 @@ impl Sources {
         &mut self,
         temporaries: &mut Temporaries<'_>,
         source: &Url,
-    ) -> eyre::Result<(&Path, Option<SystemTime>)> {
+        remote: Option<&mut (dyn RemoteSources + '_)>,
+    ) -> eyre::Result<(&SourceContent, Option<SourceVersion>)> {
         match self.feched.raw_entry_mut().from_key(source) {
             RawEntryMut::Occupied(entry) => {
-                let (path, local) = entry.into_mut();
-                if *local {
-                    let modified = path.metadata()?.modified()?;
-                    Ok((path, Some(modified)))
-                } else {
-                    Ok((path, None))
-                }
+                let (content, version) = entry.into_mut();
+                Ok((content, version.clone()))
             }
-            RawEntryMut::Vacant(entry) => match source.scheme().parse() {
-                Ok(Scheme::File) => {
-                    let path = source
-                        .to_file_path()
-                        .map_err(|()| eyre::eyre!("Invalid file: URL"))?;
-
-                    let modified = path.metadata()?.modified()?;
+            RawEntryMut::Vacant(entry) if source.scheme() == REMOTE_SCHEME => {
+                let remote = remote.ok_or_else(|| {
+                    eyre::eyre!(
+                        "Remote source '{}' can be fetched only from remote client",
+                        source
+                    )
+                })?;
 
-                    tracing::debug!("Fetching file '{}' ('{}')", source, path.display());
-                    let (_, (path, _)) = entry.insert(source.clone(), (path, true));
+                let path = remote_source_path(source)?;
 
-                    Ok((path, Some(modified)))
-                }
-                Ok(Scheme::Data) => {
-                    let data_start = source.as_str()[size_of_val("data:")..]
-                        .find(',')
-                        .ok_or_else(|| eyre::eyre!("Invalid data URL"))?
-                        + 1
-                        + size_of_val("data:");
-                    let data = &source.as_str()[data_start..];
+                tracing::debug!("Fetching remote source '{}'", source);
+                let fetched = remote
+                    .fetch(&path)
+                    .await?
+                    .ok_or_else(|| eyre::eyre!("Remote source '{}' not found", source))?;
 
-                    let temp = temporaries.make_temporary();
-                    let mut file = File::create(&temp)
-                        .wrap_err("Failed to create temporary file to store data URL content")?;
-
-                    if source.as_str()[..data_start].ends_with(";base64,") {
-                        let decoded =
-                            base64::decode_engine(data, &FastPortable::from(&URL_SAFE, NO_PAD))
-                                .wrap_err("Failed to decode base64 data url")?;
+                let version = SourceVersion::Modified(fetched.modified);
+                let (_, (content, version)) = entry.insert(
+                    source.clone(),
+                    (SourceContent::Memory(fetched.data), Some(version)),
+                );
+                Ok((content, version.clone()))
+            }
+            RawEntryMut::Vacant(entry) => {
+                let provider = self
+                    .providers
+                    .get(source.scheme())
+                    .ok_or_else(|| eyre::eyre!("Unsupported scheme '{}'", source.scheme()))?;
 
-                        file.write_all(&decoded).wrap_err_with(|| {
-                            format!(
-                                "Failed to write data URL content to temporary file '{}'",
-                                temp.display(),
-                            )
-                        })?;
-                    } else {
-                        file.write_all(data.as_bytes()).wrap_err_with(|| {
-                            format!(
-                                "Failed to write data URL content to temporary file '{}'",
-                                temp.display(),
-                            )
-                        })?;
-                    }
+                let temp = temporaries.make_temporary();
+                let fetched = provider
+                    .fetch(source, &temp)
+                    .await
+                    .wrap_err_with(|| format!("Failed to fetch source '{}'", source))?;
 
-                    let (_, (path, _)) = entry.insert(source.clone(), (temp, false));
-                    Ok((path, None))
-                }
-                Err(_) => Err(eyre::eyre!("Unsupported scheme '{}'", source.scheme())),
-            },
+                let (_, (content, version)) =
+                    entry.insert(source.clone(), (fetched.content, fetched.version));
+                Ok((content, version.clone()))
+            }
         }
     }
 }
@@ -40,7 +40,7 @@ impl<'a> Temporaries<'a> {
                             let key_bytes = key.to_le_bytes();
                             let mut filename = [0; 22];
                             let len = base64::encode_engine_slice(
-                                &key_bytes,
+                                key_bytes,
                                 &mut filename,
                                 &FastPortable::from(&URL_SAFE, NO_PAD),
                             );
@@ -54,8 +54,24 @@ impl<'a> Temporaries<'a> {
         tmp.path.clone()
     }
 
+    /// Removes all temporary files created by this container.
+    /// Other files in the base directory, such as temporaries of concurrent imports, are kept.
     pub fn clear(&mut self) {
-        std::fs::remove_dir_all(self.base).unwrap();
+        for (_, temporary) in self.map.drain() {
+            let result = match std::fs::symlink_metadata(&temporary.path) {
+                Err(_) => continue,
+                Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&temporary.path),
+                Ok(_) => std::fs::remove_file(&temporary.path),
+            };
+
+            if let Err(err) = result {
+                tracing::warn!(
+                    "Failed to remove temporary '{}'. {:#}",
+                    temporary.path.display(),
+                    err
+                );
+            }
+        }
     }
 }
 
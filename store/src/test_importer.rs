@@ -0,0 +1,85 @@
+//! Importer used by unit tests.
+
+use std::path::Path;
+
+use treasury_import::{
+    Cancellation, Dependencies, Diagnostics, ImportError, Importer, Progress, Sources,
+};
+
+/// Importer that copies source to output.
+/// Claims are set with builder methods.
+pub(crate) struct TestImporter {
+    name: &'static str,
+    target: &'static str,
+    formats: Vec<&'static str>,
+    extensions: Vec<&'static str>,
+    magic: Vec<&'static [u8]>,
+}
+
+impl TestImporter {
+    pub(crate) fn new(name: &'static str, target: &'static str) -> Self {
+        TestImporter {
+            name,
+            target,
+            formats: Vec::new(),
+            extensions: Vec::new(),
+            magic: Vec::new(),
+        }
+    }
+
+    pub(crate) fn format(mut self, format: &'static str) -> Self {
+        self.formats.push(format);
+        self
+    }
+
+    pub(crate) fn extension(mut self, extension: &'static str) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+
+    pub(crate) fn magic(mut self, magic: &'static [u8]) -> Self {
+        self.magic.push(magic);
+        self
+    }
+}
+
+impl Importer for TestImporter {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn formats(&self) -> &[&str] {
+        &self.formats
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &self.extensions
+    }
+
+    fn target(&self) -> &str {
+        self.target
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        &self.magic
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        _options: Option<&str>,
+        _sources: &mut dyn Sources,
+        _dependencies: &mut dyn Dependencies,
+        _cancellation: &dyn Cancellation,
+        _diagnostics: &mut dyn Diagnostics,
+        _progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        match std::fs::copy(source, output) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ImportError::Other {
+                reason: err.to_string(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,1126 @@
+//! Importers compiled to WASI modules.
+//!
+//! Modules are executed by embedded wasmtime runtime.
+//! Each import runs in fresh instance that can access only
+//! the source, sources it requested and the output directory.
+//!
+//! Cancelled imports are interrupted even if importer does not poll cancellation.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc,
+    },
+    time::Duration,
+};
+
+use hashbrown::HashMap;
+use tracing::level_filters::LevelFilter;
+use treasury_id::AssetId;
+use treasury_import::{
+    loading::LoadingError,
+    logging::{encode_level_filter, LogSink, Logger},
+    wasm, Cancellation, Dependencies, Diagnostic, Diagnostics, ImportError, Importer, Progress,
+    Sources, FFI_REVISION,
+};
+use wasmtime::{Caller, Config, Engine, Instance, Linker, Memory, Module, Store, UpdateDeadline};
+use wasmtime_wasi::{p1::WasiP1Ctx, FsPerms, WasiCtxBuilder};
+
+/// Guest path of the directory with source and requested sources.
+const IN_DIR: &str = "/in";
+
+/// Guest path of the directory with the output.
+const OUT_DIR: &str = "/out";
+
+const RESULT_BUF_LEN_LIMIT: u32 = 65536;
+
+/// Length of buffer for error of importers factory.
+const FACTORY_ERROR_BUF_LEN: u32 = 4096;
+
+/// Importer is not called again when result does not fit,
+/// so buffer starts with enough space for diagnostics.
+const RESULT_BUF_LEN_START: u32 = RESULT_BUF_LEN_LIMIT;
+
+/// Interval of polling cancellation while importer runs.
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Callbacks requested by the importer.
+/// Handled on the thread that called `Importer::import`.
+enum Request {
+    Source {
+        source: String,
+        reply: Sender<Result<Option<PathBuf>, String>>,
+    },
+    Dependency {
+        source: String,
+        target: String,
+        reply: Sender<Result<Option<AssetId>, String>>,
+    },
+    Log {
+        record: Vec<u8>,
+    },
+    Progress {
+        fraction: f32,
+        stage: String,
+    },
+}
+
+/// State of the current import call.
+struct Call {
+    requests: Sender<Request>,
+
+    /// Host path of the `IN_DIR`.
+    input: PathBuf,
+
+    /// Guest paths of sources already exposed to the importer.
+    exposed: HashMap<PathBuf, String>,
+
+    /// Set when import is cancelled.
+    cancelled: Arc<AtomicBool>,
+
+    /// Message of the importer panic.
+    panic: Option<String>,
+}
+
+struct State {
+    wasi: WasiP1Ctx,
+    call: Option<Call>,
+
+    /// Emits log records of the instance outside of import.
+    logger: Logger,
+}
+
+impl State {
+    fn is_cancelled(&self) -> bool {
+        self.call
+            .as_ref()
+            .is_some_and(|call| call.cancelled.load(Ordering::Relaxed))
+    }
+}
+
+/// Instantiated module.
+struct Guest {
+    store: Store<State>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl Guest {
+    fn alloc(&mut self, len: u32) -> wasmtime::Result<u32> {
+        let alloc = self
+            .instance
+            .get_typed_func::<u32, u32>(&mut self.store, wasm::ALLOC_FN_NAME)?;
+        alloc.call(&mut self.store, len)
+    }
+
+    fn dealloc(&mut self, ptr: u32, len: u32) -> wasmtime::Result<()> {
+        let dealloc = self
+            .instance
+            .get_typed_func::<(u32, u32), ()>(&mut self.store, wasm::DEALLOC_FN_NAME)?;
+        dealloc.call(&mut self.store, (ptr, len))
+    }
+
+    /// Copies bytes into newly allocated guest memory.
+    fn alloc_bytes(&mut self, bytes: &[u8]) -> wasmtime::Result<u32> {
+        let ptr = self.alloc(bytes.len() as u32)?;
+        self.memory.write(&mut self.store, ptr as usize, bytes)?;
+        Ok(ptr)
+    }
+
+    fn read(&self, ptr: u32, len: u32) -> wasmtime::Result<Vec<u8>> {
+        let mut bytes = vec![0; len as usize];
+        self.memory.read(&self.store, ptr as usize, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_u32(&self, ptr: u32) -> wasmtime::Result<u32> {
+        let mut bytes = [0; 4];
+        self.memory.read(&self.store, ptr as usize, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_u32(&mut self, ptr: u32, value: u32) -> wasmtime::Result<()> {
+        self.memory
+            .write(&mut self.store, ptr as usize, &value.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+struct WasmLibrary {
+    path: Arc<Path>,
+    module: Module,
+    linker: Linker<State>,
+
+    /// Configuration passed to importers factory of the library.
+    config: Option<Box<str>>,
+}
+
+impl WasmLibrary {
+    fn instantiate(&self, wasi: WasiP1Ctx, call: Option<Call>) -> wasmtime::Result<Guest> {
+        let mut store = Store::new(
+            self.module.engine(),
+            State {
+                wasi,
+                call,
+                logger: Logger::new(),
+            },
+        );
+
+        // Epoch is incremented when any import is cancelled.
+        // Only the cancelled one is interrupted.
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|store| {
+            if store.data().is_cancelled() {
+                Ok(UpdateDeadline::Interrupt)
+            } else {
+                Ok(UpdateDeadline::Continue(1))
+            }
+        });
+
+        let instance = self.linker.instantiate(&mut store, &self.module)?;
+
+        // Reactor modules must be initialized before any export is called.
+        if let Ok(initialize) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            initialize.call(&mut store, ())?;
+        }
+
+        if let Ok(init_log) = instance.get_typed_func::<u32, ()>(&mut store, wasm::INIT_LOG_FN_NAME)
+        {
+            init_log.call(&mut store, encode_level_filter(LevelFilter::current()))?;
+        }
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("Module does not export memory"))?;
+
+        let mut guest = Guest {
+            store,
+            instance,
+            memory,
+        };
+
+        // Importers created by the library factory are created again in each instance.
+        if let Ok(create) = instance.get_typed_func::<(u32, u32, u32, u32), i32>(
+            &mut guest.store,
+            wasm::CREATE_IMPORTERS_FN_NAME,
+        ) {
+            let (config_ptr, config_len) = match &self.config {
+                None => (0, 0),
+                Some(config) => (guest.alloc_bytes(config.as_bytes())?, config.len() as u32),
+            };
+            let error_ptr = guest.alloc(FACTORY_ERROR_BUF_LEN)?;
+            let error_len_ptr = guest.alloc(4)?;
+            guest.write_u32(error_len_ptr, FACTORY_ERROR_BUF_LEN)?;
+
+            let result = create.call(
+                &mut guest.store,
+                (config_ptr, config_len, error_ptr, error_len_ptr),
+            )?;
+
+            if result != wasm::SUCCESS {
+                let error_len = guest.read_u32(error_len_ptr)?.min(FACTORY_ERROR_BUF_LEN);
+                let error = guest.read(error_ptr, error_len)?;
+                return Err(wasmtime::Error::msg(format!(
+                    "Library failed to create importers: {}",
+                    String::from_utf8_lossy(&error)
+                )));
+            }
+        }
+
+        Ok(guest)
+    }
+
+    fn describe(&self) -> Result<Vec<wasm::ImporterDesc>, LoadingError> {
+        let wasi = WasiCtxBuilder::new().inherit_stderr().build_p1();
+        let mut guest = self.instantiate(wasi, None).map_err(wasm_error)?;
+
+        let revisions = guest
+            .instance
+            .get_typed_func::<(u32, u32), ()>(&mut guest.store, wasm::REVISIONS_FN_NAME)
+            .map_err(|_| LoadingError::VersionSymbolNotFound)?;
+
+        // Wasm modules have no adapters for older revisions.
+        let ptr = guest.alloc(8).map_err(wasm_error)?;
+        revisions
+            .call(&mut guest.store, (ptr, ptr + 4))
+            .map_err(wasm_error)?;
+        let min = guest.read_u32(ptr).map_err(wasm_error)?;
+        let max = guest.read_u32(ptr + 4).map_err(wasm_error)?;
+
+        if !(min..=max).contains(&FFI_REVISION) {
+            return Err(LoadingError::UnsupportedRevisions { min, max });
+        }
+
+        if self.config.is_some()
+            && guest
+                .instance
+                .get_func(&mut guest.store, wasm::CREATE_IMPORTERS_FN_NAME)
+                .is_none()
+        {
+            tracing::warn!(
+                "Library '{}' does not create importers from configuration, configuration is ignored",
+                self.path.display()
+            );
+        }
+
+        let export_importers = guest
+            .instance
+            .get_typed_func::<(u32, u32), u32>(&mut guest.store, wasm::IMPORTERS_FN_NAME)
+            .map_err(|_| LoadingError::ExportImportersSymbolNotFound)?;
+
+        let len = export_importers
+            .call(&mut guest.store, (0, 0))
+            .map_err(wasm_error)?;
+
+        let ptr = guest.alloc(len).map_err(wasm_error)?;
+        export_importers
+            .call(&mut guest.store, (ptr, len))
+            .map_err(wasm_error)?;
+
+        let data = guest.read(ptr, len).map_err(wasm_error)?;
+        let descs = wasm::decode_importers(&data)
+            .ok_or_else(|| LoadingError::Wasm("Malformed importers description".to_owned()))?;
+
+        for desc in &descs {
+            desc.validate().map_err(LoadingError::InvalidDescription)?;
+        }
+        Ok(descs)
+    }
+
+    fn probe(&self, index: u32, head: &[u8]) -> wasmtime::Result<bool> {
+        let wasi = WasiCtxBuilder::new().inherit_stderr().build_p1();
+        let mut guest = self.instantiate(wasi, None)?;
+
+        let probe = guest
+            .instance
+            .get_typed_func::<(u32, u32, u32), i32>(&mut guest.store, wasm::PROBE_FN_NAME)?;
+
+        let head_ptr = guest.alloc_bytes(head)?;
+        let result = probe.call(&mut guest.store, (index, head_ptr, head.len() as u32))?;
+        Ok(result == wasm::SUCCESS)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn import(
+        &self,
+        index: u32,
+        sandbox: &Sandbox,
+        source: &str,
+        output: &str,
+        options: Option<&str>,
+        requests: Sender<Request>,
+        cancelled: Arc<AtomicBool>,
+    ) -> wasmtime::Result<(Result<(), ImportError>, Vec<Diagnostic>)> {
+        let wasi = WasiCtxBuilder::new()
+            .inherit_stderr()
+            .preopened_dir(&sandbox.input, IN_DIR, FsPerms::ReadOnly)?
+            .preopened_dir(&sandbox.output, OUT_DIR, FsPerms::ReadWrite)?
+            .build_p1();
+
+        let call = Call {
+            requests,
+            input: sandbox.input.clone(),
+            exposed: HashMap::new(),
+            cancelled,
+            panic: None,
+        };
+
+        let mut guest = self.instantiate(wasi, Some(call))?;
+
+        let import = guest
+            .instance
+            .get_typed_func::<(u32, u32, u32, u32, u32, u32, u32, u32, u32), i32>(
+                &mut guest.store,
+                wasm::IMPORT_FN_NAME,
+            )?;
+
+        let source_ptr = guest.alloc_bytes(source.as_bytes())?;
+        let output_ptr = guest.alloc_bytes(output.as_bytes())?;
+        let (options_ptr, options_len) = match options {
+            None => (0, 0),
+            Some(options) => (guest.alloc_bytes(options.as_bytes())?, options.len() as u32),
+        };
+        let result_len_ptr = guest.alloc(4)?;
+
+        let mut cap = RESULT_BUF_LEN_START;
+        loop {
+            let result_ptr = guest.alloc(cap)?;
+            guest.write_u32(result_len_ptr, cap)?;
+
+            let result = import.call(
+                &mut guest.store,
+                (
+                    index,
+                    source_ptr,
+                    source.len() as u32,
+                    output_ptr,
+                    output.len() as u32,
+                    options_ptr,
+                    options_len,
+                    result_ptr,
+                    result_len_ptr,
+                ),
+            );
+
+            // Panicking importer reports the message before the trap.
+            let result = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    let panic = guest
+                        .store
+                        .data_mut()
+                        .call
+                        .as_mut()
+                        .and_then(|call| call.panic.take());
+
+                    return match panic {
+                        Some(message) => Ok((Err(ImportError::Panicked { message }), Vec::new())),
+                        None => Err(err),
+                    };
+                }
+            };
+
+            let result_len = guest.read_u32(result_len_ptr)?;
+
+            if result == wasm::BUFFER_IS_TOO_SMALL {
+                if result_len > RESULT_BUF_LEN_LIMIT {
+                    let error = ImportError::Other {
+                        reason: format!(
+                            "Result does not fit into limit '{}', '{}' required",
+                            RESULT_BUF_LEN_LIMIT, result_len
+                        ),
+                    };
+                    return Ok((Err(error), Vec::new()));
+                }
+
+                guest.dealloc(result_ptr, cap)?;
+                cap = result_len;
+                continue;
+            }
+
+            let result_buf = guest.read(result_ptr, result_len.min(cap))?;
+            let mut diagnostics = Vec::new();
+            let result = wasm::decode_import_result(result, &result_buf, &mut diagnostics);
+            return Ok((result, diagnostics));
+        }
+    }
+}
+
+fn wasm_error(err: wasmtime::Error) -> LoadingError {
+    LoadingError::Wasm(format!("{:#}", err))
+}
+
+/// Runs the closure on a thread outside of async runtime,
+/// as WASI implementation blocks on its own runtime.
+fn off_runtime<R>(f: impl FnOnce() -> R + Send) -> R
+where
+    R: Send,
+{
+    std::thread::scope(|scope| match scope.spawn(f).join() {
+        Ok(result) => result,
+        Err(panic) => std::panic::resume_unwind(panic),
+    })
+}
+
+fn guest_memory(caller: &mut Caller<'_, State>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("Module does not export memory"))
+}
+
+fn read_str(
+    caller: &mut Caller<'_, State>,
+    memory: Memory,
+    ptr: u32,
+    len: u32,
+) -> wasmtime::Result<Option<String>> {
+    let mut bytes = vec![0; len as usize];
+    memory.read(&*caller, ptr as usize, &mut bytes)?;
+    Ok(String::from_utf8(bytes).ok())
+}
+
+fn current_call<'a>(caller: &'a mut Caller<'_, State>) -> wasmtime::Result<&'a mut Call> {
+    caller
+        .data_mut()
+        .call
+        .as_mut()
+        .ok_or_else(|| wasmtime::Error::msg("Host function called outside of import"))
+}
+
+fn add_host_functions(linker: &mut Linker<State>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        wasm::HOST_MODULE,
+        wasm::SOURCES_GET_NAME,
+        |mut caller: Caller<'_, State>,
+         source_ptr: u32,
+         source_len: u32,
+         path_ptr: u32,
+         path_len_ptr: u32|
+         -> wasmtime::Result<i32> {
+            let memory = guest_memory(&mut caller)?;
+            let source = match read_str(&mut caller, memory, source_ptr, source_len)? {
+                None => return Ok(wasm::NOT_UTF8),
+                Some(source) => source,
+            };
+
+            let call = current_call(&mut caller)?;
+
+            let (reply, response) = channel();
+            call.requests.send(Request::Source { source, reply })?;
+
+            let path = match response.recv()? {
+                Err(err) => {
+                    tracing::error!("Sources::get failed. {}", err);
+                    return Ok(wasm::OTHER_ERROR);
+                }
+                Ok(None) => return Ok(wasm::NOT_FOUND),
+                Ok(Some(path)) => path,
+            };
+
+            let guest_path = match call.exposed.get(&path) {
+                Some(guest_path) => guest_path.clone(),
+                None => {
+                    let dir = format!("sources/{}", call.exposed.len());
+                    match expose(&call.input, &dir, &path) {
+                        Ok(guest_path) => {
+                            call.exposed.insert(path, guest_path.clone());
+                            guest_path
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                "Failed to expose source '{}' to WebAssembly importer. {:#}",
+                                path.display(),
+                                err
+                            );
+                            return Ok(wasm::OTHER_ERROR);
+                        }
+                    }
+                }
+            };
+
+            let mut cap = [0; 4];
+            memory.read(&caller, path_len_ptr as usize, &mut cap)?;
+            let cap = u32::from_le_bytes(cap);
+
+            let len = guest_path.len() as u32;
+            memory.write(&mut caller, path_len_ptr as usize, &len.to_le_bytes())?;
+
+            if len > cap {
+                return Ok(wasm::BUFFER_IS_TOO_SMALL);
+            }
+
+            memory.write(&mut caller, path_ptr as usize, guest_path.as_bytes())?;
+            Ok(wasm::SUCCESS)
+        },
+    )?;
+
+    linker.func_wrap(
+        wasm::HOST_MODULE,
+        wasm::LOG_NAME,
+        |mut caller: Caller<'_, State>, record_ptr: u32, record_len: u32| -> wasmtime::Result<()> {
+            let memory = guest_memory(&mut caller)?;
+            let mut record = vec![0; record_len as usize];
+            memory.read(&caller, record_ptr as usize, &mut record)?;
+
+            // Records of import are emitted on the thread that runs the import, inside its span.
+            let state = caller.data();
+            match &state.call {
+                Some(call) => call.requests.send(Request::Log { record })?,
+                None => state.logger.log(&record),
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        wasm::HOST_MODULE,
+        wasm::PROGRESS_REPORT_NAME,
+        |mut caller: Caller<'_, State>,
+         fraction: f32,
+         stage_ptr: u32,
+         stage_len: u32|
+         -> wasmtime::Result<()> {
+            let memory = guest_memory(&mut caller)?;
+            let mut stage = vec![0; stage_len as usize];
+            memory.read(&caller, stage_ptr as usize, &mut stage)?;
+            let stage = String::from_utf8_lossy(&stage).into_owned();
+
+            let call = current_call(&mut caller)?;
+            call.requests.send(Request::Progress { fraction, stage })?;
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        wasm::HOST_MODULE,
+        wasm::PANIC_NAME,
+        |mut caller: Caller<'_, State>,
+         message_ptr: u32,
+         message_len: u32|
+         -> wasmtime::Result<()> {
+            let memory = guest_memory(&mut caller)?;
+            let mut message = vec![0; message_len as usize];
+            memory.read(&caller, message_ptr as usize, &mut message)?;
+
+            // Panics outside of import fail the call that caused them.
+            if let Some(call) = &mut caller.data_mut().call {
+                call.panic = Some(String::from_utf8_lossy(&message).into_owned());
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        wasm::HOST_MODULE,
+        wasm::IS_CANCELLED_NAME,
+        |caller: Caller<'_, State>| -> i32 {
+            if caller.data().is_cancelled() {
+                wasm::CANCELLED
+            } else {
+                wasm::SUCCESS
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        wasm::HOST_MODULE,
+        wasm::DEPENDENCIES_GET_NAME,
+        |mut caller: Caller<'_, State>,
+         source_ptr: u32,
+         source_len: u32,
+         target_ptr: u32,
+         target_len: u32,
+         id_ptr: u32|
+         -> wasmtime::Result<i32> {
+            let memory = guest_memory(&mut caller)?;
+            let source = match read_str(&mut caller, memory, source_ptr, source_len)? {
+                None => return Ok(wasm::NOT_UTF8),
+                Some(source) => source,
+            };
+            let target = match read_str(&mut caller, memory, target_ptr, target_len)? {
+                None => return Ok(wasm::NOT_UTF8),
+                Some(target) => target,
+            };
+
+            let call = current_call(&mut caller)?;
+
+            let (reply, response) = channel();
+            call.requests.send(Request::Dependency {
+                source,
+                target,
+                reply,
+            })?;
+
+            match response.recv()? {
+                Err(err) => {
+                    tracing::error!("Dependencies::get failed. {}", err);
+                    Ok(wasm::OTHER_ERROR)
+                }
+                Ok(None) => Ok(wasm::NOT_FOUND),
+                Ok(Some(id)) => {
+                    memory.write(
+                        &mut caller,
+                        id_ptr as usize,
+                        &id.value().get().to_le_bytes(),
+                    )?;
+                    Ok(wasm::SUCCESS)
+                }
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Makes file available to the importer in `dir` inside `IN_DIR`.
+/// Returns guest path of the file.
+fn expose(input: &Path, dir: &str, path: &Path) -> std::io::Result<String> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("source");
+
+    let host_dir = input.join(dir);
+    std::fs::create_dir_all(&host_dir)?;
+
+    let host_path = host_dir.join(file_name);
+    if std::fs::hard_link(path, &host_path).is_err() {
+        std::fs::copy(path, &host_path)?;
+    }
+
+    Ok(format!("{}/{}/{}", IN_DIR, dir, file_name))
+}
+
+/// Host directories mapped into importer sandbox.
+/// Removed when dropped.
+struct Sandbox {
+    root: PathBuf,
+    input: PathBuf,
+    output: PathBuf,
+}
+
+impl Sandbox {
+    fn new(output: &Path) -> std::io::Result<Self> {
+        let mut root = output.as_os_str().to_owned();
+        root.push(".sandbox");
+        let root = PathBuf::from(root);
+
+        let sandbox = Sandbox {
+            input: root.join("in"),
+            output: root.join("out"),
+            root,
+        };
+
+        std::fs::create_dir_all(&sandbox.input)?;
+        std::fs::create_dir_all(&sandbox.output)?;
+        Ok(sandbox)
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.root) {
+            tracing::warn!(
+                "Failed to remove importer sandbox '{}'. {:#}",
+                self.root.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Loads importers from WASI module.
+pub(crate) fn load_importers(
+    lib_path: &Path,
+    config: Option<&str>,
+) -> Result<Vec<WasmImporter>, LoadingError> {
+    tracing::info!(
+        "Loading WebAssembly importers from '{}'",
+        lib_path.display()
+    );
+
+    let engine = Engine::new(Config::new().epoch_interruption(true)).map_err(wasm_error)?;
+    let module = Module::from_file(&engine, lib_path).map_err(wasm_error)?;
+
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::p1::add_to_linker_sync(&mut linker, |state: &mut State| &mut state.wasi)
+        .map_err(wasm_error)?;
+    add_host_functions(&mut linker).map_err(wasm_error)?;
+
+    let library = Arc::new(WasmLibrary {
+        path: Arc::from(lib_path),
+        module,
+        linker,
+        config: config.map(Into::into),
+    });
+
+    let descs = off_runtime(|| library.describe())?;
+
+    Ok(descs
+        .into_iter()
+        .enumerate()
+        .map(|(idx, desc)| WasmImporter {
+            library: library.clone(),
+            index: idx as u32,
+            name: desc.name.into(),
+            formats: desc.formats.into_iter().map(Into::into).collect(),
+            extensions: desc.extensions.into_iter().map(Into::into).collect(),
+            target: desc.target.into(),
+            magic: desc.magic.into_iter().map(Into::into).collect(),
+            description: desc.description.into(),
+            version: desc.version.map(Into::into),
+            package: desc.package.map(Into::into),
+            options_schema: desc.options_schema.map(Into::into),
+        })
+        .collect())
+}
+
+/// Importer from WASI module.
+pub(crate) struct WasmImporter {
+    library: Arc<WasmLibrary>,
+    index: u32,
+    name: Box<str>,
+    formats: Vec<Box<str>>,
+    extensions: Vec<Box<str>>,
+    target: Box<str>,
+    magic: Vec<Box<[u8]>>,
+    description: Box<str>,
+    version: Option<Box<str>>,
+    package: Option<Box<str>>,
+    options_schema: Option<Box<str>>,
+}
+
+impl WasmImporter {
+    fn failed(&self, err: impl std::fmt::Display) -> ImportError {
+        ImportError::Other {
+            reason: format!(
+                "WebAssembly importer '{}' failed. {}",
+                self.library.path.display(),
+                err
+            ),
+        }
+    }
+}
+
+impl Importer for WasmImporter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formats(&self) -> &[&str] {
+        unsafe {
+            std::slice::from_raw_parts(self.formats.as_ptr() as *const &str, self.formats.len())
+        }
+    }
+
+    fn extensions(&self) -> &[&str] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.extensions.as_ptr() as *const &str,
+                self.extensions.len(),
+            )
+        }
+    }
+
+    fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    fn package(&self) -> Option<&str> {
+        self.package.as_deref()
+    }
+
+    fn options_schema(&self) -> Option<&str> {
+        self.options_schema.as_deref()
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        unsafe { std::slice::from_raw_parts(self.magic.as_ptr() as *const &[u8], self.magic.len()) }
+    }
+
+    fn probe(&self, head: &[u8]) -> bool {
+        match off_runtime(|| self.library.probe(self.index, head)) {
+            Ok(recognized) => recognized,
+            Err(err) => {
+                tracing::error!(
+                    "WebAssembly importer '{}' failed to probe. {:#}",
+                    self.library.path.display(),
+                    err
+                );
+                false
+            }
+        }
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        options: Option<&str>,
+        sources: &mut dyn Sources,
+        dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        let sandbox = Sandbox::new(output).map_err(|err| self.failed(err))?;
+
+        let guest_source =
+            expose(&sandbox.input, "source", source).map_err(|err| self.failed(err))?;
+        let guest_output = format!("{}/output", OUT_DIR);
+
+        let (requests, incoming) = channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let logger = Logger::new();
+
+        let result = std::thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                self.library.import(
+                    self.index,
+                    &sandbox,
+                    &guest_source,
+                    &guest_output,
+                    options,
+                    requests,
+                    cancelled.clone(),
+                )
+            });
+
+            // Serve callbacks until the worker drops the sender.
+            loop {
+                let request = match incoming.recv_timeout(CANCELLATION_POLL_INTERVAL) {
+                    Ok(request) => request,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if !cancelled.load(Ordering::Relaxed) && cancellation.is_cancelled() {
+                            cancelled.store(true, Ordering::Relaxed);
+                            self.library.module.engine().increment_epoch();
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                match request {
+                    Request::Source { source, reply } => {
+                        let _ = reply.send(sources.get(&source));
+                    }
+                    Request::Dependency {
+                        source,
+                        target,
+                        reply,
+                    } => {
+                        let _ = reply.send(dependencies.get(&source, &target));
+                    }
+                    Request::Log { record } => logger.log(&record),
+                    Request::Progress { fraction, stage } => progress.report(fraction, &stage),
+                }
+            }
+
+            match worker.join() {
+                Ok(result) => result,
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        });
+
+        let result = result.map(|(result, reported)| {
+            for diagnostic in reported {
+                diagnostics.report(diagnostic);
+            }
+            result
+        });
+
+        match result {
+            Err(_) if cancelled.load(Ordering::Relaxed) => Err(ImportError::Cancelled),
+            Err(err) => Err(self.failed(format_args!("{:#}", err))),
+            Ok(Err(err)) => Err(err),
+            Ok(Ok(())) => {
+                std::fs::rename(sandbox.output.join("output"), output)
+                    .map_err(|err| self.failed(format_args!("Failed to move output. {}", err)))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use treasury_import::Importer;
+    use treasury_import_testing::{FakeDependencies, FakeSources, Fixture, RunError};
+
+    use crate::test_importer::TestImporter;
+
+    use super::*;
+
+    /// Offset of importers description in guest memory.
+    const DESC_OFFSET: usize = 512;
+
+    /// Module with importers that write output, panic, loop forever
+    /// and try to escape the sandbox.
+    fn module(min: u32, max: u32) -> String {
+        let importers = [
+            TestImporter::new("Writer", "written").format("written"),
+            TestImporter::new("Panicking", "panic").format("panic"),
+            TestImporter::new("Looping", "loop").format("loop"),
+            TestImporter::new("Escaping", "escape").format("escape"),
+        ];
+        let importers: Vec<&dyn Importer> = importers.iter().map(|i| i as &dyn Importer).collect();
+        let desc = wasm::encode_importers(&importers);
+
+        let mut data = String::new();
+        for byte in &desc {
+            write!(data, "\\{:02x}", byte).unwrap();
+        }
+
+        format!(
+            r#"(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "treasury" "panic" (func $panic (param i32 i32)))
+
+  (memory (export "memory") 64)
+  (global $heap (mut i32) (i32.const 65536))
+
+  (data (i32.const 256) "output")
+  (data (i32.const 272) "../escaped")
+  (data (i32.const 288) "boom")
+  (data (i32.const 304) "wasm")
+  (data (i32.const 320) "\30\01\00\00\04\00\00\00")
+  (data (i32.const {offset}) "{data}")
+
+  (func (export "treasury_wasm_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap (i32.add (local.get $ptr) (local.get $len)))
+    (local.get $ptr))
+
+  (func (export "treasury_wasm_dealloc") (param i32 i32))
+
+  (func (export "treasury_importer_ffi_revisions") (param $min i32) (param $max i32)
+    (i32.store (local.get $min) (i32.const {min}))
+    (i32.store (local.get $max) (i32.const {max})))
+
+  (func (export "treasury_wasm_importers") (param $buf i32) (param $cap i32) (result i32)
+    (if (i32.ge_u (local.get $cap) (i32.const {len}))
+      (then (memory.copy (local.get $buf) (i32.const {offset}) (i32.const {len}))))
+    (i32.const {len}))
+
+  (func (export "treasury_wasm_probe") (param i32 i32 i32) (result i32)
+    (i32.const 0))
+
+  ;; Creates file in the output directory, preopened after the input one.
+  (func $create (param $path i32) (param $len i32) (result i32)
+    (call $path_open (i32.const 4) (i32.const 0) (local.get $path) (local.get $len)
+      (i32.const 9) (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) (i32.const 336)))
+
+  ;; Writes "wasm" into the output.
+  (func $write (result i32)
+    (if (call $create (i32.const 256) (i32.const 6))
+      (then (return (i32.const -6))))
+    (drop (call $fd_write (i32.load (i32.const 336)) (i32.const 320) (i32.const 1) (i32.const 340)))
+    (i32.const 0))
+
+  (func (export "treasury_wasm_import")
+    (param $importer i32) (param i32 i32 i32 i32 i32 i32)
+    (param $result i32) (param $result_len i32) (result i32)
+    ;; Empty payload and no diagnostics.
+    (i64.store (local.get $result) (i64.const 0))
+    (i32.store (local.get $result_len) (i32.const 8))
+    (block $escape
+      (block $loop
+        (block $panic
+          (block $write
+            (br_table $write $panic $loop $escape (local.get $importer)))
+          (return (call $write)))
+        (call $panic (i32.const 288) (i32.const 4))
+        (unreachable))
+      (loop $forever (br $forever)))
+    (if (call $create (i32.const 272) (i32.const 10))
+      (then (return (i32.const -6))))
+    (call $write)))
+"#,
+            offset = DESC_OFFSET,
+            len = desc.len(),
+        )
+    }
+
+    fn load(dir: &Path, min: u32, max: u32) -> Result<Vec<WasmImporter>, LoadingError> {
+        let path = dir.join("importers.wasm");
+        std::fs::write(&path, module(min, max)).unwrap();
+        load_importers(&path, None)
+    }
+
+    fn importer(dir: &Path, name: &str) -> WasmImporter {
+        load(dir, FFI_REVISION, FFI_REVISION)
+            .unwrap()
+            .into_iter()
+            .find(|importer| importer.name() == name)
+            .unwrap()
+    }
+
+    struct Cancelled;
+
+    impl Cancellation for Cancelled {
+        fn is_cancelled(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn describes_importers() {
+        let dir = tempfile::tempdir().unwrap();
+        let importers = load(dir.path(), FFI_REVISION, FFI_REVISION).unwrap();
+
+        let names: Vec<_> = importers.iter().map(|i| (i.name(), i.target())).collect();
+        assert_eq!(
+            names,
+            [
+                ("Writer", "written"),
+                ("Panicking", "panic"),
+                ("Looping", "loop"),
+                ("Escaping", "escape")
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_revisions() {
+        let dir = tempfile::tempdir().unwrap();
+        match load(dir.path(), FFI_REVISION + 1, FFI_REVISION + 2) {
+            Err(LoadingError::UnsupportedRevisions { min, max }) => {
+                assert_eq!((min, max), (FFI_REVISION + 1, FFI_REVISION + 2));
+            }
+            Err(err) => panic!("Unexpected error {}", err),
+            Ok(_) => panic!("Module with unsupported revisions is loaded"),
+        }
+    }
+
+    #[test]
+    fn moves_output_out_of_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let importer = importer(dir.path(), "Writer");
+
+        let mut fixture = Fixture::new("source.txt", "source").unwrap();
+        let imported = fixture.run(&importer).unwrap();
+        assert_eq!(imported.output, b"wasm");
+    }
+
+    #[test]
+    fn reports_panic() {
+        let dir = tempfile::tempdir().unwrap();
+        let importer = importer(dir.path(), "Panicking");
+
+        let mut fixture = Fixture::new("source.txt", "source").unwrap();
+        match fixture.run(&importer) {
+            Err(RunError::Panicked { message }) => assert_eq!(message, "boom"),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn keeps_importer_in_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let importer = importer(dir.path(), "Escaping");
+
+        let mut fixture = Fixture::new("source.txt", "source").unwrap();
+        assert!(matches!(
+            fixture.run(&importer),
+            Err(RunError::Failed { .. })
+        ));
+    }
+
+    #[test]
+    fn interrupts_cancelled_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let importer = importer(dir.path(), "Looping");
+
+        let source = dir.path().join("source.txt");
+        std::fs::write(&source, "source").unwrap();
+
+        let result = importer.import(
+            &source,
+            &dir.path().join("output"),
+            None,
+            &mut FakeSources::new().unwrap(),
+            &mut FakeDependencies::new(),
+            &Cancelled,
+            &mut Vec::new(),
+            &mut (),
+        );
+        assert!(matches!(result, Err(ImportError::Cancelled)));
+    }
+}
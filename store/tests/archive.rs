@@ -0,0 +1,56 @@
+mod common;
+
+use std::{fs::File, io::Write, path::Path};
+
+use common::{new_id, CopyImporter};
+use treasury_store::{Treasury, TreasuryInfo};
+
+fn files_containing(dir: &Path, needle: &str, found: &mut Vec<String>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files_containing(&path, needle, found);
+        } else if let Ok(content) = std::fs::read_to_string(&path) {
+            if content.contains(needle) {
+                found.push(path.display().to_string());
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn records_archive_relative_to_base() {
+    let (dir, mut treasury) = common::treasury();
+    treasury.register_importer(CopyImporter::new("copy", &["txt"]));
+
+    std::fs::create_dir(dir.path().join("packs")).unwrap();
+    let mut zip = zip::ZipWriter::new(File::create(dir.path().join("packs/pack.zip")).unwrap());
+    zip.start_file("a.txt", zip::write::FileOptions::default())
+        .unwrap();
+    zip.write_all(b"hello").unwrap();
+    zip.finish().unwrap();
+
+    let (id, path) = treasury
+        .store("archive:packs/pack.zip!/a.txt", None, "copy", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), b"hello");
+
+    let base = dunce::canonicalize(dir.path()).unwrap();
+    let mut found = Vec::new();
+    files_containing(&base, base.to_str().unwrap(), &mut found);
+    assert!(found.is_empty(), "Absolute paths recorded in {:?}", found);
+
+    // Relative archive URL is resolved when checking if source was modified.
+    let (again, _) = treasury
+        .store("archive:packs/pack.zip!/a.txt", None, "copy", new_id)
+        .await
+        .unwrap();
+    assert_eq!(id, again);
+
+    // Asset is found by id after treasury is reopened.
+    let mut reopened = Treasury::new(dir.path(), TreasuryInfo::default()).unwrap();
+    reopened.register_importer(CopyImporter::new("copy", &["txt"]));
+    let path = reopened.fetch(id, new_id).await.unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), b"hello");
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use std::{
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use common::new_id;
+use treasury_import::{
+    Cancellation, Dependencies, Diagnostics, ImportError, Importer, Progress, Sources,
+};
+
+/// Importer that waits for a signal from another task.
+struct WaitingImporter {
+    signal: Mutex<mpsc::Receiver<()>>,
+}
+
+impl Importer for WaitingImporter {
+    fn name(&self) -> &str {
+        "waiting"
+    }
+
+    fn formats(&self) -> &[&str] {
+        &[]
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+
+    fn target(&self) -> &str {
+        "waited"
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        _options: Option<&str>,
+        _sources: &mut dyn Sources,
+        _dependencies: &mut dyn Dependencies,
+        _cancellation: &dyn Cancellation,
+        _diagnostics: &mut dyn Diagnostics,
+        _progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        let signal = self.signal.lock().unwrap();
+        if signal.recv_timeout(Duration::from_secs(10)).is_err() {
+            return Err(ImportError::Other {
+                reason: "Other tasks are stalled".to_owned(),
+            });
+        }
+        std::fs::copy(source, output).unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn does_not_stall_other_tasks() {
+    let (dir, mut treasury) = common::treasury();
+    let (tx, rx) = mpsc::channel();
+    treasury.register_importer(WaitingImporter {
+        signal: Mutex::new(rx),
+    });
+    std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+
+    let signal = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tx.send(()).unwrap();
+    });
+
+    // Test body is not run by the runtime worker, spawned tasks are.
+    let treasury = Arc::new(treasury);
+    let store = tokio::spawn(async move {
+        treasury
+            .store("a.txt", None, "waited", new_id)
+            .await
+            .unwrap();
+    });
+
+    store.await.unwrap();
+    signal.await.unwrap();
+}
@@ -0,0 +1,87 @@
+#![allow(dead_code)]
+
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tempfile::TempDir;
+use treasury_id::AssetId;
+use treasury_import::{
+    Cancellation, Dependencies, Diagnostics, ImportError, Importer, Progress, Sources,
+};
+use treasury_store::{Treasury, TreasuryInfo};
+
+/// Importer that copies source to output.
+pub struct CopyImporter {
+    pub name: &'static str,
+    pub formats: &'static [&'static str],
+    pub extensions: &'static [&'static str],
+    pub target: &'static str,
+    pub magic: &'static [&'static [u8]],
+}
+
+impl CopyImporter {
+    pub fn new(name: &'static str, extensions: &'static [&'static str]) -> Self {
+        CopyImporter {
+            name,
+            formats: &[],
+            extensions,
+            target: "copy",
+            magic: &[],
+        }
+    }
+}
+
+impl Importer for CopyImporter {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn formats(&self) -> &[&str] {
+        self.formats
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    fn target(&self) -> &str {
+        self.target
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        self.magic
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        _options: Option<&str>,
+        _sources: &mut dyn Sources,
+        _dependencies: &mut dyn Dependencies,
+        _cancellation: &dyn Cancellation,
+        _diagnostics: &mut dyn Diagnostics,
+        _progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        match std::fs::copy(source, output) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ImportError::Other {
+                reason: err.to_string(),
+            }),
+        }
+    }
+}
+
+/// Creates treasury in a new temporary directory.
+pub fn treasury() -> (TempDir, Treasury) {
+    let dir = tempfile::tempdir().unwrap();
+    let treasury = Treasury::new(dir.path(), TreasuryInfo::default()).unwrap();
+    (dir, treasury)
+}
+
+pub fn new_id() -> AssetId {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    AssetId::new(NEXT.fetch_add(1, Ordering::Relaxed)).unwrap()
+}
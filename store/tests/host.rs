@@ -0,0 +1,119 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::new_id;
+use treasury_import::{loading::LoadingError, Severity};
+use treasury_store::{host::ImporterHost, ImporterOrigin, Treasury};
+
+fn host() -> ImporterHost {
+    ImporterHost {
+        executable: Some(PathBuf::from(env!("CARGO_BIN_EXE_treasury-importer-host"))),
+        timeout: Some(10),
+    }
+}
+
+fn hosted_treasury() -> (tempfile::TempDir, Treasury) {
+    let (dir, mut treasury) = common::treasury();
+    treasury.set_importer_host(Some(&host()));
+    (dir, treasury)
+}
+
+#[tokio::test]
+async fn imports_in_host_process() {
+    let (dir, mut treasury) = hosted_treasury();
+    unsafe {
+        treasury
+            .register_importers_lib(Path::new(c_importer::LIBRARY_PATH))
+            .unwrap()
+    };
+
+    let importers = treasury.importers();
+    assert_eq!(importers.len(), 1);
+    assert_eq!(importers[0].name, "C uppercase importer");
+    assert_eq!(
+        importers[0].description,
+        "Converts ASCII text to upper case"
+    );
+    assert_eq!(importers[0].version.as_deref(), Some("0.1.0"));
+    assert_eq!(
+        importers[0].origin,
+        ImporterOrigin::Library(PathBuf::from(c_importer::LIBRARY_PATH))
+    );
+
+    // Source requested by the importer is served by the store.
+    std::fs::write(dir.path().join("main.upper"), "+other.upper\nmain, ").unwrap();
+    std::fs::write(dir.path().join("other.upper"), "other").unwrap();
+
+    let (_, path) = treasury
+        .store("main.upper", None, "text", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), b"MAIN, OTHER");
+
+    let diagnostics = treasury.diagnostics("main.upper", "text").unwrap().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Info);
+    assert_eq!(diagnostics[0].message, "Converted 11 bytes");
+}
+
+#[tokio::test]
+async fn importer_printing_to_stdout_does_not_corrupt_messages() {
+    let (dir, mut treasury) = hosted_treasury();
+    unsafe {
+        treasury
+            .register_importers_lib(Path::new(c_importer::PRINTING_LIBRARY_PATH))
+            .unwrap()
+    };
+    assert_eq!(treasury.importers()[0].name, "C printing importer");
+
+    std::fs::write(dir.path().join("a.print"), "hello").unwrap();
+
+    let (_, path) = treasury
+        .store("a.print", None, "text", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn reports_missing_source_from_host() {
+    let (dir, mut treasury) = hosted_treasury();
+    unsafe {
+        treasury
+            .register_importers_lib(Path::new(c_importer::LIBRARY_PATH))
+            .unwrap()
+    };
+
+    std::fs::write(dir.path().join("main.upper"), "+missing.upper\nmain").unwrap();
+
+    let err = treasury
+        .store("main.upper", None, "text", new_id)
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("missing.upper"), "{:#}", err);
+}
+
+#[test]
+fn reports_host_load_failure() {
+    let (dir, mut treasury) = hosted_treasury();
+    let lib_path = dir.path().join("not-a-library.so");
+    std::fs::write(&lib_path, "not a library").unwrap();
+
+    let err = unsafe { treasury.register_importers_lib(&lib_path) }.unwrap_err();
+    assert!(matches!(err, LoadingError::Host(_)), "{}", err);
+    assert!(treasury.importers().is_empty());
+}
+
+#[test]
+fn reports_missing_host_executable() {
+    let (dir, mut treasury) = common::treasury();
+    treasury.set_importer_host(Some(&ImporterHost {
+        executable: Some(dir.path().join("missing-host")),
+        timeout: None,
+    }));
+
+    assert!(
+        unsafe { treasury.register_importers_lib(Path::new(c_importer::LIBRARY_PATH)) }.is_err()
+    );
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use std::path::Path;
+
+use common::new_id;
+use treasury_import::{
+    Cancellation, Dependencies, Diagnostics, ImportError, Importer, Progress, Sources,
+};
+use treasury_store::{StoreOptions, Treasury};
+use url::Url;
+
+const SCHEMA: &str = r#"{
+    "type": "object",
+    "properties": { "level": { "type": "integer" } },
+    "additionalProperties": false
+}"#;
+
+/// Importer that appends options it receives to the source.
+struct OptionsImporter {
+    name: &'static str,
+    formats: &'static [&'static str],
+    extensions: &'static [&'static str],
+    target: &'static str,
+    schema: Option<&'static str>,
+}
+
+impl Importer for OptionsImporter {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn formats(&self) -> &[&str] {
+        self.formats
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    fn target(&self) -> &str {
+        self.target
+    }
+
+    fn options_schema(&self) -> Option<&str> {
+        self.schema
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        options: Option<&str>,
+        _sources: &mut dyn Sources,
+        _dependencies: &mut dyn Dependencies,
+        _cancellation: &dyn Cancellation,
+        _diagnostics: &mut dyn Diagnostics,
+        _progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        let mut data = std::fs::read_to_string(source).unwrap();
+        data.push('|');
+        data.push_str(options.unwrap_or("none"));
+        std::fs::write(output, data).unwrap();
+        Ok(())
+    }
+}
+
+/// Chain of `a -> b` importer without schema and `b -> text` importer with schema.
+fn chained_treasury() -> (tempfile::TempDir, Treasury) {
+    let (dir, mut treasury) = common::treasury();
+    treasury.register_importer(OptionsImporter {
+        name: "a2b",
+        formats: &["a"],
+        extensions: &["a"],
+        target: "b",
+        schema: None,
+    });
+    treasury.register_importer(OptionsImporter {
+        name: "b2text",
+        formats: &["b"],
+        extensions: &[],
+        target: "text",
+        schema: Some(SCHEMA),
+    });
+    std::fs::write(dir.path().join("source.a"), "source").unwrap();
+    (dir, treasury)
+}
+
+async fn store_with_options(
+    dir: &Path,
+    treasury: &Treasury,
+    options: serde_json::Value,
+) -> eyre::Result<Vec<u8>> {
+    let source = Url::from_file_path(dir.join("source.a")).unwrap();
+    let (_, path) = treasury
+        .store_url_with(
+            source,
+            None,
+            "text",
+            StoreOptions::new().options(&options),
+            new_id,
+        )
+        .await?;
+    Ok(std::fs::read(path).unwrap())
+}
+
+#[tokio::test]
+async fn passes_options_to_last_step() {
+    let (dir, treasury) = chained_treasury();
+
+    let output = store_with_options(dir.path(), &treasury, serde_json::json!({ "level": 3 }))
+        .await
+        .unwrap();
+    assert_eq!(output, br#"source|none|{"level":3}"#);
+}
+
+#[tokio::test]
+async fn rejects_invalid_options() {
+    let (dir, treasury) = chained_treasury();
+
+    let err = store_with_options(
+        dir.path(),
+        &treasury,
+        serde_json::json!({ "level": "high" }),
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Import options are invalid for importer 'b2text'"),
+        "{:#}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn rejects_options_for_importer_without_schema() {
+    let (dir, mut treasury) = common::treasury();
+    treasury.register_importer(OptionsImporter {
+        name: "a2text",
+        formats: &["a"],
+        extensions: &["a"],
+        target: "text",
+        schema: None,
+    });
+    std::fs::write(dir.path().join("source.a"), "source").unwrap();
+
+    let err = store_with_options(dir.path(), &treasury, serde_json::json!({ "level": 3 }))
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Importer 'a2text' accepts no options"),
+        "{:#}",
+        err
+    );
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures_util::future::BoxFuture;
+use treasury_store::{FetchedSource, SourceContent, SourceProvider, SourceVersion};
+use url::Url;
+
+use common::{new_id, CopyImporter};
+
+/// Source content and version by URL.
+type MemSources = HashMap<String, (Vec<u8>, u32)>;
+
+/// Provider of in-memory sources with version tokens.
+#[derive(Clone, Default)]
+struct MemProvider {
+    schemes: Vec<&'static str>,
+    sources: Arc<Mutex<MemSources>>,
+    fetches: Arc<AtomicUsize>,
+}
+
+impl MemProvider {
+    fn new(schemes: &[&'static str]) -> Self {
+        MemProvider {
+            schemes: schemes.to_vec(),
+            ..MemProvider::default()
+        }
+    }
+
+    fn set(&self, source: &str, data: &[u8]) {
+        let mut sources = self.sources.lock().unwrap();
+        let version = sources.get(source).map_or(0, |(_, version)| version + 1);
+        sources.insert(source.to_owned(), (data.to_vec(), version));
+    }
+}
+
+impl SourceProvider for MemProvider {
+    fn schemes(&self) -> &[&str] {
+        &self.schemes
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        source: &'a Url,
+        _temp: &'a Path,
+    ) -> BoxFuture<'a, eyre::Result<FetchedSource>> {
+        Box::pin(async move {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+
+            let sources = self.sources.lock().unwrap();
+            let (data, version) = sources
+                .get(source.as_str())
+                .ok_or_else(|| eyre::eyre!("'{}' not found", source))?;
+
+            Ok(FetchedSource {
+                content: SourceContent::Memory(data.clone()),
+                version: Some(SourceVersion::Token(version.to_string())),
+            })
+        })
+    }
+
+    fn is_modified<'a>(
+        &'a self,
+        source: &'a Url,
+        version: &'a SourceVersion,
+    ) -> BoxFuture<'a, eyre::Result<bool>> {
+        Box::pin(async move {
+            let sources = self.sources.lock().unwrap();
+            let current = sources.get(source.as_str()).map(|(_, v)| v.to_string());
+            Ok(!matches!(version, SourceVersion::Token(token) if Some(token) == current.as_ref()))
+        })
+    }
+}
+
+#[tokio::test]
+async fn dispatches_custom_scheme_to_provider() {
+    let (_dir, mut treasury) = common::treasury();
+    treasury.register_importer(CopyImporter::new("copy", &["txt"]));
+
+    let provider = MemProvider::new(&["mem"]);
+    provider.set("mem:a.txt", b"first");
+    treasury.register_source_provider(provider.clone());
+
+    let source = Url::parse("mem:a.txt").unwrap();
+    let (id, path) = treasury
+        .store_url(source.clone(), None, "copy", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+    // Unchanged source is not fetched again.
+    let (again, _) = treasury
+        .store_url(source.clone(), None, "copy", new_id)
+        .await
+        .unwrap();
+    assert_eq!(id, again);
+    assert_eq!(provider.fetches.load(Ordering::Relaxed), 1);
+
+    // Provider reports new version, source is reimported.
+    provider.set("mem:a.txt", b"second");
+    let (_, path) = treasury
+        .store_url(source, None, "copy", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"second");
+    assert_eq!(provider.fetches.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn registered_provider_replaces_builtin() {
+    let (_dir, mut treasury) = common::treasury();
+    treasury.register_importer(CopyImporter::new("copy", &["txt"]));
+
+    let provider = MemProvider::new(&["data"]);
+    provider.set("data:,builtin", b"custom");
+    treasury.register_source_provider(provider.clone());
+
+    let source = Url::parse("data:,builtin").unwrap();
+    let (_, path) = treasury
+        .store_url(source, None, "copy", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"custom");
+    assert_eq!(provider.fetches.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn fails_on_unsupported_scheme() {
+    let (_dir, mut treasury) = common::treasury();
+    treasury.register_importer(CopyImporter::new("copy", &["txt"]));
+
+    let source = Url::parse("unknown:a.txt").unwrap();
+    let err = treasury
+        .store_url(source, None, "copy", new_id)
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("unknown"), "{:#}", err);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use common::new_id;
+use treasury_store::{ImporterOrigin, Treasury};
+
+/// Copies C importer library into `dir` so that tests can modify it.
+fn copy_library(dir: &Path) -> std::path::PathBuf {
+    let source = Path::new(c_importer::LIBRARY_PATH);
+    let lib_path = dir.join(source.file_name().unwrap());
+    std::fs::copy(source, &lib_path).unwrap();
+    lib_path
+}
+
+/// Changes library file without changing its code.
+fn touch_library(lib_path: &Path) {
+    let mut file = OpenOptions::new().append(true).open(lib_path).unwrap();
+    file.write_all(&[0]).unwrap();
+}
+
+async fn store_upper(treasury: &Treasury) -> std::path::PathBuf {
+    let (_, path) = treasury
+        .store("a.upper", None, "text", new_id)
+        .await
+        .unwrap();
+    path
+}
+
+#[tokio::test]
+async fn skips_unchanged_library() {
+    let (dir, mut treasury) = common::treasury();
+    let lib_path = copy_library(dir.path());
+    unsafe { treasury.register_importers_lib(&lib_path).unwrap() };
+
+    assert!(!unsafe { treasury.reload_importers_lib(&lib_path).unwrap() });
+    assert!(treasury.library_errors().is_empty());
+}
+
+#[tokio::test]
+async fn reimports_assets_after_reload() {
+    let (dir, mut treasury) = common::treasury();
+    let lib_path = copy_library(dir.path());
+    unsafe { treasury.register_importers_lib(&lib_path).unwrap() };
+    std::fs::write(dir.path().join("a.upper"), "hello").unwrap();
+
+    let path = store_upper(&treasury).await;
+    assert_eq!(std::fs::read(&path).unwrap(), b"HELLO");
+
+    // Unchanged importers reuse artifact.
+    std::fs::write(&path, "stale").unwrap();
+    let path = store_upper(&treasury).await;
+    assert_eq!(std::fs::read(&path).unwrap(), b"stale");
+
+    touch_library(&lib_path);
+    assert!(unsafe { treasury.reload_importers_lib(&lib_path).unwrap() });
+
+    let importers = treasury.importers();
+    assert_eq!(importers.len(), 1);
+    assert_eq!(importers[0].origin, ImporterOrigin::Library(lib_path));
+
+    let path = store_upper(&treasury).await;
+    assert_eq!(std::fs::read(&path).unwrap(), b"HELLO");
+}
+
+#[tokio::test]
+async fn keeps_importers_when_reload_fails() {
+    let (dir, mut treasury) = common::treasury();
+    let lib_path = copy_library(dir.path());
+    unsafe { treasury.register_importers_lib(&lib_path).unwrap() };
+    std::fs::write(dir.path().join("a.upper"), "hello").unwrap();
+
+    std::fs::write(&lib_path, "not a library").unwrap();
+    let err = unsafe { treasury.reload_importers_lib(&lib_path).unwrap_err() };
+    assert_eq!(err.path, lib_path);
+
+    let errors = treasury.library_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].path, lib_path);
+
+    let path = store_upper(&treasury).await;
+    assert_eq!(std::fs::read(&path).unwrap(), b"HELLO");
+
+    // Restored library is the one already loaded, reloading it clears the error.
+    copy_library(dir.path());
+    assert!(!unsafe { treasury.reload_importers_lib(&lib_path).unwrap() });
+    assert!(treasury.library_errors().is_empty());
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use std::path::Path;
+
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+use treasury_store::{
+    remote::{self, Client, ClientMessage, ServerMessage},
+    Treasury,
+};
+
+use common::{new_id, CopyImporter};
+
+fn treasury() -> (tempfile::TempDir, Treasury) {
+    let (dir, mut treasury) = common::treasury();
+    treasury.register_importer(CopyImporter::new("copy", &["txt"]));
+    (dir, treasury)
+}
+
+/// Runs client and server over in-memory stream until client is dropped.
+async fn with_server<F, R>(treasury: &Treasury, base: &Path, f: F) -> R
+where
+    F: AsyncFnOnce(&mut Client<DuplexStream>) -> R,
+{
+    let (client, server) = duplex(1024);
+    let mut client = Client::new(client, base);
+
+    let serve = remote::serve(treasury, server, new_id);
+    let run = async move {
+        let result = f(&mut client).await;
+        drop(client);
+        result
+    };
+
+    let (served, result) = tokio::join!(serve, run);
+    served.unwrap();
+    result
+}
+
+#[tokio::test]
+async fn messages_round_trip() {
+    let (mut a, mut b) = duplex(64);
+
+    let message = ClientMessage::Store {
+        source: "a.txt".to_owned(),
+        format: None,
+        target: "copy".to_owned(),
+    };
+    remote::send(&mut a, &message).await.unwrap();
+
+    match remote::recv(&mut b).await.unwrap() {
+        Some(ClientMessage::Store {
+            source,
+            format,
+            target,
+        }) => {
+            assert_eq!(source, "a.txt");
+            assert_eq!(format, None);
+            assert_eq!(target, "copy");
+        }
+        _ => panic!("Unexpected message"),
+    }
+
+    drop(a);
+    assert!(remote::recv::<_, ClientMessage>(&mut b)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn rejects_oversized_message() {
+    let (mut a, mut b) = duplex(64);
+
+    a.write_all(&u32::MAX.to_le_bytes()).await.unwrap();
+    assert!(remote::recv::<_, ServerMessage>(&mut b).await.is_err());
+}
+
+#[tokio::test]
+async fn rejects_truncated_message() {
+    let (mut a, mut b) = duplex(64);
+
+    a.write_all(&16u32.to_le_bytes()).await.unwrap();
+    a.write_all(&[0; 4]).await.unwrap();
+    drop(a);
+
+    assert!(remote::recv::<_, ServerMessage>(&mut b).await.is_err());
+}
+
+#[tokio::test]
+async fn stores_client_source() {
+    let (_dir, treasury) = treasury();
+    let client_dir = tempfile::tempdir().unwrap();
+    std::fs::write(client_dir.path().join("a.txt"), "hello").unwrap();
+
+    let (first, second) = with_server(&treasury, client_dir.path(), async |client| {
+        let first = client.store("a.txt", None, "copy").await.unwrap();
+        // Second store only asks client whether the source was modified.
+        let second = client.store("a.txt", None, "copy").await.unwrap();
+        (first, second)
+    })
+    .await;
+
+    assert_eq!(first, second);
+}
+
+#[tokio::test]
+async fn stores_source_larger_than_message() {
+    let (_dir, treasury) = treasury();
+    let client_dir = tempfile::tempdir().unwrap();
+
+    // Source is sent in chunks, as it does not fit into single message.
+    let data: Vec<u8> = (0..3 << 20).map(|i| i as u8).collect();
+    std::fs::write(client_dir.path().join("a.txt"), &data).unwrap();
+
+    let id = with_server(&treasury, client_dir.path(), async |client| {
+        client.store("a.txt", None, "copy").await.unwrap()
+    })
+    .await;
+
+    let path = treasury.fetch(id, new_id).await.unwrap();
+    assert!(std::fs::read(path).unwrap() == data);
+}
+
+#[tokio::test]
+async fn reports_missing_source() {
+    let (_dir, treasury) = treasury();
+    let client_dir = tempfile::tempdir().unwrap();
+
+    let err = with_server(&treasury, client_dir.path(), async |client| {
+        client.store("missing.txt", None, "copy").await.unwrap_err()
+    })
+    .await;
+
+    assert!(format!("{:#}", err).contains("not found"), "{:#}", err);
+}
+
+#[tokio::test]
+async fn reports_client_read_error() {
+    let (_dir, treasury) = treasury();
+    let client_dir = tempfile::tempdir().unwrap();
+
+    // Reading a directory fails after its metadata is read successfully.
+    std::fs::create_dir(client_dir.path().join("dir.txt")).unwrap();
+
+    let err = with_server(&treasury, client_dir.path(), async |client| {
+        client.store("dir.txt", None, "copy").await.unwrap_err()
+    })
+    .await;
+
+    assert!(
+        format!("{:#}", err).contains("Failed to read source file"),
+        "{:#}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn replies_with_failure_to_sources_outside_of_base() {
+    let client_dir = tempfile::tempdir().unwrap();
+    let (client, mut server) = duplex(1024);
+    let mut client = Client::new(client, client_dir.path());
+
+    let fake_server = async move {
+        let request = remote::recv::<_, ClientMessage>(&mut server).await.unwrap();
+        assert!(matches!(request, Some(ClientMessage::Store { .. })));
+
+        let fetch = ServerMessage::FetchSource {
+            source: "../secret".to_owned(),
+        };
+        remote::send(&mut server, &fetch).await.unwrap();
+
+        let reply = remote::recv::<_, ClientMessage>(&mut server).await.unwrap();
+        let reason = match reply {
+            Some(ClientMessage::Failed { source, reason }) => {
+                assert_eq!(source, "../secret");
+                reason
+            }
+            _ => panic!("Unexpected reply"),
+        };
+
+        remote::send(&mut server, &ServerMessage::Failed { reason })
+            .await
+            .unwrap();
+    };
+
+    let (_, result) = tokio::join!(fake_server, client.store("a.txt", None, "copy"));
+    let err = result.unwrap_err();
+
+    assert!(
+        format!("{:#}", err).contains("outside of base directory"),
+        "{:#}",
+        err
+    );
+}
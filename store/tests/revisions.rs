@@ -0,0 +1,52 @@
+mod common;
+
+use std::path::Path;
+
+use common::new_id;
+
+#[tokio::test]
+async fn loads_revision_3_library() {
+    let (dir, mut treasury) = common::treasury();
+    unsafe {
+        treasury
+            .register_importers_lib(Path::new(c_importer::REV3_LIBRARY_PATH))
+            .unwrap()
+    };
+
+    let importers = treasury.importers();
+    assert_eq!(importers.len(), 1);
+    assert_eq!(importers[0].name, "C lowercase importer");
+    assert_eq!(importers[0].formats, ["lowercase"]);
+    assert_eq!(importers[0].extensions, ["lower"]);
+    assert_eq!(importers[0].target, "text");
+
+    // Revision 3 has no metadata.
+    assert_eq!(importers[0].description, "");
+    assert_eq!(importers[0].version, None);
+
+    std::fs::write(dir.path().join("a.lower"), "Hello, World!").unwrap();
+    let (_, path) = treasury
+        .store("a.lower", None, "text", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), b"hello, world!");
+}
+
+#[tokio::test]
+async fn requires_sources_through_revision_3() {
+    let (dir, mut treasury) = common::treasury();
+    unsafe {
+        treasury
+            .register_importers_lib(Path::new(c_importer::REV3_LIBRARY_PATH))
+            .unwrap()
+    };
+
+    std::fs::write(dir.path().join("main.lower"), "+other.lower\nMain, ").unwrap();
+    std::fs::write(dir.path().join("other.lower"), "Other").unwrap();
+
+    let (_, path) = treasury
+        .store("main.lower", None, "text", new_id)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), b"main, other");
+}
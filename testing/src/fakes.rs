@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use tempfile::TempDir;
+use treasury_id::AssetId;
+use treasury_import::{Dependencies, Dependency, SourceStreams, Sources};
+
+/// In-memory [`Sources`] and [`SourceStreams`] fake that records requests.
+///
+/// Like the store, it returns only sources that were fetched.
+/// [`Fixture::run`](crate::Fixture::run) fetches sources importer requires and runs it again.
+/// Fetched sources are written into temporary directory.
+pub struct FakeSources {
+    dir: TempDir,
+    data: HashMap<String, Vec<u8>>,
+    fetched: HashMap<String, PathBuf>,
+    requests: Vec<String>,
+}
+
+impl FakeSources {
+    pub fn new() -> io::Result<Self> {
+        Ok(FakeSources {
+            dir: tempfile::tempdir()?,
+            data: HashMap::new(),
+            fetched: HashMap::new(),
+            requests: Vec::new(),
+        })
+    }
+
+    /// Adds source that importer may request.
+    pub fn insert(&mut self, source: impl Into<String>, data: impl Into<Vec<u8>>) {
+        let source = source.into();
+        self.fetched.remove(&source);
+        self.data.insert(source, data.into());
+    }
+
+    /// Writes source into temporary directory, so that [`Sources::get`] returns it.
+    /// Returns `false` if there is no such source.
+    pub fn fetch(&mut self, source: &str) -> io::Result<bool> {
+        if self.fetched.contains_key(source) {
+            return Ok(true);
+        }
+
+        let data = match self.data.get(source) {
+            None => return Ok(false),
+            Some(data) => data,
+        };
+
+        // Keep file name, importers may look at the extension.
+        // Directories of previous runs are reused.
+        let dir = self.dir.path().join(self.fetched.len().to_string());
+        let path = dir.join(file_name(source));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(&path, data)?;
+        self.fetched.insert(source.to_owned(), path);
+        Ok(true)
+    }
+
+    /// Returns all sources requested by importer, in order of requests.
+    pub fn requests(&self) -> &[String] {
+        &self.requests
+    }
+
+    /// Forgets fetched sources and requests.
+    pub fn reset(&mut self) {
+        self.fetched.clear();
+        self.requests.clear();
+    }
+}
+
+impl Sources for FakeSources {
+    fn get(&mut self, source: &str) -> Result<Option<PathBuf>, String> {
+        self.requests.push(source.to_owned());
+        Ok(self.fetched.get(source).cloned())
+    }
+}
+
+impl SourceStreams for FakeSources {
+    fn open(&mut self, source: &str) -> Result<Option<Box<dyn Read + '_>>, String> {
+        self.requests.push(source.to_owned());
+        if !self.fetched.contains_key(source) {
+            return Ok(None);
+        }
+        Ok(self
+            .data
+            .get(source)
+            .map(|data| Box::new(&data[..]) as Box<dyn Read>))
+    }
+}
+
+/// Returns last segment of source path or URL.
+pub(crate) fn file_name(source: &str) -> &str {
+    match source.rsplit(['/', '\\']).next() {
+        Some(name) if !name.is_empty() => name,
+        _ => "source",
+    }
+}
+
+/// In-memory [`Dependencies`] fake that records requests.
+///
+/// Like the store, it returns only dependencies that were stored.
+/// [`Fixture::run`](crate::Fixture::run) stores dependencies importer requires and runs it again.
+pub struct FakeDependencies {
+    assets: HashMap<Dependency, AssetId>,
+    stored: HashSet<Dependency>,
+    requests: Vec<Dependency>,
+}
+
+impl FakeDependencies {
+    pub fn new() -> Self {
+        FakeDependencies {
+            assets: HashMap::new(),
+            stored: HashSet::new(),
+            requests: Vec::new(),
+        }
+    }
+
+    /// Adds asset that importer may depend on.
+    pub fn insert(&mut self, source: impl Into<String>, target: impl Into<String>, id: AssetId) {
+        let dependency = Dependency {
+            source: source.into(),
+            target: target.into(),
+        };
+        self.stored.remove(&dependency);
+        self.assets.insert(dependency, id);
+    }
+
+    /// Marks dependency as stored, so that [`Dependencies::get`] returns it.
+    /// Returns `false` if there is no such asset.
+    pub fn store(&mut self, dependency: &Dependency) -> bool {
+        if !self.assets.contains_key(dependency) {
+            return false;
+        }
+        self.stored.insert(dependency.clone());
+        true
+    }
+
+    /// Returns all dependencies requested by importer, in order of requests.
+    pub fn requests(&self) -> &[Dependency] {
+        &self.requests
+    }
+
+    /// Forgets stored dependencies and requests.
+    pub fn reset(&mut self) {
+        self.stored.clear();
+        self.requests.clear();
+    }
+}
+
+impl Default for FakeDependencies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dependencies for FakeDependencies {
+    fn get(&mut self, source: &str, target: &str) -> Result<Option<AssetId>, String> {
+        let dependency = Dependency {
+            source: source.to_owned(),
+            target: target.to_owned(),
+        };
+
+        let id = if self.stored.contains(&dependency) {
+            self.assets.get(&dependency).copied()
+        } else {
+            None
+        };
+
+        self.requests.push(dependency);
+        Ok(id)
+    }
+}
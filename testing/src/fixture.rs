@@ -0,0 +1,246 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use tempfile::TempDir;
+use treasury_id::AssetId;
+use treasury_import::{Dependency, Diagnostic, ImportError, Importer, Progress};
+
+use crate::fakes::{file_name, FakeDependencies, FakeSources};
+
+/// Same limit as the store uses to break infinite loops.
+const MAX_ATTEMPTS: u32 = 1024;
+
+/// Source to import along with sources and dependencies importer may request.
+///
+/// The same fixture can be run through importer type directly
+/// and through [`DylibImporter`](treasury_import::loading::DylibImporter) loaded from built library.
+pub struct Fixture {
+    dir: TempDir,
+    source: PathBuf,
+    options: Option<String>,
+    sources: FakeSources,
+    dependencies: FakeDependencies,
+}
+
+impl Fixture {
+    /// Creates fixture with source data.
+    /// `name` is the source file name, importers may look at its extension.
+    pub fn new(name: &str, data: impl AsRef<[u8]>) -> io::Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("source").join(file_name(name));
+        std::fs::create_dir(source.parent().unwrap())?;
+        std::fs::write(&source, data)?;
+
+        Ok(Fixture {
+            dir,
+            source,
+            options: None,
+            sources: FakeSources::new()?,
+            dependencies: FakeDependencies::new(),
+        })
+    }
+
+    /// Adds source that importer may request.
+    pub fn with_source(mut self, source: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        self.sources.insert(source, data);
+        self
+    }
+
+    /// Adds asset that importer may depend on.
+    pub fn with_dependency(
+        mut self,
+        source: impl Into<String>,
+        target: impl Into<String>,
+        id: AssetId,
+    ) -> Self {
+        self.dependencies.insert(source, target, id);
+        self
+    }
+
+    /// Sets import options, a JSON document.
+    pub fn with_options(mut self, options: impl Into<String>) -> Self {
+        self.options = Some(options.into());
+        self
+    }
+
+    /// Returns path of the source file.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// Returns sources fake with requests of the last run.
+    pub fn sources(&self) -> &FakeSources {
+        &self.sources
+    }
+
+    /// Returns dependencies fake with requests of the last run.
+    pub fn dependencies(&self) -> &FakeDependencies {
+        &self.dependencies
+    }
+
+    /// Runs the importer until it succeeds or fails.
+    ///
+    /// Like the store, fetches sources and stores dependencies importer requires
+    /// and runs it again. Fails if they are missing in the fixture.
+    ///
+    /// Importers that support streams are run with [`Importer::import_stream`].
+    pub fn run(&mut self, importer: &dyn Importer) -> Result<Imported, RunError> {
+        self.run_impl(importer, importer.supports_streams())
+    }
+
+    /// Same as [`Fixture::run`], but runs [`Importer::import`] even if importer supports streams.
+    pub fn run_paths(&mut self, importer: &dyn Importer) -> Result<Imported, RunError> {
+        self.run_impl(importer, false)
+    }
+
+    fn run_impl(&mut self, importer: &dyn Importer, streams: bool) -> Result<Imported, RunError> {
+        self.sources.reset();
+        self.dependencies.reset();
+
+        let output = self.dir.path().join("output");
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            if output.exists() {
+                std::fs::remove_file(&output)?;
+            }
+
+            let mut diagnostics = Vec::new();
+            let mut progress = Reports(Vec::new());
+            let mut streamed = Vec::new();
+
+            let result = if streams {
+                let mut source = BufReader::new(File::open(&self.source)?);
+                importer.import_stream(
+                    &mut source,
+                    &mut streamed,
+                    self.options.as_deref(),
+                    &mut self.sources,
+                    &mut self.dependencies,
+                    &(),
+                    &mut diagnostics,
+                    &mut progress,
+                )
+            } else {
+                importer.import(
+                    &self.source,
+                    &output,
+                    self.options.as_deref(),
+                    &mut self.sources,
+                    &mut self.dependencies,
+                    &(),
+                    &mut diagnostics,
+                    &mut progress,
+                )
+            };
+
+            match result {
+                Ok(()) => {
+                    return Ok(Imported {
+                        output: if streams {
+                            streamed
+                        } else {
+                            std::fs::read(&output)?
+                        },
+                        diagnostics,
+                        progress: progress.0,
+                        attempts: attempt,
+                    })
+                }
+                Err(ImportError::RequireSources { sources }) => {
+                    let mut missing = Vec::new();
+                    for source in sources {
+                        if !self.sources.fetch(&source)? {
+                            missing.push(source);
+                        }
+                    }
+                    if !missing.is_empty() {
+                        return Err(RunError::MissingSources(missing));
+                    }
+                }
+                Err(ImportError::RequireDependencies { dependencies }) => {
+                    let missing: Vec<_> = dependencies
+                        .into_iter()
+                        .filter(|dependency| !self.dependencies.store(dependency))
+                        .collect();
+                    if !missing.is_empty() {
+                        return Err(RunError::MissingDependencies(missing));
+                    }
+                }
+                Err(ImportError::Cancelled) => return Err(RunError::Cancelled),
+                Err(ImportError::Other { reason }) => {
+                    return Err(RunError::Failed {
+                        reason,
+                        diagnostics,
+                    })
+                }
+                Err(ImportError::Panicked { message }) => {
+                    return Err(RunError::Panicked { message })
+                }
+            }
+        }
+
+        Err(RunError::TooManyAttempts)
+    }
+}
+
+/// Result of successful import.
+#[derive(Debug)]
+pub struct Imported {
+    /// Content of the output.
+    pub output: Vec<u8>,
+
+    /// Diagnostics reported by the last attempt.
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// Fractions and stages reported by the last attempt.
+    pub progress: Vec<(f32, String)>,
+
+    /// Number of times importer was called.
+    pub attempts: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RunError {
+    #[error("Importer requires sources missing in the fixture: {}", .0.join(", "))]
+    MissingSources(Vec<String>),
+
+    #[error("Importer requires dependencies missing in the fixture: {}", display_dependencies(.0))]
+    MissingDependencies(Vec<Dependency>),
+
+    #[error("Too many import attempts")]
+    TooManyAttempts,
+
+    #[error("Importer cancelled the import")]
+    Cancelled,
+
+    #[error("Import failed. {reason}")]
+    Failed {
+        reason: String,
+        diagnostics: Vec<Diagnostic>,
+    },
+
+    #[error("Importer panicked: {message}")]
+    Panicked { message: String },
+
+    #[error("Failed to access fixture files")]
+    Io(#[from] io::Error),
+}
+
+fn display_dependencies(dependencies: &[Dependency]) -> String {
+    dependencies
+        .iter()
+        .map(|dependency| format!("'{}' -> '{}'", dependency.source, dependency.target))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+struct Reports(Vec<(f32, String)>);
+
+impl Progress for Reports {
+    fn report(&mut self, fraction: f32, stage: &str) {
+        self.0.push((fraction, stage.to_owned()));
+    }
+}
@@ -0,0 +1,83 @@
+//! Test harness for treasury importers.
+//!
+//! Runs importers without the store, with in-memory sources and dependencies.
+//! The same fixtures can be run through importer type directly
+//! and through importers loaded from built library with [`load_importers`],
+//! and outputs compared with snapshots using [`assert_snapshot`].
+//!
+//! # Usage
+//!
+//! ```
+//! use treasury_import::{
+//!     Cancellation, Dependencies, Diagnostics, ImportError, Importer, Progress, Sources,
+//! };
+//! use treasury_import_testing::Fixture;
+//!
+//! struct ConcatImporter;
+//!
+//! impl Importer for ConcatImporter {
+//!     fn name(&self) -> &str {
+//!         "Concat importer"
+//!     }
+//!
+//!     fn formats(&self) -> &[&str] {
+//!         &["concat"]
+//!     }
+//!
+//!     fn target(&self) -> &str {
+//!         "text"
+//!     }
+//!
+//!     fn extensions(&self) -> &[&str] {
+//!         &["txt"]
+//!     }
+//!
+//!     fn import(
+//!         &self,
+//!         source: &std::path::Path,
+//!         output: &std::path::Path,
+//!         _options: Option<&str>,
+//!         sources: &mut dyn Sources,
+//!         _dependencies: &mut dyn Dependencies,
+//!         _cancellation: &dyn Cancellation,
+//!         _diagnostics: &mut dyn Diagnostics,
+//!         _progress: &mut dyn Progress,
+//!     ) -> Result<(), ImportError> {
+//!         let other = match sources.get("other.txt") {
+//!             Ok(Some(path)) => path,
+//!             Ok(None) => {
+//!                 return Err(ImportError::RequireSources {
+//!                     sources: vec!["other.txt".to_owned()],
+//!                 })
+//!             }
+//!             Err(reason) => return Err(ImportError::Other { reason }),
+//!         };
+//!
+//!         let mut data = std::fs::read(source).unwrap();
+//!         data.extend(std::fs::read(other).unwrap());
+//!         std::fs::write(output, data).unwrap();
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut fixture = Fixture::new("main.txt", "Hello, ")
+//!     .unwrap()
+//!     .with_source("other.txt", "World!");
+//!
+//! let imported = fixture.run(&ConcatImporter).unwrap();
+//! assert_eq!(imported.output, b"Hello, World!");
+//! assert_eq!(imported.attempts, 2);
+//! assert_eq!(fixture.sources().requests(), ["other.txt", "other.txt"]);
+//! ```
+
+mod fakes;
+mod fixture;
+mod library;
+mod snapshot;
+
+pub use self::{
+    fakes::{FakeDependencies, FakeSources},
+    fixture::{Fixture, Imported, RunError},
+    library::{cdylib_path, load_importers},
+    snapshot::{assert_snapshot, UPDATE_SNAPSHOTS_VAR},
+};
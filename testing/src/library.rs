@@ -0,0 +1,42 @@
+use std::{
+    env::consts::{DLL_PREFIX, DLL_SUFFIX},
+    path::{Path, PathBuf},
+};
+
+use treasury_import::loading::{DylibImporter, LoadingError};
+
+/// Returns path of the dynamic library built from `package`
+/// in the target directory of the running test executable.
+///
+/// Cargo does not build cdylib of the package for its own integration tests,
+/// run `cargo build` before `cargo test` to roundtrip importers through FFI.
+pub fn cdylib_path(package: &str) -> PathBuf {
+    let exe = std::env::current_exe().expect("Failed to get path of the test executable");
+    let mut dir = exe
+        .parent()
+        .expect("Test executable must be in a directory");
+
+    // Test executables are placed into `deps` next to built libraries.
+    if dir.ends_with("deps") {
+        dir = dir.parent().unwrap();
+    }
+
+    dir.join(format!(
+        "{}{}{}",
+        DLL_PREFIX,
+        package.replace('-', "_"),
+        DLL_SUFFIX
+    ))
+}
+
+/// Loads all importers from dynamic library at specified path.
+///
+/// Loaded importers can be passed to [`Fixture::run`](crate::Fixture::run)
+/// to check that they behave through FFI the same way as without it.
+///
+/// # Safety
+///
+/// Same as for [`treasury_import::loading::load_importers`].
+pub unsafe fn load_importers(path: &Path) -> Result<Vec<DylibImporter>, LoadingError> {
+    Ok(treasury_import::loading::load_importers(path)?.collect())
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+/// Set this environment variable to overwrite existing snapshots.
+pub const UPDATE_SNAPSHOTS_VAR: &str = "TREASURY_UPDATE_SNAPSHOTS";
+
+/// Compares import output with snapshot stored in `tests/snapshots/<name>`
+/// of the package being tested.
+///
+/// Missing snapshot is written and the check passes.
+/// Set [`UPDATE_SNAPSHOTS_VAR`] to overwrite existing snapshots.
+///
+/// # Panics
+///
+/// Panics if output does not match the snapshot.
+#[track_caller]
+pub fn assert_snapshot(name: &str, actual: &[u8]) {
+    let path = snapshot_path(name);
+
+    if std::env::var_os(UPDATE_SNAPSHOTS_VAR).is_some() || !path.exists() {
+        if let Some(parent) = path.parent() {
+            if let Err(err) = std::fs::create_dir_all(parent) {
+                panic!("Failed to create '{}'. {}", parent.display(), err);
+            }
+        }
+        if let Err(err) = std::fs::write(&path, actual) {
+            panic!("Failed to write snapshot '{}'. {}", path.display(), err);
+        }
+        return;
+    }
+
+    let expected = match std::fs::read(&path) {
+        Ok(expected) => expected,
+        Err(err) => panic!("Failed to read snapshot '{}'. {}", path.display(), err),
+    };
+
+    if expected == actual {
+        return;
+    }
+
+    match (std::str::from_utf8(&expected), std::str::from_utf8(actual)) {
+        (Ok(expected), Ok(actual)) => {
+            let mut expected_lines = expected.lines();
+            let mut actual_lines = actual.lines();
+            let mut line = 1;
+            loop {
+                match (expected_lines.next(), actual_lines.next()) {
+                    (Some(e), Some(a)) if e == a => line += 1,
+                    (None, None) => panic!(
+                        "Output does not match snapshot '{}' in line endings\nSet {} to update snapshots",
+                        path.display(),
+                        UPDATE_SNAPSHOTS_VAR,
+                    ),
+                    (e, a) => panic!(
+                        "Output does not match snapshot '{}' at line {}\nexpected: {}\n  actual: {}\nSet {} to update snapshots",
+                        path.display(),
+                        line,
+                        e.unwrap_or("<end>"),
+                        a.unwrap_or("<end>"),
+                        UPDATE_SNAPSHOTS_VAR,
+                    ),
+                }
+            }
+        }
+        _ => {
+            let offset = expected
+                .iter()
+                .zip(actual)
+                .position(|(e, a)| e != a)
+                .unwrap_or_else(|| expected.len().min(actual.len()));
+
+            panic!(
+                "Output does not match snapshot '{}' at byte {} (expected {} bytes, got {})\nSet {} to update snapshots",
+                path.display(),
+                offset,
+                expected.len(),
+                actual.len(),
+                UPDATE_SNAPSHOTS_VAR,
+            );
+        }
+    }
+}
+
+fn snapshot_path(name: &str) -> PathBuf {
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR")
+        .expect("Snapshots can be checked only in tests run by cargo");
+
+    PathBuf::from(manifest_dir)
+        .join("tests")
+        .join("snapshots")
+        .join(name)
+}
@@ -0,0 +1,297 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+use treasury_id::AssetId;
+use treasury_import::{
+    import_with_streams, Cancellation, Dependencies, Dependency, Diagnostic, Diagnostics,
+    ImportError, Importer, Progress, Severity, SourceStreams, Sources,
+};
+use treasury_import_testing::{assert_snapshot, cdylib_path, load_importers, Fixture, RunError};
+
+/// Well-behaved streaming importer that prepends `header.txt` source
+/// and requires `dep.txt` asset.
+struct HeaderImporter;
+
+impl Importer for HeaderImporter {
+    fn name(&self) -> &str {
+        "Header importer"
+    }
+
+    fn formats(&self) -> &[&str] {
+        &["header"]
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+
+    fn target(&self) -> &str {
+        "text"
+    }
+
+    fn supports_streams(&self) -> bool {
+        true
+    }
+
+    fn import(
+        &self,
+        source: &Path,
+        output: &Path,
+        options: Option<&str>,
+        sources: &mut dyn Sources,
+        dependencies: &mut dyn Dependencies,
+        cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        import_with_streams(
+            self,
+            source,
+            output,
+            options,
+            sources,
+            dependencies,
+            cancellation,
+            diagnostics,
+            progress,
+        )
+    }
+
+    fn import_stream(
+        &self,
+        source: &mut dyn Read,
+        output: &mut dyn Write,
+        _options: Option<&str>,
+        sources: &mut dyn SourceStreams,
+        dependencies: &mut dyn Dependencies,
+        _cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        let other = |err: std::io::Error| ImportError::Other {
+            reason: err.to_string(),
+        };
+
+        let mut header = match sources.open("header.txt") {
+            Ok(Some(header)) => header,
+            Ok(None) => {
+                return Err(ImportError::RequireSources {
+                    sources: vec!["header.txt".to_owned()],
+                })
+            }
+            Err(reason) => return Err(ImportError::Other { reason }),
+        };
+        std::io::copy(&mut header, output).map_err(other)?;
+        drop(header);
+
+        match dependencies.get("dep.txt", "text") {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Err(ImportError::RequireDependencies {
+                    dependencies: vec![Dependency {
+                        source: "dep.txt".to_owned(),
+                        target: "text".to_owned(),
+                    }],
+                })
+            }
+            Err(reason) => return Err(ImportError::Other { reason }),
+        }
+
+        progress.report(0.5, "Copying");
+        std::io::copy(source, output).map_err(other)?;
+        diagnostics.report(Diagnostic::info("Prepended header"));
+        Ok(())
+    }
+}
+
+/// Importer that requests the same source forever.
+struct LoopingImporter;
+
+impl Importer for LoopingImporter {
+    fn name(&self) -> &str {
+        "Looping importer"
+    }
+
+    fn formats(&self) -> &[&str] {
+        &["loop"]
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+
+    fn target(&self) -> &str {
+        "text"
+    }
+
+    fn import(
+        &self,
+        _source: &Path,
+        _output: &Path,
+        _options: Option<&str>,
+        _sources: &mut dyn Sources,
+        _dependencies: &mut dyn Dependencies,
+        _cancellation: &dyn Cancellation,
+        _diagnostics: &mut dyn Diagnostics,
+        _progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        Err(ImportError::RequireSources {
+            sources: vec!["header.txt".to_owned()],
+        })
+    }
+}
+
+/// Importer that fails with diagnostic.
+struct FailingImporter;
+
+impl Importer for FailingImporter {
+    fn name(&self) -> &str {
+        "Failing importer"
+    }
+
+    fn formats(&self) -> &[&str] {
+        &["fail"]
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+
+    fn target(&self) -> &str {
+        "text"
+    }
+
+    fn import(
+        &self,
+        _source: &Path,
+        _output: &Path,
+        options: Option<&str>,
+        _sources: &mut dyn Sources,
+        _dependencies: &mut dyn Dependencies,
+        _cancellation: &dyn Cancellation,
+        diagnostics: &mut dyn Diagnostics,
+        _progress: &mut dyn Progress,
+    ) -> Result<(), ImportError> {
+        diagnostics.report(Diagnostic::error("Broken source"));
+        Err(ImportError::Other {
+            reason: format!("Failed with options {}", options.unwrap_or("none")),
+        })
+    }
+}
+
+fn header_fixture() -> Fixture {
+    Fixture::new("main.txt", "Main")
+        .unwrap()
+        .with_source("header.txt", "Header\n")
+        .with_dependency("dep.txt", "text", AssetId::new(1).unwrap())
+}
+
+#[test]
+fn runs_until_requirements_are_met() {
+    let mut fixture = header_fixture();
+    let imported = fixture.run(&HeaderImporter).unwrap();
+
+    assert_eq!(imported.output, b"Header\nMain");
+    assert_eq!(imported.attempts, 3);
+    assert_eq!(imported.progress, [(0.5, "Copying".to_owned())]);
+    assert_eq!(imported.diagnostics.len(), 1);
+    assert_eq!(imported.diagnostics[0].severity, Severity::Info);
+    assert_eq!(
+        fixture.sources().requests(),
+        ["header.txt", "header.txt", "header.txt"]
+    );
+    assert_eq!(fixture.dependencies().requests().len(), 2);
+}
+
+#[test]
+fn runs_paths_same_as_streams() {
+    let mut fixture = header_fixture();
+    let streamed = fixture.run(&HeaderImporter).unwrap();
+    let imported = fixture.run_paths(&HeaderImporter).unwrap();
+
+    assert_eq!(imported.output, streamed.output);
+    assert_eq!(imported.attempts, streamed.attempts);
+    assert_eq!(imported.diagnostics, streamed.diagnostics);
+}
+
+#[test]
+fn matches_snapshot() {
+    let imported = header_fixture().run(&HeaderImporter).unwrap();
+    assert_snapshot("header.txt", &imported.output);
+}
+
+#[test]
+#[should_panic(expected = "Output does not match snapshot")]
+fn rejects_output_different_from_snapshot() {
+    assert_snapshot("header.txt", b"Header\nOther");
+}
+
+#[test]
+fn reports_missing_sources() {
+    let mut fixture = Fixture::new("main.txt", "Main").unwrap();
+
+    match fixture.run(&HeaderImporter) {
+        Err(RunError::MissingSources(sources)) => assert_eq!(sources, ["header.txt"]),
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn reports_missing_dependencies() {
+    let mut fixture = Fixture::new("main.txt", "Main")
+        .unwrap()
+        .with_source("header.txt", "Header\n");
+
+    match fixture.run(&HeaderImporter) {
+        Err(RunError::MissingDependencies(dependencies)) => {
+            assert_eq!(dependencies.len(), 1);
+            assert_eq!(dependencies[0].source, "dep.txt");
+            assert_eq!(dependencies[0].target, "text");
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn stops_importer_that_never_succeeds() {
+    let mut fixture = header_fixture();
+
+    match fixture.run(&LoopingImporter) {
+        Err(RunError::TooManyAttempts) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn reports_failure_with_diagnostics() {
+    let mut fixture = Fixture::new("main.txt", "Main")
+        .unwrap()
+        .with_options(r#"{"strict":true}"#);
+
+    match fixture.run(&FailingImporter) {
+        Err(RunError::Failed {
+            reason,
+            diagnostics,
+        }) => {
+            assert_eq!(reason, r#"Failed with options {"strict":true}"#);
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].severity, Severity::Error);
+            assert_eq!(diagnostics[0].message, "Broken source");
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn fails_to_load_missing_library() {
+    let path = cdylib_path("missing-importer");
+    assert!(path
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .contains("missing_importer"));
+    assert!(unsafe { load_importers(&path) }.is_err());
+}
@@ -0,0 +1,120 @@
+use treasury_import::{importer, Diagnostic, ImportContext, Importer, Severity};
+use treasury_import_testing::{Fixture, RunError};
+
+const SCHEMA: &str = r#"{ "type": "object" }"#;
+
+fn is_shout(head: &[u8]) -> bool {
+    head.ends_with(b"!")
+}
+
+/// Converts text to upper case, prepending `header.txt` source.
+#[importer(
+    name = "Shout importer",
+    formats = ["shout"],
+    extensions = ["shout", "txt"],
+    target = "text",
+    description = "Converts text to upper case",
+    version = "1.2.3",
+    package = env!("CARGO_PKG_NAME"),
+    options_schema = SCHEMA,
+    magic = [b"SHOUT"],
+    probe = is_shout
+)]
+pub fn shout_importer(ctx: &mut ImportContext) -> Result<(), String> {
+    let header = ctx
+        .get_source("header.txt")
+        .map_err(|err| err.to_string())?;
+    let header = std::fs::read_to_string(header).map_err(|err| err.to_string())?;
+    let text = ctx.read_source_to_string().map_err(|err| err.to_string())?;
+
+    if text.is_empty() {
+        return Err("Nothing to shout".to_owned());
+    }
+
+    ctx.progress(0.5, "Shouting");
+    ctx.report(Diagnostic::info(format!(
+        "Options {}",
+        ctx.options().unwrap_or("none")
+    )));
+    ctx.write_output(format!("{}{}", header, text.to_uppercase()))
+        .map_err(|err| err.to_string())
+}
+
+#[importer(name = "Quiet importer", formats = ["quiet"], target = "text")]
+fn quiet_importer(ctx: &mut ImportContext) -> Result<(), std::io::Error> {
+    std::fs::copy(ctx.source(), ctx.output()).map(|_| ())
+}
+
+#[test]
+fn implements_metadata() {
+    let importer: &dyn Importer = &shout_importer;
+    assert_eq!(importer.name(), "Shout importer");
+    assert_eq!(importer.formats(), ["shout"]);
+    assert_eq!(importer.extensions(), ["shout", "txt"]);
+    assert_eq!(importer.target(), "text");
+    assert_eq!(importer.description(), "Converts text to upper case");
+    assert_eq!(importer.version(), Some("1.2.3"));
+    assert_eq!(importer.package(), Some("treasury-import-testing"));
+    assert_eq!(importer.options_schema(), Some(SCHEMA));
+    assert_eq!(importer.magic(), [b"SHOUT"]);
+    assert!(importer.probe(b"hey!"));
+    assert!(!importer.probe(b"hey"));
+}
+
+#[test]
+fn keeps_defaults_of_omitted_arguments() {
+    let importer: &dyn Importer = &quiet_importer;
+    assert_eq!(importer.name(), "Quiet importer");
+    assert!(importer.extensions().is_empty());
+    assert_eq!(importer.description(), "");
+    assert_eq!(importer.version(), None);
+    assert_eq!(importer.package(), None);
+    assert_eq!(importer.options_schema(), None);
+    assert!(importer.magic().is_empty());
+    assert!(!importer.probe(b"anything"));
+    assert!(!importer.supports_streams());
+
+    let imported = Fixture::new("a.quiet", "quiet")
+        .unwrap()
+        .run(&quiet_importer)
+        .unwrap();
+    assert_eq!(imported.output, b"quiet");
+}
+
+#[test]
+fn runs_importer_function() {
+    let mut fixture = Fixture::new("a.shout", "hello")
+        .unwrap()
+        .with_source("header.txt", "> ")
+        .with_options(r#"{"loud":true}"#);
+    let imported = fixture.run(&shout_importer).unwrap();
+
+    assert_eq!(imported.output, b"> HELLO");
+    assert_eq!(imported.attempts, 2);
+    assert_eq!(imported.progress, [(0.5, "Shouting".to_owned())]);
+    assert_eq!(imported.diagnostics.len(), 1);
+    assert_eq!(imported.diagnostics[0].severity, Severity::Info);
+    assert_eq!(imported.diagnostics[0].message, r#"Options {"loud":true}"#);
+}
+
+#[test]
+fn requires_missing_sources_despite_error() {
+    let mut fixture = Fixture::new("a.shout", "hello").unwrap();
+
+    match fixture.run(&shout_importer) {
+        Err(RunError::MissingSources(sources)) => assert_eq!(sources, ["header.txt"]),
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn reports_function_error() {
+    let mut fixture = Fixture::new("a.shout", "")
+        .unwrap()
+        .with_source("header.txt", "> ");
+
+    match fixture.run(&shout_importer) {
+        Err(RunError::Failed { reason, .. }) => assert_eq!(reason, "Nothing to shout"),
+        result => panic!("Unexpected result {:?}", result),
+    }
+}